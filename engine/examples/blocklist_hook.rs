@@ -0,0 +1,65 @@
+use std::collections::HashSet;
+
+use playit_engine::{
+    async_trait, Engine, EngineCommand, EngineConfig, Hook, HookContext, HookDecision, NopeReason,
+};
+
+struct Blocklist {
+    blocked: HashSet<String>,
+}
+
+#[async_trait]
+impl Hook for Blocklist {
+    fn name(&self) -> &str {
+        "blocklist"
+    }
+
+    async fn on_command(&self, command: &EngineCommand, context: &HookContext) -> HookDecision {
+        if context.internal {
+            return HookDecision::Continue;
+        }
+
+        let recordings = match command {
+            EngineCommand::Play(Some(id)) => vec![id.clone()],
+            EngineCommand::Queue(Some(ids)) => ids.clone(),
+            EngineCommand::QueueAfterCurrentGroup { recordings } => recordings.clone(),
+            _ => return HookDecision::Continue,
+        };
+
+        let allowed = recordings
+            .iter()
+            .filter(|id| !self.blocked.contains(*id))
+            .cloned()
+            .collect::<Vec<String>>();
+
+        match command {
+            _ if allowed.len() == recordings.len() => HookDecision::Continue,
+            _ if allowed.is_empty() => HookDecision::Reject(NopeReason::PermissionDenied),
+            EngineCommand::Queue(_) => HookDecision::Mutate(EngineCommand::Queue(Some(allowed))),
+            _ => HookDecision::Mutate(EngineCommand::QueueAfterCurrentGroup {
+                recordings: allowed,
+            }),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut config = EngineConfig::default();
+
+    config.hooks.register(Blocklist {
+        blocked: std::env::args().skip(1).collect(),
+    });
+
+    let Ok((audio_engine, _command_sender, _response_receiver)) =
+        Engine::create_with_config(config).await
+    else {
+        eprintln!("engine failed to start");
+
+        return;
+    };
+
+    let _ = tokio::signal::ctrl_c().await;
+
+    audio_engine.shutdown().await;
+}
@@ -0,0 +1,63 @@
+use std::sync::Arc;
+
+use playit_engine::{
+    Engine, EngineConnectionStatus, EngineEvent, EventError, PlaybackPhase, TrackSummary,
+};
+use tokio_stream::StreamExt;
+
+#[derive(Debug, Default)]
+struct PlayerState {
+    track: Option<Arc<TrackSummary>>,
+    playing: bool,
+    queue: Arc<[String]>,
+    volume: f32,
+    connected: bool,
+    last_error: Option<EventError>,
+}
+
+impl PlayerState {
+    fn reduce(&mut self, event: EngineEvent) {
+        match event {
+            EngineEvent::TrackChanged(track) => self.track = track,
+            EngineEvent::PlaybackPhaseChanged(phase) => {
+                self.playing = phase == PlaybackPhase::Playing
+            }
+            EngineEvent::QueueChanged(queue) => self.queue = queue,
+            EngineEvent::VolumeChanged(volume) => self.volume = volume,
+            EngineEvent::LibraryChanged { .. } => {}
+            EngineEvent::ConnectionChanged(status) => {
+                self.connected = status != EngineConnectionStatus::Disconnected
+            }
+            EngineEvent::Error(error) => self.last_error = Some(error),
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let Ok((audio_engine, _command_sender, _response_receiver)) = Engine::create().await else {
+        eprintln!("engine failed to start");
+
+        return;
+    };
+
+    let mut events = Box::pin(audio_engine.subscribe_events());
+    let mut state = PlayerState::default();
+
+    loop {
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            event = events.next() => {
+                let Some(event) = event else {
+                    break;
+                };
+
+                state.reduce(event);
+
+                println!("{:?}", state);
+            }
+        }
+    }
+
+    audio_engine.shutdown().await;
+}
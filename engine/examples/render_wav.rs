@@ -0,0 +1,106 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Seek, SeekFrom, Write},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use playit_engine::{
+    AudioOutputChoice, Engine, EngineCommand, EngineConfig, EngineResponse, PcmCallback,
+};
+use tokio::sync::broadcast;
+
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: u16 = 2;
+const WAV_HEADER_LEN: u32 = 44;
+
+#[tokio::main]
+async fn main() -> std::io::Result<()> {
+    let mut args = std::env::args().skip(1);
+
+    let Some(path) = args.next() else {
+        eprintln!("usage: render_wav <output.wav> <recording id>...");
+
+        return Ok(());
+    };
+
+    let recordings = args.collect::<Vec<String>>();
+
+    let mut file = File::create(&path)?;
+    file.write_all(&wav_header(0))?;
+
+    let data_len = Arc::new(AtomicU32::new(0));
+    let written = data_len.clone();
+
+    let output = PcmCallback::new(SAMPLE_RATE, CHANNELS, move |samples: &[f32]| {
+        let bytes = samples
+            .iter()
+            .flat_map(|sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes())
+            .collect::<Vec<u8>>();
+
+        if file.write_all(&bytes).is_ok() {
+            written.fetch_add(bytes.len() as u32, Ordering::Relaxed);
+        }
+    });
+
+    let config = EngineConfig {
+        socket: Some(format!("playit-render-{}.sock", std::process::id())),
+        audio_output: AudioOutputChoice::custom(output),
+        ..Default::default()
+    };
+
+    let Ok((audio_engine, command_sender, mut response_receiver)) =
+        Engine::create_with_config(config).await
+    else {
+        eprintln!("engine failed to start");
+
+        return Ok(());
+    };
+
+    let _ = command_sender.send(EngineCommand::Queue(Some(recordings)));
+    let _ = command_sender.send(EngineCommand::Next);
+
+    loop {
+        match response_receiver.recv().await {
+            Ok(EngineResponse::QueueEnded { .. }) | Err(broadcast::error::RecvError::Closed) => {
+                break
+            }
+            Ok(EngineResponse::Nope { command, reason }) => {
+                eprintln!("{:?} rejected: {:?}", command, reason);
+            }
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+        }
+    }
+
+    audio_engine.shutdown().await;
+
+    let mut file = OpenOptions::new().write(true).open(&path)?;
+
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&wav_header(data_len.load(Ordering::Relaxed)))?;
+
+    Ok(())
+}
+
+fn wav_header(data_len: u32) -> Vec<u8> {
+    let block_align = CHANNELS * 2;
+
+    [
+        b"RIFF".as_slice(),
+        &(data_len + WAV_HEADER_LEN - 8).to_le_bytes(),
+        b"WAVE",
+        b"fmt ",
+        &16u32.to_le_bytes(),
+        &1u16.to_le_bytes(),
+        &CHANNELS.to_le_bytes(),
+        &SAMPLE_RATE.to_le_bytes(),
+        &(SAMPLE_RATE * block_align as u32).to_le_bytes(),
+        &block_align.to_le_bytes(),
+        &16u16.to_le_bytes(),
+        b"data",
+        &data_len.to_le_bytes(),
+    ]
+    .concat()
+}
@@ -0,0 +1,886 @@
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use tokio::sync::{broadcast, mpsc};
+use uuid::Uuid;
+
+use crate::{
+    player::{
+        database::Database,
+        sequencer::{MAIN_QUEUE, REQUESTS_QUEUE},
+        LocalTagPatch, PlaylistMetadata, SessionSnapshot,
+    },
+    route_response, ChangeFeed, CommandContext, ConflictResolution, EngineCommand, EngineResponse,
+    EngineSettings, ExportFormat, LoopMode, NopeReason, OnQueueEnd, Permission, QueueModel,
+    QueueRemoveTarget, SortDirection,
+};
+
+const DEVICE_PERMISSIONS_SETTING: &str = "device_permissions";
+const DEVICE_TOKENS_SETTING: &str = "device_tokens";
+const PERMISSION_REQUEST_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandAccess {
+    Open,
+    Requires(Permission),
+    InternalOnly,
+    Unsupported,
+}
+
+impl CommandAccess {
+    pub fn allows(&self, internal: bool, permissions: &[Permission]) -> bool {
+        match self {
+            CommandAccess::Open => true,
+            CommandAccess::Requires(permission) => internal || permissions.contains(permission),
+            CommandAccess::InternalOnly => internal,
+            CommandAccess::Unsupported => false,
+        }
+    }
+}
+
+pub fn command_access(command: &EngineCommand, permissions: &[Permission]) -> CommandAccess {
+    match command {
+        EngineCommand::None
+        | EngineCommand::Goodbye
+        | EngineCommand::Hello { .. }
+        | EngineCommand::GetState
+        | EngineCommand::GetPosition
+        | EngineCommand::Queue(None)
+        | EngineCommand::GetQueues
+        | EngineCommand::GetHistory { .. }
+        | EngineCommand::QueueRange { .. }
+        | EngineCommand::RecordingMetadata { .. }
+        | EngineCommand::RecordingDuration { .. }
+        | EngineCommand::RecordingStats { .. }
+        | EngineCommand::RecordingFile(_)
+        | EngineCommand::RecordingFileChunk { .. }
+        | EngineCommand::TransferAck { .. }
+        | EngineCommand::RecordingProvenance { .. }
+        | EngineCommand::Waveform { .. }
+        | EngineCommand::PlaylistMetadata { .. }
+        | EngineCommand::ListPlaylists { .. }
+        | EngineCommand::CachePlaylist { .. }
+        | EngineCommand::ValidatePlaylist { prune: false, .. }
+        | EngineCommand::GetPermissions
+        | EngineCommand::RequestPermissions { .. }
+        | EngineCommand::AllowedCommands
+        | EngineCommand::GetSpeed
+        | EngineCommand::GetVolume
+        | EngineCommand::Capabilities
+        | EngineCommand::HealthCheck
+        | EngineCommand::SupportedFormats
+        | EngineCommand::Metrics { reset: false }
+        | EngineCommand::AudioStatus
+        | EngineCommand::ListOrphanedRecordings
+        | EngineCommand::ListTrash
+        | EngineCommand::ListTags
+        | EngineCommand::RecordingsByTag { .. }
+        | EngineCommand::ListRecordings { .. }
+        | EngineCommand::RelatedRecordings { .. }
+        | EngineCommand::PreviewAt { .. }
+        | EngineCommand::ChangesSince { .. }
+        | EngineCommand::Batch { .. } => CommandAccess::Open,
+        EngineCommand::Play(_)
+        | EngineCommand::PlayFromStart { .. }
+        | EngineCommand::Pause
+        | EngineCommand::Stop
+        | EngineCommand::Next
+        | EngineCommand::Previous
+        | EngineCommand::PlayQueueIndex { .. }
+        | EngineCommand::Seek(_)
+        | EngineCommand::SeekPercent { .. }
+        | EngineCommand::SeekRelative { .. }
+        | EngineCommand::ShuffleQueue { .. }
+        | EngineCommand::LoopMode(_)
+        | EngineCommand::OnQueueEnd(_)
+        | EngineCommand::SmoothLevelTransition { .. }
+        | EngineCommand::PauseFade(_)
+        | EngineCommand::StopAfterCurrent { .. }
+        | EngineCommand::SetSpeed { .. }
+        | EngineCommand::SetVolume { .. }
+        | EngineCommand::Mute { .. } => CommandAccess::Requires(Permission::Control),
+        EngineCommand::QueueTo { queue, .. }
+            if queue == REQUESTS_QUEUE && permissions.contains(&Permission::Request) =>
+        {
+            CommandAccess::Requires(Permission::Request)
+        }
+        EngineCommand::Queue(Some(_))
+        | EngineCommand::QueuePlaylist { .. }
+        | EngineCommand::QueueRelease { .. }
+        | EngineCommand::QueueAfterCurrentGroup { .. }
+        | EngineCommand::QueueNext { .. }
+        | EngineCommand::QueueTo { .. }
+        | EngineCommand::ClearQueue { .. }
+        | EngineCommand::QueueRemove(_)
+        | EngineCommand::QueueMove { .. }
+        | EngineCommand::SetRadioMode { .. }
+        | EngineCommand::QueueModel(_) => CommandAccess::Requires(Permission::Queue),
+        EngineCommand::ValidatePlaylist { prune: true, .. }
+        | EngineCommand::SetPlaylistMetadata(_)
+        | EngineCommand::ResolvePlaylistConflict { .. }
+        | EngineCommand::TagRecording { .. }
+        | EngineCommand::DeletePlaylist { .. } => CommandAccess::Requires(Permission::Playlist),
+        EngineCommand::StreamListen { .. } if !cfg!(feature = "opus-stream") => {
+            CommandAccess::Unsupported
+        }
+        EngineCommand::SendRecording(_)
+        | EngineCommand::SendVerifiedRecording { .. }
+        | EngineCommand::SendRecordingChunk { .. }
+        | EngineCommand::StreamListen { .. }
+        | EngineCommand::HandOff { .. }
+        | EngineCommand::AdoptSession(_) => CommandAccess::Requires(Permission::Transfer),
+        EngineCommand::LibraryEvents { .. }
+        | EngineCommand::ExportHistory { .. }
+        | EngineCommand::VerifyLibrary { .. }
+        | EngineCommand::RefreshLibrary { .. }
+        | EngineCommand::CancelJob { .. }
+        | EngineCommand::EditLocalMetadata { .. }
+        | EngineCommand::SetSpokenWord { .. }
+        | EngineCommand::AdoptLooseFiles
+        | EngineCommand::DeleteRecording { .. }
+        | EngineCommand::RestoreFromTrash { .. }
+        | EngineCommand::EmptyTrash { .. } => CommandAccess::Requires(Permission::Library),
+        EngineCommand::ResolvePermissionRequest { .. }
+        | EngineCommand::UpdateSettings(_)
+        | EngineCommand::ClearSavedState { .. }
+        | EngineCommand::Metrics { reset: true }
+        | EngineCommand::ResetMetrics
+        | EngineCommand::DiagnosticBundle
+        | EngineCommand::AuditLog { .. }
+        | EngineCommand::ListClients
+        | EngineCommand::SetDeviceTransferBudget { .. } => {
+            CommandAccess::Requires(Permission::Admin)
+        }
+        EngineCommand::LinkExternalFile { .. }
+        | EngineCommand::PlayCue { .. }
+        | EngineCommand::SetPermissions(_) => CommandAccess::InternalOnly,
+        EngineCommand::FollowPlayback { .. } => CommandAccess::Unsupported,
+    }
+}
+
+pub fn allowed_commands(internal: bool, permissions: &[Permission]) -> Vec<String> {
+    let mut allowed = Vec::<String>::new();
+
+    for command in representative_commands() {
+        let kind = command.kind();
+
+        if command_access(&command, permissions).allows(internal, permissions)
+            && !allowed.iter().any(|allowed| allowed == kind)
+        {
+            allowed.push(kind.to_owned());
+        }
+    }
+
+    allowed
+}
+
+pub struct PermissionRequest {
+    pub connection: Uuid,
+    device: String,
+    requested: Vec<Permission>,
+}
+
+/// Who is connected, which device each connection speaks for and what those
+/// devices have been granted.
+pub struct Connections {
+    pub current_user_permissions: Vec<Permission>,
+    pub device_permissions: HashMap<String, Vec<Permission>>,
+    pub device_tokens: HashMap<String, String>,
+    pub connection_devices: HashMap<Uuid, String>,
+    pub connection_names: HashMap<Uuid, String>,
+    pub permission_requests: HashMap<Uuid, PermissionRequest>,
+    permission_expiry_sender: mpsc::Sender<Uuid>,
+}
+
+impl Connections {
+    pub async fn load(database: &Database, permission_expiry_sender: mpsc::Sender<Uuid>) -> Self {
+        Self {
+            current_user_permissions: Vec::new(),
+            device_permissions: database
+                .get_setting::<HashMap<String, Vec<Permission>>>(DEVICE_PERMISSIONS_SETTING)
+                .await
+                .unwrap_or_default(),
+            device_tokens: database
+                .get_setting::<HashMap<String, String>>(DEVICE_TOKENS_SETTING)
+                .await
+                .unwrap_or_default(),
+            connection_devices: HashMap::new(),
+            connection_names: HashMap::new(),
+            permission_requests: HashMap::new(),
+            permission_expiry_sender,
+        }
+    }
+
+    pub fn permissions(&self, connection: Uuid) -> Vec<Permission> {
+        self.device_grants(self.connection_devices.get(&connection))
+    }
+
+    /// Whether the connection speaks for a device the engine paired, rather
+    /// than standing in for itself until it pairs.
+    pub fn is_paired(&self, connection: Uuid) -> bool {
+        self.connection_devices
+            .get(&connection)
+            .is_some_and(|device| self.device_tokens.values().any(|paired| paired == device))
+    }
+
+    pub fn forget(&mut self, connection: Uuid) {
+        self.connection_devices.remove(&connection);
+        self.connection_names.remove(&connection);
+        self.permission_requests
+            .retain(|_, request| request.connection != connection);
+    }
+
+    fn device_grants(&self, device: Option<&String>) -> Vec<Permission> {
+        let mut permissions = self.current_user_permissions.clone();
+
+        if let Some(granted) = device.and_then(|device| self.device_permissions.get(device)) {
+            for permission in granted {
+                if !permissions.contains(permission) {
+                    permissions.push(permission.clone());
+                }
+            }
+        }
+
+        permissions
+    }
+
+    fn label(&self, connection: Uuid) -> String {
+        self.connection_names
+            .get(&connection)
+            .or_else(|| self.connection_devices.get(&connection))
+            .cloned()
+            .unwrap_or_else(|| connection.to_string())
+    }
+
+    fn route_to_admins(
+        &self,
+        internal_sender: &broadcast::Sender<EngineResponse>,
+        remote_sender: &ChangeFeed,
+        response: EngineResponse,
+    ) {
+        let _ = internal_sender.send(response.clone());
+
+        for (connection, device) in &self.connection_devices {
+            if self
+                .device_grants(Some(device))
+                .contains(&Permission::Admin)
+            {
+                let _ = remote_sender.send((response.clone(), *connection));
+            }
+        }
+    }
+}
+
+/// Refuses `command` for lacking `permission`, recording the denial and
+/// letting admins know when it made it into the audit log.
+pub async fn deny(
+    command: EngineCommand,
+    permission: Permission,
+    context: CommandContext<'_>,
+    connections: &Connections,
+) {
+    let audit_entry = context
+        .database
+        .record_denial(
+            connections.label(context.uuid),
+            command.kind().to_owned(),
+            permission,
+        )
+        .await;
+
+    let _ = context.response_sender.send((
+        EngineResponse::Nope {
+            command,
+            reason: NopeReason::PermissionDenied,
+        },
+        context.uuid,
+    ));
+
+    if let Some(audit_entry) = audit_entry {
+        connections.route_to_admins(
+            context.internal_response_sender,
+            context.response_sender,
+            EngineResponse::AuditEvent(audit_entry),
+        );
+    }
+}
+
+/// Hands `command` back if the connection may run it, answering it otherwise.
+pub async fn authorize(
+    command: EngineCommand,
+    context: CommandContext<'_>,
+    connections: &Connections,
+) -> Option<EngineCommand> {
+    let permissions = connections.permissions(context.uuid);
+
+    match command_access(&command, &permissions) {
+        CommandAccess::Requires(permission)
+            if !context.internal && !permissions.contains(&permission) =>
+        {
+            deny(command, permission, context, connections).await;
+
+            None
+        }
+        CommandAccess::InternalOnly if !context.internal => {
+            route_response(
+                context.internal,
+                context.internal_response_sender,
+                context.response_sender,
+                EngineResponse::Nope {
+                    command,
+                    reason: NopeReason::PermissionDenied,
+                },
+                context.uuid,
+            );
+
+            None
+        }
+        _ => Some(command),
+    }
+}
+
+pub async fn handle(
+    command: EngineCommand,
+    context: CommandContext<'_>,
+    connections: &mut Connections,
+) {
+    let CommandContext {
+        internal,
+        uuid,
+        database,
+        internal_response_sender,
+        response_sender,
+        ..
+    } = context;
+
+    let user_permissions = connections.permissions(uuid);
+
+    match command {
+        EngineCommand::GetPermissions => {
+            if internal {
+                let _ = internal_response_sender.send(EngineResponse::Permissions {
+                    permissions: vec![
+                        Permission::Control,
+                        Permission::Queue,
+                        Permission::Playlist,
+                        Permission::Transfer,
+                        Permission::Library,
+                        Permission::Admin,
+                    ],
+                });
+            } else {
+                let _ = response_sender.send((
+                    EngineResponse::Permissions {
+                        permissions: user_permissions,
+                    },
+                    uuid,
+                ));
+            }
+        }
+        EngineCommand::RequestPermissions {
+            permissions: ref requested,
+        } => {
+            if internal {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::InvalidArgument,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let requested = requested
+                .iter()
+                .filter(|permission| !user_permissions.contains(permission))
+                .cloned()
+                .collect::<Vec<Permission>>();
+
+            if requested.is_empty() {
+                let _ = response_sender.send((
+                    EngineResponse::Permissions {
+                        permissions: user_permissions,
+                    },
+                    uuid,
+                ));
+
+                return;
+            }
+
+            let request_id = Uuid::new_v4();
+            let device = connections
+                .connection_devices
+                .get(&uuid)
+                .cloned()
+                .unwrap_or_else(|| uuid.to_string());
+
+            connections.permission_requests.insert(
+                request_id,
+                PermissionRequest {
+                    connection: uuid,
+                    device: device.clone(),
+                    requested: requested.clone(),
+                },
+            );
+
+            connections.route_to_admins(
+                internal_response_sender,
+                response_sender,
+                EngineResponse::PermissionRequest {
+                    request_id,
+                    device,
+                    name: connections.connection_names.get(&uuid).cloned(),
+                    requested,
+                },
+            );
+
+            let expiry_sender = connections.permission_expiry_sender.clone();
+
+            tokio::spawn(async move {
+                tokio::time::sleep(PERMISSION_REQUEST_TIMEOUT).await;
+
+                let _ = expiry_sender.send(request_id).await;
+            });
+        }
+        EngineCommand::ResolvePermissionRequest {
+            request_id,
+            ref grant,
+        } => {
+            let Some(request) = connections.permission_requests.remove(&request_id) else {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::NotFound,
+                    },
+                    uuid,
+                );
+
+                return;
+            };
+
+            let granted = grant
+                .iter()
+                .filter(|permission| request.requested.contains(permission))
+                .cloned()
+                .collect::<Vec<Permission>>();
+
+            // Grants are only persisted against identities the engine issued, so an
+            // unpaired requester is paired before anything is stored for it. Pending
+            // requests are dropped on disconnect, so the requester is still here.
+            let mut device = request.device.clone();
+
+            if !granted.is_empty()
+                && !connections
+                    .device_tokens
+                    .values()
+                    .any(|paired| *paired == device)
+            {
+                let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+                device = Uuid::new_v4().to_string();
+
+                connections
+                    .device_tokens
+                    .insert(sha256::digest(token.as_str()), device.clone());
+                database
+                    .set_setting(DEVICE_TOKENS_SETTING, &connections.device_tokens)
+                    .await;
+
+                connections
+                    .connection_devices
+                    .insert(request.connection, device.clone());
+
+                let _ = response_sender.send((
+                    EngineResponse::Paired {
+                        device: device.clone(),
+                        token,
+                    },
+                    request.connection,
+                ));
+            }
+
+            if !granted.is_empty() {
+                let device_grant = connections
+                    .device_permissions
+                    .entry(device.clone())
+                    .or_default();
+
+                for permission in &granted {
+                    if !device_grant.contains(permission) {
+                        device_grant.push(permission.clone());
+                    }
+                }
+
+                database
+                    .set_setting(DEVICE_PERMISSIONS_SETTING, &connections.device_permissions)
+                    .await;
+
+                let permissions = connections.device_grants(Some(&device));
+
+                for (connection, _) in connections
+                    .connection_devices
+                    .iter()
+                    .filter(|(_, connection_device)| **connection_device == device)
+                {
+                    let _ = response_sender.send((
+                        EngineResponse::AllowedCommands {
+                            commands: allowed_commands(false, &permissions),
+                        },
+                        *connection,
+                    ));
+                }
+            }
+
+            let _ = response_sender.send((
+                EngineResponse::Permissions {
+                    permissions: connections.device_grants(Some(&device)),
+                },
+                request.connection,
+            ));
+
+            connections.route_to_admins(
+                internal_response_sender,
+                response_sender,
+                EngineResponse::PermissionRequestResolved {
+                    request_id,
+                    granted,
+                },
+            );
+        }
+        EngineCommand::AllowedCommands => {
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::AllowedCommands {
+                    commands: allowed_commands(internal, &user_permissions),
+                },
+                uuid,
+            );
+        }
+        EngineCommand::SetPermissions(ref new_permissions) => {
+            connections.current_user_permissions = new_permissions.to_vec();
+
+            let _ = internal_response_sender.send(EngineResponse::Permissions {
+                permissions: connections.current_user_permissions.clone(),
+            });
+
+            for (connection, device) in &connections.connection_devices {
+                let permissions = connections.device_grants(Some(device));
+
+                let _ = response_sender.send((
+                    EngineResponse::AllowedCommands {
+                        commands: allowed_commands(false, &permissions),
+                    },
+                    *connection,
+                ));
+            }
+        }
+        _ => unreachable!("{} is not an access command", command.kind()),
+    }
+}
+
+fn representative_commands() -> Vec<EngineCommand> {
+    vec![
+        EngineCommand::None,
+        EngineCommand::Goodbye,
+        EngineCommand::Hello {
+            current_time_hz: None,
+            device: None,
+            token: None,
+        },
+        EngineCommand::GetState,
+        EngineCommand::GetPosition,
+        EngineCommand::Play(Some(String::new())),
+        EngineCommand::PlayFromStart { id: String::new() },
+        EngineCommand::Pause,
+        EngineCommand::Stop,
+        EngineCommand::Next,
+        EngineCommand::Previous,
+        EngineCommand::PlayQueueIndex {
+            index: 0,
+            keep_previous: false,
+        },
+        EngineCommand::Seek(Duration::ZERO),
+        EngineCommand::SeekPercent { fraction: 0.0 },
+        EngineCommand::SeekRelative { offset_ms: 0 },
+        EngineCommand::Queue(Some(Vec::new())),
+        EngineCommand::QueuePlaylist {
+            id: String::new(),
+            resume: false,
+        },
+        EngineCommand::QueueRelease {
+            release_id: String::new(),
+            only_local: false,
+        },
+        EngineCommand::QueueAfterCurrentGroup {
+            recordings: Vec::new(),
+        },
+        EngineCommand::QueueNext {
+            recordings: Vec::new(),
+        },
+        EngineCommand::QueueTo {
+            queue: MAIN_QUEUE.to_owned(),
+            recordings: Vec::new(),
+        },
+        EngineCommand::QueueTo {
+            queue: REQUESTS_QUEUE.to_owned(),
+            recordings: Vec::new(),
+        },
+        EngineCommand::GetQueues,
+        EngineCommand::GetHistory { count: None },
+        EngineCommand::QueueRange {
+            offset: 0,
+            limit: 0,
+        },
+        EngineCommand::ShuffleQueue {
+            enable: false,
+            seed: None,
+        },
+        EngineCommand::ClearQueue { preview: false },
+        EngineCommand::QueueRemove(QueueRemoveTarget::Index(0)),
+        EngineCommand::QueueMove { from: 0, to: 0 },
+        EngineCommand::SetRadioMode { enabled: false },
+        EngineCommand::LoopMode(LoopMode::None),
+        EngineCommand::QueueModel(QueueModel::default()),
+        EngineCommand::OnQueueEnd(OnQueueEnd::default()),
+        EngineCommand::SmoothLevelTransition { enabled: false },
+        EngineCommand::PauseFade(Duration::ZERO),
+        EngineCommand::StopAfterCurrent { enabled: false },
+        EngineCommand::RecordingMetadata { id: String::new() },
+        EngineCommand::RecordingDuration { id: String::new() },
+        EngineCommand::RecordingStats { id: String::new() },
+        EngineCommand::RecordingFile(String::new()),
+        EngineCommand::RecordingFileChunk {
+            id: String::new(),
+            offset: 0,
+        },
+        EngineCommand::TransferAck { id: String::new() },
+        EngineCommand::SendRecording((String::new(), Vec::new())),
+        EngineCommand::SendVerifiedRecording {
+            id: String::new(),
+            sha256: String::new(),
+            data: Vec::new(),
+        },
+        EngineCommand::SendRecordingChunk {
+            id: String::new(),
+            offset: 0,
+            total_len: 0,
+            sha256: String::new(),
+            data: Vec::new(),
+        },
+        EngineCommand::LinkExternalFile {
+            id: String::new(),
+            path: PathBuf::new(),
+            transferable: false,
+        },
+        EngineCommand::RecordingProvenance { id: String::new() },
+        EngineCommand::Waveform { id: String::new() },
+        EngineCommand::PlaylistMetadata { id: String::new() },
+        EngineCommand::SetPlaylistMetadata(PlaylistMetadata {
+            id: String::new(),
+            name: String::new(),
+            recordings: Vec::new(),
+            resume_position: None,
+            modified: 0,
+        }),
+        EngineCommand::ListPlaylists {
+            sort: None,
+            direction: SortDirection::default(),
+        },
+        EngineCommand::CachePlaylist { id: String::new() },
+        EngineCommand::ValidatePlaylist {
+            id: String::new(),
+            prune: false,
+        },
+        EngineCommand::ResolvePlaylistConflict {
+            id: String::new(),
+            keep: ConflictResolution::Local,
+        },
+        EngineCommand::SetVolume { volume: 0.0 },
+        EngineCommand::Mute { muted: true },
+        EngineCommand::GetVolume,
+        EngineCommand::SetSpeed { speed: 1.0 },
+        EngineCommand::GetSpeed,
+        EngineCommand::PlayCue {
+            data: Vec::new(),
+            duck_db: 0.0,
+        },
+        EngineCommand::FollowPlayback { enabled: false },
+        EngineCommand::StreamListen { enabled: false },
+        EngineCommand::LibraryEvents { enabled: false },
+        EngineCommand::HandOff {
+            target: String::new(),
+        },
+        EngineCommand::AdoptSession(SessionSnapshot {
+            recording_id: String::new(),
+            position: Duration::ZERO,
+            queue: Vec::new(),
+            loop_mode: LoopMode::None,
+            shuffle: false,
+            shuffle_seed: None,
+        }),
+        EngineCommand::GetPermissions,
+        EngineCommand::SetPermissions(Vec::new()),
+        EngineCommand::RequestPermissions {
+            permissions: Vec::new(),
+        },
+        EngineCommand::ResolvePermissionRequest {
+            request_id: Uuid::nil(),
+            grant: Vec::new(),
+        },
+        EngineCommand::AllowedCommands,
+        EngineCommand::UpdateSettings(EngineSettings {
+            musicbrainz_base_url: None,
+            musicbrainz_user_agent: String::new(),
+            collation: None,
+        }),
+        EngineCommand::ClearSavedState { keep_saving: false },
+        EngineCommand::Capabilities,
+        EngineCommand::HealthCheck,
+        EngineCommand::SupportedFormats,
+        EngineCommand::Metrics { reset: false },
+        EngineCommand::ResetMetrics,
+        EngineCommand::AudioStatus,
+        EngineCommand::DiagnosticBundle,
+        EngineCommand::ExportHistory {
+            since: None,
+            format: ExportFormat::Json,
+        },
+        EngineCommand::AuditLog {
+            since: None,
+            limit: None,
+        },
+        EngineCommand::ListClients,
+        EngineCommand::SetDeviceTransferBudget {
+            device_id: String::new(),
+            bytes_per_day: None,
+        },
+        EngineCommand::VerifyLibrary {
+            job_id: Uuid::nil(),
+        },
+        EngineCommand::RefreshLibrary {
+            job_id: Uuid::nil(),
+        },
+        EngineCommand::CancelJob {
+            job_id: Uuid::nil(),
+        },
+        EngineCommand::ListOrphanedRecordings,
+        EngineCommand::AdoptLooseFiles,
+        EngineCommand::DeleteRecording { id: String::new() },
+        EngineCommand::DeletePlaylist { id: String::new() },
+        EngineCommand::ListTrash,
+        EngineCommand::RestoreFromTrash { id: String::new() },
+        EngineCommand::EmptyTrash { older_than: None },
+        EngineCommand::TagRecording {
+            id: String::new(),
+            add: Vec::new(),
+            remove: Vec::new(),
+        },
+        EngineCommand::EditLocalMetadata {
+            ids: Vec::new(),
+            set: LocalTagPatch::default(),
+            write_tags: false,
+        },
+        EngineCommand::SetSpokenWord {
+            id: String::new(),
+            spoken_word: None,
+        },
+        EngineCommand::ListTags,
+        EngineCommand::RecordingsByTag { tag: String::new() },
+        EngineCommand::ListRecordings {
+            tag: None,
+            sort: None,
+            direction: SortDirection::default(),
+        },
+        EngineCommand::RelatedRecordings { id: String::new() },
+        EngineCommand::PreviewAt {
+            id: String::new(),
+            position: Duration::ZERO,
+        },
+        EngineCommand::ChangesSince { sequence: 0 },
+        EngineCommand::Batch {
+            commands: Vec::new(),
+        },
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // serde lists every variant of the tag when it meets one it does not
+    // know, which is the only way to enumerate EngineCommand at runtime.
+    fn command_variants() -> Vec<String> {
+        let Err(error) = serde_json::from_str::<EngineCommand>(r#"{"type":"?"}"#) else {
+            panic!("an unknown command deserialized");
+        };
+
+        let message = error.to_string();
+        let Some((_, expected)) = message.split_once("expected one of ") else {
+            panic!("unexpected serde error: {}", message);
+        };
+
+        let expected = expected.split(" at line").next().unwrap_or_default();
+
+        expected
+            .split(',')
+            .map(|variant| variant.trim().trim_matches('`').to_owned())
+            .filter(|variant| !variant.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn every_command_kind_is_represented() {
+        let represented = representative_commands()
+            .iter()
+            .map(|command| command.kind().to_owned())
+            .collect::<Vec<String>>();
+
+        for variant in command_variants() {
+            assert!(
+                represented.contains(&variant),
+                "{} is missing from representative_commands",
+                variant
+            );
+        }
+
+        for kind in &represented {
+            assert!(
+                command_variants().contains(kind),
+                "{} is not an EngineCommand variant",
+                kind
+            );
+        }
+    }
+
+    #[test]
+    fn internal_callers_may_use_everything_supported() {
+        for command in representative_commands() {
+            let access = command_access(&command, &[]);
+
+            assert_eq!(
+                access.allows(true, &[]),
+                access != CommandAccess::Unsupported,
+                "{}",
+                command.kind()
+            );
+        }
+    }
+
+    #[test]
+    fn allowed_commands_follow_the_required_permission() {
+        assert!(allowed_commands(false, &[]).contains(&"GetState".to_owned()));
+        assert!(!allowed_commands(false, &[]).contains(&"Pause".to_owned()));
+        assert!(allowed_commands(false, &[Permission::Control]).contains(&"Pause".to_owned()));
+        assert!(!allowed_commands(false, &[Permission::Admin]).contains(&"PlayCue".to_owned()));
+        assert!(allowed_commands(true, &[]).contains(&"PlayCue".to_owned()));
+        assert!(!allowed_commands(true, &[]).contains(&"FollowPlayback".to_owned()));
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const BUDGET_WARNING_FRACTION: f64 = 0.8;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TransferDirection {
+    Sent,
+    Received,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TransferTotals {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+impl TransferTotals {
+    fn add(&mut self, direction: TransferDirection, bytes: u64) {
+        match direction {
+            TransferDirection::Sent => self.bytes_sent = self.bytes_sent.saturating_add(bytes),
+            TransferDirection::Received => {
+                self.bytes_received = self.bytes_received.saturating_add(bytes)
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct DailyUsage {
+    day: u64,
+    used: u64,
+    warned: bool,
+}
+
+impl DailyUsage {
+    fn roll(&mut self, day: u64) {
+        if self.day != day {
+            *self = DailyUsage {
+                day,
+                ..DailyUsage::default()
+            };
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct BandwidthLedger {
+    budgets: HashMap<String, u64>,
+    totals: TransferTotals,
+    connections: HashMap<Uuid, TransferTotals>,
+    devices: HashMap<String, DailyUsage>,
+}
+
+impl BandwidthLedger {
+    pub fn new(budgets: HashMap<String, u64>) -> BandwidthLedger {
+        BandwidthLedger {
+            budgets,
+            ..BandwidthLedger::default()
+        }
+    }
+
+    pub fn budgets(&self) -> &HashMap<String, u64> {
+        &self.budgets
+    }
+
+    pub fn budget(&self, device: &str) -> Option<u64> {
+        self.budgets.get(device).copied()
+    }
+
+    pub fn set_budget(&mut self, device: String, bytes_per_day: Option<u64>) {
+        if let Some(usage) = self.devices.get_mut(&device) {
+            usage.warned = false;
+        }
+
+        match bytes_per_day {
+            Some(bytes_per_day) => self.budgets.insert(device, bytes_per_day),
+            None => self.budgets.remove(&device),
+        };
+    }
+
+    pub fn charge(
+        &mut self,
+        connection: Uuid,
+        device: &str,
+        direction: TransferDirection,
+        bytes: u64,
+        now: u64,
+    ) -> Result<Option<(u64, u64)>, u64> {
+        let budget = self.budget(device);
+
+        let usage = self.devices.entry(device.to_owned()).or_default();
+
+        usage.roll(now / SECONDS_PER_DAY);
+
+        if let Some(budget) = budget {
+            if usage.used.saturating_add(bytes) > budget {
+                return Err(budget);
+            }
+        }
+
+        usage.used = usage.used.saturating_add(bytes);
+
+        self.totals.add(direction, bytes);
+        self.connections
+            .entry(connection)
+            .or_default()
+            .add(direction, bytes);
+
+        let Some(budget) = budget else {
+            return Ok(None);
+        };
+
+        if usage.warned || (usage.used as f64) < budget as f64 * BUDGET_WARNING_FRACTION {
+            return Ok(None);
+        }
+
+        usage.warned = true;
+
+        Ok(Some((usage.used, budget)))
+    }
+
+    pub fn used_today(&self, device: &str, now: u64) -> u64 {
+        self.devices
+            .get(device)
+            .filter(|usage| usage.day == now / SECONDS_PER_DAY)
+            .map_or(0, |usage| usage.used)
+    }
+
+    pub fn connection_totals(&self, connection: &Uuid) -> TransferTotals {
+        self.connections
+            .get(connection)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    pub fn connections(&self) -> impl Iterator<Item = &Uuid> {
+        self.connections.keys()
+    }
+
+    pub fn totals(&self) -> TransferTotals {
+        self.totals
+    }
+
+    pub fn forget_connection(&mut self, connection: &Uuid) {
+        self.connections.remove(connection);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: u64 = SECONDS_PER_DAY;
+
+    #[test]
+    fn charges_beyond_the_budget_are_rejected() {
+        let mut ledger = BandwidthLedger::new(HashMap::from([("phone".to_owned(), 100)]));
+        let connection = Uuid::new_v4();
+
+        assert_eq!(
+            ledger.charge(connection, "phone", TransferDirection::Sent, 60, DAY),
+            Ok(None)
+        );
+        assert_eq!(
+            ledger.charge(connection, "phone", TransferDirection::Sent, 50, DAY),
+            Err(100)
+        );
+        assert_eq!(ledger.used_today("phone", DAY), 60);
+        assert_eq!(ledger.connection_totals(&connection).bytes_sent, 60);
+    }
+
+    #[test]
+    fn usage_resets_each_day() {
+        let mut ledger = BandwidthLedger::new(HashMap::from([("phone".to_owned(), 100)]));
+        let connection = Uuid::new_v4();
+
+        let _ = ledger.charge(connection, "phone", TransferDirection::Received, 70, DAY);
+
+        assert!(ledger
+            .charge(
+                connection,
+                "phone",
+                TransferDirection::Received,
+                70,
+                DAY + 1
+            )
+            .is_err());
+        assert_eq!(
+            ledger.charge(
+                connection,
+                "phone",
+                TransferDirection::Received,
+                70,
+                2 * DAY
+            ),
+            Ok(None)
+        );
+        assert_eq!(ledger.used_today("phone", 2 * DAY), 70);
+        assert_eq!(ledger.totals().bytes_received, 140);
+    }
+
+    #[test]
+    fn warns_once_when_nearing_the_budget() {
+        let mut ledger = BandwidthLedger::new(HashMap::from([("phone".to_owned(), 100)]));
+        let connection = Uuid::new_v4();
+
+        assert_eq!(
+            ledger.charge(connection, "phone", TransferDirection::Sent, 85, DAY),
+            Ok(Some((85, 100)))
+        );
+        assert_eq!(
+            ledger.charge(connection, "phone", TransferDirection::Sent, 5, DAY),
+            Ok(None)
+        );
+
+        ledger.set_budget("phone".to_owned(), Some(95));
+
+        assert_eq!(
+            ledger.charge(connection, "phone", TransferDirection::Sent, 1, DAY),
+            Ok(Some((91, 95)))
+        );
+    }
+
+    #[test]
+    fn devices_without_a_budget_are_only_counted() {
+        let mut ledger = BandwidthLedger::default();
+        let connection = Uuid::new_v4();
+
+        assert_eq!(
+            ledger.charge(connection, "guest", TransferDirection::Sent, u64::MAX, DAY),
+            Ok(None)
+        );
+        assert_eq!(ledger.used_today("guest", DAY), u64::MAX);
+
+        ledger.forget_connection(&connection);
+
+        assert_eq!(ledger.connections().count(), 0);
+    }
+}
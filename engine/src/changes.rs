@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::EngineResponse;
+
+const CHANGE_LOG_CAPACITY: usize = 256;
+
+pub type Broadcast = (EngineResponse, Uuid, Option<u64>);
+
+type Capture = Option<(Uuid, Vec<EngineResponse>)>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type")]
+pub enum StateDelta {
+    NowPlaying { recording_id: Option<String> },
+    Paused { paused: bool },
+    Volume { volume: f32 },
+    QueueLength { length: usize },
+}
+
+#[derive(Default)]
+struct ChangeLog {
+    sequence: u64,
+    floor: u64,
+    deltas: VecDeque<(u64, StateDelta)>,
+}
+
+impl ChangeLog {
+    fn stamp(&mut self, response: &EngineResponse) -> u64 {
+        self.sequence += 1;
+
+        for delta in state_deltas(response) {
+            if self.deltas.len() >= CHANGE_LOG_CAPACITY {
+                if let Some((sequence, _)) = self.deltas.pop_front() {
+                    self.floor = sequence;
+                }
+            }
+
+            self.deltas.push_back((self.sequence, delta));
+        }
+
+        self.sequence
+    }
+
+    fn since(&self, sequence: u64) -> Option<Vec<StateDelta>> {
+        if sequence < self.floor || sequence > self.sequence {
+            return None;
+        }
+
+        let mut deltas = Vec::<StateDelta>::new();
+
+        for (_, delta) in self
+            .deltas
+            .iter()
+            .filter(|(delta_sequence, _)| *delta_sequence > sequence)
+        {
+            deltas.retain(|existing| mem::discriminant(existing) != mem::discriminant(delta));
+            deltas.push(delta.clone());
+        }
+
+        Some(deltas)
+    }
+}
+
+#[derive(Clone)]
+pub struct ChangeFeed {
+    sender: broadcast::Sender<Broadcast>,
+    log: Arc<Mutex<ChangeLog>>,
+    capture: Arc<Mutex<Capture>>,
+}
+
+impl ChangeFeed {
+    pub fn new(capacity: usize) -> ChangeFeed {
+        let (sender, _) = broadcast::channel::<Broadcast>(capacity);
+
+        ChangeFeed {
+            sender,
+            log: Arc::new(Mutex::new(ChangeLog::default())),
+            capture: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn begin_capture(&self, owner: Uuid) {
+        if let Ok(mut capture) = self.capture.lock() {
+            *capture = Some((owner, Vec::new()));
+        }
+    }
+
+    pub fn end_capture(&self) -> Vec<EngineResponse> {
+        let Ok(mut capture) = self.capture.lock() else {
+            return Vec::new();
+        };
+
+        capture
+            .take()
+            .map(|(_, responses)| responses)
+            .unwrap_or_default()
+    }
+
+    pub fn send(&self, (response, uuid): (EngineResponse, Uuid)) -> Option<usize> {
+        if let Ok(mut capture) = self.capture.lock() {
+            if let Some((owner, responses)) = capture.as_mut() {
+                if uuid == *owner {
+                    responses.push(response);
+
+                    return Some(0);
+                }
+
+                if uuid.is_nil() {
+                    responses.push(response.clone());
+                }
+            }
+        }
+
+        if !uuid.is_nil() {
+            return self.sender.send((response, uuid, None)).ok();
+        }
+
+        let Ok(mut locked_log) = self.log.lock() else {
+            return self.sender.send((response, uuid, None)).ok();
+        };
+
+        let sequence = locked_log.stamp(&response);
+
+        self.sender.send((response, uuid, Some(sequence))).ok()
+    }
+
+    pub fn subscribe(&self) -> (broadcast::Receiver<Broadcast>, u64) {
+        let Ok(locked_log) = self.log.lock() else {
+            return (self.sender.subscribe(), 0);
+        };
+
+        (self.sender.subscribe(), locked_log.sequence)
+    }
+
+    pub fn changes_since(&self, sequence: u64) -> (EngineResponse, u64) {
+        let Ok(locked_log) = self.log.lock() else {
+            return (
+                EngineResponse::Changes {
+                    sequence,
+                    deltas: Vec::new(),
+                    snapshot_required: true,
+                },
+                sequence,
+            );
+        };
+
+        let response = match locked_log.since(sequence) {
+            Some(deltas) => EngineResponse::Changes {
+                sequence: locked_log.sequence,
+                deltas,
+                snapshot_required: false,
+            },
+            None => EngineResponse::Changes {
+                sequence: locked_log.sequence,
+                deltas: Vec::new(),
+                snapshot_required: true,
+            },
+        };
+
+        (response, locked_log.sequence)
+    }
+}
+
+fn state_deltas(response: &EngineResponse) -> Vec<StateDelta> {
+    match response {
+        EngineResponse::NowPlaying { id } => vec![
+            StateDelta::NowPlaying {
+                recording_id: Some(id.clone()),
+            },
+            StateDelta::Paused { paused: false },
+        ],
+        EngineResponse::NowPaused => vec![StateDelta::Paused { paused: true }],
+        EngineResponse::State(state) => vec![
+            StateDelta::NowPlaying {
+                recording_id: state.recording_id.clone(),
+            },
+            StateDelta::Paused {
+                paused: state.paused,
+            },
+            StateDelta::QueueLength {
+                length: state.queue_length,
+            },
+        ],
+        EngineResponse::Queue { queue } | EngineResponse::QueueExtended { queue, .. } => {
+            vec![StateDelta::QueueLength {
+                length: queue.len(),
+            }]
+        }
+        EngineResponse::QueueSpliced { total, .. } => {
+            vec![StateDelta::QueueLength { length: *total }]
+        }
+        EngineResponse::Volume { volume } => vec![StateDelta::Volume { volume: *volume }],
+        _ => Vec::new(),
+    }
+}
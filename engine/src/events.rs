@@ -0,0 +1,556 @@
+use std::{sync::Arc, time::Duration};
+
+use tokio::sync::{broadcast, mpsc, watch};
+use tokio_stream::{wrappers::ReceiverStream, Stream};
+use uuid::Uuid;
+
+use crate::{
+    changes::ChangeFeed,
+    diagnostics::DiagnosticLog,
+    player::{
+        database::{Database, DatabaseEvent},
+        sequencer::{Sequencer, SequencerEvent},
+    },
+    recv_stream, route_response, EngineConnectionStatus, EngineResponse, LibraryChangeKind,
+    NopeReason, PlaybackErrorReason,
+};
+
+const EVENT_CHANNEL_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum EngineEvent {
+    TrackChanged(Option<Arc<TrackSummary>>),
+    PlaybackPhaseChanged(PlaybackPhase),
+    QueueChanged(Arc<[String]>),
+    VolumeChanged(f32),
+    LibraryChanged {
+        kind: LibraryChangeKind,
+        id: Arc<str>,
+    },
+    ConnectionChanged(EngineConnectionStatus),
+    Error(EventError),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackSummary {
+    pub id: String,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackPhase {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventError {
+    Rejected {
+        command: &'static str,
+        reason: NopeReason,
+    },
+    Playback {
+        reason: PlaybackErrorReason,
+        recoverable: bool,
+    },
+}
+
+struct EventTranslator {
+    database: Option<Database>,
+
+    track: Option<String>,
+    phase: PlaybackPhase,
+    queue: Vec<String>,
+}
+
+impl EventTranslator {
+    async fn translate(&mut self, response: EngineResponse) -> Vec<EngineEvent> {
+        match response {
+            EngineResponse::NowPlaying { id } => {
+                let mut events = self.track_changed(Some(id)).await;
+
+                events.extend(self.phase_changed(PlaybackPhase::Playing));
+
+                events
+            }
+            EngineResponse::NowPaused => self.phase_changed(PlaybackPhase::Paused),
+            EngineResponse::State(state) => {
+                let phase = match (&state.recording_id, state.paused) {
+                    (None, _) => PlaybackPhase::Stopped,
+                    (Some(_), true) => PlaybackPhase::Paused,
+                    (Some(_), false) => PlaybackPhase::Playing,
+                };
+
+                let mut events = self.track_changed(state.recording_id).await;
+
+                events.extend(self.phase_changed(phase));
+
+                events
+            }
+            EngineResponse::Queue { queue } | EngineResponse::QueueExtended { queue, .. } => {
+                self.queue = queue;
+
+                vec![EngineEvent::QueueChanged(self.queue.as_slice().into())]
+            }
+            EngineResponse::QueueSpliced {
+                offset,
+                removed,
+                inserted,
+                ..
+            } => {
+                let start = offset.min(self.queue.len());
+                let end = (start + removed).min(self.queue.len());
+
+                self.queue.splice(start..end, inserted);
+
+                vec![EngineEvent::QueueChanged(self.queue.as_slice().into())]
+            }
+            EngineResponse::Volume { volume } => vec![EngineEvent::VolumeChanged(volume)],
+            EngineResponse::LibraryChanged { kind, id } if self.database.is_none() => {
+                vec![EngineEvent::LibraryChanged {
+                    kind,
+                    id: id.into(),
+                }]
+            }
+            EngineResponse::Nope { command, reason } => {
+                vec![EngineEvent::Error(EventError::Rejected {
+                    command: command.kind(),
+                    reason,
+                })]
+            }
+            EngineResponse::PlaybackError {
+                reason,
+                recoverable,
+                ..
+            } => vec![EngineEvent::Error(EventError::Playback {
+                reason,
+                recoverable,
+            })],
+            _ => Vec::new(),
+        }
+    }
+
+    async fn track_changed(&mut self, id: Option<String>) -> Vec<EngineEvent> {
+        if self.track == id {
+            return Vec::new();
+        }
+
+        self.track = id.clone();
+
+        let Some(id) = id else {
+            return vec![EngineEvent::TrackChanged(None)];
+        };
+
+        let metadata = match &self.database {
+            Some(database) => database.get_cached_recording_metadata(id.clone()).await,
+            None => None,
+        };
+
+        vec![EngineEvent::TrackChanged(Some(Arc::new(TrackSummary {
+            title: metadata
+                .as_ref()
+                .map(|metadata| metadata.recording.title.clone()),
+            artist: metadata.as_ref().map(|metadata| metadata.artist()),
+            duration: metadata
+                .as_ref()
+                .and_then(|metadata| metadata.recording.length)
+                .map(|length| Duration::from_millis(length as u64)),
+            id,
+        })))]
+    }
+
+    fn phase_changed(&mut self, phase: PlaybackPhase) -> Vec<EngineEvent> {
+        if self.phase == phase {
+            return Vec::new();
+        }
+
+        self.phase = phase;
+
+        vec![EngineEvent::PlaybackPhaseChanged(phase)]
+    }
+}
+
+pub fn translate_events(
+    mut response_receiver: broadcast::Receiver<EngineResponse>,
+    mut status_receiver: watch::Receiver<EngineConnectionStatus>,
+    database: Option<Database>,
+) -> impl Stream<Item = EngineEvent> {
+    let (event_sender, event_receiver) = mpsc::channel::<EngineEvent>(EVENT_CHANNEL_CAPACITY);
+
+    let mut library_receiver: Option<broadcast::Receiver<DatabaseEvent>> =
+        database.as_ref().map(Database::subscribe);
+
+    tokio::spawn(async move {
+        let mut translator = EventTranslator {
+            database,
+
+            track: None,
+            phase: PlaybackPhase::Stopped,
+            queue: Vec::new(),
+        };
+
+        loop {
+            let events = tokio::select! {
+                _ = event_sender.closed() => return,
+                val = status_receiver.changed() => {
+                    if val.is_err() {
+                        return;
+                    }
+
+                    vec![EngineEvent::ConnectionChanged(*status_receiver.borrow_and_update())]
+                }
+                val = recv_stream(&mut library_receiver) => match val {
+                    Ok(event) => match library_change_response(event) {
+                        Some(EngineResponse::LibraryChanged { kind, id }) => {
+                            vec![EngineEvent::LibraryChanged { kind, id: id.into() }]
+                        }
+                        _ => Vec::new(),
+                    },
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+                val = response_receiver.recv() => match val {
+                    Ok(response) => translator.translate(response).await,
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            };
+
+            for event in events {
+                if event_sender.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+    });
+
+    ReceiverStream::new(event_receiver)
+}
+
+/// Announces what the sequencer reports to every client, keeping
+/// `broadcast_queue` in step with the queue it last announced in full.
+pub fn forward_sequencer_event(
+    event: SequencerEvent,
+    database: &Database,
+    internal_sender: &broadcast::Sender<EngineResponse>,
+    remote_sender: &ChangeFeed,
+    broadcast_queue: &mut Vec<String>,
+) {
+    if let SequencerEvent::Transition { ref to, .. } = event {
+        let track_database = database.clone();
+        let track_internal_sender = internal_sender.clone();
+        let track_remote_sender = remote_sender.clone();
+        let id = to.clone();
+
+        tokio::spawn(async move {
+            let Ok(metadata) = track_database.get_recording_metadata(id.clone()).await else {
+                return;
+            };
+
+            route_response(
+                false,
+                &track_internal_sender,
+                &track_remote_sender,
+                EngineResponse::TrackChanged {
+                    id,
+                    duration: metadata.duration(),
+                    metadata,
+                },
+                Uuid::nil(),
+            );
+        });
+    }
+
+    for response in sequencer_event_responses(event) {
+        if let EngineResponse::QueueExtended { queue, .. } = &response {
+            *broadcast_queue = queue.clone();
+        }
+
+        route_response(false, internal_sender, remote_sender, response, Uuid::nil());
+    }
+}
+
+/// Announces what the database reports, sending library changes only to the
+/// connections that asked for them.
+pub async fn forward_database_event(
+    event: DatabaseEvent,
+    sequencer: &Sequencer,
+    diagnostics: &DiagnosticLog,
+    internal_sender: &broadcast::Sender<EngineResponse>,
+    remote_sender: &ChangeFeed,
+    library_listeners: &[Uuid],
+) {
+    let response = match event {
+        DatabaseEvent::NetworkStateChanged(state) => EngineResponse::NetworkState(state),
+        DatabaseEvent::StorageStateChanged(state) => EngineResponse::StorageState(state),
+        DatabaseEvent::ExternalFileMissing { id, path } => {
+            EngineResponse::ExternalFileMissing { id, path }
+        }
+        DatabaseEvent::WriteFailed { context, ref error } => {
+            diagnostics.record_error(context, error);
+
+            return;
+        }
+        event => {
+            if let DatabaseEvent::PlaylistDeleted(ref id) = event {
+                sequencer.end_playlist_loop(id).await;
+            }
+
+            let Some(response) = library_change_response(event) else {
+                return;
+            };
+
+            for listener in library_listeners {
+                let _ = remote_sender.send((response.clone(), *listener));
+            }
+
+            return;
+        }
+    };
+
+    route_response(false, internal_sender, remote_sender, response, Uuid::nil());
+}
+
+fn sequencer_event_responses(event: SequencerEvent) -> Vec<EngineResponse> {
+    match event {
+        SequencerEvent::Suspended {
+            id,
+            position,
+            resumed,
+        } => vec![
+            EngineResponse::PlaybackError {
+                reason: PlaybackErrorReason::Suspended,
+                recoverable: true,
+                recording_id: Some(id.clone()),
+            },
+            EngineResponse::Seek(position),
+            if resumed {
+                EngineResponse::NowPlaying { id }
+            } else {
+                EngineResponse::NowPaused
+            },
+        ],
+        SequencerEvent::Advanced { id } => vec![EngineResponse::NowPlaying { id }],
+        SequencerEvent::Transition { from, to, gapless } => {
+            vec![EngineResponse::TrackTransition { from, to, gapless }]
+        }
+        SequencerEvent::Faulty { id } => vec![EngineResponse::PlaybackError {
+            reason: PlaybackErrorReason::Faulty,
+            recoverable: false,
+            recording_id: Some(id),
+        }],
+        SequencerEvent::QueueExtended { queue, auto_added } => {
+            vec![EngineResponse::QueueExtended { queue, auto_added }]
+        }
+        SequencerEvent::QueueEnded { action_taken } => {
+            vec![EngineResponse::QueueEnded { action_taken }]
+        }
+        SequencerEvent::LoopModeChanged(loop_mode) => vec![EngineResponse::LoopMode(loop_mode)],
+        SequencerEvent::StopAfterCurrent { enabled, fired } => {
+            let mut responses = vec![EngineResponse::StopAfterCurrent { enabled }];
+
+            if fired {
+                responses.push(EngineResponse::NowPaused);
+            }
+
+            responses
+        }
+    }
+}
+
+fn library_change_response(event: DatabaseEvent) -> Option<EngineResponse> {
+    let (kind, id) = match event {
+        DatabaseEvent::RecordingUpserted(id) => (LibraryChangeKind::RecordingUpserted, id),
+        DatabaseEvent::RecordingDeleted(id) => (LibraryChangeKind::RecordingDeleted, id),
+        DatabaseEvent::PlaylistUpserted(id) => (LibraryChangeKind::PlaylistUpserted, id),
+        DatabaseEvent::PlaylistDeleted(id) => (LibraryChangeKind::PlaylistDeleted, id),
+        DatabaseEvent::AudioStored { id, hash: _ } => (LibraryChangeKind::AudioStored, id),
+        DatabaseEvent::NetworkStateChanged(_)
+        | DatabaseEvent::StorageStateChanged(_)
+        | DatabaseEvent::ExternalFileMissing { .. }
+        | DatabaseEvent::WriteFailed { .. } => return None,
+    };
+
+    Some(EngineResponse::LibraryChanged { kind, id })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EngineCommand, PlaybackState};
+
+    fn translator() -> EventTranslator {
+        EventTranslator {
+            database: None,
+
+            track: None,
+            phase: PlaybackPhase::Stopped,
+            queue: Vec::new(),
+        }
+    }
+
+    fn track(id: &str) -> EngineEvent {
+        EngineEvent::TrackChanged(Some(Arc::new(TrackSummary {
+            id: id.to_owned(),
+            title: None,
+            artist: None,
+            duration: None,
+        })))
+    }
+
+    fn state(recording_id: Option<&str>, paused: bool) -> EngineResponse {
+        EngineResponse::State(PlaybackState {
+            recording_id: recording_id.map(str::to_owned),
+            title: None,
+            artist: None,
+            paused,
+            position: Duration::ZERO,
+            queue_length: 0,
+            shuffle: false,
+            shuffle_seed: None,
+            on_queue_end: Default::default(),
+            smooth_level_transition: false,
+        })
+    }
+
+    fn queue(ids: &[&str]) -> EngineEvent {
+        EngineEvent::QueueChanged(ids.iter().map(|id| id.to_string()).collect())
+    }
+
+    #[tokio::test]
+    async fn playback_changes_are_reported_once() {
+        let mut translator = translator();
+
+        assert_eq!(
+            translator
+                .translate(EngineResponse::NowPlaying { id: "a".to_owned() })
+                .await,
+            vec![
+                track("a"),
+                EngineEvent::PlaybackPhaseChanged(PlaybackPhase::Playing)
+            ]
+        );
+        assert_eq!(translator.translate(state(Some("a"), false)).await, vec![]);
+        assert_eq!(
+            translator.translate(EngineResponse::NowPaused).await,
+            vec![EngineEvent::PlaybackPhaseChanged(PlaybackPhase::Paused)]
+        );
+        assert_eq!(
+            translator.translate(state(Some("b"), true)).await,
+            vec![track("b")]
+        );
+        assert_eq!(
+            translator.translate(state(None, false)).await,
+            vec![
+                EngineEvent::TrackChanged(None),
+                EngineEvent::PlaybackPhaseChanged(PlaybackPhase::Stopped)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn queue_splices_apply_to_the_last_full_queue() {
+        let mut translator = translator();
+
+        assert_eq!(
+            translator
+                .translate(EngineResponse::Queue {
+                    queue: vec!["a".to_owned(), "b".to_owned(), "c".to_owned()],
+                })
+                .await,
+            vec![queue(&["a", "b", "c"])]
+        );
+        assert_eq!(
+            translator
+                .translate(EngineResponse::QueueSpliced {
+                    total: 3,
+                    offset: 1,
+                    removed: 1,
+                    inserted: vec!["d".to_owned()],
+                })
+                .await,
+            vec![queue(&["a", "d", "c"])]
+        );
+        assert_eq!(
+            translator
+                .translate(EngineResponse::QueueSpliced {
+                    total: 4,
+                    offset: 10,
+                    removed: 5,
+                    inserted: vec!["e".to_owned()],
+                })
+                .await,
+            vec![queue(&["a", "d", "c", "e"])]
+        );
+    }
+
+    #[tokio::test]
+    async fn volume_library_and_errors_map_to_their_events() {
+        let mut translator = translator();
+
+        assert_eq!(
+            translator
+                .translate(EngineResponse::Volume { volume: 0.5 })
+                .await,
+            vec![EngineEvent::VolumeChanged(0.5)]
+        );
+        assert_eq!(
+            translator
+                .translate(EngineResponse::LibraryChanged {
+                    kind: LibraryChangeKind::RecordingDeleted,
+                    id: "a".to_owned(),
+                })
+                .await,
+            vec![EngineEvent::LibraryChanged {
+                kind: LibraryChangeKind::RecordingDeleted,
+                id: "a".into(),
+            }]
+        );
+        assert_eq!(
+            translator
+                .translate(EngineResponse::Nope {
+                    command: EngineCommand::Pause,
+                    reason: NopeReason::PermissionDenied,
+                })
+                .await,
+            vec![EngineEvent::Error(EventError::Rejected {
+                command: "Pause",
+                reason: NopeReason::PermissionDenied,
+            })]
+        );
+        assert_eq!(
+            translator
+                .translate(EngineResponse::PlaybackError {
+                    reason: PlaybackErrorReason::Faulty,
+                    recoverable: false,
+                    recording_id: Some("a".to_owned()),
+                })
+                .await,
+            vec![EngineEvent::Error(EventError::Playback {
+                reason: PlaybackErrorReason::Faulty,
+                recoverable: false,
+            })]
+        );
+    }
+
+    #[tokio::test]
+    async fn bulk_payloads_are_not_translated() {
+        let mut translator = translator();
+
+        for response in [
+            EngineResponse::CurrentTime(Duration::from_secs(1)),
+            EngineResponse::Waveform {
+                id: "a".to_owned(),
+                buckets: vec![0; 64],
+            },
+            EngineResponse::Ok {
+                command: EngineCommand::Pause,
+            },
+        ] {
+            assert_eq!(translator.translate(response).await, vec![]);
+        }
+    }
+}
@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use cpal::traits::{DeviceTrait, HostTrait};
+
+use crate::{
+    ipc::{server::ListenerStatus, SocketAddress},
+    player::database::{Database, DatabaseError},
+    HealthState,
+};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub async fn check_audio(custom_output: bool) -> (HealthState, String) {
+    if custom_output {
+        return (
+            HealthState::Healthy,
+            "audio: rendering to a custom output".to_owned(),
+        );
+    }
+
+    let probe = tokio::task::spawn_blocking(|| {
+        let host = cpal::default_host();
+
+        let Some(device) = host.default_output_device() else {
+            return (HealthState::Unhealthy, "audio: no output device".to_owned());
+        };
+
+        let device_name = device.name().unwrap_or_else(|_| "unknown".to_owned());
+
+        let Ok(supported_config) = device.default_output_config() else {
+            return (
+                HealthState::Unhealthy,
+                format!("audio: {} has no usable output configuration", device_name),
+            );
+        };
+
+        let stream = device.build_output_stream_raw(
+            &supported_config.config(),
+            supported_config.sample_format(),
+            |data, _| data.bytes_mut().fill(0),
+            |_| {},
+            Some(HEALTH_CHECK_TIMEOUT),
+        );
+
+        match stream {
+            Ok(_) => (
+                HealthState::Healthy,
+                format!("audio: output stream available on {}", device_name),
+            ),
+            Err(_) => (
+                HealthState::Degraded,
+                format!(
+                    "audio: {} found but an output stream could not be built",
+                    device_name
+                ),
+            ),
+        }
+    });
+
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, probe).await {
+        Ok(Ok(result)) => result,
+        Ok(Err(_)) => (HealthState::Unhealthy, "audio: probe failed".to_owned()),
+        Err(_) => (HealthState::Unhealthy, "audio: probe timed out".to_owned()),
+    }
+}
+
+pub async fn check_database(database: &Database) -> (HealthState, String) {
+    match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, database.health_check()).await {
+        Ok(Ok(())) => (
+            HealthState::Healthy,
+            "database: stores open and round-trip succeeded".to_owned(),
+        ),
+        Ok(Err(DatabaseError::DataConversionFailure)) => (
+            HealthState::Unhealthy,
+            "database: round-trip returned different data".to_owned(),
+        ),
+        Ok(Err(_)) => (
+            HealthState::Unhealthy,
+            "database: store access failed".to_owned(),
+        ),
+        Err(_) => (
+            HealthState::Unhealthy,
+            "database: check timed out".to_owned(),
+        ),
+    }
+}
+
+pub fn check_network(
+    address: &str,
+    listener_status: ListenerStatus,
+    accept_errors: u64,
+) -> (HealthState, String) {
+    match listener_status {
+        ListenerStatus::Listening => {}
+        ListenerStatus::BackingOff | ListenerStatus::Recreating => {
+            return (
+                HealthState::Degraded,
+                format!(
+                    "network: listener recovering on {} after {} accept errors",
+                    address, accept_errors
+                ),
+            );
+        }
+        ListenerStatus::Failed => {
+            return (
+                HealthState::Unhealthy,
+                format!(
+                    "network: listener on {} failed after {} accept errors",
+                    address, accept_errors
+                ),
+            );
+        }
+    }
+
+    match SocketAddress::parse(address) {
+        SocketAddress::Path(path) if !path.exists() => (
+            HealthState::Unhealthy,
+            format!("network: socket {} is missing", path.display()),
+        ),
+        _ => (
+            HealthState::Healthy,
+            format!("network: listening on {}", address),
+        ),
+    }
+}
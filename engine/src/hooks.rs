@@ -0,0 +1,153 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use tokio::{sync::broadcast, task::JoinHandle, time};
+use uuid::Uuid;
+
+use crate::{EngineCommand, EngineResponse, NopeReason, Permission};
+
+const HOOK_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[async_trait]
+pub trait Hook: Send + Sync + 'static {
+    fn name(&self) -> &str;
+
+    async fn on_command(&self, _command: &EngineCommand, _context: &HookContext) -> HookDecision {
+        HookDecision::Continue
+    }
+
+    async fn on_event(&self, _response: &EngineResponse) {}
+}
+
+#[derive(Debug, Clone)]
+pub struct HookContext {
+    pub connection: Uuid,
+    pub internal: bool,
+    pub device: Option<String>,
+    pub permissions: Vec<Permission>,
+}
+
+#[derive(Debug, Clone)]
+pub enum HookDecision {
+    Continue,
+    Reject(NopeReason),
+    Mutate(EngineCommand),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum HookFailureReason {
+    Panicked,
+    TimedOut,
+}
+
+#[derive(Clone)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+    timeout: Duration,
+}
+
+impl Default for Hooks {
+    fn default() -> Hooks {
+        Hooks {
+            hooks: Vec::new(),
+            timeout: HOOK_TIMEOUT,
+        }
+    }
+}
+
+impl fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.hooks.iter().map(|hook| hook.name()))
+            .finish()
+    }
+}
+
+impl Hooks {
+    pub fn register(&mut self, hook: impl Hook) {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub async fn on_command(
+        &self,
+        mut command: EngineCommand,
+        context: &HookContext,
+        failure_sender: &broadcast::Sender<EngineResponse>,
+    ) -> Result<EngineCommand, (EngineCommand, NopeReason)> {
+        for hook in &self.hooks {
+            let hook_command = command.clone();
+            let hook_context = context.clone();
+            let task_hook = hook.clone();
+
+            let decision = self
+                .isolate(
+                    hook,
+                    tokio::spawn(async move {
+                        task_hook.on_command(&hook_command, &hook_context).await
+                    }),
+                    failure_sender,
+                )
+                .await;
+
+            match decision {
+                Some(HookDecision::Continue) | None => {}
+                Some(HookDecision::Reject(reason)) => return Err((command, reason)),
+                Some(HookDecision::Mutate(mutated)) => command = mutated,
+            }
+        }
+
+        Ok(command)
+    }
+
+    pub async fn on_event(
+        &self,
+        response: EngineResponse,
+        failure_sender: &broadcast::Sender<EngineResponse>,
+    ) {
+        for hook in &self.hooks {
+            let hook_response = response.clone();
+            let task_hook = hook.clone();
+
+            self.isolate(
+                hook,
+                tokio::spawn(async move { task_hook.on_event(&hook_response).await }),
+                failure_sender,
+            )
+            .await;
+        }
+    }
+
+    async fn isolate<T>(
+        &self,
+        hook: &Arc<dyn Hook>,
+        mut task: JoinHandle<T>,
+        failure_sender: &broadcast::Sender<EngineResponse>,
+    ) -> Option<T> {
+        let reason = match time::timeout(self.timeout, &mut task).await {
+            Ok(Ok(value)) => return Some(value),
+            Ok(Err(_)) => HookFailureReason::Panicked,
+            Err(_) => {
+                task.abort();
+
+                HookFailureReason::TimedOut
+            }
+        };
+
+        let _ = failure_sender.send(EngineResponse::HookFailed {
+            hook: hook.name().to_owned(),
+            reason,
+        });
+
+        None
+    }
+}
@@ -1,12 +1,15 @@
-use std::io::{BufRead, BufReader, BufWriter, Write};
-
-use interprocess::local_socket::{
-    tokio::prelude::*, traits::Stream as StreamTrait, GenericNamespaced, Stream,
+use interprocess::local_socket::tokio::{prelude::*, Stream};
+use tokio::{
+    io::{AsyncBufReadExt, BufReader, BufWriter},
+    sync::mpsc,
+    task::JoinHandle,
 };
-use tokio::{sync::mpsc, task::JoinHandle};
 
-use crate::{EngineCommand, EngineResponse};
+use crate::{diagnostics::DiagnosticLog, EngineCommand, EngineResponse};
+
+use super::{write_message, ResponseFrame, SocketAddress};
 
+#[derive(Debug)]
 pub enum IPCClientError {
     InvalidAddress,
     ConnectionFailed,
@@ -19,8 +22,9 @@ pub struct IPCClient {
 }
 
 impl IPCClient {
-    pub fn create(
+    pub async fn create(
         address: String,
+        diagnostics: DiagnosticLog,
     ) -> Result<
         (
             IPCClient,
@@ -29,11 +33,13 @@ impl IPCClient {
         ),
         IPCClientError,
     > {
-        let Ok(socket_ns_name) = address.to_ns_name::<GenericNamespaced>() else {
+        let socket_address = SocketAddress::parse(&address);
+
+        let Ok(socket_name) = socket_address.to_name() else {
             return Err(IPCClientError::InvalidAddress);
         };
 
-        let Ok(stream) = Stream::connect(socket_ns_name) else {
+        let Ok(stream) = Stream::connect(socket_name).await else {
             return Err(IPCClientError::ConnectionFailed);
         };
 
@@ -44,13 +50,15 @@ impl IPCClient {
 
         let (receiver, sender) = stream.split();
 
+        let reader_diagnostics = diagnostics.clone();
+
         let connection_reader = tokio::spawn(async move {
             let mut receiver = BufReader::new(receiver);
 
             loop {
                 let mut buffer: String = String::new();
 
-                let Ok(_) = receiver.read_line(&mut buffer) else {
+                let Ok(_) = receiver.read_line(&mut buffer).await else {
                     continue;
                 };
 
@@ -58,13 +66,16 @@ impl IPCClient {
                     break;
                 }
 
-                let Ok(message): Result<EngineResponse, serde_json::Error> =
-                    serde_json::from_str(&buffer)
-                else {
-                    continue;
+                let message = match serde_json::from_str::<ResponseFrame>(&buffer) {
+                    Ok(frame) => frame.into_response(),
+                    Err(error) => {
+                        reader_diagnostics.record_error("IPCRead", &error);
+
+                        continue;
+                    }
                 };
 
-                let _ = response_sender.send(message);
+                let _ = response_sender.send(message).await;
             }
         });
 
@@ -76,15 +87,22 @@ impl IPCClient {
                     continue;
                 };
 
-                let Ok(mut message): Result<Vec<u8>, serde_json::Error> =
-                    serde_json::to_vec(&command)
-                else {
-                    continue;
+                let mut message = match serde_json::to_vec(&command) {
+                    Ok(message) => message,
+                    Err(error) => {
+                        diagnostics.record_error("IPCWrite", &error);
+
+                        continue;
+                    }
                 };
 
                 message.push(b'\n');
 
-                let _ = sender.write_all(&*message);
+                if let Err(error) = write_message(&mut sender, &message).await {
+                    diagnostics.record_error("IPCWrite", &error);
+
+                    break;
+                }
             }
         });
 
@@ -102,7 +120,9 @@ impl IPCClient {
 
 impl Drop for IPCClient {
     fn drop(&mut self) {
-        let _ = self.internal_command_sender.send(EngineCommand::Goodbye);
+        let _ = self
+            .internal_command_sender
+            .try_send(EngineCommand::Goodbye);
 
         self.connection_reader.abort();
         self.connection_writer.abort();
@@ -1,2 +1,260 @@
+use std::{io, path::PathBuf};
+
+use interprocess::local_socket::{prelude::*, GenericFilePath, GenericNamespaced, Name};
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use crate::EngineResponse;
+
 pub mod client;
 pub mod server;
+
+/// One line written to a connection. Broadcasts carry the change sequence
+/// they were stamped with next to the response rather than inside it, since
+/// tagged responses do not survive being flattened into another object.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ResponseFrame {
+    Sequenced {
+        sequence: u64,
+        response: EngineResponse,
+    },
+    Bare(EngineResponse),
+}
+
+impl ResponseFrame {
+    pub fn new(response: EngineResponse, sequence: Option<u64>) -> ResponseFrame {
+        match sequence {
+            Some(sequence) => ResponseFrame::Sequenced { sequence, response },
+            None => ResponseFrame::Bare(response),
+        }
+    }
+
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            ResponseFrame::Sequenced { sequence, .. } => Some(*sequence),
+            ResponseFrame::Bare(_) => None,
+        }
+    }
+
+    pub fn into_response(self) -> EngineResponse {
+        match self {
+            ResponseFrame::Sequenced { response, .. } | ResponseFrame::Bare(response) => response,
+        }
+    }
+}
+
+pub enum SocketAddress {
+    Path(PathBuf),
+    Namespaced(String),
+}
+
+impl SocketAddress {
+    pub fn parse(address: &str) -> SocketAddress {
+        if let Some(path) = address.strip_prefix("path:") {
+            SocketAddress::Path(PathBuf::from(path))
+        } else if address.starts_with('/') {
+            SocketAddress::Path(PathBuf::from(address))
+        } else {
+            SocketAddress::Namespaced(address.to_owned())
+        }
+    }
+
+    pub fn to_name(&self) -> io::Result<Name<'_>> {
+        match self {
+            SocketAddress::Path(path) => path.as_path().to_fs_name::<GenericFilePath>(),
+            SocketAddress::Namespaced(name) => name.as_str().to_ns_name::<GenericNamespaced>(),
+        }
+    }
+}
+
+async fn write_message(sender: &mut (impl AsyncWrite + Unpin), message: &[u8]) -> io::Result<()> {
+    sender.write_all(message).await?;
+    sender.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use uuid::Uuid;
+
+    use super::{server::ListenerStatus, *};
+    use crate::{
+        changes::StateDelta,
+        player::{
+            recovery::recover_recording_metadata, AuditEntry, NetworkState, PlaybackState,
+            StorageState,
+        },
+        EngineCommand, HookFailureReason, IdleAction, LibraryChangeKind, LoopMode, OnQueueEnd,
+        Permission, PlaybackErrorReason, QueueModel,
+    };
+
+    fn broadcasts() -> Vec<EngineResponse> {
+        let metadata = recover_recording_metadata("recording", br#"{"title":"Hand-off"}"#).unwrap();
+
+        vec![
+            EngineResponse::NowPlaying {
+                id: "recording".to_owned(),
+            },
+            EngineResponse::NowPaused,
+            EngineResponse::State(PlaybackState {
+                recording_id: Some("recording".to_owned()),
+                title: Some("Hand-off".to_owned()),
+                artist: None,
+                paused: false,
+                position: Duration::from_secs(1),
+                queue_length: 2,
+                shuffle: true,
+                shuffle_seed: Some(3),
+                on_queue_end: OnQueueEnd::RepeatLast,
+                smooth_level_transition: false,
+            }),
+            EngineResponse::TrackTransition {
+                from: None,
+                to: "recording".to_owned(),
+                gapless: true,
+            },
+            EngineResponse::TrackChanged {
+                id: "recording".to_owned(),
+                duration: metadata.duration(),
+                metadata,
+            },
+            EngineResponse::Volume { volume: 0.5 },
+            EngineResponse::Speed { speed: 1.25 },
+            EngineResponse::Seek(Duration::from_millis(1500)),
+            EngineResponse::CurrentTime(Duration::from_millis(2500)),
+            EngineResponse::PlaybackError {
+                reason: PlaybackErrorReason::Faulty,
+                recoverable: false,
+                recording_id: Some("recording".to_owned()),
+            },
+            EngineResponse::Queue {
+                queue: vec!["recording".to_owned()],
+            },
+            EngineResponse::Shuffle {
+                enable: true,
+                seed: Some(3),
+            },
+            EngineResponse::QueueExtended {
+                queue: vec!["recording".to_owned()],
+                auto_added: vec!["recording".to_owned()],
+            },
+            EngineResponse::QueueSpliced {
+                total: 3,
+                offset: 1,
+                removed: 1,
+                inserted: vec!["recording".to_owned()],
+            },
+            EngineResponse::QueueEnded {
+                action_taken: OnQueueEnd::FadeOut(Duration::from_secs(2)),
+            },
+            EngineResponse::QueueInserted {
+                index: 0,
+                recordings: vec!["recording".to_owned()],
+            },
+            EngineResponse::LoopMode(LoopMode::None),
+            EngineResponse::LoopMode(LoopMode::LoopPlaylist("playlist".to_owned())),
+            EngineResponse::QueueModel(QueueModel::Cursor),
+            EngineResponse::OnQueueEnd(OnQueueEnd::Radio),
+            EngineResponse::OnQueueEnd(OnQueueEnd::FadeOut(Duration::from_secs(2))),
+            EngineResponse::SmoothLevelTransition { enabled: true },
+            EngineResponse::PauseFade(Duration::from_millis(300)),
+            EngineResponse::StopAfterCurrent { enabled: true },
+            EngineResponse::LibraryChanged {
+                kind: LibraryChangeKind::PlaylistDeleted,
+                id: "playlist".to_owned(),
+            },
+            EngineResponse::ExternalFileMissing {
+                id: "recording".to_owned(),
+                path: "/music/missing.flac".into(),
+            },
+            EngineResponse::Changes {
+                sequence: 4,
+                deltas: vec![StateDelta::Volume { volume: 0.5 }],
+                snapshot_required: false,
+            },
+            EngineResponse::HandOffComplete {
+                target: "kitchen".to_owned(),
+            },
+            EngineResponse::PermissionRequest {
+                request_id: Uuid::new_v4(),
+                device: "phone".to_owned(),
+                name: Some("Phone".to_owned()),
+                requested: vec![Permission::Queue],
+            },
+            EngineResponse::PermissionRequestResolved {
+                request_id: Uuid::new_v4(),
+                granted: vec![Permission::Queue],
+            },
+            EngineResponse::AuditEvent(AuditEntry {
+                timestamp: 1,
+                device: "phone".to_owned(),
+                command: "Pause".to_owned(),
+                permission: Permission::Control,
+                count: 2,
+            }),
+            EngineResponse::Idle {
+                action: IdleAction::Park,
+            },
+            EngineResponse::NetworkState(NetworkState::Online),
+            EngineResponse::NetworkState(NetworkState::Offline { since: 5 }),
+            EngineResponse::StorageState(StorageState::Low {
+                available: 1,
+                reserve: 2,
+            }),
+            EngineResponse::ListenerStatus(ListenerStatus::BackingOff),
+            EngineResponse::AudioUnderruns {
+                underruns_per_minute: 7.0,
+                threshold: 6.0,
+            },
+            EngineResponse::HookFailed {
+                hook: "profanity".to_owned(),
+                reason: HookFailureReason::TimedOut,
+            },
+            EngineResponse::Ok {
+                command: EngineCommand::SetVolume { volume: 0.5 },
+            },
+        ]
+    }
+
+    #[test]
+    fn broadcasts_survive_the_wire() {
+        for response in broadcasts() {
+            let expected = serde_json::to_value(&response).unwrap();
+
+            let line = serde_json::to_string(&ResponseFrame::new(response, Some(9)))
+                .unwrap_or_else(|error| panic!("{} did not serialize: {}", expected, error));
+
+            let frame = serde_json::from_str::<ResponseFrame>(&line)
+                .unwrap_or_else(|error| panic!("{} did not deserialize: {}", line, error));
+
+            assert_eq!(frame.sequence(), Some(9), "{}", line);
+            assert_eq!(
+                serde_json::to_value(frame.into_response()).unwrap(),
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn direct_replies_go_out_bare() {
+        let line = serde_json::to_string(&ResponseFrame::new(
+            EngineResponse::Speed { speed: 1.25 },
+            None,
+        ))
+        .unwrap();
+
+        assert!(
+            serde_json::from_str::<EngineResponse>(&line).is_ok(),
+            "{}",
+            line
+        );
+        assert_eq!(
+            serde_json::from_str::<ResponseFrame>(&line)
+                .unwrap()
+                .sequence(),
+            None
+        );
+    }
+}
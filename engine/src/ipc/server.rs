@@ -1,136 +1,455 @@
-use interprocess::local_socket::{tokio::prelude::*, GenericNamespaced, ListenerOptions};
+use std::{
+    fs, io,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use interprocess::local_socket::{
+    tokio::{prelude::*, Listener},
+    traits::Stream as StreamTrait,
+    ListenerOptions, Stream,
+};
+#[cfg(unix)]
+use interprocess::os::unix::local_socket::ListenerOptionsExt;
+use serde::{Deserialize, Serialize};
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, BufWriter},
+    io::{AsyncBufReadExt, BufReader, BufWriter},
     sync::{
         broadcast::{self},
         mpsc::{self},
+        watch,
     },
     task::JoinHandle,
-    try_join,
+    time, try_join,
 };
 use uuid::Uuid;
 
-use crate::{EngineCommand, EngineResponse};
+use crate::{
+    changes::ChangeFeed, diagnostics::DiagnosticLog, player::StreamChunk, EngineCommand,
+    EngineResponse,
+};
+
+use super::{write_message, ResponseFrame, SocketAddress};
 
+#[derive(Debug)]
 pub enum IPCServerError {
     InvalidAddress,
     AddressInUse,
 }
 
+const ACCEPT_BACKOFF_MIN: Duration = Duration::from_millis(50);
+const ACCEPT_BACKOFF_MAX: Duration = Duration::from_secs(5);
+const SOCKET_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const LISTENER_RECREATE_ATTEMPTS: u32 = 3;
+const LISTENER_RECREATE_DELAY: Duration = Duration::from_millis(500);
+const STREAM_CHANNEL_CAPACITY: usize = 32;
+
+pub type StreamFeed = broadcast::Sender<(StreamChunk, Uuid)>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum ListenerStatus {
+    Listening,
+    BackingOff,
+    Recreating,
+    Failed,
+}
+
+#[derive(Clone)]
+pub struct ListenerMonitor {
+    status: watch::Receiver<ListenerStatus>,
+    accept_errors: Arc<AtomicU64>,
+}
+
+impl ListenerMonitor {
+    pub fn status(&self) -> ListenerStatus {
+        *self.status.borrow()
+    }
+
+    pub fn accept_errors(&self) -> u64 {
+        self.accept_errors.load(Ordering::Relaxed)
+    }
+
+    pub async fn changed(&mut self) -> ListenerStatus {
+        if self.status.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+
+        *self.status.borrow_and_update()
+    }
+}
+
 pub struct IPCServer {
     socket_listener: JoinHandle<()>,
+    monitor: ListenerMonitor,
+    stream_sender: StreamFeed,
 }
 
-impl IPCServer {
-    pub fn create() -> Result<
-        (
-            IPCServer,
-            mpsc::Receiver<(EngineCommand, Uuid)>,
-            broadcast::Sender<(EngineResponse, Uuid)>,
-        ),
-        IPCServerError,
-    > {
-        let Ok(socket_ns_name) = "playit.sock".to_ns_name::<GenericNamespaced>() else {
-            return Err(IPCServerError::InvalidAddress);
-        };
+#[derive(Default)]
+struct TopicThrottle {
+    last_value: Option<Duration>,
+    last_sent: Option<Instant>,
+}
 
-        let listener_options = ListenerOptions::new().name(socket_ns_name);
+impl TopicThrottle {
+    fn allow(&mut self, value: Duration, rate_hz: Option<f32>) -> bool {
+        if self.last_value == Some(value) {
+            return false;
+        }
 
-        let Ok(listener) = listener_options.create_tokio() else {
-            return Err(IPCServerError::AddressInUse);
-        };
+        if let Some(rate_hz) = rate_hz {
+            if rate_hz.is_nan() || rate_hz <= 0.0 {
+                return false;
+            }
+
+            if let Some(last_sent) = self.last_sent {
+                // A rate so low that its interval does not fit in a Duration
+                // only ever lets the first update through.
+                let Ok(interval) = Duration::try_from_secs_f32(1.0 / rate_hz) else {
+                    return false;
+                };
+
+                if last_sent.elapsed() < interval {
+                    return false;
+                }
+            }
+        }
+
+        self.last_value = Some(value);
+        self.last_sent = Some(Instant::now());
+
+        true
+    }
+}
+
+impl IPCServer {
+    pub fn create(
+        address: String,
+        socket_mode: u32,
+        diagnostics: DiagnosticLog,
+    ) -> Result<(IPCServer, mpsc::Receiver<(EngineCommand, Uuid)>, ChangeFeed), IPCServerError>
+    {
+        let socket_address = SocketAddress::parse(&address);
 
-        let (response_sender, _) = broadcast::channel::<(EngineResponse, Uuid)>(16);
+        let mut listener = create_listener(&socket_address, socket_mode)?;
+
+        let response_sender = ChangeFeed::new(16);
         let (command_sender, command_receiver) = mpsc::channel::<(EngineCommand, Uuid)>(16);
 
         let external_response_sender = response_sender.clone();
 
+        // Stream audio gets its own channel so a slow listener only drops audio
+        // chunks instead of lagging every other connection's responses.
+        let (stream_sender, _) = broadcast::channel::<(StreamChunk, Uuid)>(STREAM_CHANNEL_CAPACITY);
+        let external_stream_sender = stream_sender.clone();
+
+        let (status_sender, status_receiver) = watch::channel(ListenerStatus::Listening);
+        let accept_errors = Arc::new(AtomicU64::new(0));
+
+        let monitor = ListenerMonitor {
+            status: status_receiver,
+            accept_errors: accept_errors.clone(),
+        };
+
         let socket_listener = tokio::spawn(async move {
-            loop {
-                let connection = match listener.accept().await {
-                    Ok(x) => x,
-                    Err(_) => {
-                        continue;
-                    }
-                };
+            let mut backoff = ACCEPT_BACKOFF_MIN;
 
-                let reader_connection_id = Uuid::new_v4();
-                let sender_connection_id = reader_connection_id.clone();
+            loop {
+                loop {
+                    let accepted = tokio::select! {
+                        accepted = listener.accept() => accepted,
+                        _ = time::sleep(SOCKET_CHECK_INTERVAL) => {
+                            if socket_present(&socket_address) {
+                                continue;
+                            }
 
-                let new_command_sender = command_sender.clone();
+                            Err(io::Error::from(io::ErrorKind::NotFound))
+                        }
+                    };
 
-                let (receiver, sender) = connection.split();
+                    let connection = match accepted {
+                        Ok(connection) => {
+                            backoff = ACCEPT_BACKOFF_MIN;
 
-                let connection_reader = tokio::spawn(async move {
-                    let mut receiver = BufReader::new(receiver);
+                            status_sender.send_if_modified(|status| {
+                                let modified = *status != ListenerStatus::Listening;
 
-                    loop {
-                        let mut buffer: String = String::new();
+                                *status = ListenerStatus::Listening;
 
-                        let readline = receiver.read_line(&mut buffer);
+                                modified
+                            });
 
-                        if try_join!(readline).is_err() {
-                            continue;
+                            connection
                         }
+                        Err(error) => {
+                            accept_errors.fetch_add(1, Ordering::Relaxed);
+                            diagnostics.record_error("IPCAccept", &error);
+
+                            if !is_persistent(&error, &socket_address) {
+                                status_sender.send_replace(ListenerStatus::BackingOff);
+
+                                time::sleep(backoff).await;
+
+                                backoff = (backoff * 2).min(ACCEPT_BACKOFF_MAX);
+
+                                continue;
+                            }
 
-                        if buffer.is_empty() {
                             break;
                         }
+                    };
+
+                    let reader_connection_id = Uuid::new_v4();
+                    let sender_connection_id = reader_connection_id.clone();
+
+                    let new_command_sender = command_sender.clone();
+                    let reader_diagnostics = diagnostics.clone();
+                    let writer_diagnostics = diagnostics.clone();
+
+                    let (receiver, sender) = connection.split();
+
+                    let (current_time_rate_sender, current_time_rate_receiver) =
+                        watch::channel::<Option<f32>>(None);
+
+                    let connection_reader = tokio::spawn(async move {
+                        let mut receiver = BufReader::new(receiver);
+
+                        loop {
+                            let mut buffer: String = String::new();
+
+                            let readline = receiver.read_line(&mut buffer);
 
-                        let Ok(message): Result<EngineCommand, serde_json::Error> =
-                            serde_json::from_str(&buffer)
-                        else {
-                            continue;
-                        };
+                            if try_join!(readline).is_err() {
+                                continue;
+                            }
 
-                        match message {
-                            EngineCommand::Goodbye => {
+                            if buffer.is_empty() {
                                 break;
                             }
-                            other_command => {
-                                let _ = new_command_sender
-                                    .send((other_command, reader_connection_id))
-                                    .await;
+
+                            let message: EngineCommand = match serde_json::from_str(&buffer) {
+                                Ok(message) => message,
+                                Err(error) => {
+                                    reader_diagnostics.record_error("IPCRead", &error);
+
+                                    continue;
+                                }
+                            };
+
+                            match message {
+                                EngineCommand::Goodbye => {
+                                    break;
+                                }
+                                hello @ EngineCommand::Hello {
+                                    current_time_hz, ..
+                                } => {
+                                    let _ = current_time_rate_sender.send(current_time_hz);
+
+                                    let _ = new_command_sender
+                                        .send((hello, reader_connection_id))
+                                        .await;
+                                }
+                                other_command => {
+                                    let _ = new_command_sender
+                                        .send((other_command, reader_connection_id))
+                                        .await;
+                                }
+                            };
+                        }
+
+                        let _ = new_command_sender
+                            .send((EngineCommand::Goodbye, reader_connection_id))
+                            .await;
+                    });
+
+                    let (mut new_response_receiver, mut last_sequence) =
+                        response_sender.subscribe();
+                    let writer_response_sender = response_sender.clone();
+                    let mut stream_receiver = stream_sender.subscribe();
+
+                    let connection_writer = tokio::spawn(async move {
+                        let mut sender = BufWriter::new(sender);
+
+                        let mut current_time_throttle = TopicThrottle::default();
+
+                        loop {
+                            let (response, uuid, sequence) = tokio::select! {
+                                broadcast = new_response_receiver.recv() => match broadcast {
+                                    Ok(broadcast) => broadcast,
+                                    Err(broadcast::error::RecvError::Lagged(_)) => {
+                                        let (changes, sequence) =
+                                            writer_response_sender.changes_since(last_sequence);
+
+                                        (changes, sender_connection_id, Some(sequence))
+                                    }
+                                    Err(broadcast::error::RecvError::Closed) => continue,
+                                },
+                                chunk = stream_receiver.recv() => match chunk {
+                                    Ok((chunk, uuid)) => (EngineResponse::StreamChunk(chunk), uuid, None),
+                                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                    Err(broadcast::error::RecvError::Closed) => break,
+                                },
+                            };
+
+                            if let Some(sequence) = sequence {
+                                last_sequence = sequence;
+                            }
+
+                            if uuid != sender_connection_id && !uuid.is_nil() {
+                                continue;
+                            }
+
+                            if let EngineResponse::CurrentTime(position) = response {
+                                if !current_time_throttle
+                                    .allow(position, *current_time_rate_receiver.borrow())
+                                {
+                                    continue;
+                                }
                             }
-                        };
-                    }
-                });
 
-                let mut new_response_receiver = response_sender.subscribe();
+                            let frame = ResponseFrame::new(response, sequence);
 
-                let connection_writer = tokio::spawn(async move {
-                    let mut sender = BufWriter::new(sender);
+                            let mut message = match serde_json::to_vec(&frame) {
+                                Ok(message) => message,
+                                Err(error) => {
+                                    writer_diagnostics.record_error("IPCWrite", &error);
 
-                    loop {
-                        let Ok((response, uuid)) = new_response_receiver.recv().await else {
-                            continue;
-                        };
+                                    continue;
+                                }
+                            };
 
-                        if uuid != sender_connection_id && !uuid.is_nil() {
-                            continue;
+                            message.push(b'\n');
+
+                            if let Err(error) = write_message(&mut sender, &message).await {
+                                writer_diagnostics.record_error("IPCWrite", &error);
+
+                                break;
+                            }
                         }
+                    });
 
-                        let Ok(message): Result<String, serde_json::Error> =
-                            serde_json::to_string(&response)
-                        else {
-                            continue;
-                        };
+                    // Waiting here would leave every other client unserved
+                    // until this one hangs up.
+                    tokio::spawn(async move {
+                        let _ = connection_reader.await;
+                        connection_writer.abort();
+                    });
+                }
 
-                        let _ = sender.write_all(message.as_bytes()).await;
-                    }
-                });
+                drop(listener);
+
+                status_sender.send_replace(ListenerStatus::Recreating);
+
+                let Some(new_listener) = recreate_listener(&socket_address, socket_mode).await
+                else {
+                    status_sender.send_replace(ListenerStatus::Failed);
+
+                    return;
+                };
 
-                let _ = connection_reader.await;
-                connection_writer.abort();
+                listener = new_listener;
+
+                status_sender.send_replace(ListenerStatus::Listening);
             }
         });
 
         Ok((
-            IPCServer { socket_listener },
+            IPCServer {
+                socket_listener,
+                monitor,
+                stream_sender: external_stream_sender,
+            },
             command_receiver,
             external_response_sender,
         ))
     }
+
+    pub fn monitor(&self) -> ListenerMonitor {
+        self.monitor.clone()
+    }
+
+    pub fn stream_feed(&self) -> StreamFeed {
+        self.stream_sender.clone()
+    }
+
+    /// Stops listening and waits until the listener has let go of the socket,
+    /// so the address can be bound again straight away.
+    pub async fn shutdown(mut self) {
+        self.socket_listener.abort();
+
+        let _ = (&mut self.socket_listener).await;
+    }
+}
+
+fn create_listener(
+    socket_address: &SocketAddress,
+    socket_mode: u32,
+) -> Result<Listener, IPCServerError> {
+    if let SocketAddress::Path(path) = socket_address {
+        remove_stale_socket(socket_address, path);
+    }
+
+    let Ok(socket_name) = socket_address.to_name() else {
+        return Err(IPCServerError::InvalidAddress);
+    };
+
+    let listener_options = ListenerOptions::new().name(socket_name).reclaim_name(true);
+
+    #[cfg(unix)]
+    let listener_options = listener_options.mode(socket_mode as _);
+    #[cfg(not(unix))]
+    let _ = socket_mode;
+
+    let Ok(listener) = listener_options.create_tokio() else {
+        return Err(IPCServerError::AddressInUse);
+    };
+
+    Ok(listener)
+}
+
+async fn recreate_listener(socket_address: &SocketAddress, socket_mode: u32) -> Option<Listener> {
+    for attempt in 1..=LISTENER_RECREATE_ATTEMPTS {
+        time::sleep(LISTENER_RECREATE_DELAY * attempt).await;
+
+        if let Ok(listener) = create_listener(socket_address, socket_mode) {
+            return Some(listener);
+        }
+    }
+
+    None
+}
+
+fn is_persistent(error: &io::Error, socket_address: &SocketAddress) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::InvalidInput
+    ) || !socket_present(socket_address)
+}
+
+fn socket_present(socket_address: &SocketAddress) -> bool {
+    match socket_address {
+        SocketAddress::Path(path) => path.exists(),
+        SocketAddress::Namespaced(_) => true,
+    }
+}
+
+fn remove_stale_socket(socket_address: &SocketAddress, path: &std::path::Path) {
+    if !path.exists() {
+        return;
+    }
+
+    let Ok(socket_name) = socket_address.to_name() else {
+        return;
+    };
+
+    if Stream::connect(socket_name).is_err() {
+        let _ = fs::remove_file(path);
+    }
 }
 
 impl Drop for IPCServer {
@@ -138,3 +457,44 @@ impl Drop for IPCServer {
         self.socket_listener.abort();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn throttle_drops_repeated_values() {
+        let mut throttle = TopicThrottle::default();
+
+        assert!(throttle.allow(Duration::from_secs(1), None));
+        assert!(!throttle.allow(Duration::from_secs(1), None));
+        assert!(throttle.allow(Duration::from_secs(2), None));
+    }
+
+    #[test]
+    fn throttle_limits_updates_to_the_rate() {
+        let mut throttle = TopicThrottle::default();
+
+        assert!(throttle.allow(Duration::from_secs(1), Some(1.0)));
+        assert!(!throttle.allow(Duration::from_secs(2), Some(1.0)));
+
+        throttle.last_sent = Some(Instant::now() - Duration::from_secs(2));
+
+        assert!(throttle.allow(Duration::from_secs(3), Some(1.0)));
+    }
+
+    #[test]
+    fn throttle_survives_degenerate_rates() {
+        for rate_hz in [0.0, -1.0, f32::NAN] {
+            let mut throttle = TopicThrottle::default();
+
+            assert!(!throttle.allow(Duration::from_secs(1), Some(rate_hz)));
+        }
+
+        let mut throttle = TopicThrottle::default();
+
+        assert!(throttle.allow(Duration::from_secs(1), Some(f32::MIN_POSITIVE)));
+        assert!(!throttle.allow(Duration::from_secs(2), Some(f32::MIN_POSITIVE)));
+        assert!(!throttle.allow(Duration::from_secs(3), Some(1e-45)));
+    }
+}
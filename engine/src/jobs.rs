@@ -0,0 +1,274 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+use crate::{
+    diagnostics::DiagnosticLog,
+    player::{
+        database::{AudioFileStatus, Database, RefreshOutcome},
+        sequencer::Sequencer,
+        AudioLocation,
+    },
+    route_response, CommandContext, EngineCommand, EngineResponse, NopeReason,
+};
+
+const REFRESH_REQUEST_INTERVAL: Duration = Duration::from_secs(1);
+
+pub type Jobs = Arc<Mutex<HashMap<Uuid, CancellationToken>>>;
+
+pub async fn handle(command: EngineCommand, context: CommandContext<'_>, jobs: &Jobs) {
+    let CommandContext {
+        internal,
+        uuid,
+        database,
+        sequencer,
+        diagnostics,
+        internal_response_sender,
+        response_sender,
+    } = context;
+
+    match command {
+        EngineCommand::VerifyLibrary { job_id } | EngineCommand::RefreshLibrary { job_id } => {
+            let refresh = matches!(command, EngineCommand::RefreshLibrary { .. });
+
+            let cancellation = CancellationToken::new();
+
+            {
+                let mut locked_jobs = jobs.lock().await;
+
+                if locked_jobs.contains_key(&job_id) {
+                    route_response(
+                        internal,
+                        internal_response_sender,
+                        response_sender,
+                        EngineResponse::Nope {
+                            command,
+                            reason: NopeReason::InvalidArgument,
+                        },
+                        uuid,
+                    );
+
+                    return;
+                }
+
+                locked_jobs.insert(job_id, cancellation.clone());
+            }
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::JobStarted { job_id },
+                uuid,
+            );
+
+            let job_database = database.clone();
+            let job_sequencer = sequencer.clone();
+            let job_diagnostics = diagnostics.clone();
+            let job_internal_response_sender = internal_response_sender.clone();
+            let job_response_sender = response_sender.clone();
+            let job_registry = jobs.clone();
+
+            tokio::spawn(async move {
+                let report = |response| {
+                    route_response(
+                        internal,
+                        &job_internal_response_sender,
+                        &job_response_sender,
+                        response,
+                        uuid,
+                    );
+                };
+
+                if refresh {
+                    refresh_library(
+                        &job_database,
+                        &job_sequencer,
+                        &job_diagnostics,
+                        job_id,
+                        &cancellation,
+                        report,
+                    )
+                    .await;
+                } else {
+                    verify_library(&job_database, job_id, &cancellation, report).await;
+                }
+
+                job_registry.lock().await.remove(&job_id);
+            });
+        }
+        EngineCommand::ListOrphanedRecordings => {
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::OrphanedRecordings {
+                    recordings: database.get_orphaned_recordings().await,
+                },
+                uuid,
+            );
+        }
+        EngineCommand::AdoptLooseFiles => {
+            let adoption_database = database.clone();
+            let adoption_internal_response_sender = internal_response_sender.clone();
+            let adoption_response_sender = response_sender.clone();
+
+            tokio::spawn(async move {
+                let summary = adoption_database.adopt_loose_files().await;
+
+                route_response(
+                    internal,
+                    &adoption_internal_response_sender,
+                    &adoption_response_sender,
+                    EngineResponse::LooseFilesAdopted { summary },
+                    uuid,
+                );
+            });
+        }
+        EngineCommand::CancelJob { job_id } => {
+            let Some(cancellation) = jobs.lock().await.get(&job_id).cloned() else {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::NotFound,
+                    },
+                    uuid,
+                );
+
+                return;
+            };
+
+            cancellation.cancel();
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Ok { command },
+                uuid,
+            );
+        }
+        _ => unreachable!("{} is not a job command", command.kind()),
+    }
+}
+
+async fn verify_library(
+    database: &Database,
+    job_id: Uuid,
+    cancellation: &CancellationToken,
+    report: impl Fn(EngineResponse),
+) {
+    let library = database.get_library().await;
+    let total = library.len();
+
+    let mut missing = Vec::new();
+    let mut mismatched = Vec::new();
+
+    for (index, (id, metadata)) in library.into_iter().enumerate() {
+        if cancellation.is_cancelled() {
+            report(EngineResponse::JobFinished {
+                job_id,
+                cancelled: true,
+            });
+
+            return;
+        }
+
+        let status = match (metadata.audio_location, metadata.audio_file_hash) {
+            (AudioLocation::External { path, .. }, _) => {
+                Some(database.verify_external_file(id.clone(), path).await)
+            }
+            (AudioLocation::Managed, Some(audio_file_hash)) => {
+                Some(database.verify_audio_file(audio_file_hash).await)
+            }
+            (AudioLocation::Managed, None) => None,
+        };
+
+        match status {
+            Some(AudioFileStatus::Intact) | None => {}
+            Some(AudioFileStatus::Missing) => missing.push(id),
+            Some(AudioFileStatus::Mismatched) => mismatched.push(id),
+        }
+
+        report(EngineResponse::JobProgress {
+            job_id,
+            completed: index + 1,
+            total,
+        });
+    }
+
+    report(EngineResponse::LibraryVerified {
+        job_id,
+        missing,
+        mismatched,
+    });
+    report(EngineResponse::JobFinished {
+        job_id,
+        cancelled: false,
+    });
+}
+
+async fn refresh_library(
+    database: &Database,
+    sequencer: &Sequencer,
+    diagnostics: &DiagnosticLog,
+    job_id: Uuid,
+    cancellation: &CancellationToken,
+    report: impl Fn(EngineResponse),
+) {
+    let library = database.get_library().await;
+    let total = library.len();
+
+    let mut merged = Vec::new();
+    let mut orphaned = Vec::new();
+
+    for (index, (id, _)) in library.into_iter().enumerate() {
+        if index > 0 {
+            tokio::select! {
+                _ = cancellation.cancelled() => {}
+                _ = tokio::time::sleep(REFRESH_REQUEST_INTERVAL) => {}
+            }
+        }
+
+        if cancellation.is_cancelled() {
+            report(EngineResponse::JobFinished {
+                job_id,
+                cancelled: true,
+            });
+
+            return;
+        }
+
+        match database.refresh_recording_metadata(id.clone()).await {
+            Ok(RefreshOutcome::Merged(new_id)) => {
+                sequencer.replace_recording(&id, &new_id).await;
+
+                merged.push((id, new_id));
+            }
+            Ok(RefreshOutcome::Orphaned) => orphaned.push(id),
+            Ok(RefreshOutcome::Current) => {}
+            Err(error) => diagnostics.record_error("RefreshLibrary", &error),
+        }
+
+        report(EngineResponse::JobProgress {
+            job_id,
+            completed: index + 1,
+            total,
+        });
+    }
+
+    report(EngineResponse::LibraryRefreshed {
+        job_id,
+        merged,
+        orphaned,
+    });
+    report(EngineResponse::JobFinished {
+        job_id,
+        cancelled: false,
+    });
+}
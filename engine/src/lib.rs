@@ -1,36 +1,215 @@
-use std::{io::Read, time::Duration};
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io::Read,
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
 
-use ipc::{client::IPCClient, server::IPCServer};
-use player::{database::Database, sequencer::Sequencer, PlaylistMetadata, RecordingMetadata};
+use access::{command_access, CommandAccess, Connections};
+use changes::{ChangeFeed, StateDelta};
+use diagnostics::{redact_settings, DiagnosticLog};
+use ipc::{
+    client::IPCClient,
+    server::{IPCServer, ListenerMonitor, ListenerStatus, StreamFeed},
+};
+use jobs::Jobs;
+use metrics::LatencyRecorder;
+use player::{
+    collation::{sort_entries, Collation},
+    database::{Database, DatabaseError, DatabaseEvent, PlaylistSync, PlaylistValidation},
+    export,
+    preview::{decode_preview, PREVIEW_SAMPLE_RATE},
+    probe::SUPPORTED_CODECS,
+    provider::{MetadataProvider, ProviderError, DEFAULT_USER_AGENT},
+    sequencer::{Sequencer, SequencerError},
+    state::{resolve_state, start_state_mirror, StateMirror},
+    underrun::UnderrunWindow,
+    AdoptionSummary, AudioSource, AudioStatus, AuditEntry, EffectSummary, LocalTagPatch,
+    NamedQueue, NetworkState, PlaybackState, PlaylistMetadata, ProvenanceEntry, RecordingMetadata,
+    RecordingRelationship, SavedPlayerState, SessionSnapshot, StorageState, StreamChunk,
+    TrashEntry,
+};
+use streaming::{Follower, StreamListeners, StreamUpdate};
 use tokio::{
     sync::{
         broadcast,
         mpsc::{self},
+        watch, Mutex,
     },
     task::JoinHandle,
 };
+use tokio_stream::Stream;
+use tokio_util::sync::CancellationToken;
+use transfer::{is_transferable, Transfers};
+
+pub use async_trait::async_trait;
+pub use events::{EngineEvent, EventError, PlaybackPhase, TrackSummary};
+pub use hooks::{Hook, HookContext, HookDecision, HookFailureReason, Hooks};
+pub use ipc::ResponseFrame;
 
+mod access;
+mod bandwidth;
+mod changes;
+mod diagnostics;
+mod events;
+mod health;
+mod hooks;
 mod ipc;
+mod jobs;
+mod metrics;
 mod player;
+mod streaming;
+mod tags;
+mod transfer;
+mod trash;
+
+const EXPORT_CHUNK_SIZE: usize = 64 * 1024;
+const LOCAL_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+const LOCAL_ADDRESS: &str = "playit.sock";
+const LOCAL_DEVICE: &str = "local";
+const HAND_OFF_TIMEOUT: Duration = Duration::from_secs(30);
+const STATE_FILE_INTERVAL: Duration = Duration::from_secs(2);
+const STORAGE_RESERVE: u64 = 500 * 1024 * 1024;
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const QUEUE_PAGE_THRESHOLD: usize = 500;
+const UNDERRUN_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const UNDERRUN_WARNING_THRESHOLD: f32 = 6.0;
+const POSITION_BROADCAST_INTERVAL: Duration = Duration::from_secs(1);
+const TRASH_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+const TRASH_PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+const PREVIEW_REQUEST_INTERVAL: Duration = Duration::from_millis(250);
+const PLAYER_STATE_SAVE_INTERVAL: Duration = Duration::from_secs(2);
+const QUEUE_MODEL_SETTING: &str = "queue_model";
+const ON_QUEUE_END_SETTING: &str = "on_queue_end";
+const SMOOTH_LEVEL_TRANSITION_SETTING: &str = "smooth_level_transition";
+const PAUSE_FADE_SETTING: &str = "pause_fade";
+const COLLATION_SETTING: &str = "collation";
+const PLAYER_STATE_SETTING: &str = "player_state";
+const PERSIST_PLAYER_STATE_SETTING: &str = "persist_player_state";
+const SOCKET_MODE: u32 = 0o600;
+const CHANNEL_CAPACITY: usize = 16;
+const LOW_MEMORY_CHANNEL_CAPACITY: usize = 4;
+const LOW_MEMORY_PAGE_SIZE: usize = 50;
+const MAX_QUEUE_LENGTH: usize = 5000;
+const HISTORY_CAPACITY: usize = 1000;
+const MIN_PLAYBACK_INTERVAL: Duration = Duration::from_millis(500);
+const TRANSFER_LIMIT: usize = 2;
+const RELATED_REMOTE_LIMIT: usize = 10;
 
 pub struct Engine {
-    sequencer: Sequencer,
-    database: Database,
+    sequencer: Option<Sequencer>,
+    database: Option<Database>,
 
-    location: EngineLocation,
+    location: Mutex<EngineLocation>,
+    status_sender: Arc<watch::Sender<EngineConnectionStatus>>,
 
     engine_command_sender: broadcast::Sender<EngineCommand>,
     engine_response_sender: broadcast::Sender<EngineResponse>,
+
+    local_address: String,
+    socket_mode: u32,
+
+    low_memory: bool,
+    transfer_limit: usize,
+
+    idle_timeout: Option<Duration>,
+    idle_action: IdleAction,
+    underrun_threshold: f32,
+    queue_page_threshold: usize,
+    trash_retention: Duration,
+    adopt_loose_files: bool,
+    hooks: Hooks,
+
+    settings: EngineSettings,
+    diagnostics: DiagnosticLog,
+
+    state_mirror: Mutex<Option<StateMirror>>,
 }
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 #[serde()]
 pub enum LoopMode {
     None,
     LoopQueue,
     LoopRecording,
+    LoopPlaylist(String),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum HealthState {
+    Healthy,
+    Degraded,
+    Unhealthy,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum ConflictResolution {
+    Local,
+    Remote,
+    Merge,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde()]
+pub enum PlaylistOrigin {
+    #[default]
+    Local,
+    Remote,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde()]
+pub enum OnQueueEnd {
+    #[default]
+    Stop,
+    Radio,
+    RepeatLast,
+    FadeOut(Duration),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde()]
+pub enum IdleAction {
+    #[default]
+    Shutdown,
+    Park,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum QueueRejectReason {
+    MissingAudio,
+    UnknownRecording,
+    QueueFull,
+    Duplicate,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum MetadataEditRejectReason {
+    NotLocal,
+    UnknownRecording,
+    TagWriteFailed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde()]
+pub enum QueueModel {
+    #[default]
+    Consuming,
+    Cursor,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde()]
+pub enum QueueRemoveTarget {
+    Index(usize),
+    Id(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -38,8 +217,224 @@ pub enum LoopMode {
 pub enum Permission {
     Control,
     Queue,
+    Request,
     Playlist,
     Transfer,
+    Library,
+    Admin,
+}
+
+#[derive(Debug, Clone, Default)]
+pub enum StorageBackend {
+    #[default]
+    Sled,
+    Memory,
+}
+
+pub trait AudioOutput: Send + 'static {
+    fn sample_rate(&self) -> u32;
+    fn channels(&self) -> u16;
+
+    fn write(&mut self, samples: &[f32]);
+}
+
+pub struct PcmCallback<F>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    sample_rate: u32,
+    channels: u16,
+    callback: F,
+}
+
+impl<F> PcmCallback<F>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    pub fn new(sample_rate: u32, channels: u16, callback: F) -> PcmCallback<F> {
+        PcmCallback {
+            sample_rate,
+            channels,
+            callback,
+        }
+    }
+}
+
+impl<F> AudioOutput for PcmCallback<F>
+where
+    F: FnMut(&[f32]) + Send + 'static,
+{
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        (self.callback)(samples)
+    }
+}
+
+#[derive(Clone, Default)]
+pub enum AudioOutputChoice {
+    #[default]
+    Default,
+    Custom(Arc<std::sync::Mutex<dyn AudioOutput>>),
+}
+
+impl AudioOutputChoice {
+    pub fn custom(output: impl AudioOutput) -> AudioOutputChoice {
+        AudioOutputChoice::Custom(Arc::new(std::sync::Mutex::new(output)))
+    }
+}
+
+impl std::fmt::Debug for AudioOutputChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AudioOutputChoice::Default => write!(f, "Default"),
+            AudioOutputChoice::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct EngineConfig {
+    pub storage: StorageBackend,
+    pub resume_after_suspend: bool,
+    pub state_file: Option<PathBuf>,
+    pub state_file_interval: Option<Duration>,
+    pub socket: Option<String>,
+    pub socket_mode: Option<u32>,
+    pub low_memory: bool,
+    pub musicbrainz_base_url: Option<String>,
+    pub musicbrainz_user_agent: Option<String>,
+    pub max_queue_length: Option<usize>,
+    pub history_capacity: Option<usize>,
+    pub min_playback_interval: Option<Duration>,
+    pub transfer_limit: Option<usize>,
+    pub audio_output: AudioOutputChoice,
+    pub idle_timeout: Option<Duration>,
+    pub idle_action: IdleAction,
+    pub storage_reserve: Option<u64>,
+    pub underrun_warning_threshold: Option<f32>,
+    pub queue_page_threshold: Option<usize>,
+    pub trash_retention: Option<Duration>,
+    pub adopt_loose_files: bool,
+    pub hooks: Hooks,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CommandLatency {
+    pub command: String,
+    pub count: u64,
+    pub buckets: Vec<u64>,
+    pub p50: Option<Duration>,
+    pub p99: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MetricsSnapshot {
+    pub latencies: Vec<CommandLatency>,
+    #[serde(default)]
+    pub history_len: usize,
+    #[serde(default)]
+    pub transfers_in_flight: usize,
+    #[serde(default)]
+    pub accept_errors: u64,
+    #[serde(default)]
+    pub seek_fallbacks: u64,
+    #[serde(default)]
+    pub underruns: u64,
+    #[serde(default)]
+    pub bytes_sent: u64,
+    #[serde(default)]
+    pub bytes_received: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ClientUsage {
+    pub connection: Uuid,
+    pub device: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    pub budget: Option<u64>,
+    pub used_today: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct LibraryStats {
+    pub recordings: usize,
+    pub playlists: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EngineSettings {
+    pub musicbrainz_base_url: Option<String>,
+    pub musicbrainz_user_agent: String,
+    #[serde(default)]
+    pub collation: Option<Collation>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum LibrarySort {
+    Title,
+    Artist,
+    RecentlyAdded,
+    PlayCount,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde()]
+pub enum SortDirection {
+    #[default]
+    Ascending,
+    Descending,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum PlaybackErrorReason {
+    Suspended,
+    Faulty,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum NopeReason {
+    Unspecified,
+    PermissionDenied,
+    DecodeFailed,
+    HandOffFailed,
+    InvalidArgument,
+    NotFound,
+    Busy,
+    RateLimited { retry_after: Duration },
+    NetworkUnavailable,
+    ServerError,
+    InsufficientStorage { needed: u64, available: u64 },
+    IntegrityMismatch,
+    NotTransferable,
+    BudgetExceeded { budget: u64 },
+    Unsupported,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum LibraryChangeKind {
+    RecordingUpserted,
+    RecordingDeleted,
+    PlaylistUpserted,
+    PlaylistDeleted,
+    AudioStored,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -47,148 +442,1221 @@ pub enum Permission {
 pub enum EngineCommand {
     None,
     Goodbye,
+    Hello {
+        current_time_hz: Option<f32>,
+        #[serde(default)]
+        device: Option<String>,
+        #[serde(default)]
+        token: Option<String>,
+    },
+
+    GetState,
+    GetPosition,
 
     Play(Option<String>),
+    PlayFromStart {
+        id: String,
+    },
     Pause,
+    Stop,
 
     Next,
     Previous,
+    PlayQueueIndex {
+        index: usize,
+        #[serde(default)]
+        keep_previous: bool,
+    },
 
     Seek(Duration),
+    SeekPercent {
+        fraction: f32,
+    },
+    SeekRelative {
+        offset_ms: i64,
+    },
 
     Queue(Option<Vec<String>>),
-    ShuffleQueue(bool),
-    ClearQueue,
+    QueuePlaylist {
+        id: String,
+        resume: bool,
+    },
+    QueueRelease {
+        release_id: String,
+        only_local: bool,
+    },
+    QueueAfterCurrentGroup {
+        recordings: Vec<String>,
+    },
+    QueueNext {
+        recordings: Vec<String>,
+    },
+    QueueTo {
+        queue: String,
+        recordings: Vec<String>,
+    },
+    GetQueues,
+    GetHistory {
+        #[serde(default)]
+        count: Option<usize>,
+    },
+    QueueRange {
+        offset: usize,
+        limit: usize,
+    },
+    ShuffleQueue {
+        enable: bool,
+        #[serde(default)]
+        seed: Option<u64>,
+    },
+    ClearQueue {
+        #[serde(default)]
+        preview: bool,
+    },
+    QueueRemove(QueueRemoveTarget),
+    QueueMove {
+        from: usize,
+        to: usize,
+    },
+    SetRadioMode {
+        enabled: bool,
+    },
 
     LoopMode(LoopMode),
+    QueueModel(QueueModel),
+    OnQueueEnd(OnQueueEnd),
+    SmoothLevelTransition {
+        enabled: bool,
+    },
+    PauseFade(Duration),
+    StopAfterCurrent {
+        enabled: bool,
+    },
 
-    RecordingMetadata(String),
+    RecordingMetadata {
+        id: String,
+    },
+    RecordingDuration {
+        id: String,
+    },
+    RecordingStats {
+        id: String,
+    },
     RecordingFile(String),
+    RecordingFileChunk {
+        id: String,
+        offset: u64,
+    },
+    TransferAck {
+        id: String,
+    },
     SendRecording((String, Vec<u8>)),
+    SendVerifiedRecording {
+        id: String,
+        sha256: String,
+        data: Vec<u8>,
+    },
+    SendRecordingChunk {
+        id: String,
+        offset: u64,
+        total_len: u64,
+        sha256: String,
+        data: Vec<u8>,
+    },
+    LinkExternalFile {
+        id: String,
+        path: PathBuf,
+        #[serde(default)]
+        transferable: bool,
+    },
+    RecordingProvenance {
+        id: String,
+    },
+    Waveform {
+        id: String,
+    },
 
-    PlaylistMetadata(String),
+    PlaylistMetadata {
+        id: String,
+    },
     SetPlaylistMetadata(PlaylistMetadata),
+    ListPlaylists {
+        #[serde(default)]
+        sort: Option<LibrarySort>,
+        #[serde(default)]
+        direction: SortDirection,
+    },
+    CachePlaylist {
+        id: String,
+    },
+    ValidatePlaylist {
+        id: String,
+        #[serde(default)]
+        prune: bool,
+    },
+    ResolvePlaylistConflict {
+        id: String,
+        keep: ConflictResolution,
+    },
+
+    SetVolume {
+        volume: f32,
+    },
+    Mute {
+        muted: bool,
+    },
+    GetVolume,
+    SetSpeed {
+        speed: f32,
+    },
+    GetSpeed,
+    PlayCue {
+        data: Vec<u8>,
+        duck_db: f32,
+    },
+
+    FollowPlayback {
+        enabled: bool,
+    },
+    StreamListen {
+        enabled: bool,
+    },
+    LibraryEvents {
+        enabled: bool,
+    },
 
-    SetVolume(f32),
+    HandOff {
+        target: String,
+    },
+    AdoptSession(SessionSnapshot),
 
     GetPermissions,
     SetPermissions(Vec<Permission>),
+    RequestPermissions {
+        permissions: Vec<Permission>,
+    },
+    ResolvePermissionRequest {
+        request_id: Uuid,
+        grant: Vec<Permission>,
+    },
+    AllowedCommands,
+    UpdateSettings(EngineSettings),
+    ClearSavedState {
+        #[serde(default)]
+        keep_saving: bool,
+    },
+
+    Capabilities,
+    HealthCheck,
+    SupportedFormats,
+    Metrics {
+        reset: bool,
+    },
+    ResetMetrics,
+    AudioStatus,
+    DiagnosticBundle,
+
+    ExportHistory {
+        since: Option<u64>,
+        format: ExportFormat,
+    },
+
+    AuditLog {
+        since: Option<u64>,
+        limit: Option<usize>,
+    },
+
+    ListClients,
+    SetDeviceTransferBudget {
+        device_id: String,
+        bytes_per_day: Option<u64>,
+    },
+
+    VerifyLibrary {
+        job_id: Uuid,
+    },
+    RefreshLibrary {
+        job_id: Uuid,
+    },
+    CancelJob {
+        job_id: Uuid,
+    },
+    ListOrphanedRecordings,
+    AdoptLooseFiles,
+    DeleteRecording {
+        id: String,
+    },
+    DeletePlaylist {
+        id: String,
+    },
+    ListTrash,
+    RestoreFromTrash {
+        id: String,
+    },
+    EmptyTrash {
+        #[serde(default)]
+        older_than: Option<Duration>,
+    },
+
+    TagRecording {
+        id: String,
+        add: Vec<String>,
+        remove: Vec<String>,
+    },
+    EditLocalMetadata {
+        ids: Vec<String>,
+        set: LocalTagPatch,
+        #[serde(default)]
+        write_tags: bool,
+    },
+    SetSpokenWord {
+        id: String,
+        spoken_word: Option<bool>,
+    },
+    ListTags,
+    RecordingsByTag {
+        tag: String,
+    },
+    ListRecordings {
+        tag: Option<String>,
+        #[serde(default)]
+        sort: Option<LibrarySort>,
+        #[serde(default)]
+        direction: SortDirection,
+    },
+    RelatedRecordings {
+        id: String,
+    },
+    PreviewAt {
+        id: String,
+        position: Duration,
+    },
+    ChangesSince {
+        sequence: u64,
+    },
+    Batch {
+        commands: Vec<EngineCommand>,
+    },
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 pub enum EngineResponse {
-    Ok(EngineCommand),
-    Nope(EngineCommand),
+    Ok {
+        command: EngineCommand,
+    },
+    Nope {
+        command: EngineCommand,
+        reason: NopeReason,
+    },
+
+    State(PlaybackState),
 
-    NowPlaying(String),
+    NowPlaying {
+        id: String,
+    },
     NowPaused,
+    TrackTransition {
+        from: Option<String>,
+        to: String,
+        gapless: bool,
+    },
+    TrackChanged {
+        id: String,
+        metadata: RecordingMetadata,
+        duration: Option<Duration>,
+    },
+    Volume {
+        volume: f32,
+    },
+    Speed {
+        speed: f32,
+    },
 
     Seek(Duration),
     CurrentTime(Duration),
 
-    Queue(Vec<String>),
+    PlaybackError {
+        reason: PlaybackErrorReason,
+        recoverable: bool,
+        #[serde(default)]
+        recording_id: Option<String>,
+    },
+
+    Queue {
+        queue: Vec<String>,
+    },
+    Shuffle {
+        enable: bool,
+        seed: Option<u64>,
+    },
+    Queues {
+        queues: Vec<NamedQueue>,
+    },
+    History {
+        ids: Vec<String>,
+    },
+    QueueExtended {
+        queue: Vec<String>,
+        auto_added: Vec<String>,
+    },
+    QueuePage {
+        total: usize,
+        offset: usize,
+        items: Vec<String>,
+    },
+    QueueSpliced {
+        total: usize,
+        offset: usize,
+        removed: usize,
+        inserted: Vec<String>,
+    },
+    QueueEnded {
+        action_taken: OnQueueEnd,
+    },
+    QueueRejected {
+        rejected: Vec<(String, QueueRejectReason)>,
+    },
+    QueueInserted {
+        index: usize,
+        recordings: Vec<String>,
+    },
 
     LoopMode(LoopMode),
+    QueueModel(QueueModel),
+    OnQueueEnd(OnQueueEnd),
+    SmoothLevelTransition {
+        enabled: bool,
+    },
+    PauseFade(Duration),
+    StopAfterCurrent {
+        enabled: bool,
+    },
 
     RecordingMetadata(RecordingMetadata),
+    RecordingDuration {
+        id: String,
+        duration: Duration,
+    },
+    RecordingStats {
+        id: String,
+        play_count: u64,
+        last_played: Option<SystemTime>,
+    },
     RecordingFile((String, Vec<u8>)),
+    RecordingStored {
+        id: String,
+        sha256: String,
+    },
+    RecordingFileChunk {
+        id: String,
+        offset: u64,
+        total_len: u64,
+        data: Vec<u8>,
+    },
+    TransferProgress {
+        id: String,
+        received: u64,
+    },
+    ExternalFileMissing {
+        id: String,
+        path: PathBuf,
+    },
+    PreviewClip {
+        id: String,
+        position: Duration,
+        sample_rate: u32,
+        data: Vec<u8>,
+    },
+    Changes {
+        sequence: u64,
+        deltas: Vec<StateDelta>,
+        snapshot_required: bool,
+    },
+    BatchResult {
+        responses: Vec<EngineResponse>,
+    },
+    RecordingProvenance {
+        id: String,
+        provenance: Vec<ProvenanceEntry>,
+    },
 
-    PlaylistMetadata(PlaylistMetadata),
-
-    Permissions(Vec<Permission>),
-}
+    Waveform {
+        id: String,
+        buckets: Vec<u8>,
+    },
 
-pub enum EngineLocation {
-    Invalid,
-    Internal {
-        ipc_server: IPCServer,
-        command_processor: JoinHandle<()>,
+    PlaylistMetadata {
+        metadata: PlaylistMetadata,
+        #[serde(default)]
+        origin: PlaylistOrigin,
     },
-    Local {
-        ipc_client: IPCClient,
-        command_relay: JoinHandle<()>,
+    Playlists {
+        playlists: Vec<PlaylistMetadata>,
+        #[serde(default)]
+        origin: PlaylistOrigin,
     },
-    Remote {
-        ipc_client: IPCClient,
-        command_relay: JoinHandle<()>,
+    PlaylistValidation {
+        id: String,
+        missing_metadata: Vec<String>,
+        missing_audio: Vec<String>,
+    },
+    PlaylistConflict {
+        id: String,
+        local_modified: u64,
+        remote_modified: u64,
+    },
+    LibraryChanged {
+        kind: LibraryChangeKind,
+        id: String,
     },
-}
 
-pub enum EngineError {
-    AudioInitializationFailed,
-    DatabaseInitializationFailed,
-}
+    StreamChunk(StreamChunk),
+    StreamStopped,
 
-pub enum EngineConnectionStatus {
-    ConnectedLocal,
-    ConnectedRemote,
+    HandOffComplete {
+        target: String,
+    },
 
-    Disconnected,
-}
+    Permissions {
+        permissions: Vec<Permission>,
+    },
+    PermissionRequest {
+        request_id: Uuid,
+        device: String,
+        name: Option<String>,
+        requested: Vec<Permission>,
+    },
+    PermissionRequestResolved {
+        request_id: Uuid,
+        granted: Vec<Permission>,
+    },
+    Paired {
+        device: String,
+        token: String,
+    },
+    AllowedCommands {
+        commands: Vec<String>,
+    },
 
-pub enum EngineLocalConnectionError {
-    StartFailed,
-}
+    Preview {
+        command: EngineCommand,
+        summary: EffectSummary,
+    },
 
-pub enum EngineRemoteConnectionError {
-    InvalidAddress,
-    ConnectionFailed,
-}
+    Capabilities {
+        version: String,
+        #[serde(default)]
+        low_memory: bool,
+        #[serde(default)]
+        idle_timeout: Option<Duration>,
+        #[serde(default)]
+        parked: bool,
+        #[serde(default)]
+        queue_page_threshold: Option<usize>,
+    },
+    Idle {
+        action: IdleAction,
+    },
+    NetworkState(NetworkState),
+    StorageState(StorageState),
+    ListenerStatus(ListenerStatus),
+    AudioStatus(AudioStatus),
+    AudioUnderruns {
+        underruns_per_minute: f32,
+        threshold: f32,
+    },
+    Diagnostics {
+        uptime: Duration,
+        version: String,
+        location_history: Vec<(u64, EngineConnectionStatus)>,
+        last_errors: Vec<(u64, String)>,
+        metrics: MetricsSnapshot,
+        settings_redacted: EngineSettings,
+        audio_status: AudioStatus,
+        library_stats: LibraryStats,
+    },
+    HookFailed {
+        hook: String,
+        reason: HookFailureReason,
+    },
+    Health {
+        audio: HealthState,
+        database: HealthState,
+        network: HealthState,
+        details: Vec<String>,
+    },
+    SupportedFormats {
+        codecs: Vec<String>,
+    },
+    Metrics(MetricsSnapshot),
 
-pub enum EngineCommandError {
-    Disconnected,
-}
+    HistoryExport {
+        format: ExportFormat,
+        offset: usize,
+        total_len: usize,
+        data: Vec<u8>,
+    },
 
-impl Engine {
-    pub fn create() -> Result<
-        (
-            Engine,
+    AuditEvent(AuditEntry),
+    AuditLog {
+        entries: Vec<AuditEntry>,
+    },
+
+    Clients {
+        clients: Vec<ClientUsage>,
+    },
+    TransferBudgetWarning {
+        device_id: String,
+        used: u64,
+        budget: u64,
+    },
+
+    JobStarted {
+        job_id: Uuid,
+    },
+    JobProgress {
+        job_id: Uuid,
+        completed: usize,
+        total: usize,
+    },
+    JobFinished {
+        job_id: Uuid,
+        cancelled: bool,
+    },
+    LibraryVerified {
+        job_id: Uuid,
+        missing: Vec<String>,
+        mismatched: Vec<String>,
+    },
+    LibraryRefreshed {
+        job_id: Uuid,
+        merged: Vec<(String, String)>,
+        orphaned: Vec<String>,
+    },
+    OrphanedRecordings {
+        recordings: Vec<RecordingMetadata>,
+    },
+    LooseFilesAdopted {
+        summary: AdoptionSummary,
+    },
+    Trash {
+        entries: Vec<TrashEntry>,
+    },
+    TrashEmptied {
+        removed: Vec<String>,
+        bytes_reclaimed: u64,
+    },
+
+    RecordingTags {
+        id: String,
+        tags: Vec<String>,
+    },
+    LocalMetadataEdited {
+        edited: Vec<String>,
+        rejected: Vec<(String, MetadataEditRejectReason)>,
+    },
+    Tags {
+        tags: Vec<(String, usize)>,
+    },
+    Recordings {
+        recordings: Vec<String>,
+        #[serde(default)]
+        relationships: Vec<RecordingRelationship>,
+    },
+}
+
+impl EngineResponse {
+    pub fn job_id(&self) -> Option<Uuid> {
+        match self {
+            EngineResponse::JobStarted { job_id }
+            | EngineResponse::JobProgress { job_id, .. }
+            | EngineResponse::JobFinished { job_id, .. }
+            | EngineResponse::LibraryVerified { job_id, .. }
+            | EngineResponse::LibraryRefreshed { job_id, .. } => Some(*job_id),
+            _ => None,
+        }
+    }
+}
+
+impl EngineCommand {
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineCommand::None => "None",
+            EngineCommand::Goodbye => "Goodbye",
+            EngineCommand::Hello { .. } => "Hello",
+            EngineCommand::GetState => "GetState",
+            EngineCommand::GetPosition => "GetPosition",
+            EngineCommand::Play(_) => "Play",
+            EngineCommand::PlayFromStart { .. } => "PlayFromStart",
+            EngineCommand::Pause => "Pause",
+            EngineCommand::Stop => "Stop",
+            EngineCommand::Next => "Next",
+            EngineCommand::Previous => "Previous",
+            EngineCommand::PlayQueueIndex { .. } => "PlayQueueIndex",
+            EngineCommand::Seek(_) => "Seek",
+            EngineCommand::SeekPercent { .. } => "SeekPercent",
+            EngineCommand::SeekRelative { .. } => "SeekRelative",
+            EngineCommand::Queue(_) => "Queue",
+            EngineCommand::QueuePlaylist { .. } => "QueuePlaylist",
+            EngineCommand::QueueRelease { .. } => "QueueRelease",
+            EngineCommand::QueueAfterCurrentGroup { .. } => "QueueAfterCurrentGroup",
+            EngineCommand::QueueNext { .. } => "QueueNext",
+            EngineCommand::QueueTo { .. } => "QueueTo",
+            EngineCommand::GetQueues => "GetQueues",
+            EngineCommand::GetHistory { .. } => "GetHistory",
+            EngineCommand::QueueRange { .. } => "QueueRange",
+            EngineCommand::ShuffleQueue { .. } => "ShuffleQueue",
+            EngineCommand::ClearQueue { .. } => "ClearQueue",
+            EngineCommand::QueueRemove(_) => "QueueRemove",
+            EngineCommand::QueueMove { .. } => "QueueMove",
+            EngineCommand::SetRadioMode { .. } => "SetRadioMode",
+            EngineCommand::LoopMode(_) => "LoopMode",
+            EngineCommand::QueueModel(_) => "QueueModel",
+            EngineCommand::OnQueueEnd(_) => "OnQueueEnd",
+            EngineCommand::SmoothLevelTransition { .. } => "SmoothLevelTransition",
+            EngineCommand::PauseFade(_) => "PauseFade",
+            EngineCommand::StopAfterCurrent { .. } => "StopAfterCurrent",
+            EngineCommand::RecordingMetadata { .. } => "RecordingMetadata",
+            EngineCommand::RecordingDuration { .. } => "RecordingDuration",
+            EngineCommand::RecordingStats { .. } => "RecordingStats",
+            EngineCommand::RecordingFile(_) => "RecordingFile",
+            EngineCommand::RecordingFileChunk { .. } => "RecordingFileChunk",
+            EngineCommand::TransferAck { .. } => "TransferAck",
+            EngineCommand::SendRecording(_) => "SendRecording",
+            EngineCommand::SendVerifiedRecording { .. } => "SendVerifiedRecording",
+            EngineCommand::SendRecordingChunk { .. } => "SendRecordingChunk",
+            EngineCommand::LinkExternalFile { .. } => "LinkExternalFile",
+            EngineCommand::RecordingProvenance { .. } => "RecordingProvenance",
+            EngineCommand::Waveform { .. } => "Waveform",
+            EngineCommand::PlaylistMetadata { .. } => "PlaylistMetadata",
+            EngineCommand::SetPlaylistMetadata(_) => "SetPlaylistMetadata",
+            EngineCommand::ListPlaylists { .. } => "ListPlaylists",
+            EngineCommand::CachePlaylist { .. } => "CachePlaylist",
+            EngineCommand::ValidatePlaylist { .. } => "ValidatePlaylist",
+            EngineCommand::ResolvePlaylistConflict { .. } => "ResolvePlaylistConflict",
+            EngineCommand::SetVolume { .. } => "SetVolume",
+            EngineCommand::Mute { .. } => "Mute",
+            EngineCommand::GetVolume => "GetVolume",
+            EngineCommand::SetSpeed { .. } => "SetSpeed",
+            EngineCommand::GetSpeed => "GetSpeed",
+            EngineCommand::PlayCue { .. } => "PlayCue",
+            EngineCommand::FollowPlayback { .. } => "FollowPlayback",
+            EngineCommand::StreamListen { .. } => "StreamListen",
+            EngineCommand::LibraryEvents { .. } => "LibraryEvents",
+            EngineCommand::HandOff { .. } => "HandOff",
+            EngineCommand::AdoptSession(_) => "AdoptSession",
+            EngineCommand::GetPermissions => "GetPermissions",
+            EngineCommand::SetPermissions(_) => "SetPermissions",
+            EngineCommand::RequestPermissions { .. } => "RequestPermissions",
+            EngineCommand::ResolvePermissionRequest { .. } => "ResolvePermissionRequest",
+            EngineCommand::AllowedCommands => "AllowedCommands",
+            EngineCommand::UpdateSettings(_) => "UpdateSettings",
+            EngineCommand::ClearSavedState { .. } => "ClearSavedState",
+            EngineCommand::Capabilities => "Capabilities",
+            EngineCommand::HealthCheck => "HealthCheck",
+            EngineCommand::SupportedFormats => "SupportedFormats",
+            EngineCommand::Metrics { .. } => "Metrics",
+            EngineCommand::ResetMetrics => "ResetMetrics",
+            EngineCommand::AudioStatus => "AudioStatus",
+            EngineCommand::DiagnosticBundle => "DiagnosticBundle",
+            EngineCommand::ExportHistory { .. } => "ExportHistory",
+            EngineCommand::AuditLog { .. } => "AuditLog",
+            EngineCommand::ListClients => "ListClients",
+            EngineCommand::SetDeviceTransferBudget { .. } => "SetDeviceTransferBudget",
+            EngineCommand::VerifyLibrary { .. } => "VerifyLibrary",
+            EngineCommand::RefreshLibrary { .. } => "RefreshLibrary",
+            EngineCommand::CancelJob { .. } => "CancelJob",
+            EngineCommand::ListOrphanedRecordings => "ListOrphanedRecordings",
+            EngineCommand::AdoptLooseFiles => "AdoptLooseFiles",
+            EngineCommand::DeleteRecording { .. } => "DeleteRecording",
+            EngineCommand::DeletePlaylist { .. } => "DeletePlaylist",
+            EngineCommand::ListTrash => "ListTrash",
+            EngineCommand::RestoreFromTrash { .. } => "RestoreFromTrash",
+            EngineCommand::EmptyTrash { .. } => "EmptyTrash",
+            EngineCommand::TagRecording { .. } => "TagRecording",
+            EngineCommand::EditLocalMetadata { .. } => "EditLocalMetadata",
+            EngineCommand::SetSpokenWord { .. } => "SetSpokenWord",
+            EngineCommand::ListTags => "ListTags",
+            EngineCommand::RecordingsByTag { .. } => "RecordingsByTag",
+            EngineCommand::ListRecordings { .. } => "ListRecordings",
+            EngineCommand::RelatedRecordings { .. } => "RelatedRecordings",
+            EngineCommand::PreviewAt { .. } => "PreviewAt",
+            EngineCommand::ChangesSince { .. } => "ChangesSince",
+            EngineCommand::Batch { .. } => "Batch",
+        }
+    }
+}
+
+pub enum EngineLocation {
+    Invalid,
+    Internal {
+        ipc_server: IPCServer,
+        command_processor: EngineTask,
+    },
+    Local {
+        ipc_client: IPCClient,
+        command_relay: EngineTask,
+    },
+    Remote {
+        ipc_client: IPCClient,
+        command_relay: EngineTask,
+    },
+}
+
+pub struct EngineTask {
+    handle: JoinHandle<()>,
+    cancellation: CancellationToken,
+}
+
+impl EngineTask {
+    async fn shutdown(self) {
+        self.cancellation.cancel();
+
+        let _ = self.handle.await;
+    }
+}
+
+/// Everything a command handler needs to answer the command it was given.
+#[derive(Clone, Copy)]
+struct CommandContext<'a> {
+    internal: bool,
+    uuid: Uuid,
+    database: &'a Database,
+    sequencer: &'a Sequencer,
+    diagnostics: &'a DiagnosticLog,
+    internal_response_sender: &'a broadcast::Sender<EngineResponse>,
+    response_sender: &'a ChangeFeed,
+}
+
+pub struct JobHandle {
+    job_id: Uuid,
+    command_sender: broadcast::Sender<EngineCommand>,
+    response_receiver: broadcast::Receiver<EngineResponse>,
+    finished: bool,
+}
+
+impl JobHandle {
+    pub fn id(&self) -> Uuid {
+        self.job_id
+    }
+
+    pub fn cancel(&self) {
+        let _ = self.command_sender.send(EngineCommand::CancelJob {
+            job_id: self.job_id,
+        });
+    }
+
+    pub async fn progress(&mut self) -> Option<EngineResponse> {
+        while !self.finished {
+            let response = match self.response_receiver.recv().await {
+                Ok(response) => response,
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            };
+
+            if response.job_id() != Some(self.job_id) {
+                continue;
+            }
+
+            if let EngineResponse::JobFinished { .. } = response {
+                self.finished = true;
+            }
+
+            return Some(response);
+        }
+
+        None
+    }
+}
+
+pub enum EngineError {
+    AudioInitializationFailed,
+    DatabaseInitializationFailed,
+    InvalidConfiguration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde()]
+pub enum EngineConnectionStatus {
+    ConnectedLocal,
+    ConnectedLocalClient,
+    ConnectedRemote,
+
+    Degraded,
+    Disconnected,
+}
+
+pub enum EngineLocalConnectionError {
+    StartFailed,
+}
+
+pub enum EngineRemoteConnectionError {
+    InvalidAddress,
+    ConnectionFailed,
+}
+
+pub enum EngineCommandError {
+    Disconnected,
+}
+
+impl Engine {
+    pub async fn create() -> Result<
+        (
+            Engine,
+            broadcast::Sender<EngineCommand>,
+            broadcast::Receiver<EngineResponse>,
+        ),
+        EngineError,
+    > {
+        Engine::create_with_config(EngineConfig::default()).await
+    }
+
+    pub async fn create_with_config(
+        config: EngineConfig,
+    ) -> Result<
+        (
+            Engine,
             broadcast::Sender<EngineCommand>,
             broadcast::Receiver<EngineResponse>,
         ),
         EngineError,
     > {
-        let (engine_command_sender, _) = broadcast::channel::<EngineCommand>(16);
+        let channel_capacity = if config.low_memory {
+            LOW_MEMORY_CHANNEL_CAPACITY
+        } else {
+            CHANNEL_CAPACITY
+        };
+
+        let (engine_command_sender, _) = broadcast::channel::<EngineCommand>(channel_capacity);
         let (engine_response_sender, engine_response_receiver) =
-            broadcast::channel::<EngineResponse>(16);
+            broadcast::channel::<EngineResponse>(channel_capacity);
+
+        let local_address = config
+            .socket
+            .clone()
+            .unwrap_or_else(|| LOCAL_ADDRESS.to_owned());
+        let socket_mode = config.socket_mode.unwrap_or(SOCKET_MODE);
+
+        let diagnostics = DiagnosticLog::default();
 
-        let Ok(database) = Database::new() else {
+        if let Some((ipc_client, receiver, sender)) =
+            probe_local_engine(local_address.clone(), diagnostics.clone()).await
+        {
+            let new_engine = Engine {
+                sequencer: None,
+                database: None,
+                location: Mutex::new(EngineLocation::Invalid),
+                status_sender: Arc::new(watch::Sender::new(EngineConnectionStatus::Disconnected)),
+                engine_command_sender: engine_command_sender.clone(),
+                engine_response_sender,
+                local_address: local_address.clone(),
+                socket_mode,
+                low_memory: config.low_memory,
+                transfer_limit: config.transfer_limit.unwrap_or(TRANSFER_LIMIT),
+
+                idle_timeout: config.idle_timeout,
+                idle_action: config.idle_action,
+                underrun_threshold: config
+                    .underrun_warning_threshold
+                    .unwrap_or(UNDERRUN_WARNING_THRESHOLD),
+                queue_page_threshold: config.queue_page_threshold.unwrap_or(QUEUE_PAGE_THRESHOLD),
+                trash_retention: config.trash_retention.unwrap_or(TRASH_RETENTION),
+                adopt_loose_files: config.adopt_loose_files,
+                hooks: config.hooks.clone(),
+
+                settings: configured_settings(&config),
+                diagnostics,
+
+                state_mirror: Mutex::new(None),
+            };
+
+            let command_relay = new_engine.start_command_relay(local_address, receiver, sender);
+
+            new_engine
+                .replace_location(
+                    &mut *new_engine.location.lock().await,
+                    EngineLocation::Local {
+                        ipc_client,
+                        command_relay,
+                    },
+                )
+                .await;
+
+            return Ok((new_engine, engine_command_sender, engine_response_receiver));
+        }
+
+        let Ok(provider) = MetadataProvider::new(
+            config.musicbrainz_base_url.clone(),
+            config
+                .musicbrainz_user_agent
+                .clone()
+                .unwrap_or_else(|| DEFAULT_USER_AGENT.to_owned()),
+        ) else {
+            return Err(EngineError::InvalidConfiguration);
+        };
+
+        let Ok(database) = Database::new(&config.storage, provider) else {
             return Err(EngineError::DatabaseInitializationFailed);
         };
-        let Ok(sequencer) = Sequencer::new(database.clone()) else {
+
+        database
+            .set_storage_reserve(config.storage_reserve.unwrap_or(STORAGE_RESERVE))
+            .await;
+        let Ok(sequencer) = Sequencer::new(
+            database.clone(),
+            config.resume_after_suspend,
+            config.low_memory,
+            config.max_queue_length.unwrap_or(MAX_QUEUE_LENGTH),
+            config.history_capacity.unwrap_or(HISTORY_CAPACITY),
+            config
+                .min_playback_interval
+                .unwrap_or(MIN_PLAYBACK_INTERVAL),
+            config.audio_output.clone(),
+        ) else {
             return Err(EngineError::AudioInitializationFailed);
         };
 
-        let mut new_engine = Engine {
-            sequencer,
-            database,
-            location: EngineLocation::Invalid,
+        // The saved queue is laid out for the queue model, so the model has to
+        // be in place before the state is restored.
+        if let Some(model) = database.get_setting(QUEUE_MODEL_SETTING).await {
+            sequencer.set_queue_model(model).await;
+        }
+
+        let mut restored_id = None;
+
+        if database
+            .get_setting::<bool>(PERSIST_PLAYER_STATE_SETTING)
+            .await
+            != Some(false)
+        {
+            if let Some(saved_state) = database
+                .get_setting::<SavedPlayerState>(PLAYER_STATE_SETTING)
+                .await
+            {
+                restored_id = saved_state.recording_id.clone();
+
+                let _ = sequencer.restore_saved_state(saved_state).await;
+            }
+        }
+
+        let warm_up_sequencer = sequencer.clone();
+        let warm_up_database = database.clone();
+
+        tokio::spawn(async move {
+            if let Some(on_queue_end) = warm_up_database.get_setting(ON_QUEUE_END_SETTING).await {
+                warm_up_sequencer.set_on_queue_end(on_queue_end).await;
+            }
+
+            if let Some(enable) = warm_up_database
+                .get_setting(SMOOTH_LEVEL_TRANSITION_SETTING)
+                .await
+            {
+                warm_up_sequencer.set_smooth_level_transition(enable).await;
+            }
+
+            if let Some(fade) = warm_up_database.get_setting(PAUSE_FADE_SETTING).await {
+                warm_up_sequencer.set_pause_fade(fade).await;
+            }
+
+            if let Some(id) = restored_id {
+                warm_up_sequencer.warm_up(id).await;
+            }
+        });
+
+        let settings = configured_settings(&config);
+
+        let state_mirror = config.state_file.map(|state_file| {
+            start_state_mirror(
+                sequencer.clone(),
+                database.clone(),
+                state_file,
+                config.state_file_interval.unwrap_or(STATE_FILE_INTERVAL),
+            )
+        });
+
+        let new_engine = Engine {
+            sequencer: Some(sequencer),
+            database: Some(database),
+            location: Mutex::new(EngineLocation::Invalid),
+            status_sender: Arc::new(watch::Sender::new(EngineConnectionStatus::Disconnected)),
             engine_command_sender: engine_command_sender.clone(),
             engine_response_sender,
+            local_address,
+            socket_mode,
+            low_memory: config.low_memory,
+            transfer_limit: config.transfer_limit.unwrap_or(TRANSFER_LIMIT),
+
+            idle_timeout: config.idle_timeout,
+            idle_action: config.idle_action,
+            underrun_threshold: config
+                .underrun_warning_threshold
+                .unwrap_or(UNDERRUN_WARNING_THRESHOLD),
+            queue_page_threshold: config.queue_page_threshold.unwrap_or(QUEUE_PAGE_THRESHOLD),
+            trash_retention: config.trash_retention.unwrap_or(TRASH_RETENTION),
+            adopt_loose_files: config.adopt_loose_files,
+            hooks: config.hooks,
+
+            settings,
+            diagnostics,
+
+            state_mirror: Mutex::new(state_mirror),
         };
 
-        let _ = new_engine.connect_to_local();
+        let _ = new_engine.connect_to_local().await;
 
         Ok((new_engine, engine_command_sender, engine_response_receiver))
     }
 
     fn start_command_processor(
-        &mut self,
+        &self,
+        database: Database,
+        sequencer: Sequencer,
         mut command_receiver: mpsc::Receiver<(EngineCommand, Uuid)>,
-        response_sender: broadcast::Sender<(EngineResponse, Uuid)>,
-    ) -> JoinHandle<()> {
+        response_sender: ChangeFeed,
+        stream_sender: StreamFeed,
+        mut listener_monitor: ListenerMonitor,
+    ) -> EngineTask {
         let mut internal_command_receiver = self.engine_command_sender.subscribe();
         let internal_response_sender = self.engine_response_sender.clone();
 
-        let database = self.database.clone();
-        let sequencer = self.sequencer.clone();
+        let mut sequencer_event_receiver = sequencer.subscribe();
+        let mut database_event_receiver = database.subscribe();
 
-        tokio::spawn(async move {
-            let mut current_user_permissions = Vec::<Permission>::new();
+        let low_memory = self.low_memory;
+        let transfer_limit = self.transfer_limit;
+        let idle_timeout = self.idle_timeout;
+        let idle_action = self.idle_action;
+        let underrun_threshold = self.underrun_threshold;
+        let queue_page_threshold = self.queue_page_threshold;
+        let trash_retention = self.trash_retention;
+        let adopt_loose_files = self.adopt_loose_files;
+        let hooks = self.hooks.clone();
+        let mut engine_settings = self.settings.clone();
+        let diagnostics = self.diagnostics.clone();
+        let local_address = self.local_address.clone();
+        let status_sender = self.status_sender.clone();
+
+        let cancellation = CancellationToken::new();
+        let processor_cancellation = cancellation.clone();
+
+        let handle = tokio::spawn(async move {
+            let mut stream_listeners = StreamListeners::default();
+
+            let mut library_listeners = Vec::<Uuid>::new();
+
+            let jobs = Jobs::default();
+
+            let (permission_expiry_sender, mut permission_expiry_receiver) =
+                mpsc::channel::<Uuid>(CHANNEL_CAPACITY);
+            let mut connections = Connections::load(&database, permission_expiry_sender).await;
+
+            let mut last_activity = tokio::time::Instant::now();
+            let mut idle = false;
+
+            let mut preview_requests = HashMap::<Uuid, std::time::Instant>::new();
+            let previews = Arc::new(Mutex::new(
+                HashMap::<(String, Duration), Vec<(bool, Uuid)>>::new(),
+            ));
+
+            let mut storage_check = tokio::time::interval(STORAGE_CHECK_INTERVAL);
+
+            let mut underrun_check = tokio::time::interval(UNDERRUN_CHECK_INTERVAL);
+            let mut underrun_window = UnderrunWindow::default();
+            let mut underruns_per_minute = 0.0;
+
+            let mut position_broadcast = tokio::time::interval(POSITION_BROADCAST_INTERVAL);
+
+            let mut trash_purge = tokio::time::interval(TRASH_PURGE_INTERVAL);
+
+            let mut player_state_save = tokio::time::interval(PLAYER_STATE_SAVE_INTERVAL);
+            let mut persist_player_state = database
+                .get_setting::<bool>(PERSIST_PLAYER_STATE_SETTING)
+                .await
+                != Some(false);
+            let mut saved_player_state = None::<SavedPlayerState>;
+
+            if adopt_loose_files {
+                let adoption_database = database.clone();
+                let adoption_internal_response_sender = internal_response_sender.clone();
+                let adoption_response_sender = response_sender.clone();
+
+                tokio::spawn(async move {
+                    let summary = adoption_database.adopt_loose_files().await;
+
+                    route_response(
+                        false,
+                        &adoption_internal_response_sender,
+                        &adoption_response_sender,
+                        EngineResponse::LooseFilesAdopted { summary },
+                        Uuid::nil(),
+                    );
+                });
+            }
+
+            if !hooks.is_empty() {
+                let event_hooks = hooks.clone();
+                let mut event_receiver = internal_response_sender.subscribe();
+                let event_failure_sender = internal_response_sender.clone();
+                let event_cancellation = processor_cancellation.clone();
+
+                tokio::spawn(async move {
+                    loop {
+                        let response = tokio::select! {
+                            _ = event_cancellation.cancelled() => return,
+                            val = event_receiver.recv() => match val {
+                                Ok(EngineResponse::HookFailed { .. }) => continue,
+                                Ok(response) => response,
+                                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                                Err(broadcast::error::RecvError::Closed) => return,
+                            },
+                        };
+
+                        event_hooks.on_event(response, &event_failure_sender).await;
+                    }
+                });
+            }
+
+            let mut latencies = LatencyRecorder::default();
+            let mut in_flight: Option<(&'static str, std::time::Instant)> = None;
+
+            let mut batch_pending = VecDeque::<(EngineCommand, Uuid, bool)>::new();
+            let mut batch_owner: Option<Uuid> = None;
+
+            let mut broadcast_queue = Vec::<String>::new();
+
+            let mut collation = database
+                .get_setting::<Collation>(COLLATION_SETTING)
+                .await
+                .unwrap_or_default();
+
+            let mut transfers = Transfers::load(&database, transfer_limit).await;
 
             loop {
-                let (command, uuid, internal) = tokio::select! {
+                if let Some((kind, started)) = in_flight.take() {
+                    latencies.record(kind, started.elapsed());
+                }
+
+                if batch_pending.is_empty() {
+                    if let Some(owner) = batch_owner.take() {
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::BatchResult {
+                                responses: response_sender.end_capture(),
+                            },
+                            owner,
+                        );
+                    }
+                }
+
+                let (command, uuid, internal) = match batch_pending.pop_front() {
+                    Some((command, owner, internal)) => (command, owner, internal),
+                    None => tokio::select! {
+                    _ = processor_cancellation.cancelled() => {
+                        return;
+                    }
                     val = internal_command_receiver.recv() => {
                         let Ok(command) = val else {
                             continue;
@@ -201,500 +1669,2805 @@ impl Engine {
                             continue;
                         };
 
-                        (command, uuid, false)
+                        (command, uuid, false)
+                    }
+                    val = permission_expiry_receiver.recv() => {
+                        let Some(request_id) = val else {
+                            continue;
+                        };
+
+                        if !connections.permission_requests.contains_key(&request_id) {
+                            continue;
+                        }
+
+                        (EngineCommand::ResolvePermissionRequest { request_id, grant: Vec::new() }, Uuid::nil(), true)
+                    }
+                    _ = storage_check.tick() => {
+                        database.storage_state().await;
+
+                        continue;
+                    }
+                    _ = trash_purge.tick() => {
+                        database.empty_trash(Some(trash_retention)).await;
+
+                        continue;
+                    }
+                    _ = player_state_save.tick() => {
+                        if !persist_player_state {
+                            continue;
+                        }
+
+                        let player_state = sequencer.saved_state().await;
+
+                        if saved_player_state.as_ref() != Some(&player_state) {
+                            database.set_setting(PLAYER_STATE_SETTING, &player_state).await;
+
+                            saved_player_state = Some(player_state);
+                        }
+
+                        continue;
+                    }
+                    _ = underrun_check.tick() => {
+                        let was_exceeded = underruns_per_minute > underrun_threshold;
+
+                        underruns_per_minute = underrun_window
+                            .observe(std::time::Instant::now(), sequencer.underruns());
+
+                        if underruns_per_minute > underrun_threshold && !was_exceeded {
+                            route_response(
+                                false,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::AudioUnderruns {
+                                    underruns_per_minute,
+                                    threshold: underrun_threshold,
+                                },
+                                Uuid::nil(),
+                            );
+                        }
+
+                        continue;
+                    }
+                    _ = position_broadcast.tick() => {
+                        if sequencer.is_advancing().await {
+                            route_response(
+                                false,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::CurrentTime(sequencer.position().await),
+                                Uuid::nil(),
+                            );
+                        }
+
+                        continue;
+                    }
+                    listener_status = listener_monitor.changed() => {
+                        if listener_status == ListenerStatus::Failed {
+                            status_sender.send_replace(EngineConnectionStatus::Degraded);
+                            diagnostics.record_location(EngineConnectionStatus::Degraded);
+                        }
+
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::ListenerStatus(listener_status),
+                            Uuid::nil(),
+                        );
+
+                        continue;
+                    }
+                    val = sequencer_event_receiver.recv() => {
+                        let Ok(event) = val else {
+                            continue;
+                        };
+
+                        last_activity = tokio::time::Instant::now();
+                        idle = false;
+
+                        events::forward_sequencer_event(
+                            event,
+                            &database,
+                            &internal_response_sender,
+                            &response_sender,
+                            &mut broadcast_queue,
+                        );
+
+                        continue;
+                    }
+                    val = database_event_receiver.recv() => {
+                        let Ok(event) = val else {
+                            continue;
+                        };
+
+                        events::forward_database_event(
+                            event,
+                            &sequencer,
+                            &diagnostics,
+                            &internal_response_sender,
+                            &response_sender,
+                            &library_listeners,
+                        )
+                        .await;
+
+                        continue;
+                    }
+                    _ = idle_deadline(idle_timeout, last_activity), if !idle => {
+                        last_activity = tokio::time::Instant::now();
+
+                        if !connections.connection_devices.is_empty()
+                            || sequencer.get_playing().await.is_some()
+                            || !jobs.lock().await.is_empty()
+                        {
+                            continue;
+                        }
+
+                        idle = true;
+
+                        database.flush().await;
+
+                        if idle_action == IdleAction::Park {
+                            sequencer.park().await;
+                        }
+
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Idle { action: idle_action },
+                            Uuid::nil(),
+                        );
+
+                        continue;
+                    }
+                    update = stream_listeners.next() => {
+                        match update {
+                            StreamUpdate::Audio(chunks) => {
+                                for chunk in chunks {
+                                    let _ = stream_sender.send(chunk);
+                                }
+                            }
+                            StreamUpdate::FellBehind(listeners) => {
+                                for listener in listeners {
+                                    let _ = response_sender.send((EngineResponse::StreamStopped, listener));
+                                }
+                            }
+                        }
+
+                        continue;
+                    }
+                    },
+                };
+
+                last_activity = tokio::time::Instant::now();
+                idle = false;
+
+                in_flight = Some((command.kind(), std::time::Instant::now()));
+
+                if !internal {
+                    connections
+                        .connection_devices
+                        .entry(uuid)
+                        .or_insert_with(|| uuid.to_string());
+                }
+
+                if let EngineCommand::Goodbye = command {
+                    // Paired devices keep their partial uploads so they can
+                    // resume after reconnecting.
+                    if !internal && !connections.is_paired(uuid) {
+                        let transfer_database = database.clone();
+                        let owner = uuid.to_string();
+
+                        tokio::spawn(async move {
+                            transfer_database.discard_transfers(&owner).await;
+                        });
+                    }
+
+                    stream_listeners.leave(uuid);
+                    library_listeners.retain(|listener| *listener != uuid);
+                    connections.forget(uuid);
+                    transfers.forget(uuid);
+                    preview_requests.remove(&uuid);
+                }
+
+                let user_permissions = connections.permissions(uuid);
+
+                let context = CommandContext {
+                    internal,
+                    uuid,
+                    database: &database,
+                    sequencer: &sequencer,
+                    diagnostics: &diagnostics,
+                    internal_response_sender: &internal_response_sender,
+                    response_sender: &response_sender,
+                };
+
+                let command = if hooks.is_empty() {
+                    command
+                } else {
+                    let hook_context = HookContext {
+                        connection: uuid,
+                        internal,
+                        device: connections.connection_devices.get(&uuid).cloned(),
+                        permissions: user_permissions.clone(),
+                    };
+
+                    match hooks
+                        .on_command(command, &hook_context, &internal_response_sender)
+                        .await
+                    {
+                        Ok(command) => command,
+                        Err((command, reason)) => {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope { command, reason },
+                                uuid,
+                            );
+
+                            continue;
+                        }
+                    }
+                };
+
+                let Some(command) = access::authorize(command, context, &connections).await else {
+                    continue;
+                };
+
+                match command {
+                    EngineCommand::None | EngineCommand::Goodbye => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::Hello {
+                        ref device,
+                        ref token,
+                        ..
+                    } => {
+                        if !internal {
+                            if let Some(name) = device {
+                                connections.connection_names.insert(uuid, name.clone());
+                            }
+
+                            if let Some(paired) = token.as_ref().and_then(|token| {
+                                connections
+                                    .device_tokens
+                                    .get(&sha256::digest(token.as_str()))
+                            }) {
+                                connections.connection_devices.insert(uuid, paired.clone());
+                            }
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::GetState => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::State(resolve_state(&sequencer, &database).await),
+                            uuid,
+                        );
+                    }
+                    EngineCommand::GetPosition => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::CurrentTime(sequencer.position().await),
+                            uuid,
+                        );
+                    }
+                    EngineCommand::Play(id) => {
+                        let Some(id) = id else {
+                            match sequencer.resume().await {
+                                Ok(playing) => route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    match playing {
+                                        Some(id) => EngineResponse::NowPlaying { id },
+                                        None => EngineResponse::NowPaused,
+                                    },
+                                    Uuid::nil(),
+                                ),
+                                Err(error) => route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        command: EngineCommand::Play(None),
+                                        reason: match error {
+                                            SequencerError::MissingAudioFile => {
+                                                NopeReason::NotFound
+                                            }
+                                            _ => NopeReason::Unspecified,
+                                        },
+                                    },
+                                    uuid,
+                                ),
+                            }
+
+                            continue;
+                        };
+
+                        match sequencer.play(id.clone(), true).await {
+                            Ok(resumed) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    if let Some(id) = sequencer.get_playing().await {
+                                        EngineResponse::NowPlaying { id }
+                                    } else {
+                                        EngineResponse::NowPaused
+                                    },
+                                    Uuid::nil(),
+                                );
+
+                                if let Some(position) = resumed {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Seek(position),
+                                        Uuid::nil(),
+                                    );
+                                }
+
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    queue_broadcast(
+                                        sequencer.get_queue().await,
+                                        queue_page_threshold,
+                                        &mut broadcast_queue,
+                                    ),
+                                    Uuid::nil(),
+                                );
+                            }
+                            Err(error) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        command: EngineCommand::Play(Some(id)),
+                                        reason: match error {
+                                            SequencerError::MissingAudioFile => {
+                                                NopeReason::NotFound
+                                            }
+                                            _ => NopeReason::Unspecified,
+                                        },
+                                    },
+                                    uuid,
+                                );
+                            }
+                        }
+                    }
+                    EngineCommand::PlayFromStart { ref id } => {
+                        match sequencer.play(id.clone(), false).await {
+                            Ok(_) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    if let Some(id) = sequencer.get_playing().await {
+                                        EngineResponse::NowPlaying { id }
+                                    } else {
+                                        EngineResponse::NowPaused
+                                    },
+                                    Uuid::nil(),
+                                );
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    queue_broadcast(
+                                        sequencer.get_queue().await,
+                                        queue_page_threshold,
+                                        &mut broadcast_queue,
+                                    ),
+                                    Uuid::nil(),
+                                );
+                            }
+                            Err(error) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        reason: match error {
+                                            SequencerError::MissingAudioFile => {
+                                                NopeReason::NotFound
+                                            }
+                                            _ => NopeReason::Unspecified,
+                                        },
+                                        command,
+                                    },
+                                    uuid,
+                                );
+                            }
+                        }
+                    }
+                    EngineCommand::Pause => {
+                        sequencer.pause().await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            if let Some(id) = sequencer.get_playing().await {
+                                EngineResponse::NowPlaying { id }
+                            } else {
+                                EngineResponse::NowPaused
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::Stop => {
+                        sequencer.stop().await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::NowPaused,
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::Next => {
+                        if sequencer.next().await.is_ok() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                if let Some(id) = sequencer.get_playing().await {
+                                    EngineResponse::NowPlaying { id }
+                                } else {
+                                    EngineResponse::NowPaused
+                                },
+                                Uuid::nil(),
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                queue_broadcast(
+                                    sequencer.get_queue().await,
+                                    queue_page_threshold,
+                                    &mut broadcast_queue,
+                                ),
+                                Uuid::nil(),
+                            );
+                        } else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Next,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                        }
+                    }
+                    EngineCommand::PlayQueueIndex {
+                        index,
+                        keep_previous,
+                    } => match sequencer.play_index(index, keep_previous).await {
+                        Ok(()) => {
+                            if let Some(id) = sequencer.get_playing().await {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::NowPlaying { id },
+                                    Uuid::nil(),
+                                );
+                            }
+
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                queue_broadcast(
+                                    sequencer.get_queue().await,
+                                    queue_page_threshold,
+                                    &mut broadcast_queue,
+                                ),
+                                Uuid::nil(),
+                            );
+                        }
+                        Err(error) => route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Nope {
+                                command,
+                                reason: match error {
+                                    SequencerError::NotInQueue => NopeReason::InvalidArgument,
+                                    _ => NopeReason::Unspecified,
+                                },
+                            },
+                            uuid,
+                        ),
+                    },
+                    EngineCommand::Previous => {
+                        if sequencer.previous().await.is_ok() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                if let Some(id) = sequencer.get_playing().await {
+                                    EngineResponse::NowPlaying { id }
+                                } else {
+                                    EngineResponse::NowPaused
+                                },
+                                Uuid::nil(),
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                queue_broadcast(
+                                    sequencer.get_queue().await,
+                                    queue_page_threshold,
+                                    &mut broadcast_queue,
+                                ),
+                                Uuid::nil(),
+                            );
+                        } else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Previous,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                        }
+                    }
+                    EngineCommand::Seek(position) => {
+                        if let Ok(position) = sequencer.seek(position).await {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Seek(position),
+                                Uuid::nil(),
+                            );
+                            route_response(
+                                false,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::CurrentTime(position),
+                                Uuid::nil(),
+                            );
+                        } else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Seek(position),
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                        }
+                    }
+                    EngineCommand::SeekRelative { offset_ms } => {
+                        match sequencer.seek_relative(offset_ms).await {
+                            Ok(position) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Seek(position),
+                                    Uuid::nil(),
+                                );
+                                route_response(
+                                    false,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::CurrentTime(position),
+                                    Uuid::nil(),
+                                );
+                            }
+                            Err(_) => route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            ),
+                        }
+                    }
+                    EngineCommand::SeekPercent { fraction } => {
+                        let result = if fraction.is_nan() {
+                            Err(SequencerError::UnknownDuration)
+                        } else {
+                            sequencer.seek_fraction(fraction).await
+                        };
+
+                        match result {
+                            Ok(position) => {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Seek(position),
+                                    Uuid::nil(),
+                                );
+                                route_response(
+                                    false,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::CurrentTime(position),
+                                    Uuid::nil(),
+                                );
+                            }
+                            Err(SequencerError::UnknownDuration) => route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
+                                },
+                                uuid,
+                            ),
+                            Err(error) => {
+                                diagnostics.record_error(command.kind(), &error);
+
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        command,
+                                        reason: NopeReason::Unspecified,
+                                    },
+                                    uuid,
+                                );
+                            }
+                        }
+                    }
+                    EngineCommand::Queue(recording_ids) => {
+                        let Some(recording_ids) = recording_ids else {
+                            let queue = sequencer.get_queue().await;
+
+                            if queue.len() > queue_page_threshold {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::QueuePage {
+                                        total: queue.len(),
+                                        offset: 0,
+                                        items: queue
+                                            .into_iter()
+                                            .take(queue_page_threshold)
+                                            .collect(),
+                                    },
+                                    uuid,
+                                );
+
+                                continue;
+                            }
+
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                queue_broadcast(queue, queue_page_threshold, &mut broadcast_queue),
+                                Uuid::nil(),
+                            );
+
+                            continue;
+                        };
+
+                        let Ok(not_queued) = sequencer.add_queue(recording_ids.clone()).await
+                        else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Queue(Some(recording_ids)),
+                                    reason: NopeReason::Unspecified,
+                                },
+                                Uuid::nil(),
+                            );
+
+                            continue;
+                        };
+
+                        if not_queued.len() != 0 {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Queue(Some(rejected_ids(&not_queued))),
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected {
+                                    rejected: not_queued,
+                                },
+                                uuid,
+                            );
+                        }
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueRelease {
+                        ref release_id,
+                        only_local,
+                    } => {
+                        let recordings =
+                            match database.get_release_recordings(release_id.clone()).await {
+                                Ok(recordings) => recordings,
+                                Err(error) => {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command,
+                                            reason: database_nope_reason(&error),
+                                        },
+                                        uuid,
+                                    );
+
+                                    continue;
+                                }
+                            };
+
+                        let mut not_queued = Vec::new();
+                        let mut queueable = Vec::new();
+
+                        for id in recordings {
+                            if !only_local {
+                                queueable.push(id);
+
+                                continue;
+                            }
+
+                            match database.get_cached_recording_metadata(id.clone()).await {
+                                Some(metadata) if metadata.audio_file_hash.is_some() => {
+                                    queueable.push(id)
+                                }
+                                Some(_) => not_queued.push((id, QueueRejectReason::MissingAudio)),
+                                None => not_queued.push((id, QueueRejectReason::UnknownRecording)),
+                            }
+                        }
+
+                        let Ok(rejected) = sequencer.add_queue(queueable).await else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        };
+
+                        not_queued.extend(rejected);
+
+                        if !not_queued.is_empty() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected {
+                                    rejected: not_queued,
+                                },
+                                uuid,
+                            );
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueAfterCurrentGroup { ref recordings } => {
+                        let Ok((index, rejected)) = sequencer
+                            .queue_after_current_group(recordings.clone())
+                            .await
+                        else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        };
+
+                        let inserted = recordings
+                            .iter()
+                            .filter(|id| {
+                                !rejected.iter().any(|(rejected_id, _)| rejected_id == *id)
+                            })
+                            .cloned()
+                            .collect::<Vec<String>>();
+
+                        if !rejected.is_empty() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected { rejected },
+                                uuid,
+                            );
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::QueueInserted {
+                                index,
+                                recordings: inserted,
+                            },
+                            uuid,
+                        );
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueNext { ref recordings } => {
+                        let Ok(not_queued) = sequencer.queue_next(recordings.clone()).await else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        };
+
+                        if !not_queued.is_empty() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::QueueNext {
+                                        recordings: rejected_ids(&not_queued),
+                                    },
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected {
+                                    rejected: not_queued,
+                                },
+                                uuid,
+                            );
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueTo {
+                        ref queue,
+                        ref recordings,
+                    } => {
+                        let not_queued =
+                            match sequencer.add_to_queue(queue, recordings.clone()).await {
+                                Ok(not_queued) => not_queued,
+                                Err(SequencerError::UnknownQueue) => {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command,
+                                            reason: NopeReason::InvalidArgument,
+                                        },
+                                        uuid,
+                                    );
+
+                                    continue;
+                                }
+                                Err(error) => {
+                                    diagnostics.record_error(command.kind(), &error);
+
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command,
+                                            reason: NopeReason::Unspecified,
+                                        },
+                                        uuid,
+                                    );
+
+                                    continue;
+                                }
+                            };
+
+                        if !not_queued.is_empty() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::QueueTo {
+                                        queue: queue.clone(),
+                                        recordings: rejected_ids(&not_queued),
+                                    },
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected {
+                                    rejected: not_queued,
+                                },
+                                uuid,
+                            );
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Queues {
+                                queues: sequencer.get_queues().await,
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::GetQueues => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Queues {
+                                queues: sequencer.get_queues().await,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::GetHistory { count } => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::History {
+                                ids: sequencer.get_history(count).await,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::QueueRange { offset, limit } => {
+                        let queue = sequencer.get_queue().await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::QueuePage {
+                                total: queue.len(),
+                                offset,
+                                items: queue
+                                    .into_iter()
+                                    .skip(offset)
+                                    .take(limit.min(queue_page_threshold))
+                                    .collect(),
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::QueuePlaylist { ref id, resume } => {
+                        let Ok(playlist_metadata) = database.get_playlist(id.clone()).await else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        };
+
+                        let start = if resume {
+                            playlist_metadata
+                                .resume_index()
+                                .min(playlist_metadata.recordings.len())
+                        } else {
+                            0
+                        };
+
+                        let mut not_queued = validation_rejections(
+                            &database
+                                .validate_recordings(&playlist_metadata.recordings[start..])
+                                .await,
+                        );
+
+                        let entries = playlist_metadata.recordings[start..]
+                            .iter()
+                            .cloned()
+                            .enumerate()
+                            .map(|(offset, recording_id)| (start + offset, recording_id))
+                            .filter(|(_, recording_id)| {
+                                !not_queued.iter().any(|(id, _)| id == recording_id)
+                            })
+                            .collect::<Vec<(usize, String)>>();
+
+                        let queueable = entries
+                            .iter()
+                            .map(|(_, recording_id)| recording_id.clone())
+                            .collect::<Vec<String>>();
+
+                        sequencer.tag_queue_entries(id, entries).await;
+
+                        let Ok(rejected) = sequencer.add_queue(queueable).await else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        };
+
+                        not_queued.extend(rejected);
+
+                        if !not_queued.is_empty() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::Queue(Some(rejected_ids(&not_queued))),
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::QueueRejected {
+                                    rejected: not_queued,
+                                },
+                                uuid,
+                            );
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::ShuffleQueue { enable, seed } => {
+                        let seed = sequencer.set_shuffle(enable, seed).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Shuffle { enable, seed },
+                            Uuid::nil(),
+                        );
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::ClearQueue { preview: true } => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Preview {
+                                summary: sequencer.clear_queue(true).await,
+                                command,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::ClearQueue { preview: false } => {
+                        sequencer.clear_queue(false).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(Vec::new(), queue_page_threshold, &mut broadcast_queue),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueRemove(ref target) => {
+                        if let Err(error) = sequencer.remove_from_queue(target).await {
+                            let reason = match (error, target) {
+                                (SequencerError::NotInQueue, QueueRemoveTarget::Index(_)) => {
+                                    NopeReason::InvalidArgument
+                                }
+                                (SequencerError::NotInQueue, QueueRemoveTarget::Id(_)) => {
+                                    NopeReason::NotFound
+                                }
+                                _ => NopeReason::Unspecified,
+                            };
+
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope { command, reason },
+                                uuid,
+                            );
+
+                            continue;
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueMove { from, to } => {
+                        if sequencer.move_queue_item(from, to).await.is_err() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
+                                },
+                                uuid,
+                            );
+
+                            continue;
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::SetRadioMode { enabled } => {
+                        sequencer.set_radio_mode(enabled).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::LoopMode(loop_mode) => {
+                        if let LoopMode::LoopPlaylist(ref id) = loop_mode {
+                            if let Err(error) = database.get_playlist(id.clone()).await {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        reason: database_nope_reason(&error),
+                                        command: EngineCommand::LoopMode(loop_mode),
+                                    },
+                                    uuid,
+                                );
+
+                                continue;
+                            }
+                        }
+
+                        sequencer.set_loop_mode(loop_mode.clone()).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::LoopMode(loop_mode),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::QueueModel(model) => {
+                        sequencer.set_queue_model(model).await;
+                        database.set_setting(QUEUE_MODEL_SETTING, &model).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::QueueModel(model),
+                            Uuid::nil(),
+                        );
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            queue_broadcast(
+                                sequencer.get_queue().await,
+                                queue_page_threshold,
+                                &mut broadcast_queue,
+                            ),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::OnQueueEnd(on_queue_end) => {
+                        sequencer.set_on_queue_end(on_queue_end).await;
+                        database
+                            .set_setting(ON_QUEUE_END_SETTING, &on_queue_end)
+                            .await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::OnQueueEnd(on_queue_end),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::SmoothLevelTransition { enabled } => {
+                        sequencer.set_smooth_level_transition(enabled).await;
+                        database
+                            .set_setting(SMOOTH_LEVEL_TRANSITION_SETTING, &enabled)
+                            .await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::SmoothLevelTransition { enabled },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::PauseFade(fade) => {
+                        let fade = sequencer.set_pause_fade(fade).await;
+                        database.set_setting(PAUSE_FADE_SETTING, &fade).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::PauseFade(fade),
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::StopAfterCurrent { enabled } => {
+                        sequencer.set_stop_after_current(enabled).await;
+
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::StopAfterCurrent { enabled },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::RecordingMetadata { id } => {
+                        let mut recording_metadata =
+                            match database.get_recording_metadata(id.clone()).await {
+                                Ok(recording_metadata) => recording_metadata,
+                                Err(error) => {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command: EngineCommand::RecordingMetadata { id },
+                                            reason: database_nope_reason(&error),
+                                        },
+                                        uuid,
+                                    );
+                                    continue;
+                                }
+                            };
+
+                        recording_metadata.updating = database.is_recording_updating(&id);
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::RecordingMetadata(recording_metadata),
+                            uuid,
+                        );
+                    }
+                    EngineCommand::RecordingStats { ref id } => {
+                        let response = match database.get_recording_metadata(id.clone()).await {
+                            Ok(metadata) => EngineResponse::RecordingStats {
+                                id: id.clone(),
+                                play_count: metadata.play_count,
+                                last_played: metadata.last_played,
+                            },
+                            Err(error) => EngineResponse::Nope {
+                                reason: database_nope_reason(&error),
+                                command,
+                            },
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            response,
+                            uuid,
+                        );
+                    }
+                    EngineCommand::RecordingDuration { ref id } => {
+                        let response = match database.get_recording_duration(id.clone()).await {
+                            Ok(duration) => EngineResponse::RecordingDuration {
+                                id: id.clone(),
+                                duration,
+                            },
+                            Err(error) => EngineResponse::Nope {
+                                reason: database_nope_reason(&error),
+                                command,
+                            },
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            response,
+                            uuid,
+                        );
+                    }
+                    EngineCommand::RecordingFile(_)
+                    | EngineCommand::RecordingFileChunk { .. }
+                    | EngineCommand::TransferAck { .. }
+                    | EngineCommand::SendRecording(_)
+                    | EngineCommand::SendVerifiedRecording { .. }
+                    | EngineCommand::SendRecordingChunk { .. }
+                    | EngineCommand::ListClients
+                    | EngineCommand::SetDeviceTransferBudget { .. } => {
+                        transfer::handle(
+                            command,
+                            context,
+                            &mut transfers,
+                            &connections.connection_devices,
+                        )
+                        .await
+                    }
+                    EngineCommand::LinkExternalFile {
+                        ref id,
+                        ref path,
+                        transferable,
+                    } => {
+                        let response = match database
+                            .link_external_file(id.clone(), path.clone(), transferable)
+                            .await
+                        {
+                            Ok(recording_metadata) => {
+                                EngineResponse::RecordingMetadata(recording_metadata)
+                            }
+                            Err(error) => EngineResponse::Nope {
+                                reason: database_nope_reason(&error),
+                                command,
+                            },
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            response,
+                            uuid,
+                        );
+                    }
+                    EngineCommand::RecordingProvenance { id } => {
+                        let recording_metadata =
+                            match database.get_recording_metadata(id.clone()).await {
+                                Ok(recording_metadata) => recording_metadata,
+                                Err(error) => {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command: EngineCommand::RecordingProvenance { id },
+                                            reason: database_nope_reason(&error),
+                                        },
+                                        uuid,
+                                    );
+                                    continue;
+                                }
+                            };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::RecordingProvenance {
+                                id,
+                                provenance: recording_metadata.provenance,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::Waveform { ref id } => {
+                        let id = id.clone();
+
+                        let waveform_database = database.clone();
+                        let waveform_internal_response_sender = internal_response_sender.clone();
+                        let waveform_response_sender = response_sender.clone();
+                        let waveform_diagnostics = diagnostics.clone();
+
+                        tokio::spawn(async move {
+                            let response = match waveform_database.get_waveform(id.clone()).await {
+                                Ok(buckets) => EngineResponse::Waveform { id, buckets },
+                                Err(DatabaseError::DecodeFailed) => EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::DecodeFailed,
+                                },
+                                Err(DatabaseError::RecordingFileNotFound) => EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::NotFound,
+                                },
+                                Err(error) => {
+                                    waveform_diagnostics.record_error(command.kind(), &error);
+
+                                    EngineResponse::Nope {
+                                        command,
+                                        reason: NopeReason::Unspecified,
+                                    }
+                                }
+                            };
+
+                            route_response(
+                                internal,
+                                &waveform_internal_response_sender,
+                                &waveform_response_sender,
+                                response,
+                                uuid,
+                            );
+                        });
+                    }
+                    EngineCommand::PlaylistMetadata { id } => {
+                        let Ok(playlist_metadata) = database.get_playlist(id.clone()).await else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command: EngineCommand::PlaylistMetadata { id },
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            continue;
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::PlaylistMetadata {
+                                metadata: playlist_metadata,
+                                origin: PlaylistOrigin::Local,
+                            },
+                            uuid,
+                        );
                     }
-                };
+                    EngineCommand::ListPlaylists { sort, direction } => {
+                        let mut playlists = database.get_playlists().await;
+
+                        if let Some(sort) = sort {
+                            playlists = sort_entries(
+                                playlists
+                                    .into_iter()
+                                    .map(|playlist| {
+                                        let (text, number) = match sort {
+                                            LibrarySort::RecentlyAdded => {
+                                                (String::new(), playlist.modified)
+                                            }
+                                            _ => (collation.sort_key(&playlist.name), 0),
+                                        };
+                                        let id = playlist.id.clone();
+
+                                        (playlist, text, number, id)
+                                    })
+                                    .collect(),
+                                direction == SortDirection::Descending,
+                            );
+                        }
 
-                match command {
-                    EngineCommand::None | EngineCommand::Goodbye => {
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::Ok(command),
+                            EngineResponse::Playlists {
+                                playlists,
+                                origin: PlaylistOrigin::Local,
+                            },
                             uuid,
                         );
                     }
-                    EngineCommand::Play(id) => {
-                        let Some(id) = id else {
+                    EngineCommand::CachePlaylist { id } => {
+                        let Ok(playlist_metadata) = database.get_playlist(id.clone()).await else {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                if let Some(id) = sequencer.get_playing().await {
-                                    EngineResponse::NowPlaying(id)
-                                } else {
-                                    EngineResponse::NowPaused
+                                EngineResponse::Nope {
+                                    command: EngineCommand::CachePlaylist { id },
+                                    reason: NopeReason::NotFound,
                                 },
-                                Uuid::nil(),
+                                uuid,
                             );
-
                             continue;
                         };
 
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender
-                                .send((EngineResponse::Nope(EngineCommand::Play(Some(id))), uuid));
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::PlaylistMetadata {
+                                metadata: playlist_metadata,
+                                origin: PlaylistOrigin::Local,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::ValidatePlaylist { ref id, prune } => {
+                        let validation_database = database.clone();
+                        let validation_internal_response_sender = internal_response_sender.clone();
+                        let validation_response_sender = response_sender.clone();
+                        let id = id.clone();
 
-                            continue;
-                        }
+                        tokio::spawn(async move {
+                            let validation = if prune {
+                                validation_database
+                                    .prune_playlist(id.clone())
+                                    .await
+                                    .map(|(_, validation)| validation)
+                            } else {
+                                match validation_database.get_playlist(id.clone()).await {
+                                    Ok(playlist_metadata) => Ok(validation_database
+                                        .validate_recordings(&playlist_metadata.recordings)
+                                        .await),
+                                    Err(error) => Err(error),
+                                }
+                            };
 
-                        if sequencer.play(id.clone()).await.is_ok() {
-                            route_response(
-                                internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                if let Some(id) = sequencer.get_playing().await {
-                                    EngineResponse::NowPlaying(id)
-                                } else {
-                                    EngineResponse::NowPaused
+                            let response = match validation {
+                                Ok(validation) => EngineResponse::PlaylistValidation {
+                                    id,
+                                    missing_metadata: validation.missing_metadata,
+                                    missing_audio: validation.missing_audio,
                                 },
-                                Uuid::nil(),
-                            );
+                                Err(error) => EngineResponse::Nope {
+                                    reason: database_nope_reason(&error),
+                                    command,
+                                },
+                            };
+
                             route_response(
                                 internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Queue(sequencer.get_queue().await),
-                                Uuid::nil(),
+                                &validation_internal_response_sender,
+                                &validation_response_sender,
+                                response,
+                                uuid,
                             );
-                        } else {
+                        });
+                    }
+                    EngineCommand::SetPlaylistMetadata(metadata) => {
+                        let metadata = database.set_playlist(metadata).await;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::PlaylistMetadata {
+                                metadata,
+                                origin: PlaylistOrigin::Local,
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::ResolvePlaylistConflict { ref id, keep } => {
+                        let Ok(metadata) =
+                            database.resolve_playlist_conflict(id.clone(), keep).await
+                        else {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Nope(EngineCommand::Play(Some(id))),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::NotFound,
+                                },
                                 uuid,
                             );
-                        }
-                    }
-                    EngineCommand::Pause => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
 
                             continue;
-                        }
-
-                        sequencer.pause().await;
+                        };
 
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            if let Some(id) = sequencer.get_playing().await {
-                                EngineResponse::NowPlaying(id)
-                            } else {
-                                EngineResponse::NowPaused
+                            EngineResponse::PlaylistMetadata {
+                                metadata,
+                                origin: PlaylistOrigin::Local,
                             },
                             Uuid::nil(),
                         );
                     }
-                    EngineCommand::Next => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
-
-                            continue;
-                        }
-
-                        if sequencer.next().await.is_ok() {
+                    EngineCommand::SetVolume { volume } => {
+                        if volume.is_nan() {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                if let Some(id) = sequencer.get_playing().await {
-                                    EngineResponse::NowPlaying(id)
-                                } else {
-                                    EngineResponse::NowPaused
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
                                 },
-                                Uuid::nil(),
-                            );
-                            route_response(
-                                internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Queue(sequencer.get_queue().await),
-                                Uuid::nil(),
-                            );
-                        } else {
-                            route_response(
-                                internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Nope(EngineCommand::Next),
                                 uuid,
                             );
-                        }
-                    }
-                    EngineCommand::Previous => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
 
                             continue;
                         }
 
-                        if sequencer.previous().await.is_ok() {
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Volume {
+                                volume: sequencer.set_volume(volume).await,
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::Mute { muted } => {
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Volume {
+                                volume: sequencer.mute(muted).await,
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::GetVolume => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Volume {
+                                volume: sequencer.get_volume().await,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::SetSpeed { speed } => {
+                        if speed.is_nan() {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                if let Some(id) = sequencer.get_playing().await {
-                                    EngineResponse::NowPlaying(id)
-                                } else {
-                                    EngineResponse::NowPaused
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
                                 },
-                                Uuid::nil(),
+                                uuid,
                             );
+
+                            continue;
+                        }
+
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Speed {
+                                speed: sequencer.set_speed(speed).await,
+                            },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::GetSpeed => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Speed {
+                                speed: sequencer.speed().await,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::PlayCue { ref data, duck_db } => {
+                        let response = match sequencer.play_cue(data.clone(), duck_db).await {
+                            Ok(()) => EngineResponse::Ok { command },
+                            Err(SequencerError::DecodingError) => EngineResponse::Nope {
+                                command,
+                                reason: NopeReason::DecodeFailed,
+                            },
+                            Err(error) => {
+                                diagnostics.record_error(command.kind(), &error);
+
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                }
+                            }
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            response,
+                            uuid,
+                        );
+                    }
+                    EngineCommand::FollowPlayback { .. } => {
+                        // Only an engine relaying to a peer has a stream to follow.
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Nope {
+                                command,
+                                reason: NopeReason::Unsupported,
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::StreamListen { enabled } => {
+                        if internal {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Queue(sequencer.get_queue().await),
-                                Uuid::nil(),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
                             );
+
+                            continue;
+                        }
+
+                        let response = if enabled {
+                            match stream_listeners.join(uuid, || sequencer.subscribe_stream()) {
+                                Ok(()) => EngineResponse::Ok { command },
+                                Err(reason) => EngineResponse::Nope { command, reason },
+                            }
                         } else {
+                            stream_listeners.leave(uuid);
+
+                            EngineResponse::Ok { command }
+                        };
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            response,
+                            uuid,
+                        );
+                    }
+                    EngineCommand::LibraryEvents { enabled } => {
+                        if internal {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Nope(EngineCommand::Previous),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
                                 uuid,
                             );
-                        }
-                    }
-                    EngineCommand::Seek(position) => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
 
                             continue;
                         }
 
-                        if sequencer.seek(position).await.is_ok() {
+                        library_listeners.retain(|listener| *listener != uuid);
+
+                        if enabled {
+                            library_listeners.push(uuid);
+                        }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::HandOff { ref target } => {
+                        let target = target.clone();
+
+                        let hand_off_database = database.clone();
+                        let hand_off_sequencer = sequencer.clone();
+                        let hand_off_diagnostics = diagnostics.clone();
+                        let hand_off_internal_response_sender = internal_response_sender.clone();
+                        let hand_off_response_sender = response_sender.clone();
+
+                        tokio::spawn(async move {
+                            if !hand_off(
+                                &hand_off_database,
+                                &hand_off_sequencer,
+                                &hand_off_diagnostics,
+                                target.clone(),
+                            )
+                            .await
+                            {
+                                route_response(
+                                    internal,
+                                    &hand_off_internal_response_sender,
+                                    &hand_off_response_sender,
+                                    EngineResponse::Nope {
+                                        command,
+                                        reason: NopeReason::HandOffFailed,
+                                    },
+                                    uuid,
+                                );
+
+                                return;
+                            }
+
                             route_response(
-                                internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Seek(Duration::from_secs(0)),
+                                false,
+                                &hand_off_internal_response_sender,
+                                &hand_off_response_sender,
+                                EngineResponse::NowPaused,
                                 Uuid::nil(),
                             );
-                        } else {
+                            route_response(
+                                internal,
+                                &hand_off_internal_response_sender,
+                                &hand_off_response_sender,
+                                EngineResponse::HandOffComplete { target },
+                                uuid,
+                            );
+                        });
+                    }
+                    EngineCommand::AdoptSession(snapshot) => {
+                        let recording_id = snapshot.recording_id.clone();
+
+                        if sequencer.adopt(snapshot.clone()).await.is_err() {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Nope(EngineCommand::Seek(position)),
+                                EngineResponse::Nope {
+                                    command: EngineCommand::AdoptSession(snapshot),
+                                    reason: NopeReason::Unspecified,
+                                },
                                 uuid,
                             );
+
+                            continue;
                         }
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok {
+                                command: EngineCommand::AdoptSession(snapshot),
+                            },
+                            uuid,
+                        );
+                        route_response(
+                            false,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::NowPlaying { id: recording_id },
+                            Uuid::nil(),
+                        );
+                    }
+                    EngineCommand::GetPermissions
+                    | EngineCommand::RequestPermissions { .. }
+                    | EngineCommand::ResolvePermissionRequest { .. }
+                    | EngineCommand::AllowedCommands
+                    | EngineCommand::SetPermissions(_) => {
+                        access::handle(command, context, &mut connections).await
+                    }
+                    EngineCommand::ClearSavedState { keep_saving } => {
+                        database.remove_setting(PLAYER_STATE_SETTING).await;
+                        database
+                            .set_setting(PERSIST_PLAYER_STATE_SETTING, &keep_saving)
+                            .await;
+
+                        persist_player_state = keep_saving;
+                        saved_player_state = None;
+
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
                     }
-                    EngineCommand::Queue(recording_ids) => {
-                        let Some(recording_ids) = recording_ids else {
+                    EngineCommand::UpdateSettings(ref settings) => {
+                        let Ok(provider) = MetadataProvider::new(
+                            settings.musicbrainz_base_url.clone(),
+                            settings.musicbrainz_user_agent.clone(),
+                        ) else {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Queue(sequencer.get_queue().await),
-                                Uuid::nil(),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
+                                },
+                                uuid,
                             );
 
                             continue;
                         };
 
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Queue)
-                        {
-                            let _ = response_sender.send((
-                                EngineResponse::Nope(EngineCommand::Queue(Some(recording_ids))),
-                                uuid,
-                            ));
+                        database.set_provider(provider).await;
 
-                            continue;
+                        engine_settings = settings.clone();
+
+                        if let Some(new_collation) = &settings.collation {
+                            collation = new_collation.clone();
+
+                            database.set_setting(COLLATION_SETTING, &collation).await;
                         }
 
-                        let Ok(not_queued) = sequencer.add_queue(recording_ids.clone()).await
-                        else {
-                            route_response(
-                                internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Nope(EngineCommand::Queue(Some(recording_ids))),
-                                Uuid::nil(),
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Ok { command },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::Capabilities => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Capabilities {
+                                version: env!("CARGO_PKG_VERSION").to_owned(),
+                                low_memory,
+                                idle_timeout,
+                                parked: sequencer.is_parked().await,
+                                queue_page_threshold: Some(queue_page_threshold),
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::HealthCheck => {
+                        let health_database = database.clone();
+                        let health_parked = sequencer.is_parked().await;
+                        let health_custom_output = sequencer.has_custom_output();
+                        let health_address = local_address.clone();
+                        let health_listener_status = listener_monitor.status();
+                        let health_accept_errors = listener_monitor.accept_errors();
+                        let health_underruns = (underruns_per_minute > underrun_threshold)
+                            .then_some(underruns_per_minute);
+                        let health_internal_response_sender = internal_response_sender.clone();
+                        let health_response_sender = response_sender.clone();
+
+                        tokio::spawn(async move {
+                            let ((audio, audio_details), (database, database_details)) = tokio::join!(
+                                health::check_audio(health_custom_output),
+                                health::check_database(&health_database)
+                            );
+                            let (network, network_details) = health::check_network(
+                                &health_address,
+                                health_listener_status,
+                                health_accept_errors,
                             );
 
-                            continue;
-                        };
+                            let mut details =
+                                vec![audio_details, database_details, network_details];
+
+                            if let Some(idle_timeout) = idle_timeout {
+                                details.push(format!(
+                                    "idle: {:?} after {}s without clients or playback{}",
+                                    idle_action,
+                                    idle_timeout.as_secs(),
+                                    if health_parked {
+                                        ", audio output parked"
+                                    } else {
+                                        ""
+                                    }
+                                ));
+                            }
+
+                            if let NetworkState::Offline { since } =
+                                health_database.network_state().await
+                            {
+                                details.push(format!(
+                                    "metadata: MusicBrainz unreachable since {}",
+                                    since
+                                ));
+                            }
+
+                            let audio = match (audio, health_underruns) {
+                                (HealthState::Healthy, Some(_)) => HealthState::Degraded,
+                                (audio, _) => audio,
+                            };
+
+                            if let Some(underruns_per_minute) = health_underruns {
+                                details.push(format!(
+                                    "audio: {:.1} underruns per minute; a larger output buffer may help",
+                                    underruns_per_minute
+                                ));
+                            }
+
+                            let storage_state = health_database.storage_state().await;
+
+                            let database = match (database, storage_state) {
+                                (HealthState::Healthy, StorageState::Low { .. }) => {
+                                    HealthState::Degraded
+                                }
+                                (database, _) => database,
+                            };
+
+                            if let Some(free_space) = health_database.free_space().await {
+                                details.push(format!(
+                                    "storage: {} MB free{}",
+                                    free_space / (1024 * 1024),
+                                    if let StorageState::Low { .. } = storage_state {
+                                        ", below the reserve; transfers paused"
+                                    } else {
+                                        ""
+                                    }
+                                ));
+                            }
 
-                        if not_queued.len() != 0 {
                             route_response(
                                 internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Nope(EngineCommand::Queue(Some(not_queued))),
+                                &health_internal_response_sender,
+                                &health_response_sender,
+                                EngineResponse::Health {
+                                    audio,
+                                    database,
+                                    network,
+                                    details,
+                                },
                                 uuid,
                             );
-                        }
+                        });
+                    }
+                    EngineCommand::SupportedFormats => {
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::Queue(sequencer.get_queue().await),
-                            Uuid::nil(),
+                            EngineResponse::SupportedFormats {
+                                codecs: SUPPORTED_CODECS
+                                    .iter()
+                                    .map(|codec| codec.to_string())
+                                    .collect(),
+                            },
+                            uuid,
                         );
                     }
-                    EngineCommand::ShuffleQueue(enable) => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
-
-                            continue;
-                        }
+                    EngineCommand::Metrics { reset } => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Metrics(MetricsSnapshot {
+                                latencies: latencies.snapshot(reset),
+                                history_len: sequencer.history_len().await,
+                                transfers_in_flight: transfers.in_flight_count(),
+                                accept_errors: listener_monitor.accept_errors(),
+                                seek_fallbacks: sequencer.seek_fallbacks(),
+                                underruns: sequencer.underruns(),
+                                bytes_sent: transfers.bandwidth.totals().bytes_sent,
+                                bytes_received: transfers.bandwidth.totals().bytes_received,
+                            }),
+                            uuid,
+                        );
+                    }
+                    EngineCommand::AudioStatus => {
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::AudioStatus(AudioStatus {
+                                underruns: sequencer.underruns(),
+                                underruns_per_minute,
+                                priority_elevated: sequencer.priority_elevated(),
+                            }),
+                            uuid,
+                        );
+                    }
+                    EngineCommand::DiagnosticBundle => {
+                        let mut settings_redacted = redact_settings(&engine_settings);
+                        settings_redacted.collation = Some(collation.clone());
 
-                        sequencer.set_shuffle(enable).await;
+                        route_response(
+                            internal,
+                            &internal_response_sender,
+                            &response_sender,
+                            EngineResponse::Diagnostics {
+                                uptime: diagnostics.uptime(),
+                                version: env!("CARGO_PKG_VERSION").to_owned(),
+                                location_history: diagnostics.location_history(),
+                                last_errors: diagnostics.last_errors(),
+                                metrics: MetricsSnapshot {
+                                    latencies: latencies.snapshot(false),
+                                    history_len: sequencer.history_len().await,
+                                    transfers_in_flight: transfers.in_flight_count(),
+                                    accept_errors: listener_monitor.accept_errors(),
+                                    seek_fallbacks: sequencer.seek_fallbacks(),
+                                    underruns: sequencer.underruns(),
+                                    bytes_sent: transfers.bandwidth.totals().bytes_sent,
+                                    bytes_received: transfers.bandwidth.totals().bytes_received,
+                                },
+                                settings_redacted,
+                                audio_status: AudioStatus {
+                                    underruns: sequencer.underruns(),
+                                    underruns_per_minute,
+                                    priority_elevated: sequencer.priority_elevated(),
+                                },
+                                library_stats: LibraryStats {
+                                    recordings: database.get_library().await.len(),
+                                    playlists: database.get_playlists().await.len(),
+                                },
+                            },
+                            uuid,
+                        );
+                    }
+                    EngineCommand::ResetMetrics => {
+                        latencies.reset();
 
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::Queue(sequencer.get_queue().await),
-                            Uuid::nil(),
+                            EngineResponse::Ok { command },
+                            uuid,
                         );
                     }
-                    EngineCommand::ClearQueue => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Queue)
-                        {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
+                    EngineCommand::AuditLog { since, limit } => {
+                        let limit = if low_memory {
+                            if limit.is_some_and(|limit| limit > LOW_MEMORY_PAGE_SIZE) {
+                                route_response(
+                                    internal,
+                                    &internal_response_sender,
+                                    &response_sender,
+                                    EngineResponse::Nope {
+                                        command,
+                                        reason: NopeReason::InvalidArgument,
+                                    },
+                                    uuid,
+                                );
 
-                            continue;
-                        }
+                                continue;
+                            }
 
-                        sequencer.clear_queue().await;
+                            Some(limit.unwrap_or(LOW_MEMORY_PAGE_SIZE))
+                        } else {
+                            limit
+                        };
 
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::Queue(Vec::new()),
-                            Uuid::nil(),
+                            EngineResponse::AuditLog {
+                                entries: database.get_audit_log(since, limit).await,
+                            },
+                            uuid,
                         );
                     }
-                    EngineCommand::LoopMode(loop_mode) => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Control)
-                        {
-                            let _ = response_sender.send((
-                                EngineResponse::Nope(EngineCommand::LoopMode(loop_mode)),
-                                uuid,
-                            ));
+                    EngineCommand::VerifyLibrary { .. }
+                    | EngineCommand::RefreshLibrary { .. }
+                    | EngineCommand::CancelJob { .. }
+                    | EngineCommand::ListOrphanedRecordings
+                    | EngineCommand::AdoptLooseFiles => jobs::handle(command, context, &jobs).await,
+                    EngineCommand::DeleteRecording { .. }
+                    | EngineCommand::DeletePlaylist { .. }
+                    | EngineCommand::ListTrash
+                    | EngineCommand::RestoreFromTrash { .. }
+                    | EngineCommand::EmptyTrash { .. } => trash::handle(command, context).await,
+                    EngineCommand::TagRecording { .. }
+                    | EngineCommand::EditLocalMetadata { .. }
+                    | EngineCommand::SetSpokenWord { .. }
+                    | EngineCommand::ListTags
+                    | EngineCommand::RecordingsByTag { .. } => tags::handle(command, context).await,
+                    EngineCommand::ListRecordings {
+                        ref tag,
+                        sort,
+                        direction,
+                    } => {
+                        let tagged = match tag {
+                            Some(tag) => match database.get_recordings_by_tag(tag).await {
+                                Ok(tagged) => Some(tagged),
+                                Err(_) => {
+                                    route_response(
+                                        internal,
+                                        &internal_response_sender,
+                                        &response_sender,
+                                        EngineResponse::Nope {
+                                            command,
+                                            reason: NopeReason::InvalidArgument,
+                                        },
+                                        uuid,
+                                    );
 
-                            continue;
-                        }
+                                    continue;
+                                }
+                            },
+                            None => None,
+                        };
 
-                        sequencer.set_loop_mode(loop_mode.clone()).await;
+                        let library = database.get_library().await.into_iter().filter(|(id, _)| {
+                            tagged.as_ref().is_none_or(|tagged| tagged.contains(id))
+                        });
+
+                        let recordings = match sort {
+                            None => library.map(|(id, _)| id).collect(),
+                            Some(sort) => {
+                                let play_counts = match sort {
+                                    LibrarySort::PlayCount => database.get_play_counts().await,
+                                    _ => HashMap::new(),
+                                };
+
+                                sort_entries(
+                                    library
+                                        .map(|(id, metadata)| {
+                                            let (text, number) = match sort {
+                                                LibrarySort::Title => (
+                                                    collation.sort_key(&metadata.recording.title),
+                                                    0,
+                                                ),
+                                                LibrarySort::Artist => {
+                                                    (collation.sort_key(&metadata.artist()), 0)
+                                                }
+                                                LibrarySort::RecentlyAdded => (
+                                                    String::new(),
+                                                    metadata
+                                                        .provenance
+                                                        .first()
+                                                        .map_or(0, |entry| entry.timestamp),
+                                                ),
+                                                LibrarySort::PlayCount => (
+                                                    String::new(),
+                                                    play_counts
+                                                        .get(&id)
+                                                        .copied()
+                                                        .unwrap_or_default(),
+                                                ),
+                                            };
+
+                                            (id.clone(), text, number, id)
+                                        })
+                                        .collect(),
+                                    direction == SortDirection::Descending,
+                                )
+                            }
+                        };
 
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::LoopMode(loop_mode),
-                            Uuid::nil(),
+                            EngineResponse::Recordings {
+                                recordings,
+                                relationships: Vec::new(),
+                            },
+                            uuid,
                         );
                     }
-                    EngineCommand::RecordingMetadata(id) => {
-                        let Ok(recording_metadata) =
-                            database.get_recording_metadata(id.clone()).await
-                        else {
+                    EngineCommand::RelatedRecordings { id } => {
+                        let related_database = database.clone();
+                        let related_internal_response_sender = internal_response_sender.clone();
+                        let related_response_sender = response_sender.clone();
+
+                        tokio::spawn(async move {
+                            let Ok(local) =
+                                related_database.get_related_recordings(id.clone()).await
+                            else {
+                                route_response(
+                                    internal,
+                                    &related_internal_response_sender,
+                                    &related_response_sender,
+                                    EngineResponse::Nope {
+                                        command: EngineCommand::RelatedRecordings { id },
+                                        reason: NopeReason::NotFound,
+                                    },
+                                    uuid,
+                                );
+
+                                return;
+                            };
+
+                            let (mut recordings, mut relationships): (Vec<String>, Vec<_>) = local
+                                .into_iter()
+                                .map(|(related_id, work)| {
+                                    (
+                                        related_id,
+                                        RecordingRelationship {
+                                            relationship: work.label(),
+                                            work_id: work.work_id,
+                                            local: true,
+                                        },
+                                    )
+                                })
+                                .unzip();
+
+                            let works = related_database
+                                .get_cached_recording_metadata(id.clone())
+                                .await
+                                .map(|metadata| metadata.works)
+                                .unwrap_or_default();
+
+                            let mut remote_count = 0;
+
+                            for work in works {
+                                if remote_count >= RELATED_REMOTE_LIMIT {
+                                    break;
+                                }
+
+                                let Ok(remote) = related_database
+                                    .get_remote_work_recordings(&work.work_id)
+                                    .await
+                                else {
+                                    break;
+                                };
+
+                                for (related_id, work) in remote {
+                                    if remote_count >= RELATED_REMOTE_LIMIT {
+                                        break;
+                                    }
+
+                                    if related_id == id || recordings.contains(&related_id) {
+                                        continue;
+                                    }
+
+                                    recordings.push(related_id);
+                                    relationships.push(RecordingRelationship {
+                                        relationship: work.label(),
+                                        work_id: work.work_id,
+                                        local: false,
+                                    });
+
+                                    remote_count += 1;
+                                }
+                            }
+
                             route_response(
                                 internal,
-                                &internal_response_sender,
-                                &response_sender,
-                                EngineResponse::Nope(EngineCommand::RecordingMetadata(id)),
+                                &related_internal_response_sender,
+                                &related_response_sender,
+                                EngineResponse::Recordings {
+                                    recordings,
+                                    relationships,
+                                },
                                 uuid,
                             );
-                            continue;
-                        };
+                        });
+                    }
+                    EngineCommand::ChangesSince { sequence } => {
+                        let (changes, _) = response_sender.changes_since(sequence);
 
                         route_response(
                             internal,
                             &internal_response_sender,
                             &response_sender,
-                            EngineResponse::RecordingMetadata(recording_metadata),
+                            changes,
                             uuid,
                         );
                     }
-                    EngineCommand::RecordingFile(id) => {
-                        let Ok(mut recording_file) = database.get_recording_file(id.clone()).await
-                        else {
+                    EngineCommand::Batch { ref commands } => {
+                        if !commands.iter().all(is_batchable) {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Nope(EngineCommand::RecordingFile(id)),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::InvalidArgument,
+                                },
                                 uuid,
                             );
+
                             continue;
-                        };
+                        }
 
-                        let mut buffer = Vec::new();
-                        let _ = recording_file.read_to_end(&mut buffer);
+                        let denied = commands
+                            .iter()
+                            .filter_map(|item| match command_access(item, &user_permissions) {
+                                CommandAccess::Requires(permission) => Some(permission),
+                                _ => None,
+                            })
+                            .find(|permission| !user_permissions.contains(permission));
 
-                        route_response(
-                            internal,
-                            &internal_response_sender,
-                            &response_sender,
-                            EngineResponse::RecordingFile((id, buffer)),
-                            uuid,
-                        );
+                        if let (false, Some(permission)) = (internal, denied) {
+                            access::deny(command, permission, context, &connections).await;
+
+                            continue;
+                        }
+
+                        if !internal {
+                            response_sender.begin_capture(uuid);
+                            batch_owner = Some(uuid);
+                        }
+
+                        batch_pending
+                            .extend(commands.iter().cloned().map(|item| (item, uuid, internal)));
                     }
-                    EngineCommand::SendRecording((id, recording)) => {
+                    EngineCommand::PreviewAt { ref id, position } => {
                         if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Transfer)
+                            && preview_requests.get(&uuid).is_some_and(|last_request| {
+                                last_request.elapsed() < PREVIEW_REQUEST_INTERVAL
+                            })
                         {
-                            let _ = response_sender.send((
-                                EngineResponse::Nope(EngineCommand::SendRecording((id, recording))),
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Busy,
+                                },
                                 uuid,
-                            ));
+                            );
 
                             continue;
                         }
 
-                        database
-                            .set_recording_file(id.clone(), Some(recording.clone()))
-                            .await;
+                        if !internal {
+                            preview_requests.insert(uuid, std::time::Instant::now());
+                        }
 
-                        route_response(
-                            internal,
-                            &internal_response_sender,
-                            &response_sender,
-                            EngineResponse::Ok(EngineCommand::SendRecording((id, recording))),
-                            uuid,
-                        );
-                    }
-                    EngineCommand::PlaylistMetadata(id) => {
-                        let Ok(playlist_metadata) = database.get_playlist(id.clone()).await else {
+                        let has_local_audio = database
+                            .get_cached_recording_metadata(id.clone())
+                            .await
+                            .is_some_and(|metadata| metadata.audio_file_hash.is_some());
+
+                        if !has_local_audio {
                             route_response(
                                 internal,
                                 &internal_response_sender,
                                 &response_sender,
-                                EngineResponse::Nope(EngineCommand::PlaylistMetadata(id)),
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::NotFound,
+                                },
                                 uuid,
                             );
+
                             continue;
-                        };
+                        }
 
-                        route_response(
-                            internal,
-                            &internal_response_sender,
-                            &response_sender,
-                            EngineResponse::PlaylistMetadata(playlist_metadata),
-                            uuid,
-                        );
-                    }
-                    EngineCommand::SetPlaylistMetadata(metadata) => {
-                        if !internal
-                            && !permission_exists(&current_user_permissions, Permission::Playlist)
                         {
-                            let _ = response_sender.send((
-                                EngineResponse::Nope(EngineCommand::SetPlaylistMetadata(metadata)),
-                                uuid,
-                            ));
+                            let mut locked_previews = previews.lock().await;
 
-                            continue;
-                        }
+                            if let Some(waiting) = locked_previews.get_mut(&(id.clone(), position))
+                            {
+                                waiting.push((internal, uuid));
 
-                        database.set_playlist(metadata.clone()).await;
+                                continue;
+                            }
 
-                        route_response(
-                            internal,
-                            &internal_response_sender,
-                            &response_sender,
-                            EngineResponse::PlaylistMetadata(metadata),
-                            Uuid::nil(),
-                        );
-                    }
-                    EngineCommand::SetVolume(volume) => {
-                        if internal {
-                            let _ = sequencer.set_volume(volume).await;
-                        }
-                    }
-                    EngineCommand::GetPermissions => {
-                        if internal {
-                            let _ =
-                                internal_response_sender.send(EngineResponse::Permissions(vec![
-                                    Permission::Control,
-                                    Permission::Queue,
-                                    Permission::Playlist,
-                                    Permission::Transfer,
-                                ]));
-                        } else {
-                            let _ = response_sender
-                                .send((EngineResponse::Permissions(Vec::new()), uuid));
+                            locked_previews.insert((id.clone(), position), vec![(internal, uuid)]);
                         }
+
+                        let id = id.clone();
+                        let preview_database = database.clone();
+                        let preview_previews = previews.clone();
+                        let preview_internal_response_sender = internal_response_sender.clone();
+                        let preview_response_sender = response_sender.clone();
+
+                        tokio::spawn(async move {
+                            let data = match preview_database.get_recording_file(id.clone()).await {
+                                Ok(mut recording_file) => tokio::task::spawn_blocking(move || {
+                                    let mut buffer = Vec::new();
+
+                                    recording_file.read_to_end(&mut buffer).ok()?;
+
+                                    decode_preview(buffer, position)
+                                })
+                                .await
+                                .ok()
+                                .flatten(),
+                                Err(_) => None,
+                            };
+
+                            let response = match data {
+                                Some(data) => EngineResponse::PreviewClip {
+                                    id: id.clone(),
+                                    position,
+                                    sample_rate: PREVIEW_SAMPLE_RATE,
+                                    data,
+                                },
+                                None => EngineResponse::Nope {
+                                    command: EngineCommand::PreviewAt {
+                                        id: id.clone(),
+                                        position,
+                                    },
+                                    reason: NopeReason::NotFound,
+                                },
+                            };
+
+                            let waiting = preview_previews
+                                .lock()
+                                .await
+                                .remove(&(id, position))
+                                .unwrap_or_default();
+
+                            for (internal, uuid) in waiting {
+                                route_response(
+                                    internal,
+                                    &preview_internal_response_sender,
+                                    &preview_response_sender,
+                                    response.clone(),
+                                    uuid,
+                                );
+                            }
+                        });
                     }
-                    EngineCommand::SetPermissions(ref new_permissions) => {
-                        if internal {
-                            current_user_permissions = new_permissions.to_vec();
+                    EngineCommand::ExportHistory { since, format } => {
+                        let Ok(data) = export::export_history(&database, since, format).await
+                        else {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::Nope {
+                                    command,
+                                    reason: NopeReason::Unspecified,
+                                },
+                                uuid,
+                            );
+                            continue;
+                        };
 
-                            let _ = internal_response_sender.send(EngineResponse::Permissions(
-                                current_user_permissions.clone(),
-                            ));
-                        } else {
-                            let _ = response_sender.send((EngineResponse::Nope(command), uuid));
+                        let total_len = data.len();
+
+                        for (index, chunk) in data.chunks(EXPORT_CHUNK_SIZE).enumerate() {
+                            route_response(
+                                internal,
+                                &internal_response_sender,
+                                &response_sender,
+                                EngineResponse::HistoryExport {
+                                    format,
+                                    offset: index * EXPORT_CHUNK_SIZE,
+                                    total_len,
+                                    data: chunk.to_vec(),
+                                },
+                                uuid,
+                            );
                         }
                     }
                 };
             }
-        })
+        });
+
+        EngineTask {
+            handle,
+            cancellation,
+        }
     }
 
     fn start_command_relay(
-        &mut self,
+        &self,
+        peer: String,
         mut response_receiver: mpsc::Receiver<EngineResponse>,
         command_sender: mpsc::Sender<EngineCommand>,
-    ) -> JoinHandle<()> {
+    ) -> EngineTask {
         let mut command_receiver = self.engine_command_sender.subscribe();
         let response_sender = self.engine_response_sender.clone();
 
         let database = self.database.clone();
         let sequencer = self.sequencer.clone();
+        let diagnostics = self.diagnostics.clone();
 
-        tokio::spawn(async move {
+        let cancellation = CancellationToken::new();
+        let relay_cancellation = cancellation.clone();
+
+        let handle = tokio::spawn(async move {
             let mut remote_device_permissions = Vec::<Permission>::new();
 
+            let mut remote_playlists = HashMap::<String, PlaylistMetadata>::new();
+            let mut follower = Follower::default();
+            let mut pending_playlist_caches = HashSet::<String>::new();
+
             loop {
                 tokio::select! {
+                    _ = relay_cancellation.cancelled() => return,
                     response = response_receiver.recv() => if let Some(response) = response {
                         match response {
                             EngineResponse::RecordingMetadata(recording_metadata) => {
-                                if permission_exists(&remote_device_permissions, Permission::Transfer) {
-                                    let _ = database.get_recording_metadata(recording_metadata.recording.id.clone());
+                                if let Some(database) = &database {
+                                    if remote_device_permissions.contains(&Permission::Transfer) {
+                                        if let Err(error) = database.get_recording_metadata(recording_metadata.recording.id.clone()).await {
+                                            diagnostics.record_error("RecordingMetadata", &error);
+                                        }
+                                    }
+                                }
+
+                                let _ = response_sender.send(EngineResponse::RecordingMetadata(recording_metadata));
+                            },
+                            EngineResponse::RecordingFile((id, data)) => {
+                                if let Some(database) = &database {
+                                    if remote_device_permissions.contains(&Permission::Transfer) && database.storage_state().await == StorageState::Available {
+                                        if let Err(error) = database.set_recording_file(id.clone(), Some(data.clone()), AudioSource::Transfer { from_device: peer.clone() }).await {
+                                            diagnostics.record_error("RecordingFile", &error);
+                                        }
+                                    }
+                                }
+
+                                relay_command(&command_sender, EngineCommand::TransferAck { id: id.clone() }, &diagnostics).await;
+
+                                let _ = response_sender.send(EngineResponse::RecordingFile((id, data)));
+                            },
+                            EngineResponse::StreamChunk(chunk) => {
+                                if let (Some(sequencer), Some(output)) = (&sequencer, follower.decode(&chunk)) {
+                                    sequencer.push_stream_chunk(output).await;
                                 }
+                            },
+                            EngineResponse::StreamStopped => {
+                                follower.stop();
 
-                                let _ = response_sender.send(EngineResponse::RecordingMetadata(recording_metadata));
+                                if let Some(sequencer) = &sequencer {
+                                    sequencer.follow(false).await;
+                                }
+
+                                let _ = response_sender.send(EngineResponse::StreamStopped);
                             },
-                            EngineResponse::RecordingFile((id, data)) => {
-                                if permission_exists(&remote_device_permissions, Permission::Transfer) {
-                                    database.set_recording_file(id.clone(), Some(data.clone())).await;
+                            EngineResponse::PlaylistMetadata { metadata, .. } => {
+                                if pending_playlist_caches.remove(&metadata.id) {
+                                    if let Some(database) = &database {
+                                        let _ = response_sender.send(cache_remote_playlist(database, metadata.clone()).await);
+                                    }
                                 }
 
-                                let _ = response_sender.send(EngineResponse::RecordingFile((id, data)));
+                                remote_playlists.insert(metadata.id.clone(), metadata.clone());
+
+                                let _ = response_sender.send(EngineResponse::PlaylistMetadata { metadata, origin: PlaylistOrigin::Remote });
                             },
-                            EngineResponse::PlaylistMetadata(playlist_metadata) => {
-                                if permission_exists(&remote_device_permissions, Permission::Playlist) {
-                                    database.set_playlist(playlist_metadata.clone()).await;
+                            EngineResponse::Playlists { playlists, .. } => {
+                                for playlist in &playlists {
+                                    remote_playlists.insert(playlist.id.clone(), playlist.clone());
                                 }
 
-                                let _ = response_sender.send(EngineResponse::PlaylistMetadata(playlist_metadata));
+                                let _ = response_sender.send(EngineResponse::Playlists { playlists, origin: PlaylistOrigin::Remote });
                             },
                             x => {
                                 let _ = response_sender.send(x);
@@ -704,151 +4477,361 @@ impl Engine {
                     command = command_receiver.recv() => if let Ok(command) = command {
                         match command {
                             EngineCommand::SendRecording((id, data)) => {
-                                database.set_recording_file(id.clone(), Some(data.clone())).await;
+                                if let Some(database) = &database {
+                                    match database.set_recording_file(id.clone(), Some(data.clone()), AudioSource::Transfer { from_device: LOCAL_DEVICE.to_owned() }).await {
+                                        Err(error @ (DatabaseError::DecodeFailed | DatabaseError::InsufficientStorage { .. })) => {
+                                            let _ = response_sender.send(EngineResponse::Nope { command: EngineCommand::SendRecording((id, data)), reason: database_nope_reason(&error) });
+
+                                            continue;
+                                        }
+                                        Err(error) => diagnostics.record_error("SendRecording", &error),
+                                        Ok(_) => {}
+                                    }
+                                }
+
+                                relay_command(&command_sender, EngineCommand::SendRecording((id, data)), &diagnostics).await;
+                            },
+                            EngineCommand::SetPlaylistMetadata(mut playlist_metadata) => {
+                                if let Some(database) = &database {
+                                    playlist_metadata = database.set_playlist(playlist_metadata).await;
+
+                                    database.record_playlist_sync(&playlist_metadata).await;
+                                }
+
+                                relay_command(&command_sender, EngineCommand::SetPlaylistMetadata(playlist_metadata), &diagnostics).await;
+                            },
+                            EngineCommand::ResolvePlaylistConflict { id, keep } => {
+                                let Some(database) = &database else {
+                                    relay_command(&command_sender, EngineCommand::ResolvePlaylistConflict { id, keep }, &diagnostics).await;
+
+                                    continue;
+                                };
 
-                                let _ = command_sender.send(EngineCommand::SendRecording((id, data)));
+                                let Ok(metadata) = database.resolve_playlist_conflict(id.clone(), keep).await else {
+                                    let _ = response_sender.send(EngineResponse::Nope { command: EngineCommand::ResolvePlaylistConflict { id, keep }, reason: NopeReason::NotFound });
+
+                                    continue;
+                                };
+
+                                if keep != ConflictResolution::Remote {
+                                    relay_command(&command_sender, EngineCommand::SetPlaylistMetadata(metadata.clone()), &diagnostics).await;
+                                }
+
+                                let _ = response_sender.send(EngineResponse::PlaylistMetadata { metadata, origin: PlaylistOrigin::Local });
+                            },
+                            EngineCommand::CachePlaylist { id } => {
+                                let Some(database) = &database else {
+                                    let _ = response_sender.send(EngineResponse::Nope { command: EngineCommand::CachePlaylist { id }, reason: NopeReason::Unspecified });
+
+                                    continue;
+                                };
+
+                                let Some(metadata) = remote_playlists.get(&id).cloned() else {
+                                    pending_playlist_caches.insert(id.clone());
+
+                                    relay_command(&command_sender, EngineCommand::PlaylistMetadata { id }, &diagnostics).await;
+
+                                    continue;
+                                };
+
+                                let _ = response_sender.send(cache_remote_playlist(database, metadata).await);
+                            },
+                            EngineCommand::SetVolume { volume } => {
+                                if let Some(sequencer) = &sequencer {
+                                    let volume = sequencer.set_volume(volume).await;
+
+                                    let _ = response_sender.send(EngineResponse::Volume { volume });
+                                } else {
+                                    relay_command(&command_sender, EngineCommand::SetVolume { volume }, &diagnostics).await;
+                                }
                             },
-                            EngineCommand::SetPlaylistMetadata(playlist_metadata) => {
-                                database.set_playlist(playlist_metadata.clone()).await;
+                            EngineCommand::Mute { muted } => {
+                                if let Some(sequencer) = &sequencer {
+                                    let volume = sequencer.mute(muted).await;
 
-                                let _ = command_sender.send(EngineCommand::SetPlaylistMetadata(playlist_metadata));
+                                    let _ = response_sender.send(EngineResponse::Volume { volume });
+                                } else {
+                                    relay_command(&command_sender, EngineCommand::Mute { muted }, &diagnostics).await;
+                                }
                             },
-                            EngineCommand::SetVolume(volume) => {
-                                let _ = sequencer.set_volume(volume).await;
+                            EngineCommand::FollowPlayback { enabled } => {
+                                if enabled {
+                                    if let Err(reason) = follower.start() {
+                                        let _ = response_sender.send(EngineResponse::Nope { command: EngineCommand::FollowPlayback { enabled }, reason });
+
+                                        continue;
+                                    }
+                                } else {
+                                    follower.stop();
+                                }
+
+                                if let Some(sequencer) = &sequencer {
+                                    sequencer.follow(enabled).await;
+                                }
+
+                                relay_command(&command_sender, EngineCommand::StreamListen { enabled }, &diagnostics).await;
                             },
                             EngineCommand::SetPermissions(ref new_permissions) => {
                                 remote_device_permissions = new_permissions.to_vec();
                             }
                             x => {
-                                let _ = command_sender.send(x);
+                                relay_command(&command_sender, x, &diagnostics).await;
                             }
                         }
                     }
                 }
             }
-        })
+        });
+
+        EngineTask {
+            handle,
+            cancellation,
+        }
     }
 
-    pub fn connect_to_local(&mut self) -> Result<(), EngineLocalConnectionError> {
+    pub async fn connect_to_local(&self) -> Result<(), EngineLocalConnectionError> {
+        let mut location = self.location.lock().await;
+
         if matches!(
-            self.connection_status(),
-            EngineConnectionStatus::ConnectedLocal
+            self.location_status(&location),
+            EngineConnectionStatus::ConnectedLocal | EngineConnectionStatus::ConnectedLocalClient
         ) {
             return Ok(());
         }
 
-        let Ok((ipc_server, receiver, sender)) = IPCServer::create() else {
-            let Ok((ipc_client, receiver, sender)) = IPCClient::create("playit.sock".to_owned())
-            else {
-                return Err(EngineLocalConnectionError::StartFailed);
-            };
-
-            let command_relay = self.start_command_relay(receiver, sender);
-
-            take_mut::take(&mut self.location, |old_engine_location| {
-                match old_engine_location {
-                    EngineLocation::Invalid => {}
-                    EngineLocation::Internal {
-                        ipc_server: _,
-                        command_processor,
-                    } => {
-                        command_processor.abort();
-                    }
-                    EngineLocation::Local {
-                        ipc_client: _,
-                        command_relay,
-                    }
-                    | EngineLocation::Remote {
-                        ipc_client: _,
-                        command_relay,
-                    } => {
-                        command_relay.abort();
-                    }
-                };
+        let (Some(database), Some(sequencer)) = (self.database.clone(), self.sequencer.clone())
+        else {
+            return self.connect_to_local_client(&mut location).await;
+        };
 
-                EngineLocation::Local {
-                    ipc_client,
-                    command_relay,
-                }
-            });
+        let (ipc_server, receiver, sender) = match IPCServer::create(
+            self.local_address.clone(),
+            self.socket_mode,
+            self.diagnostics.clone(),
+        ) {
+            Ok(created) => created,
+            Err(error) => {
+                self.diagnostics.record_error("IPCServer", &error);
 
-            return Ok(());
+                return self.connect_to_local_client(&mut location).await;
+            }
         };
 
-        let command_processor = self.start_command_processor(receiver, sender);
+        self.shutdown_location(&mut location).await;
 
-        take_mut::take(&mut self.location, |old_engine_location| {
-            match old_engine_location {
-                EngineLocation::Invalid => {}
-                EngineLocation::Internal {
-                    ipc_server: _,
-                    command_processor,
-                } => {
-                    command_processor.abort();
-                }
-                EngineLocation::Local {
-                    ipc_client: _,
-                    command_relay,
-                }
-                | EngineLocation::Remote {
-                    ipc_client: _,
-                    command_relay,
-                } => {
-                    command_relay.abort();
-                }
-            };
+        let listener_monitor = ipc_server.monitor();
+        let stream_feed = ipc_server.stream_feed();
 
+        let command_processor = self.start_command_processor(
+            database,
+            sequencer,
+            receiver,
+            sender,
+            stream_feed,
+            listener_monitor,
+        );
+
+        self.replace_location(
+            &mut location,
             EngineLocation::Internal {
                 ipc_server,
                 command_processor,
-            }
-        });
+            },
+        )
+        .await;
+
+        Ok(())
+    }
+
+    async fn connect_to_local_client(
+        &self,
+        location: &mut EngineLocation,
+    ) -> Result<(), EngineLocalConnectionError> {
+        let (ipc_client, receiver, sender) =
+            match IPCClient::create(self.local_address.clone(), self.diagnostics.clone()).await {
+                Ok(created) => created,
+                Err(error) => {
+                    self.diagnostics.record_error("IPCClient", &error);
+
+                    return Err(EngineLocalConnectionError::StartFailed);
+                }
+            };
+
+        self.shutdown_location(location).await;
+
+        let command_relay = self.start_command_relay(self.local_address.clone(), receiver, sender);
+
+        self.replace_location(
+            location,
+            EngineLocation::Local {
+                ipc_client,
+                command_relay,
+            },
+        )
+        .await;
 
         Ok(())
     }
 
     pub async fn connect_to_remote(
-        &mut self,
+        &self,
         address: String,
     ) -> Result<(), EngineRemoteConnectionError> {
-        let Ok((new_ipc_client, receiver, sender)) = IPCClient::create(address) else {
-            return Err(EngineRemoteConnectionError::ConnectionFailed);
-        };
+        let mut location = self.location.lock().await;
 
-        let command_relay = self.start_command_relay(receiver, sender);
+        let (new_ipc_client, receiver, sender) =
+            match IPCClient::create(address.clone(), self.diagnostics.clone()).await {
+                Ok(created) => created,
+                Err(error) => {
+                    self.diagnostics.record_error("IPCClient", &error);
 
-        take_mut::take(&mut self.location, |old_engine_location| {
-            match old_engine_location {
-                EngineLocation::Invalid => {}
-                EngineLocation::Internal {
-                    ipc_server: _,
-                    command_processor,
-                } => {
-                    command_processor.abort();
-                }
-                EngineLocation::Local {
-                    ipc_client: _,
-                    command_relay,
-                }
-                | EngineLocation::Remote {
-                    ipc_client: _,
-                    command_relay,
-                } => {
-                    command_relay.abort();
+                    return Err(EngineRemoteConnectionError::ConnectionFailed);
                 }
             };
 
+        self.shutdown_location(&mut location).await;
+
+        let command_relay = self.start_command_relay(address, receiver, sender);
+
+        self.replace_location(
+            &mut location,
             EngineLocation::Remote {
                 ipc_client: new_ipc_client,
                 command_relay,
-            }
-        });
+            },
+        )
+        .await;
 
         Ok(())
     }
 
+    async fn shutdown_location(&self, location: &mut EngineLocation) {
+        self.replace_location(location, EngineLocation::Invalid)
+            .await;
+    }
+
+    async fn replace_location(&self, location: &mut EngineLocation, new_location: EngineLocation) {
+        let old_location = std::mem::replace(location, new_location);
+
+        match old_location {
+            EngineLocation::Invalid => {}
+            EngineLocation::Internal {
+                ipc_server,
+                command_processor,
+            } => {
+                command_processor.shutdown().await;
+
+                ipc_server.shutdown().await;
+            }
+            EngineLocation::Local {
+                ipc_client,
+                command_relay,
+            }
+            | EngineLocation::Remote {
+                ipc_client,
+                command_relay,
+            } => {
+                command_relay.shutdown().await;
+
+                drop(ipc_client);
+            }
+        };
+
+        let status = self.location_status(location);
+
+        self.status_sender.send_replace(status);
+        self.diagnostics.record_location(status);
+    }
+
+    pub async fn shutdown(&self) {
+        self.shutdown_location(&mut *self.location.lock().await)
+            .await;
+
+        let (Some(sequencer), Some(database)) = (&self.sequencer, &self.database) else {
+            return;
+        };
+
+        let state = resolve_state(sequencer, database).await;
+
+        if let Some(state_mirror) = self.state_mirror.lock().await.take() {
+            state_mirror.finish(&state).await;
+        }
+
+        database
+            .record_shutdown(state.recording_id, state.position)
+            .await;
+
+        if database
+            .get_setting::<bool>(PERSIST_PLAYER_STATE_SETTING)
+            .await
+            != Some(false)
+        {
+            database
+                .set_setting(PLAYER_STATE_SETTING, &sequencer.saved_state().await)
+                .await;
+        }
+
+        sequencer.park().await;
+
+        database.flush().await;
+    }
+
+    pub fn subscribe_connection_status(&self) -> watch::Receiver<EngineConnectionStatus> {
+        self.status_sender.subscribe()
+    }
+
+    pub fn verify_library(&self) -> JobHandle {
+        let job_id = Uuid::new_v4();
+
+        let job_handle = JobHandle {
+            job_id,
+            command_sender: self.engine_command_sender.clone(),
+            response_receiver: self.engine_response_sender.subscribe(),
+            finished: false,
+        };
+
+        let _ = self
+            .engine_command_sender
+            .send(EngineCommand::VerifyLibrary { job_id });
+
+        job_handle
+    }
+
+    pub fn refresh_library(&self) -> JobHandle {
+        let job_id = Uuid::new_v4();
+
+        let job_handle = JobHandle {
+            job_id,
+            command_sender: self.engine_command_sender.clone(),
+            response_receiver: self.engine_response_sender.subscribe(),
+            finished: false,
+        };
+
+        let _ = self
+            .engine_command_sender
+            .send(EngineCommand::RefreshLibrary { job_id });
+
+        job_handle
+    }
+
+    pub fn subscribe_events(&self) -> impl Stream<Item = EngineEvent> {
+        events::translate_events(
+            self.engine_response_sender.subscribe(),
+            self.status_sender.subscribe(),
+            self.database.clone(),
+        )
+    }
+
+    pub fn subscribe_library_events(&self) -> Option<broadcast::Receiver<DatabaseEvent>> {
+        self.database.as_ref().map(Database::subscribe)
+    }
+
     pub fn connection_status(&self) -> EngineConnectionStatus {
-        match &self.location {
+        *self.status_sender.borrow()
+    }
+
+    fn location_status(&self, location: &EngineLocation) -> EngineConnectionStatus {
+        match location {
             EngineLocation::Invalid => EngineConnectionStatus::Disconnected,
             EngineLocation::Internal {
                 ipc_server: _,
@@ -857,7 +4840,13 @@ impl Engine {
             EngineLocation::Local {
                 ipc_client: _,
                 command_relay: _,
-            } => EngineConnectionStatus::ConnectedLocal,
+            } => {
+                if self.database.is_none() {
+                    EngineConnectionStatus::ConnectedLocalClient
+                } else {
+                    EngineConnectionStatus::ConnectedLocal
+                }
+            }
             EngineLocation::Remote {
                 ipc_client: _,
                 command_relay: _,
@@ -866,10 +4855,320 @@ impl Engine {
     }
 }
 
+async fn probe_local_engine(
+    address: String,
+    diagnostics: DiagnosticLog,
+) -> Option<(
+    IPCClient,
+    mpsc::Receiver<EngineResponse>,
+    mpsc::Sender<EngineCommand>,
+)> {
+    let Ok((ipc_client, mut receiver, sender)) = IPCClient::create(address, diagnostics).await
+    else {
+        return None;
+    };
+
+    if sender.send(EngineCommand::Capabilities).await.is_err() {
+        return None;
+    }
+
+    let handshake = tokio::time::timeout(LOCAL_PROBE_TIMEOUT, async {
+        loop {
+            match receiver.recv().await {
+                Some(EngineResponse::Capabilities { .. }) => return true,
+                Some(_) => continue,
+                None => return false,
+            }
+        }
+    });
+
+    if let Ok(true) = handshake.await {
+        Some((ipc_client, receiver, sender))
+    } else {
+        None
+    }
+}
+
+async fn hand_off(
+    database: &Database,
+    sequencer: &Sequencer,
+    diagnostics: &DiagnosticLog,
+    target: String,
+) -> bool {
+    let Some(snapshot) = sequencer.snapshot().await else {
+        return false;
+    };
+
+    let recording_id = snapshot.recording_id.clone();
+
+    let Some(local_metadata) = database
+        .get_cached_recording_metadata(recording_id.clone())
+        .await
+    else {
+        return false;
+    };
+
+    let (_ipc_client, mut receiver, sender) =
+        match IPCClient::create(target, diagnostics.clone()).await {
+            Ok(created) => created,
+            Err(error) => {
+                diagnostics.record_error("HandOff", &error);
+
+                return false;
+            }
+        };
+
+    if sender
+        .send(EngineCommand::RecordingMetadata {
+            id: recording_id.clone(),
+        })
+        .await
+        .is_err()
+    {
+        return false;
+    }
+
+    let remote_audio_file_hash = match await_reply(&mut receiver, "RecordingMetadata").await {
+        Some(EngineResponse::RecordingMetadata(remote_metadata)) => remote_metadata.audio_file_hash,
+        Some(_) => None,
+        None => return false,
+    };
+
+    if remote_audio_file_hash != local_metadata.audio_file_hash {
+        let Some(audio_file_hash) = local_metadata.audio_file_hash.clone() else {
+            return false;
+        };
+
+        if !is_transferable(&local_metadata) {
+            return false;
+        }
+
+        let Ok(mut recording_file) = database.get_recording_file(recording_id.clone()).await else {
+            return false;
+        };
+
+        let mut buffer = Vec::new();
+
+        if recording_file.read_to_end(&mut buffer).is_err() {
+            return false;
+        }
+
+        if sender
+            .send(EngineCommand::SendVerifiedRecording {
+                id: recording_id.clone(),
+                sha256: audio_file_hash,
+                data: buffer,
+            })
+            .await
+            .is_err()
+        {
+            return false;
+        }
+
+        let Some(EngineResponse::RecordingStored { .. }) =
+            await_reply(&mut receiver, "SendVerifiedRecording").await
+        else {
+            return false;
+        };
+    }
+
+    let Some(snapshot) = sequencer.snapshot().await else {
+        return false;
+    };
+
+    if snapshot.recording_id != recording_id {
+        return false;
+    }
+
+    if sender
+        .send(EngineCommand::AdoptSession(snapshot))
+        .await
+        .is_err()
+    {
+        return false;
+    }
+
+    let Some(EngineResponse::Ok { .. }) = await_reply(&mut receiver, "AdoptSession").await else {
+        return false;
+    };
+
+    sequencer.pause().await;
+
+    true
+}
+
+async fn await_reply(
+    receiver: &mut mpsc::Receiver<EngineResponse>,
+    kind: &str,
+) -> Option<EngineResponse> {
+    let reply = tokio::time::timeout(HAND_OFF_TIMEOUT, async {
+        loop {
+            let response = receiver.recv().await?;
+
+            let matches = match &response {
+                EngineResponse::Ok { command } | EngineResponse::Nope { command, .. } => {
+                    command.kind() == kind
+                }
+                EngineResponse::RecordingMetadata(_) => kind == "RecordingMetadata",
+                EngineResponse::RecordingStored { .. } => kind == "SendVerifiedRecording",
+                _ => false,
+            };
+
+            if matches {
+                return Some(response);
+            }
+        }
+    });
+
+    reply.await.ok().flatten()
+}
+
+async fn cache_remote_playlist(database: &Database, metadata: PlaylistMetadata) -> EngineResponse {
+    let id = metadata.id.clone();
+
+    match database.apply_remote_playlist(metadata.clone()).await {
+        PlaylistSync::Applied => EngineResponse::PlaylistMetadata {
+            metadata,
+            origin: PlaylistOrigin::Local,
+        },
+        PlaylistSync::Kept => EngineResponse::PlaylistMetadata {
+            metadata: database.get_playlist(id).await.unwrap_or(metadata),
+            origin: PlaylistOrigin::Local,
+        },
+        PlaylistSync::Conflict {
+            local_modified,
+            remote_modified,
+        } => EngineResponse::PlaylistConflict {
+            id,
+            local_modified,
+            remote_modified,
+        },
+    }
+}
+
+fn queue_broadcast(
+    queue: Vec<String>,
+    threshold: usize,
+    broadcast_queue: &mut Vec<String>,
+) -> EngineResponse {
+    let previous = std::mem::replace(broadcast_queue, queue.clone());
+
+    if queue.len() <= threshold {
+        return EngineResponse::Queue { queue };
+    }
+
+    let offset = previous
+        .iter()
+        .zip(&queue)
+        .take_while(|(previous, current)| previous == current)
+        .count();
+
+    let suffix = previous[offset..]
+        .iter()
+        .rev()
+        .zip(queue[offset..].iter().rev())
+        .take_while(|(previous, current)| previous == current)
+        .count();
+
+    EngineResponse::QueueSpliced {
+        total: queue.len(),
+        offset,
+        removed: previous.len() - offset - suffix,
+        inserted: queue[offset..queue.len() - suffix].to_vec(),
+    }
+}
+
+fn rejected_ids(rejected: &[(String, QueueRejectReason)]) -> Vec<String> {
+    rejected.iter().map(|(id, _)| id.clone()).collect()
+}
+
+fn validation_rejections(validation: &PlaylistValidation) -> Vec<(String, QueueRejectReason)> {
+    validation
+        .missing_metadata
+        .iter()
+        .map(|id| (id.clone(), QueueRejectReason::UnknownRecording))
+        .chain(
+            validation
+                .missing_audio
+                .iter()
+                .map(|id| (id.clone(), QueueRejectReason::MissingAudio)),
+        )
+        .collect()
+}
+
+async fn idle_deadline(timeout: Option<Duration>, since: tokio::time::Instant) {
+    match timeout {
+        Some(timeout) => tokio::time::sleep_until(since + timeout).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn recv_stream<T: Clone>(
+    receiver: &mut Option<broadcast::Receiver<T>>,
+) -> Result<T, broadcast::error::RecvError> {
+    match receiver {
+        Some(receiver) => receiver.recv().await,
+        None => std::future::pending().await,
+    }
+}
+
+fn configured_settings(config: &EngineConfig) -> EngineSettings {
+    EngineSettings {
+        musicbrainz_base_url: config.musicbrainz_base_url.clone(),
+        musicbrainz_user_agent: config
+            .musicbrainz_user_agent
+            .clone()
+            .unwrap_or_else(|| DEFAULT_USER_AGENT.to_owned()),
+        collation: None,
+    }
+}
+
+fn database_nope_reason(error: &DatabaseError) -> NopeReason {
+    match error {
+        DatabaseError::MusicbrainzFailure(ProviderError::RateLimited { retry_after }) => {
+            NopeReason::RateLimited {
+                retry_after: *retry_after,
+            }
+        }
+        DatabaseError::MusicbrainzFailure(ProviderError::NetworkUnavailable) => {
+            NopeReason::NetworkUnavailable
+        }
+        DatabaseError::MusicbrainzFailure(ProviderError::ServerError) => NopeReason::ServerError,
+        DatabaseError::MusicbrainzFailure(ProviderError::NotFound)
+        | DatabaseError::RecordingMetadataNotFound
+        | DatabaseError::PlaylistNotFound
+        | DatabaseError::ReleaseNotFound
+        | DatabaseError::ExternalFileMissing
+        | DatabaseError::TrashEntryNotFound => NopeReason::NotFound,
+        DatabaseError::DecodeFailed => NopeReason::DecodeFailed,
+        DatabaseError::HashMismatch => NopeReason::IntegrityMismatch,
+        DatabaseError::TransferOffsetMismatch { .. } => NopeReason::InvalidArgument,
+        DatabaseError::InsufficientStorage { needed, available } => {
+            NopeReason::InsufficientStorage {
+                needed: *needed,
+                available: *available,
+            }
+        }
+        _ => NopeReason::Unspecified,
+    }
+}
+
+async fn relay_command(
+    command_sender: &mpsc::Sender<EngineCommand>,
+    command: EngineCommand,
+    diagnostics: &DiagnosticLog,
+) {
+    let kind = command.kind();
+
+    if let Err(error) = command_sender.send(command).await {
+        diagnostics.record_error(kind, &error);
+    }
+}
+
 fn route_response(
     internal: bool,
     internal_sender: &broadcast::Sender<EngineResponse>,
-    remote_sender: &broadcast::Sender<(EngineResponse, Uuid)>,
+    remote_sender: &ChangeFeed,
     response: EngineResponse,
     uuid: Uuid,
 ) {
@@ -883,10 +5182,94 @@ fn route_response(
     };
 }
 
-fn permission_exists(permission_array: &Vec<Permission>, permission: Permission) -> bool {
-    if permission_array.iter().any(|e| *e == permission) {
-        true
-    } else {
-        false
+fn is_batchable(command: &EngineCommand) -> bool {
+    matches!(
+        command,
+        EngineCommand::GetState
+            | EngineCommand::GetPosition
+            | EngineCommand::Play(_)
+            | EngineCommand::PlayFromStart { .. }
+            | EngineCommand::Pause
+            | EngineCommand::Stop
+            | EngineCommand::Next
+            | EngineCommand::Previous
+            | EngineCommand::PlayQueueIndex { .. }
+            | EngineCommand::Seek(_)
+            | EngineCommand::SeekPercent { .. }
+            | EngineCommand::SeekRelative { .. }
+            | EngineCommand::Queue(_)
+            | EngineCommand::QueuePlaylist { .. }
+            | EngineCommand::QueueRelease { .. }
+            | EngineCommand::QueueAfterCurrentGroup { .. }
+            | EngineCommand::QueueNext { .. }
+            | EngineCommand::QueueTo { .. }
+            | EngineCommand::GetQueues
+            | EngineCommand::GetHistory { .. }
+            | EngineCommand::QueueRange { .. }
+            | EngineCommand::ShuffleQueue { .. }
+            | EngineCommand::ClearQueue { .. }
+            | EngineCommand::QueueRemove(_)
+            | EngineCommand::QueueMove { .. }
+            | EngineCommand::SetRadioMode { .. }
+            | EngineCommand::LoopMode(_)
+            | EngineCommand::QueueModel(_)
+            | EngineCommand::OnQueueEnd(_)
+            | EngineCommand::SmoothLevelTransition { .. }
+            | EngineCommand::PauseFade(_)
+            | EngineCommand::StopAfterCurrent { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn short_queues_are_sent_whole() {
+        let mut broadcast_queue = ids(&["a", "b"]);
+
+        let EngineResponse::Queue { queue } =
+            queue_broadcast(ids(&["a", "c"]), 2, &mut broadcast_queue)
+        else {
+            panic!("expected the whole queue");
+        };
+
+        assert_eq!(queue, ids(&["a", "c"]));
+        assert_eq!(broadcast_queue, ids(&["a", "c"]));
+    }
+
+    #[test]
+    fn long_queues_are_sent_as_splices() {
+        let mut broadcast_queue = ids(&["a", "b", "c", "d", "e"]);
+
+        let EngineResponse::QueueSpliced {
+            total,
+            offset,
+            removed,
+            inserted,
+        } = queue_broadcast(ids(&["a", "b", "x", "y", "e"]), 2, &mut broadcast_queue)
+        else {
+            panic!("expected a splice");
+        };
+
+        assert_eq!((total, offset, removed), (5, 2, 2));
+        assert_eq!(inserted, ids(&["x", "y"]));
+
+        let EngineResponse::QueueSpliced {
+            total,
+            offset,
+            removed,
+            inserted,
+        } = queue_broadcast(ids(&["b", "x", "y", "e"]), 2, &mut broadcast_queue)
+        else {
+            panic!("expected a splice");
+        };
+
+        assert_eq!((total, offset, removed), (4, 0, 1));
+        assert!(inserted.is_empty());
     }
 }
@@ -0,0 +1,75 @@
+use std::{collections::HashMap, time::Duration};
+
+use crate::CommandLatency;
+
+const BUCKET_COUNT: usize = 24;
+
+#[derive(Default)]
+pub struct LatencyRecorder {
+    histograms: HashMap<&'static str, [u64; BUCKET_COUNT + 1]>,
+}
+
+impl LatencyRecorder {
+    pub fn record(&mut self, command: &'static str, elapsed: Duration) {
+        let micros = elapsed.as_micros().max(1);
+        let bucket = ((u128::BITS - (micros - 1).leading_zeros()) as usize).min(BUCKET_COUNT);
+
+        self.histograms
+            .entry(command)
+            .or_insert([0; BUCKET_COUNT + 1])[bucket] += 1;
+    }
+
+    pub fn snapshot(&mut self, reset: bool) -> Vec<CommandLatency> {
+        let mut latencies = self
+            .histograms
+            .iter()
+            .map(|(command, buckets)| {
+                let count = buckets.iter().sum::<u64>();
+
+                CommandLatency {
+                    command: (*command).to_owned(),
+                    count,
+                    buckets: buckets.to_vec(),
+                    p50: percentile(buckets, count, 50),
+                    p99: percentile(buckets, count, 99),
+                }
+            })
+            .collect::<Vec<CommandLatency>>();
+
+        latencies.sort_by(|a, b| a.command.cmp(&b.command));
+
+        if reset {
+            self.reset();
+        }
+
+        latencies
+    }
+
+    pub fn reset(&mut self) {
+        self.histograms.clear();
+    }
+}
+
+fn bucket_bound(bucket: usize) -> Option<Duration> {
+    if bucket >= BUCKET_COUNT {
+        return None;
+    }
+
+    Some(Duration::from_micros(1 << bucket))
+}
+
+fn percentile(buckets: &[u64], count: u64, percent: u64) -> Option<Duration> {
+    let target = (count * percent).div_ceil(100).max(1);
+
+    let mut seen = 0;
+
+    for (bucket, bucket_count) in buckets.iter().enumerate() {
+        seen += bucket_count;
+
+        if seen >= target {
+            return Some(bucket_bound(bucket).unwrap_or(Duration::MAX));
+        }
+    }
+
+    None
+}
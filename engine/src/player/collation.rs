@@ -0,0 +1,152 @@
+use std::cmp::Ordering;
+
+use serde::{Deserialize, Serialize};
+use unicode_normalization::{char::is_combining_mark, UnicodeNormalization};
+
+const DEFAULT_ARTICLES: [&str; 7] = ["the", "a", "an", "la", "le", "les", "l'"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Collation {
+    #[serde(default = "default_articles")]
+    pub articles: Vec<String>,
+    #[serde(default = "default_true")]
+    pub strip_accents: bool,
+}
+
+impl Default for Collation {
+    fn default() -> Collation {
+        Collation {
+            articles: default_articles(),
+            strip_accents: true,
+        }
+    }
+}
+
+impl Collation {
+    pub fn sort_key(&self, text: &str) -> String {
+        let folded = text
+            .nfkd()
+            .filter(|c| !self.strip_accents || !is_combining_mark(*c))
+            .flat_map(char::to_lowercase)
+            .collect::<String>();
+
+        let folded = folded.trim();
+
+        for article in &self.articles {
+            let Some(rest) = folded.strip_prefix(article.as_str()) else {
+                continue;
+            };
+
+            if article.ends_with('\'') || rest.starts_with(char::is_whitespace) {
+                let rest = rest.trim_start();
+
+                if !rest.is_empty() {
+                    return rest.to_owned();
+                }
+            }
+        }
+
+        folded.to_owned()
+    }
+}
+
+pub fn sort_entries<T>(mut entries: Vec<(T, String, u64, String)>, descending: bool) -> Vec<T> {
+    entries.sort_by(|(_, a_text, a_number, a_id), (_, b_text, b_number, b_id)| {
+        let primary = a_number.cmp(b_number).then_with(|| a_text.cmp(b_text));
+
+        let primary = if descending {
+            primary.reverse()
+        } else {
+            primary
+        };
+
+        match primary {
+            Ordering::Equal => a_id.cmp(b_id),
+            ordering => ordering,
+        }
+    });
+
+    entries.into_iter().map(|(item, _, _, _)| item).collect()
+}
+
+fn default_articles() -> Vec<String> {
+    DEFAULT_ARTICLES
+        .iter()
+        .map(|article| (*article).to_owned())
+        .collect()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leading_articles_are_ignored() {
+        let collation = Collation::default();
+
+        assert_eq!(collation.sort_key("The Wall"), "wall");
+        assert_eq!(collation.sort_key("  A Love Supreme "), "love supreme");
+        assert_eq!(collation.sort_key("Les Misérables"), "miserables");
+        assert_eq!(collation.sort_key("L'Été indien"), "ete indien");
+        assert_eq!(collation.sort_key("l'amour"), "amour");
+
+        // Only whole words are articles, and a title that is nothing but an
+        // article keeps it.
+        assert_eq!(collation.sort_key("Theory"), "theory");
+        assert_eq!(collation.sort_key("Lasso"), "lasso");
+        assert_eq!(collation.sort_key("The"), "the");
+    }
+
+    #[test]
+    fn accents_fold_unless_disabled() {
+        let collation = Collation::default();
+
+        assert_eq!(collation.sort_key("Björk"), "bjork");
+        assert_eq!(collation.sort_key("Ça Plane Pour Moi"), "ca plane pour moi");
+        assert_eq!(collation.sort_key("ﬁn"), "fin");
+
+        let collation = Collation {
+            articles: Vec::new(),
+            strip_accents: false,
+        };
+
+        assert_eq!(
+            collation.sort_key("The Björk").nfc().collect::<String>(),
+            "the björk"
+        );
+    }
+
+    #[test]
+    fn ties_are_broken_by_id_in_either_direction() {
+        let entries = || {
+            vec![
+                ("c", "same".to_owned(), 0, "mbid-c".to_owned()),
+                ("a", "same".to_owned(), 0, "mbid-a".to_owned()),
+                ("z", "other".to_owned(), 0, "mbid-z".to_owned()),
+                ("b", "same".to_owned(), 0, "mbid-b".to_owned()),
+            ]
+        };
+
+        assert_eq!(sort_entries(entries(), false), ["z", "a", "b", "c"]);
+        assert_eq!(sort_entries(entries(), true), ["a", "b", "c", "z"]);
+
+        let mut reversed = entries();
+        reversed.reverse();
+
+        assert_eq!(sort_entries(reversed, false), ["z", "a", "b", "c"]);
+    }
+
+    #[test]
+    fn the_number_outranks_the_text() {
+        let entries = vec![
+            ("second", "a".to_owned(), 2, "mbid-1".to_owned()),
+            ("first", "b".to_owned(), 1, "mbid-2".to_owned()),
+        ];
+
+        assert_eq!(sort_entries(entries, false), ["first", "second"]);
+    }
+}
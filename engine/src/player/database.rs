@@ -1,88 +1,467 @@
 use std::{
+    collections::HashMap,
+    fmt::Debug,
     fs::{DirBuilder, File},
-    io::{BufReader, Write},
-    path::PathBuf,
-    sync::Arc,
-    time::Duration,
+    io::{self, BufReader, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use lazy_static::lazy_static;
-use musicbrainz_rs::{entity::recording::Recording, Fetch};
-use sled::Db;
-use tokio::{sync::Mutex, time};
+use musicbrainz_rs::entity::{
+    artist::Artist, artist_credit::ArtistCredit, recording::Recording, release::Release,
+};
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::json;
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{broadcast, Mutex},
+    time,
+};
+
+use crate::{ConflictResolution, Permission, StorageBackend};
+
+use super::{
+    embedded_tags::write_embedded_tags,
+    file_lock::RecordingFileLocks,
+    probe::{measure_duration, probe_audio, probe_audio_file},
+    provider::{take_work_relations, MetadataProvider, NetworkMonitor, ProviderError},
+    recovery::recover_recording_metadata,
+    storage::{available_space, write_atomically, StorageMonitor},
+    store::{open_store, MetadataStore},
+    waveform::compute_waveform,
+    AdoptionSummary, AudioFormat, AudioLocation, AudioSource, AuditEntry, JournalEntry,
+    LocalTagPatch, MissingFileEntry, NetworkState, PlayEvent, PlaylistMetadata, PlaylistPosition,
+    ProvenanceEntry, RecordingMetadata, ShutdownEntry, StorageState, TransitionReason, TrashEntry,
+    TrashedItem, WorkRelation, LOCAL_RECORDING_PREFIX,
+};
 
-use super::{PlaylistMetadata, RecordingMetadata};
+const DENIAL_COLLAPSE_WINDOW: u64 = 60;
+const HEALTH_CHECK_KEY: &str = "health_check";
+const ALIAS_DEPTH_LIMIT: usize = 8;
+const TAG_MAX_LENGTH: usize = 64;
+const TRASH_RECORDING_PREFIX: &str = "recording:";
+const TRASH_PLAYLIST_PREFIX: &str = "playlist:";
 
 lazy_static! {
     static ref root_db_path: PathBuf = PathBuf::from(&shellexpand::tilde("~/.playit/").to_string());
 }
 
+type Store = Arc<Mutex<Box<dyn MetadataStore>>>;
+
+#[derive(Debug, Clone)]
+pub enum DatabaseEvent {
+    RecordingUpserted(String),
+    RecordingDeleted(String),
+    PlaylistUpserted(String),
+    PlaylistDeleted(String),
+    AudioStored {
+        id: String,
+        hash: String,
+    },
+    ExternalFileMissing {
+        id: String,
+        path: PathBuf,
+    },
+    NetworkStateChanged(NetworkState),
+    StorageStateChanged(StorageState),
+    WriteFailed {
+        context: &'static str,
+        error: String,
+    },
+}
+
 pub struct Database {
-    metadata_db: Arc<Mutex<Db>>,
-    playlist_db: Arc<Mutex<Db>>,
+    metadata_db: Store,
+    playlist_db: Store,
+    history_db: Store,
+    journal_db: Store,
+    quarantine_db: Store,
+    settings_db: Store,
+    position_db: Store,
+    alias_db: Store,
+    tag_db: Store,
+    playlist_sync_db: Store,
+    playlist_conflict_db: Store,
+    release_db: Store,
+    trash_db: Store,
+
+    provider: Arc<Mutex<MetadataProvider>>,
+    network: Arc<Mutex<NetworkMonitor>>,
+    storage: Arc<Mutex<StorageMonitor>>,
+
+    waveform_jobs: Arc<Mutex<HashMap<String, Arc<AtomicBool>>>>,
+    file_locks: RecordingFileLocks,
+
+    events: broadcast::Sender<DatabaseEvent>,
+}
+
+pub enum RefreshOutcome {
+    Current,
+    Merged(String),
+    Orphaned,
+}
+
+pub enum PlaylistSync {
+    Applied,
+    Kept,
+    Conflict {
+        local_modified: u64,
+        remote_modified: u64,
+    },
+}
+
+#[derive(Default)]
+pub struct PlaylistValidation {
+    pub missing_metadata: Vec<String>,
+    pub missing_audio: Vec<String>,
+}
+
+pub enum AudioFileStatus {
+    Intact,
+    Missing,
+    Mismatched,
 }
 
+#[derive(Debug)]
 pub enum DatabaseError {
     InitializationFailed,
     DatabaseFailure,
-    MusicbrainzFailure,
+    MusicbrainzFailure(ProviderError),
     DataConversionFailure,
     RecordingMetadataNotFound,
     RecordingFileNotFound,
     PlaylistNotFound,
+    DecodeFailed,
+    InsufficientStorage { needed: u64, available: u64 },
+    InvalidTag,
+    ReleaseNotFound,
+    NotLocalRecording,
+    TagWriteFailed,
+    HashMismatch,
+    ExternalFileMissing,
+    TrashEntryNotFound,
+    TransferOffsetMismatch { received: u64 },
 }
 
 impl Database {
-    pub fn new() -> Result<Database, DatabaseError> {
+    pub fn new(
+        storage: &StorageBackend,
+        provider: MetadataProvider,
+    ) -> Result<Database, DatabaseError> {
         let _ = DirBuilder::new()
             .recursive(true)
             .create(root_db_path.clone().join("audio/"));
+        let _ = DirBuilder::new()
+            .recursive(true)
+            .create(root_db_path.clone().join("transfers/"));
+
+        let Ok(raw_metadata_db) = open_store(storage, &root_db_path.clone().join("metadata"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_playlist_db) = open_store(storage, &root_db_path.clone().join("playlist"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_history_db) = open_store(storage, &root_db_path.clone().join("history")) else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_journal_db) = open_store(storage, &root_db_path.clone().join("journal")) else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_quarantine_db) = open_store(storage, &root_db_path.clone().join("quarantine"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_settings_db) = open_store(storage, &root_db_path.clone().join("settings"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
 
-        let Ok(raw_metadata_db) = sled::open(root_db_path.clone().join("metadata")) else {
+        let Ok(raw_position_db) =
+            open_store(storage, &root_db_path.clone().join("playlist_position"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_alias_db) = open_store(storage, &root_db_path.clone().join("alias")) else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_tag_db) = open_store(storage, &root_db_path.clone().join("tag")) else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_playlist_sync_db) =
+            open_store(storage, &root_db_path.clone().join("playlist_sync"))
+        else {
             return Err(DatabaseError::InitializationFailed);
         };
-        let Ok(raw_playlist_db) = sled::open(root_db_path.clone().join("playlist")) else {
+        let Ok(raw_playlist_conflict_db) =
+            open_store(storage, &root_db_path.clone().join("playlist_conflict"))
+        else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_release_db) = open_store(storage, &root_db_path.clone().join("release")) else {
+            return Err(DatabaseError::InitializationFailed);
+        };
+        let Ok(raw_trash_db) = open_store(storage, &root_db_path.clone().join("trash")) else {
             return Err(DatabaseError::InitializationFailed);
         };
 
         let metadata_db = Arc::new(Mutex::new(raw_metadata_db));
         let playlist_db = Arc::new(Mutex::new(raw_playlist_db));
+        let history_db = Arc::new(Mutex::new(raw_history_db));
+        let journal_db = Arc::new(Mutex::new(raw_journal_db));
+        let quarantine_db = Arc::new(Mutex::new(raw_quarantine_db));
+        let settings_db = Arc::new(Mutex::new(raw_settings_db));
+        let position_db = Arc::new(Mutex::new(raw_position_db));
+        let alias_db = Arc::new(Mutex::new(raw_alias_db));
+        let tag_db = Arc::new(Mutex::new(raw_tag_db));
+        let playlist_sync_db = Arc::new(Mutex::new(raw_playlist_sync_db));
+        let playlist_conflict_db = Arc::new(Mutex::new(raw_playlist_conflict_db));
+        let release_db = Arc::new(Mutex::new(raw_release_db));
+        let trash_db = Arc::new(Mutex::new(raw_trash_db));
 
         let metadata_db_copy = metadata_db.clone();
         let playlist_db_copy = playlist_db.clone();
+        let history_db_copy = history_db.clone();
+        let journal_db_copy = journal_db.clone();
+        let quarantine_db_copy = quarantine_db.clone();
+        let settings_db_copy = settings_db.clone();
+        let position_db_copy = position_db.clone();
+        let alias_db_copy = alias_db.clone();
+        let tag_db_copy = tag_db.clone();
+        let playlist_sync_db_copy = playlist_sync_db.clone();
+        let playlist_conflict_db_copy = playlist_conflict_db.clone();
+        let release_db_copy = release_db.clone();
+        let trash_db_copy = trash_db.clone();
+
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = metadata_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = playlist_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = history_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = journal_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = quarantine_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = settings_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = position_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = alias_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = tag_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
+
+                let _ = playlist_sync_db_copy.lock().await.flush();
+            }
+        });
+        tokio::spawn(async move {
+            loop {
+                time::sleep(Duration::from_secs(30)).await;
 
+                let _ = playlist_conflict_db_copy.lock().await.flush();
+            }
+        });
         tokio::spawn(async move {
             loop {
                 time::sleep(Duration::from_secs(30)).await;
 
-                let _ = metadata_db_copy.lock().await.flush_async().await;
+                let _ = release_db_copy.lock().await.flush();
             }
         });
         tokio::spawn(async move {
             loop {
                 time::sleep(Duration::from_secs(30)).await;
 
-                let _ = playlist_db_copy.lock().await.flush_async().await;
+                let _ = trash_db_copy.lock().await.flush();
             }
         });
 
+        let (events, _) = broadcast::channel(64);
+
         Ok(Database {
             metadata_db,
             playlist_db,
+            history_db,
+            journal_db,
+            quarantine_db,
+            settings_db,
+            position_db,
+            alias_db,
+            tag_db,
+            playlist_sync_db,
+            playlist_conflict_db,
+            release_db,
+            trash_db,
+
+            provider: Arc::new(Mutex::new(provider)),
+            network: Arc::new(Mutex::new(NetworkMonitor::new())),
+            storage: Arc::new(Mutex::new(StorageMonitor::new(available_space))),
+
+            waveform_jobs: Arc::new(Mutex::new(HashMap::new())),
+            file_locks: RecordingFileLocks::default(),
+
+            events,
         })
     }
 
+    pub async fn set_provider(&self, provider: MetadataProvider) {
+        *self.provider.lock().await = provider;
+    }
+
+    pub async fn network_state(&self) -> NetworkState {
+        self.network.lock().await.state()
+    }
+
+    pub async fn set_storage_reserve(&self, reserve: u64) {
+        self.storage.lock().await.set_reserve(reserve);
+    }
+
+    pub async fn free_space(&self) -> Option<u64> {
+        self.storage.lock().await.available(&root_db_path)
+    }
+
+    pub async fn storage_state(&self) -> StorageState {
+        let mut locked_storage = self.storage.lock().await;
+
+        if let Some(state) = locked_storage.observe(&root_db_path) {
+            let _ = self.events.send(DatabaseEvent::StorageStateChanged(state));
+        }
+
+        locked_storage.state()
+    }
+
+    async fn reserve_storage(&self, needed: u64) -> Result<(), DatabaseError> {
+        let checked = self.storage.lock().await.check(&root_db_path, needed);
+
+        self.storage_state().await;
+
+        checked.map_err(|available| DatabaseError::InsufficientStorage { needed, available })
+    }
+
+    async fn observe_network<T>(
+        &self,
+        result: Result<T, ProviderError>,
+    ) -> Result<T, ProviderError> {
+        if let Some(state) = self.network.lock().await.observe(&result) {
+            let _ = self.events.send(DatabaseEvent::NetworkStateChanged(state));
+        }
+
+        result
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DatabaseEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn flush(&self) {
+        for db in [
+            &self.metadata_db,
+            &self.playlist_db,
+            &self.history_db,
+            &self.journal_db,
+            &self.quarantine_db,
+            &self.settings_db,
+            &self.position_db,
+            &self.alias_db,
+            &self.tag_db,
+            &self.playlist_sync_db,
+            &self.playlist_conflict_db,
+            &self.release_db,
+            &self.trash_db,
+        ] {
+            if let Err(error) = db.lock().await.flush() {
+                self.write_failed("Flush", error);
+            }
+        }
+    }
+
     pub async fn get_recording_file(&self, id: String) -> Result<BufReader<File>, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let file_guard = self.file_locks.read(&id).await;
+
         let Ok(metadata) = self.get_recording_metadata(id.clone()).await else {
             return Err(DatabaseError::RecordingMetadataNotFound);
         };
 
+        if let AudioLocation::External { path, .. } = &metadata.audio_location {
+            return self.open_external_file(id, path).await.map(BufReader::new);
+        }
+
         let Some(audio_file_hash) = metadata.audio_file_hash.clone() else {
             return Err(DatabaseError::RecordingFileNotFound);
         };
 
-        let Ok(file) = File::open(root_db_path.clone().join("audio/").join(audio_file_hash)) else {
-            let _ = self.set_recording_file(id, None);
+        let audio_file_path = root_db_path.clone().join("audio/").join(&audio_file_hash);
+
+        let Ok(file) = File::open(&audio_file_path) else {
+            drop(file_guard);
+
+            self.record_missing_file(id.clone(), audio_file_hash, audio_file_path)
+                .await;
+
+            if let Some(file) = self.relink_recording_file(id.clone(), metadata).await {
+                return Ok(BufReader::new(file));
+            }
+
+            let _ = self
+                .set_recording_file(id, None, AudioSource::Unknown)
+                .await;
 
             return Err(DatabaseError::RecordingFileNotFound);
         };
@@ -90,110 +469,2515 @@ impl Database {
         Ok(BufReader::new(file))
     }
 
-    pub async fn set_recording_file(&self, id: String, file_contents: Option<Vec<u8>>) {
-        let Ok(mut metadata) = self.get_recording_metadata(id.clone()).await else {
-            return;
-        };
+    async fn open_external_file(&self, id: String, path: &Path) -> Result<File, DatabaseError> {
+        File::open(path).map_err(|_| self.external_file_missing(id, path))
+    }
 
-        let Some(file_contents) = file_contents else {
-            metadata.audio_file_hash = Option::None;
+    fn write_failed(&self, context: &'static str, error: impl Debug) {
+        let _ = self.events.send(DatabaseEvent::WriteFailed {
+            context,
+            error: format!("{:?}", error),
+        });
+    }
 
-            if let Ok(metadata_bytes) = serde_json::to_vec(&metadata) {
-                let _ = self.metadata_db.lock().await.insert(id, metadata_bytes);
-            };
+    fn external_file_missing(&self, id: String, path: &Path) -> DatabaseError {
+        let _ = self.events.send(DatabaseEvent::ExternalFileMissing {
+            id,
+            path: path.to_owned(),
+        });
 
-            return;
-        };
+        DatabaseError::ExternalFileMissing
+    }
 
-        let audio_file_hash = sha256::digest(&file_contents);
+    async fn relink_recording_file(
+        &self,
+        id: String,
+        mut metadata: RecordingMetadata,
+    ) -> Option<File> {
+        let variant = metadata
+            .provenance
+            .iter()
+            .rev()
+            .filter(|entry| Some(&entry.audio_file_hash) != metadata.audio_file_hash.as_ref())
+            .find(|entry| {
+                root_db_path
+                    .clone()
+                    .join("audio/")
+                    .join(&entry.audio_file_hash)
+                    .is_file()
+            })?
+            .clone();
 
-        let Ok(mut file) = File::create(
-            root_db_path
-                .clone()
-                .join("audio/")
-                .join(audio_file_hash.clone()),
-        ) else {
+        let audio_file_path = root_db_path
+            .clone()
+            .join("audio/")
+            .join(&variant.audio_file_hash);
+
+        let file_contents = std::fs::read(&audio_file_path).ok()?;
+
+        metadata.audio_format = Some(probe_audio(&file_contents).ok()?);
+        metadata.waveform = None;
+        metadata.loudness = None;
+        metadata.audio_file_hash = Some(variant.audio_file_hash.clone());
+        metadata.audio_source = variant.source;
+
+        let metadata_bytes = serde_json::to_vec(&metadata).ok()?;
+
+        self.metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .ok()?;
+
+        let _ = self
+            .events
+            .send(DatabaseEvent::RecordingUpserted(id.clone()));
+        let _ = self.events.send(DatabaseEvent::AudioStored {
+            id,
+            hash: variant.audio_file_hash,
+        });
+
+        File::open(audio_file_path).ok()
+    }
+
+    async fn record_missing_file(
+        &self,
+        recording_id: String,
+        audio_file_hash: String,
+        path: PathBuf,
+    ) {
+        let timestamp = unix_timestamp();
+
+        let Ok(entry_bytes) = serde_json::to_vec(&JournalEntry::FileMissing(MissingFileEntry {
+            timestamp,
+            recording_id,
+            audio_file_hash,
+            path,
+        })) else {
             return;
         };
 
-        let _ = file.write_all(&file_contents);
+        let locked_journal_db = self.journal_db.lock().await;
 
-        metadata.audio_file_hash = Some(audio_file_hash);
+        let result = locked_journal_db.generate_id().and_then(|sequence| {
+            locked_journal_db.insert(&timestamp_key(timestamp, sequence), &entry_bytes)
+        });
 
-        let Ok(metadata_bytes): Result<Vec<u8>, serde_json::Error> = serde_json::to_vec(&metadata)
-        else {
+        if let Err(error) = result {
+            self.write_failed("Journal", error);
+        }
+    }
+
+    pub async fn record_shutdown(&self, recording_id: Option<String>, position: Duration) {
+        let timestamp = unix_timestamp();
+
+        let Ok(entry_bytes) = serde_json::to_vec(&JournalEntry::Shutdown(ShutdownEntry {
+            timestamp,
+            recording_id,
+            position,
+        })) else {
             return;
         };
 
-        let _ = self.metadata_db.lock().await.insert(id, &*metadata_bytes);
+        let locked_journal_db = self.journal_db.lock().await;
+
+        let result = locked_journal_db.generate_id().and_then(|sequence| {
+            locked_journal_db.insert(&timestamp_key(timestamp, sequence), &entry_bytes)
+        });
+
+        if let Err(error) = result {
+            self.write_failed("Journal", error);
+        }
     }
 
-    pub async fn get_recording_metadata(
+    pub async fn set_recording_file(
         &self,
         id: String,
-    ) -> Result<RecordingMetadata, DatabaseError> {
-        let Ok(contains) = self.metadata_db.lock().await.get(id.clone()) else {
-            return Err(DatabaseError::DatabaseFailure);
-        };
+        file_contents: Option<Vec<u8>>,
+        source: AudioSource,
+    ) -> Result<(), DatabaseError> {
+        let id = self.resolve_alias(id).await;
 
-        let Some(metadata_bytes) = contains else {
-            let Ok(recording) = Recording::fetch().id(&id).execute().await else {
-                return Err(DatabaseError::MusicbrainzFailure);
-            };
+        let file_guard = self.file_locks.write(&id).await;
 
-            let new_metadata = RecordingMetadata {
-                audio_file_hash: Option::None,
+        let (mut metadata, _) = self.load_recording_metadata(id.clone()).await?;
 
-                recording,
-            };
+        self.cancel_waveform_analysis(&id).await;
 
-            let Ok(metadata_bytes): Result<Vec<u8>, serde_json::Error> =
-                serde_json::to_vec(&new_metadata)
-            else {
-                return Err(DatabaseError::DataConversionFailure);
-            };
+        metadata.waveform = Option::None;
+        metadata.loudness = Option::None;
+        metadata.faulty = false;
+        metadata.audio_location = AudioLocation::Managed;
+
+        let Some(file_contents) = file_contents else {
+            metadata.audio_file_hash = Option::None;
+            metadata.audio_source = AudioSource::Unknown;
+            metadata.audio_format = Option::None;
 
-            let _ = self.metadata_db.lock().await.insert(id, &*metadata_bytes);
+            if let Ok(metadata_bytes) = serde_json::to_vec(&metadata) {
+                if self
+                    .metadata_db
+                    .lock()
+                    .await
+                    .insert(id.as_bytes(), &metadata_bytes)
+                    .is_ok()
+                {
+                    let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+                }
+            };
 
-            return Ok(new_metadata);
+            return Ok(());
         };
 
-        let Ok(metadata): Result<RecordingMetadata, serde_json::Error> =
-            serde_json::from_slice(&metadata_bytes)
-        else {
-            return Err(DatabaseError::DataConversionFailure);
+        let Ok(audio_format) = probe_audio(&file_contents) else {
+            return Err(DatabaseError::DecodeFailed);
         };
 
-        Ok(metadata)
-    }
+        let audio_file_hash = sha256::digest(&file_contents);
+
+        let needed = file_contents.len() as u64;
+
+        self.reserve_storage(needed).await?;
+
+        if let Err(error) = write_atomically(
+            &root_db_path.clone().join("audio/").join(&audio_file_hash),
+            &file_contents,
+        ) {
+            self.storage_state().await;
+
+            if error.kind() == io::ErrorKind::StorageFull {
+                return Err(DatabaseError::InsufficientStorage {
+                    needed,
+                    available: self.free_space().await.unwrap_or_default(),
+                });
+            }
 
-    pub async fn get_playlist(&self, id: String) -> Result<PlaylistMetadata, DatabaseError> {
-        let Ok(contains) = self.playlist_db.lock().await.get(id.clone()) else {
             return Err(DatabaseError::DatabaseFailure);
-        };
+        }
 
-        let Some(metadata_bytes) = contains else {
-            return Err(DatabaseError::PlaylistNotFound);
-        };
+        self.commit_audio_file(&id, metadata, &audio_file_hash, audio_format, source)
+            .await?;
 
-        let Ok(metadata): Result<PlaylistMetadata, serde_json::Error> =
-            serde_json::from_slice(&metadata_bytes)
-        else {
-            return Err(DatabaseError::DataConversionFailure);
-        };
+        drop(file_guard);
 
-        Ok(metadata)
+        let _ = self
+            .events
+            .send(DatabaseEvent::RecordingUpserted(id.clone()));
+        let _ = self.events.send(DatabaseEvent::AudioStored {
+            id: id.clone(),
+            hash: audio_file_hash.clone(),
+        });
+
+        let database = self.clone();
+
+        tokio::spawn(async move {
+            database
+                .analyse_waveform(id, audio_file_hash, file_contents)
+                .await;
+        });
+
+        Ok(())
     }
 
-    pub async fn set_playlist(&self, metadata: PlaylistMetadata) {
-        let id = metadata.id.clone();
+    async fn commit_audio_file(
+        &self,
+        id: &str,
+        mut metadata: RecordingMetadata,
+        audio_file_hash: &str,
+        audio_format: AudioFormat,
+        source: AudioSource,
+    ) -> Result<(), DatabaseError> {
+        metadata.provenance.push(ProvenanceEntry {
+            timestamp: unix_timestamp(),
+            audio_file_hash: audio_file_hash.to_owned(),
+            source: source.clone(),
+        });
+
+        metadata.audio_file_hash = Some(audio_file_hash.to_owned());
+        metadata.audio_source = source;
+        metadata.audio_format = Some(audio_format);
 
         let Ok(metadata_bytes): Result<Vec<u8>, serde_json::Error> = serde_json::to_vec(&metadata)
         else {
-            return;
+            return Err(DatabaseError::DataConversionFailure);
         };
 
-        let _ = self.metadata_db.lock().await.insert(id, &*metadata_bytes);
+        if self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_waveform(&self, id: String) -> Result<Vec<u8>, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let metadata = self.get_recording_metadata(id.clone()).await?;
+
+        if let Some(waveform) = metadata.waveform {
+            return Ok(waveform);
+        }
+
+        let Some(audio_file_hash) = metadata.audio_file_hash else {
+            return Err(DatabaseError::RecordingFileNotFound);
+        };
+
+        let file_contents = match &metadata.audio_location {
+            AudioLocation::External { path, .. } => match tokio::fs::read(path).await {
+                Ok(file_contents) => file_contents,
+                Err(_) => return Err(self.external_file_missing(id, path)),
+            },
+            AudioLocation::Managed => {
+                let Ok(file_contents) =
+                    tokio::fs::read(root_db_path.clone().join("audio/").join(&audio_file_hash))
+                        .await
+                else {
+                    return Err(DatabaseError::RecordingFileNotFound);
+                };
+
+                file_contents
+            }
+        };
+
+        let Some(waveform) = self
+            .analyse_waveform(id, audio_file_hash, file_contents)
+            .await
+        else {
+            return Err(DatabaseError::DecodeFailed);
+        };
+
+        Ok(waveform)
+    }
+
+    async fn analyse_waveform(
+        &self,
+        id: String,
+        audio_file_hash: String,
+        file_contents: Vec<u8>,
+    ) -> Option<Vec<u8>> {
+        let cancelled = Arc::new(AtomicBool::new(false));
+
+        if let Some(previous) = self
+            .waveform_jobs
+            .lock()
+            .await
+            .insert(id.clone(), cancelled.clone())
+        {
+            previous.store(true, Ordering::Relaxed);
+        }
+
+        let job_cancelled = cancelled.clone();
+
+        let waveform =
+            tokio::task::spawn_blocking(move || compute_waveform(file_contents, &job_cancelled))
+                .await
+                .ok()
+                .flatten();
+
+        {
+            let mut locked_jobs = self.waveform_jobs.lock().await;
+
+            if locked_jobs
+                .get(&id)
+                .is_some_and(|job| Arc::ptr_eq(job, &cancelled))
+            {
+                locked_jobs.remove(&id);
+            }
+        }
+
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        let (waveform, loudness) = waveform?;
+
+        let mut metadata = self.get_cached_recording_metadata(id.clone()).await?;
+
+        if metadata.audio_file_hash.as_ref() != Some(&audio_file_hash) {
+            return None;
+        }
+
+        metadata.waveform = Some(waveform.clone());
+        metadata.loudness = Some(loudness);
+
+        let metadata_bytes = serde_json::to_vec(&metadata).ok()?;
+
+        self.metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .ok()?;
+
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+
+        Some(waveform)
+    }
+
+    pub async fn set_verified_recording_file(
+        &self,
+        id: String,
+        file_contents: Vec<u8>,
+        expected_hash: &str,
+        source: AudioSource,
+    ) -> Result<String, DatabaseError> {
+        let Ok((file_contents, audio_file_hash)) = tokio::task::spawn_blocking(move || {
+            let audio_file_hash = sha256::digest(&file_contents);
+
+            (file_contents, audio_file_hash)
+        })
+        .await
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        if !audio_file_hash.eq_ignore_ascii_case(expected_hash) {
+            return Err(DatabaseError::HashMismatch);
+        }
+
+        self.set_recording_file(id, Some(file_contents), source)
+            .await?;
+
+        Ok(audio_file_hash)
+    }
+
+    pub async fn read_recording_chunk(
+        &self,
+        id: String,
+        offset: u64,
+        length: usize,
+    ) -> Result<(u64, Vec<u8>), DatabaseError> {
+        let mut recording_file = self.get_recording_file(id).await?;
+
+        let Ok(total_len) = recording_file
+            .get_ref()
+            .metadata()
+            .map(|metadata| metadata.len())
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let mut chunk = Vec::new();
+
+        if recording_file.seek(SeekFrom::Start(offset)).is_err()
+            || recording_file
+                .take(length as u64)
+                .read_to_end(&mut chunk)
+                .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        Ok((total_len, chunk))
+    }
+
+    pub async fn append_transfer_chunk(
+        &self,
+        owner: &str,
+        id: &str,
+        offset: u64,
+        total_len: u64,
+        data: &[u8],
+    ) -> Result<u64, DatabaseError> {
+        let partial_path = transfer_path(owner, id);
+
+        let received = tokio::fs::metadata(&partial_path)
+            .await
+            .map_or(0, |metadata| metadata.len());
+
+        if offset != received && offset != 0 {
+            return Err(DatabaseError::TransferOffsetMismatch { received });
+        }
+
+        let end = offset.saturating_add(data.len() as u64);
+
+        if end > total_len {
+            return Err(DatabaseError::TransferOffsetMismatch {
+                received: if offset == 0 { 0 } else { received },
+            });
+        }
+
+        self.reserve_storage(data.len() as u64).await?;
+
+        let Ok(mut partial_file) = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(offset != 0)
+            .write(true)
+            .truncate(offset == 0)
+            .open(&partial_path)
+            .await
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        if partial_file.write_all(data).await.is_err() || partial_file.flush().await.is_err() {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        Ok(end)
+    }
+
+    pub async fn finish_transfer(
+        &self,
+        owner: &str,
+        id: String,
+        expected_hash: &str,
+        source: AudioSource,
+    ) -> Result<String, DatabaseError> {
+        let partial_path = transfer_path(owner, &id);
+
+        let stored = self
+            .store_transferred_file(id, &partial_path, expected_hash, source)
+            .await;
+
+        if stored.is_err() && !matches!(stored, Err(DatabaseError::InsufficientStorage { .. })) {
+            let _ = tokio::fs::remove_file(&partial_path).await;
+        }
+
+        stored
+    }
+
+    async fn store_transferred_file(
+        &self,
+        id: String,
+        partial_path: &Path,
+        expected_hash: &str,
+        source: AudioSource,
+    ) -> Result<String, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let file_guard = self.file_locks.write(&id).await;
+
+        let (mut metadata, _) = self.load_recording_metadata(id.clone()).await?;
+
+        let checked_path = partial_path.to_path_buf();
+
+        let Ok((audio_file_hash, audio_format)) = tokio::task::spawn_blocking(move || {
+            (
+                sha256::try_digest(checked_path.as_path()),
+                probe_audio_file(&checked_path),
+            )
+        })
+        .await
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let Ok(audio_file_hash) = audio_file_hash else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        if !audio_file_hash.eq_ignore_ascii_case(expected_hash) {
+            return Err(DatabaseError::HashMismatch);
+        }
+
+        let Ok(audio_format) = audio_format else {
+            return Err(DatabaseError::DecodeFailed);
+        };
+
+        // The partial file was charged against the storage reserve chunk by
+        // chunk, so it is moved into place rather than written out again.
+        let audio_path = root_db_path.clone().join("audio/").join(&audio_file_hash);
+
+        if tokio::fs::rename(partial_path, &audio_path).await.is_err() {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        self.cancel_waveform_analysis(&id).await;
+
+        metadata.waveform = Option::None;
+        metadata.loudness = Option::None;
+        metadata.faulty = false;
+        metadata.audio_location = AudioLocation::Managed;
+
+        self.commit_audio_file(&id, metadata, &audio_file_hash, audio_format, source)
+            .await?;
+
+        drop(file_guard);
+
+        let _ = self
+            .events
+            .send(DatabaseEvent::RecordingUpserted(id.clone()));
+        let _ = self.events.send(DatabaseEvent::AudioStored {
+            id: id.clone(),
+            hash: audio_file_hash.clone(),
+        });
+
+        let database = self.clone();
+        let waveform_hash = audio_file_hash.clone();
+
+        tokio::spawn(async move {
+            if let Ok(file_contents) = tokio::fs::read(audio_path).await {
+                database
+                    .analyse_waveform(id, waveform_hash, file_contents)
+                    .await;
+            }
+        });
+
+        Ok(audio_file_hash)
+    }
+
+    pub async fn discard_transfers(&self, owner: &str) {
+        let prefix = transfer_owner_prefix(owner);
+
+        let Ok(mut entries) = tokio::fs::read_dir(root_db_path.clone().join("transfers/")).await
+        else {
+            return;
+        };
+
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            if entry.file_name().to_string_lossy().starts_with(&prefix) {
+                let _ = tokio::fs::remove_file(entry.path()).await;
+            }
+        }
+    }
+
+    pub async fn link_external_file(
+        &self,
+        id: String,
+        path: PathBuf,
+        transferable: bool,
+    ) -> Result<RecordingMetadata, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let _file_guard = self.file_locks.write(&id).await;
+
+        let (mut metadata, _) = self.load_recording_metadata(id.clone()).await?;
+
+        let Ok(file_contents) = tokio::fs::read(&path).await else {
+            return Err(self.external_file_missing(id, &path));
+        };
+
+        let Ok(audio_format) = probe_audio(&file_contents) else {
+            return Err(DatabaseError::DecodeFailed);
+        };
+
+        let Ok((file_contents, audio_file_hash)) = tokio::task::spawn_blocking(move || {
+            let audio_file_hash = sha256::digest(&file_contents);
+
+            (file_contents, audio_file_hash)
+        })
+        .await
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        self.cancel_waveform_analysis(&id).await;
+
+        let source = AudioSource::Import { path: path.clone() };
+
+        metadata.provenance.push(ProvenanceEntry {
+            timestamp: unix_timestamp(),
+            audio_file_hash: audio_file_hash.clone(),
+            source: source.clone(),
+        });
+
+        metadata.waveform = Option::None;
+        metadata.loudness = Option::None;
+        metadata.faulty = false;
+        metadata.audio_file_hash = Some(audio_file_hash.clone());
+        metadata.audio_source = source;
+        metadata.audio_format = Some(audio_format);
+        metadata.audio_location = AudioLocation::External {
+            path,
+            last_verified_hash: Some(audio_file_hash.clone()),
+            transferable,
+        };
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self
+            .events
+            .send(DatabaseEvent::RecordingUpserted(id.clone()));
+        let _ = self.events.send(DatabaseEvent::AudioStored {
+            id: id.clone(),
+            hash: audio_file_hash.clone(),
+        });
+
+        let database = self.clone();
+
+        tokio::spawn(async move {
+            database
+                .analyse_waveform(id, audio_file_hash, file_contents)
+                .await;
+        });
+
+        Ok(metadata)
+    }
+
+    pub async fn mark_recording_faulty(&self, id: String) {
+        let id = self.resolve_alias(id).await;
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return;
+        };
+
+        metadata.faulty = true;
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return;
+        };
+
+        if self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_ok()
+        {
+            let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+        }
+    }
+
+    pub async fn set_spoken_word(
+        &self,
+        id: String,
+        spoken_word: Option<bool>,
+    ) -> Result<RecordingMetadata, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        metadata.spoken_word = spoken_word;
+
+        if !metadata.is_spoken_word() {
+            metadata.resume_position = None;
+        }
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+
+        Ok(metadata)
+    }
+
+    pub async fn get_recording_duration(&self, id: String) -> Result<Duration, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let metadata = self.get_recording_metadata(id.clone()).await?;
+
+        if let Some(duration) = metadata
+            .audio_format
+            .as_ref()
+            .and_then(|format| format.duration)
+        {
+            return Ok(duration);
+        }
+
+        let mut file = self.get_recording_file(id.clone()).await?;
+
+        let mut file_contents = Vec::new();
+
+        if file.read_to_end(&mut file_contents).is_err() {
+            return Err(DatabaseError::RecordingFileNotFound);
+        }
+
+        let Ok((Some(duration), audio_format)) = tokio::task::spawn_blocking(move || {
+            (
+                measure_duration(&file_contents),
+                probe_audio(&file_contents).ok(),
+            )
+        })
+        .await
+        else {
+            return Err(DatabaseError::DecodeFailed);
+        };
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return Ok(duration);
+        };
+
+        metadata.audio_format =
+            metadata
+                .audio_format
+                .or(audio_format)
+                .map(|audio_format| AudioFormat {
+                    duration: Some(duration),
+                    ..audio_format
+                });
+
+        if let Ok(metadata_bytes) = serde_json::to_vec(&metadata) {
+            let _ = self
+                .metadata_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), &metadata_bytes);
+        }
+
+        Ok(duration)
+    }
+
+    pub async fn set_resume_position(&self, id: String, position: Option<Duration>) {
+        let id = self.resolve_alias(id).await;
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return;
+        };
+
+        if metadata.resume_position == position {
+            return;
+        }
+
+        metadata.resume_position = position;
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return;
+        };
+
+        let _ = self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes);
+    }
+
+    pub async fn is_recording_faulty(&self, id: String) -> bool {
+        self.get_cached_recording_metadata(id)
+            .await
+            .is_some_and(|metadata| metadata.faulty)
+    }
+
+    async fn cancel_waveform_analysis(&self, id: &str) {
+        if let Some(job) = self.waveform_jobs.lock().await.remove(id) {
+            job.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub async fn get_recording_metadata(
+        &self,
+        id: String,
+    ) -> Result<RecordingMetadata, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let (metadata, fetched) = self.load_recording_metadata(id.clone()).await?;
+
+        if fetched {
+            let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+        }
+
+        Ok(metadata)
+    }
+
+    async fn load_recording_metadata(
+        &self,
+        id: String,
+    ) -> Result<(RecordingMetadata, bool), DatabaseError> {
+        let Ok(contains) = self.metadata_db.lock().await.get(id.as_bytes()) else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let Some(metadata_bytes) = contains else {
+            if self.is_trashed(&id).await {
+                return Err(DatabaseError::RecordingMetadataNotFound);
+            }
+
+            let provider = self.provider.lock().await.clone();
+
+            let mut recording = match self
+                .observe_network(provider.fetch_recording(&id).await)
+                .await
+            {
+                Ok(recording) => recording,
+                Err(error) => return Err(DatabaseError::MusicbrainzFailure(error)),
+            };
+
+            let works = take_work_relations(&mut recording);
+
+            let new_metadata = RecordingMetadata {
+                audio_file_hash: Option::None,
+                audio_source: AudioSource::Unknown,
+                audio_location: AudioLocation::Managed,
+                provenance: Vec::new(),
+                audio_format: Option::None,
+                waveform: Option::None,
+                loudness: Option::None,
+                works,
+                orphaned: false,
+                faulty: false,
+                tags: Vec::new(),
+                spoken_word: Option::None,
+                resume_position: Option::None,
+                play_count: 0,
+                last_played: Option::None,
+                updating: false,
+
+                recording,
+            };
+
+            let Ok(metadata_bytes): Result<Vec<u8>, serde_json::Error> =
+                serde_json::to_vec(&new_metadata)
+            else {
+                return Err(DatabaseError::DataConversionFailure);
+            };
+
+            let stored = self
+                .metadata_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), &metadata_bytes)
+                .is_ok();
+
+            return Ok((new_metadata, stored));
+        };
+
+        let metadata = self.read_recording_metadata(id, &metadata_bytes).await?;
+
+        Ok((metadata, false))
+    }
+
+    async fn resolve_alias(&self, id: String) -> String {
+        let locked_alias_db = self.alias_db.lock().await;
+
+        let mut id = id;
+
+        for _ in 0..ALIAS_DEPTH_LIMIT {
+            let Ok(Some(target_bytes)) = locked_alias_db.get(id.as_bytes()) else {
+                break;
+            };
+
+            let Ok(target) = String::from_utf8(target_bytes) else {
+                break;
+            };
+
+            id = target;
+        }
+
+        id
+    }
+
+    pub async fn refresh_recording_metadata(
+        &self,
+        id: String,
+    ) -> Result<RefreshOutcome, DatabaseError> {
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        let Ok(previous_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        let provider = self.provider.lock().await.clone();
+
+        let (recording, outcome) = match self
+            .observe_network(provider.fetch_recording(&id).await)
+            .await
+        {
+            Ok(recording) if recording.id == id => (Some(recording), RefreshOutcome::Current),
+            Ok(mut recording) => {
+                let new_id = recording.id.clone();
+
+                metadata.works = take_work_relations(&mut recording);
+                metadata.recording = recording;
+                metadata.orphaned = false;
+
+                self.rekey_recording(id, new_id.clone(), metadata).await?;
+
+                return Ok(RefreshOutcome::Merged(new_id));
+            }
+            Err(ProviderError::NotFound) => (None, RefreshOutcome::Orphaned),
+            Err(error) => return Err(DatabaseError::MusicbrainzFailure(error)),
+        };
+
+        metadata.orphaned = recording.is_none();
+
+        if let Some(mut recording) = recording {
+            metadata.works = take_work_relations(&mut recording);
+            metadata.recording = recording;
+        }
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if metadata_bytes == previous_bytes {
+            return Ok(outcome);
+        }
+
+        if self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+
+        Ok(outcome)
+    }
+
+    async fn rekey_recording(
+        &self,
+        old_id: String,
+        new_id: String,
+        mut metadata: RecordingMetadata,
+    ) -> Result<(), DatabaseError> {
+        let mut changed_playlists = Vec::new();
+
+        {
+            let locked_metadata_db = self.metadata_db.lock().await;
+            let locked_playlist_db = self.playlist_db.lock().await;
+            let locked_position_db = self.position_db.lock().await;
+            let locked_history_db = self.history_db.lock().await;
+            let locked_alias_db = self.alias_db.lock().await;
+            let locked_tag_db = self.tag_db.lock().await;
+
+            if let Ok(Some(existing_bytes)) = locked_metadata_db.get(new_id.as_bytes()) {
+                if let Ok(existing) = serde_json::from_slice::<RecordingMetadata>(&existing_bytes) {
+                    for tag in existing.tags {
+                        if !metadata.tags.contains(&tag) {
+                            metadata.tags.push(tag);
+                        }
+                    }
+
+                    if existing.audio_file_hash.is_some() {
+                        metadata.provenance.extend(existing.provenance);
+                        metadata.provenance.sort_by_key(|entry| entry.timestamp);
+
+                        metadata.audio_file_hash = existing.audio_file_hash;
+                        metadata.audio_source = existing.audio_source;
+                        metadata.audio_format = existing.audio_format;
+                        metadata.waveform = existing.waveform;
+                    }
+                }
+            }
+
+            let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+                return Err(DatabaseError::DataConversionFailure);
+            };
+
+            if locked_metadata_db
+                .insert(new_id.as_bytes(), &metadata_bytes)
+                .is_err()
+            {
+                return Err(DatabaseError::DatabaseFailure);
+            }
+
+            for (key, playlist_bytes) in locked_playlist_db.range_from(&[]).unwrap_or_default() {
+                let Ok(mut playlist) = serde_json::from_slice::<PlaylistMetadata>(&playlist_bytes)
+                else {
+                    continue;
+                };
+
+                if !playlist.recordings.contains(&old_id) {
+                    continue;
+                }
+
+                for recording in playlist.recordings.iter_mut() {
+                    if *recording == old_id {
+                        *recording = new_id.clone();
+                    }
+                }
+
+                let Ok(playlist_bytes) = serde_json::to_vec(&playlist) else {
+                    continue;
+                };
+
+                if locked_playlist_db.insert(&key, &playlist_bytes).is_ok() {
+                    changed_playlists.push(playlist.id);
+                }
+            }
+
+            for (key, position_bytes) in locked_position_db.range_from(&[]).unwrap_or_default() {
+                let Ok(mut position) = serde_json::from_slice::<PlaylistPosition>(&position_bytes)
+                else {
+                    continue;
+                };
+
+                if position.recording_id != old_id {
+                    continue;
+                }
+
+                position.recording_id = new_id.clone();
+
+                if let Ok(position_bytes) = serde_json::to_vec(&position) {
+                    let _ = locked_position_db.insert(&key, &position_bytes);
+                }
+            }
+
+            for (key, event_bytes) in locked_history_db.range_from(&[]).unwrap_or_default() {
+                let Ok(mut event) = serde_json::from_slice::<PlayEvent>(&event_bytes) else {
+                    continue;
+                };
+
+                if event.recording_id != old_id {
+                    continue;
+                }
+
+                event.recording_id = new_id.clone();
+
+                if let Ok(event_bytes) = serde_json::to_vec(&event) {
+                    let _ = locked_history_db.insert(&key, &event_bytes);
+                }
+            }
+
+            for tag in &metadata.tags {
+                let _ = locked_tag_db.remove(&tag_key(tag, &old_id));
+                let _ = locked_tag_db.insert(&tag_key(tag, &new_id), &[]);
+            }
+
+            let _ = locked_alias_db.insert(old_id.as_bytes(), new_id.as_bytes());
+            let _ = locked_metadata_db.remove(old_id.as_bytes());
+        }
+
+        self.cancel_waveform_analysis(&old_id).await;
+
+        let _ = self.events.send(DatabaseEvent::RecordingDeleted(old_id));
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(new_id));
+
+        for playlist_id in changed_playlists {
+            let _ = self
+                .events
+                .send(DatabaseEvent::PlaylistUpserted(playlist_id));
+        }
+
+        Ok(())
+    }
+
+    pub async fn tag_recording(
+        &self,
+        id: String,
+        add: Vec<String>,
+        remove: Vec<String>,
+    ) -> Result<Vec<String>, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let (Some(add), Some(remove)) = (
+            add.iter()
+                .map(|tag| normalize_tag(tag))
+                .collect::<Option<Vec<String>>>(),
+            remove
+                .iter()
+                .map(|tag| normalize_tag(tag))
+                .collect::<Option<Vec<String>>>(),
+        ) else {
+            return Err(DatabaseError::InvalidTag);
+        };
+
+        let locked_metadata_db = self.metadata_db.lock().await;
+        let locked_tag_db = self.tag_db.lock().await;
+
+        let Ok(Some(metadata_bytes)) = locked_metadata_db.get(id.as_bytes()) else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        let Ok(mut metadata) = serde_json::from_slice::<RecordingMetadata>(&metadata_bytes) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        metadata.tags.retain(|tag| !remove.contains(tag));
+
+        for tag in add {
+            if !metadata.tags.contains(&tag) {
+                metadata.tags.push(tag);
+            }
+        }
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if locked_metadata_db
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        for tag in &remove {
+            let _ = locked_tag_db.remove(&tag_key(tag, &id));
+        }
+
+        for tag in &metadata.tags {
+            let _ = locked_tag_db.insert(&tag_key(tag, &id), &[]);
+        }
+
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+
+        Ok(metadata.tags)
+    }
+
+    pub async fn edit_local_metadata(
+        &self,
+        id: String,
+        patch: &LocalTagPatch,
+        write_tags: bool,
+    ) -> Result<(), DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        if !id.starts_with(LOCAL_RECORDING_PREFIX) {
+            return Err(DatabaseError::NotLocalRecording);
+        }
+
+        if write_tags {
+            self.write_local_tags(id.clone(), patch).await?;
+        }
+
+        let locked_metadata_db = self.metadata_db.lock().await;
+
+        let Ok(Some(metadata_bytes)) = locked_metadata_db.get(id.as_bytes()) else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        let Ok(mut metadata) = serde_json::from_slice::<RecordingMetadata>(&metadata_bytes) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        apply_local_patch(&mut metadata.recording, patch)?;
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if locked_metadata_db
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self.events.send(DatabaseEvent::RecordingUpserted(id));
+
+        Ok(())
+    }
+
+    async fn write_local_tags(
+        &self,
+        id: String,
+        patch: &LocalTagPatch,
+    ) -> Result<(), DatabaseError> {
+        let Ok(Some(metadata_bytes)) = self.metadata_db.lock().await.get(id.as_bytes()) else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        let Ok(metadata) = serde_json::from_slice::<RecordingMetadata>(&metadata_bytes) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        // A patch the metadata refuses must not reach the file either.
+        apply_local_patch(&mut metadata.recording.clone(), patch)?;
+
+        // Files outside the library belong to the user and are left untouched.
+        let (Some(audio_file_hash), AudioLocation::Managed) =
+            (metadata.audio_file_hash, &metadata.audio_location)
+        else {
+            return Err(DatabaseError::TagWriteFailed);
+        };
+
+        let Ok(file_contents) =
+            tokio::fs::read(root_db_path.clone().join("audio/").join(&audio_file_hash)).await
+        else {
+            return Err(DatabaseError::RecordingFileNotFound);
+        };
+
+        let patch = patch.clone();
+
+        let Ok(Ok(file_contents)) =
+            tokio::task::spawn_blocking(move || write_embedded_tags(file_contents, &patch)).await
+        else {
+            return Err(DatabaseError::TagWriteFailed);
+        };
+
+        // The rewritten file hashes differently, so it is stored like any
+        // replacement: under its new hash, with a provenance entry.
+        self.set_recording_file(id, Some(file_contents), metadata.audio_source)
+            .await
+    }
+
+    pub async fn get_tags(&self) -> Vec<(String, usize)> {
+        let Ok(entries) = self.tag_db.lock().await.range_from(&[]) else {
+            return Vec::new();
+        };
+
+        let mut tags = Vec::<(String, usize)>::new();
+
+        for (key, _) in entries {
+            let Some((tag, _)) = split_tag_key(&key) else {
+                continue;
+            };
+
+            match tags.last_mut() {
+                Some((last_tag, count)) if *last_tag == tag => *count += 1,
+                _ => tags.push((tag, 1)),
+            }
+        }
+
+        tags
+    }
+
+    pub async fn get_recordings_by_tag(&self, tag: &str) -> Result<Vec<String>, DatabaseError> {
+        let Some(tag) = normalize_tag(tag) else {
+            return Err(DatabaseError::InvalidTag);
+        };
+
+        let prefix = tag_key(&tag, "");
+
+        let Ok(entries) = self.tag_db.lock().await.range_from(&prefix) else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        Ok(entries
+            .into_iter()
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .filter_map(|(key, _)| split_tag_key(&key).map(|(_, id)| id))
+            .collect())
+    }
+
+    async fn remove_from_tag_index(&self, id: &str) {
+        let locked_tag_db = self.tag_db.lock().await;
+
+        let Ok(entries) = locked_tag_db.range_from(&[]) else {
+            return;
+        };
+
+        for (key, _) in entries {
+            if split_tag_key(&key).is_some_and(|(_, tagged_id)| tagged_id == id) {
+                let _ = locked_tag_db.remove(&key);
+            }
+        }
+    }
+
+    pub async fn get_orphaned_recordings(&self) -> Vec<RecordingMetadata> {
+        let Ok(entries) = self.metadata_db.lock().await.range_from(&[]) else {
+            return Vec::new();
+        };
+
+        entries
+            .into_iter()
+            .filter_map(|(_, metadata_bytes)| {
+                serde_json::from_slice::<RecordingMetadata>(&metadata_bytes).ok()
+            })
+            .filter(|metadata| metadata.orphaned)
+            .collect()
+    }
+
+    async fn read_recording_metadata(
+        &self,
+        id: String,
+        metadata_bytes: &[u8],
+    ) -> Result<RecordingMetadata, DatabaseError> {
+        if let Ok(metadata) = serde_json::from_slice::<RecordingMetadata>(metadata_bytes) {
+            return Ok(metadata);
+        }
+
+        let Some(metadata) = recover_recording_metadata(&id, metadata_bytes) else {
+            let _ = self
+                .quarantine_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), metadata_bytes);
+            self.cancel_waveform_analysis(&id).await;
+            self.remove_from_tag_index(&id).await;
+
+            if self.metadata_db.lock().await.remove(id.as_bytes()).is_ok() {
+                let _ = self.events.send(DatabaseEvent::RecordingDeleted(id));
+            }
+
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if let Ok(recovered_bytes) = serde_json::to_vec(&metadata) {
+            let _ = self
+                .metadata_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), &recovered_bytes);
+        }
+
+        Ok(metadata)
+    }
+
+    pub async fn get_playlist(&self, id: String) -> Result<PlaylistMetadata, DatabaseError> {
+        let Ok(contains) = self.playlist_db.lock().await.get(id.as_bytes()) else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let Some(metadata_bytes) = contains else {
+            return Err(DatabaseError::PlaylistNotFound);
+        };
+
+        let Ok(mut metadata): Result<PlaylistMetadata, serde_json::Error> =
+            serde_json::from_slice(&metadata_bytes)
+        else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        metadata.resume_position = self.get_playlist_position(id).await;
+
+        Ok(metadata)
+    }
+
+    pub async fn set_playlist(&self, mut metadata: PlaylistMetadata) -> PlaylistMetadata {
+        metadata.modified = unix_timestamp();
+
+        self.write_playlist(&metadata).await;
+
+        metadata
+    }
+
+    async fn write_playlist(&self, metadata: &PlaylistMetadata) {
+        let id = metadata.id.clone();
+
+        let Ok(metadata_bytes): Result<Vec<u8>, serde_json::Error> = serde_json::to_vec(&metadata)
+        else {
+            return;
+        };
+
+        if self
+            .playlist_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_ok()
+        {
+            let _ = self.events.send(DatabaseEvent::PlaylistUpserted(id));
+        }
+    }
+
+    pub async fn record_playlist_sync(&self, metadata: &PlaylistMetadata) {
+        let _ = self
+            .playlist_sync_db
+            .lock()
+            .await
+            .insert(metadata.id.as_bytes(), &metadata.modified.to_be_bytes());
+    }
+
+    async fn get_playlist_sync(&self, id: &str) -> u64 {
+        let Ok(Some(synced_bytes)) = self.playlist_sync_db.lock().await.get(id.as_bytes()) else {
+            return 0;
+        };
+
+        synced_bytes
+            .try_into()
+            .map(u64::from_be_bytes)
+            .unwrap_or_default()
+    }
+
+    pub async fn apply_remote_playlist(&self, remote: PlaylistMetadata) -> PlaylistSync {
+        let Ok(local) = self.get_playlist(remote.id.clone()).await else {
+            self.write_playlist(&remote).await;
+            self.record_playlist_sync(&remote).await;
+
+            return PlaylistSync::Applied;
+        };
+
+        let last_synced = self.get_playlist_sync(&remote.id).await;
+
+        if local.modified == remote.modified || remote.modified <= last_synced {
+            return PlaylistSync::Kept;
+        }
+
+        if local.modified <= last_synced {
+            self.write_playlist(&remote).await;
+            self.record_playlist_sync(&remote).await;
+
+            return PlaylistSync::Applied;
+        }
+
+        if let Ok(remote_bytes) = serde_json::to_vec(&remote) {
+            let _ = self
+                .playlist_conflict_db
+                .lock()
+                .await
+                .insert(remote.id.as_bytes(), &remote_bytes);
+        }
+
+        PlaylistSync::Conflict {
+            local_modified: local.modified,
+            remote_modified: remote.modified,
+        }
+    }
+
+    pub async fn resolve_playlist_conflict(
+        &self,
+        id: String,
+        keep: ConflictResolution,
+    ) -> Result<PlaylistMetadata, DatabaseError> {
+        let Ok(Some(remote_bytes)) = self.playlist_conflict_db.lock().await.get(id.as_bytes())
+        else {
+            return Err(DatabaseError::PlaylistNotFound);
+        };
+
+        let Ok(remote) = serde_json::from_slice::<PlaylistMetadata>(&remote_bytes) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        let local = self.get_playlist(id.clone()).await?;
+
+        let resolved = match keep {
+            ConflictResolution::Local => {
+                self.record_playlist_sync(&remote).await;
+
+                local
+            }
+            ConflictResolution::Remote => {
+                self.write_playlist(&remote).await;
+                self.record_playlist_sync(&remote).await;
+
+                remote
+            }
+            ConflictResolution::Merge => {
+                let merged = self
+                    .set_playlist(PlaylistMetadata {
+                        recordings: merge_recordings(&local.recordings, &remote.recordings),
+                        ..local
+                    })
+                    .await;
+
+                self.record_playlist_sync(&merged).await;
+
+                merged
+            }
+        };
+
+        let _ = self.playlist_conflict_db.lock().await.remove(id.as_bytes());
+
+        Ok(resolved)
+    }
+
+    pub async fn validate_recordings(&self, recordings: &[String]) -> PlaylistValidation {
+        let mut validation = PlaylistValidation::default();
+
+        for recording_id in recordings {
+            match self.get_recording_file(recording_id.clone()).await {
+                Ok(_) => {}
+                Err(DatabaseError::RecordingMetadataNotFound) => {
+                    validation.missing_metadata.push(recording_id.clone())
+                }
+                Err(_) => validation.missing_audio.push(recording_id.clone()),
+            }
+        }
+
+        validation
+    }
+
+    pub async fn prune_playlist(
+        &self,
+        id: String,
+    ) -> Result<(PlaylistMetadata, PlaylistValidation), DatabaseError> {
+        let locked_playlist_db = self.playlist_db.lock().await;
+
+        let Ok(contains) = locked_playlist_db.get(id.as_bytes()) else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let Some(metadata_bytes) = contains else {
+            return Err(DatabaseError::PlaylistNotFound);
+        };
+
+        let Ok(mut metadata) = serde_json::from_slice::<PlaylistMetadata>(&metadata_bytes) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        let validation = self.validate_recordings(&metadata.recordings).await;
+
+        if validation.missing_metadata.is_empty() {
+            return Ok((metadata, validation));
+        }
+
+        metadata
+            .recordings
+            .retain(|recording_id| !validation.missing_metadata.contains(recording_id));
+        metadata.modified = unix_timestamp();
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if locked_playlist_db
+            .insert(id.as_bytes(), &metadata_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        drop(locked_playlist_db);
+
+        let _ = self.events.send(DatabaseEvent::PlaylistUpserted(id));
+
+        Ok((metadata, validation))
+    }
+
+    pub async fn adopt_loose_files(&self) -> AdoptionSummary {
+        let audio_path = root_db_path.clone().join("audio/");
+
+        let mut summary = AdoptionSummary::default();
+
+        let Ok(entries) = std::fs::read_dir(&audio_path) else {
+            return summary;
+        };
+
+        let mut referenced = self
+            .get_trash()
+            .await
+            .into_iter()
+            .filter_map(|entry| match entry.item {
+                TrashedItem::Recording { metadata, .. } => metadata.audio_file_hash,
+                TrashedItem::Playlist(_) => None,
+            })
+            .collect::<Vec<String>>();
+
+        if let Ok(entries) = self.metadata_db.lock().await.range_from(&[]) {
+            for (_, metadata_bytes) in entries {
+                let Ok(metadata) = serde_json::from_slice::<RecordingMetadata>(&metadata_bytes)
+                else {
+                    continue;
+                };
+
+                referenced.extend(metadata.audio_file_hash);
+                referenced.extend(
+                    metadata
+                        .provenance
+                        .into_iter()
+                        .map(|entry| entry.audio_file_hash),
+                );
+            }
+        }
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+
+            if !path.is_file()
+                || path
+                    .extension()
+                    .is_some_and(|extension| extension == "partial")
+                || referenced.iter().any(|hash| hash == file_name)
+            {
+                continue;
+            }
+
+            let file_name = file_name.to_owned();
+
+            let Ok(file_contents) = std::fs::read(&path) else {
+                summary.failed.push(file_name);
+                continue;
+            };
+
+            let audio_file_hash = sha256::digest(&file_contents);
+
+            if referenced.contains(&audio_file_hash) {
+                summary.skipped += 1;
+                continue;
+            }
+
+            let Ok(audio_format) = probe_audio(&file_contents) else {
+                summary.failed.push(file_name);
+                continue;
+            };
+
+            let hashed_path = audio_path.join(&audio_file_hash);
+
+            if hashed_path != path && std::fs::rename(&path, &hashed_path).is_err() {
+                summary.failed.push(file_name);
+                continue;
+            }
+
+            let id = format!("{}{}", LOCAL_RECORDING_PREFIX, audio_file_hash);
+            let source = AudioSource::Scan { path: path.clone() };
+
+            let title = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(stem) if file_name != audio_file_hash => stem.to_owned(),
+                _ => audio_file_hash.clone(),
+            };
+
+            let metadata = RecordingMetadata {
+                audio_file_hash: Some(audio_file_hash.clone()),
+                audio_source: source.clone(),
+                audio_location: AudioLocation::Managed,
+                provenance: vec![ProvenanceEntry {
+                    timestamp: unix_timestamp(),
+                    audio_file_hash: audio_file_hash.clone(),
+                    source,
+                }],
+                audio_format: Some(audio_format.clone()),
+                waveform: Option::None,
+                loudness: Option::None,
+                works: Vec::new(),
+                orphaned: false,
+                faulty: false,
+                tags: Vec::new(),
+                spoken_word: Option::None,
+                resume_position: Option::None,
+                play_count: 0,
+                last_played: Option::None,
+                updating: false,
+
+                recording: Recording {
+                    id: id.clone(),
+                    title,
+                    video: None,
+                    length: audio_format
+                        .duration
+                        .map(|duration| duration.as_millis() as u32),
+                    disambiguation: None,
+                    isrcs: None,
+                    relations: None,
+                    releases: None,
+                    artist_credit: None,
+                    aliases: None,
+                    tags: None,
+                    rating: None,
+                    genres: None,
+                    annotation: None,
+                },
+            };
+
+            let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+                summary.failed.push(file_name);
+                continue;
+            };
+
+            if self
+                .metadata_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), &metadata_bytes)
+                .is_err()
+            {
+                summary.failed.push(file_name);
+                continue;
+            }
+
+            referenced.push(audio_file_hash.clone());
+
+            let _ = self
+                .events
+                .send(DatabaseEvent::RecordingUpserted(id.clone()));
+            let _ = self.events.send(DatabaseEvent::AudioStored {
+                id: id.clone(),
+                hash: audio_file_hash.clone(),
+            });
+
+            let database = self.clone();
+            let waveform_id = id.clone();
+
+            tokio::spawn(async move {
+                database
+                    .analyse_waveform(waveform_id, audio_file_hash, file_contents)
+                    .await;
+            });
+
+            summary.adopted.push(id);
+        }
+
+        summary
+    }
+
+    pub async fn trash_recording(&self, id: String) -> Result<TrashEntry, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let Some(metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return Err(DatabaseError::RecordingMetadataNotFound);
+        };
+
+        let mut memberships = Vec::new();
+        let mut changed_playlists = Vec::new();
+
+        for mut playlist in self.get_playlists().await {
+            let positions = playlist
+                .recordings
+                .iter()
+                .enumerate()
+                .filter(|(_, recording_id)| **recording_id == id)
+                .map(|(position, _)| position)
+                .collect::<Vec<usize>>();
+
+            if positions.is_empty() {
+                continue;
+            }
+
+            memberships.extend(
+                positions
+                    .into_iter()
+                    .map(|position| (playlist.id.clone(), position)),
+            );
+
+            playlist
+                .recordings
+                .retain(|recording_id| *recording_id != id);
+            playlist.modified = unix_timestamp();
+
+            changed_playlists.push(playlist);
+        }
+
+        let entry = TrashEntry {
+            id: id.clone(),
+            deleted_at: unix_timestamp(),
+            item: TrashedItem::Recording {
+                metadata: Box::new(metadata),
+                memberships,
+            },
+        };
+
+        self.write_trash_entry(&entry).await?;
+
+        self.cancel_waveform_analysis(&id).await;
+        self.remove_from_tag_index(&id).await;
+
+        if self.metadata_db.lock().await.remove(id.as_bytes()).is_err() {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self.events.send(DatabaseEvent::RecordingDeleted(id));
+
+        for playlist in changed_playlists {
+            self.write_playlist(&playlist).await;
+        }
+
+        Ok(entry)
+    }
+
+    pub async fn trash_playlist(&self, id: String) -> Result<TrashEntry, DatabaseError> {
+        let metadata = self.get_playlist(id.clone()).await?;
+
+        let entry = TrashEntry {
+            id: id.clone(),
+            deleted_at: unix_timestamp(),
+            item: TrashedItem::Playlist(metadata),
+        };
+
+        self.write_trash_entry(&entry).await?;
+
+        if self.playlist_db.lock().await.remove(id.as_bytes()).is_err() {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let _ = self.events.send(DatabaseEvent::PlaylistDeleted(id));
+
+        Ok(entry)
+    }
+
+    pub async fn get_trash(&self) -> Vec<TrashEntry> {
+        let Ok(entries) = self.trash_db.lock().await.range_from(&[]) else {
+            return Vec::new();
+        };
+
+        entries
+            .into_iter()
+            .filter_map(|(_, entry_bytes)| serde_json::from_slice::<TrashEntry>(&entry_bytes).ok())
+            .collect()
+    }
+
+    pub async fn restore_from_trash(&self, id: String) -> Result<TrashEntry, DatabaseError> {
+        let entry = self
+            .get_trash()
+            .await
+            .into_iter()
+            .find(|entry| entry.id == id)
+            .ok_or(DatabaseError::TrashEntryNotFound)?;
+
+        match &entry.item {
+            TrashedItem::Recording {
+                metadata,
+                memberships,
+            } => {
+                let Ok(metadata_bytes) = serde_json::to_vec(metadata) else {
+                    return Err(DatabaseError::DataConversionFailure);
+                };
+
+                if self
+                    .metadata_db
+                    .lock()
+                    .await
+                    .insert(id.as_bytes(), &metadata_bytes)
+                    .is_err()
+                {
+                    return Err(DatabaseError::DatabaseFailure);
+                }
+
+                {
+                    let locked_tag_db = self.tag_db.lock().await;
+
+                    for tag in &metadata.tags {
+                        let _ = locked_tag_db.insert(&tag_key(tag, &id), &[]);
+                    }
+                }
+
+                let _ = self
+                    .events
+                    .send(DatabaseEvent::RecordingUpserted(id.clone()));
+
+                let mut playlists = HashMap::<String, Vec<usize>>::new();
+
+                for (playlist_id, position) in memberships {
+                    playlists
+                        .entry(playlist_id.clone())
+                        .or_default()
+                        .push(*position);
+                }
+
+                for (playlist_id, mut positions) in playlists {
+                    let Ok(mut playlist) = self.get_playlist(playlist_id).await else {
+                        continue;
+                    };
+
+                    positions.sort_unstable();
+
+                    for position in positions {
+                        let position = position.min(playlist.recordings.len());
+
+                        playlist.recordings.insert(position, id.clone());
+                    }
+
+                    self.set_playlist(playlist).await;
+                }
+            }
+            TrashedItem::Playlist(metadata) => self.write_playlist(metadata).await,
+        }
+
+        let _ = self.trash_db.lock().await.remove(&trash_key(&entry));
+
+        Ok(entry)
+    }
+
+    pub async fn empty_trash(&self, older_than: Option<Duration>) -> (Vec<String>, u64) {
+        let cutoff =
+            older_than.map(|older_than| unix_timestamp().saturating_sub(older_than.as_secs()));
+
+        let (expired, kept): (Vec<TrashEntry>, Vec<TrashEntry>) = self
+            .get_trash()
+            .await
+            .into_iter()
+            .partition(|entry| cutoff.is_none_or(|cutoff| entry.deleted_at <= cutoff));
+
+        let mut referenced = self
+            .get_library()
+            .await
+            .into_iter()
+            .filter_map(|(_, metadata)| metadata.audio_file_hash)
+            .collect::<Vec<String>>();
+
+        referenced.extend(kept.into_iter().filter_map(|entry| match entry.item {
+            TrashedItem::Recording { metadata, .. } => metadata.audio_file_hash,
+            TrashedItem::Playlist(_) => None,
+        }));
+
+        let mut removed = Vec::new();
+        let mut bytes_reclaimed = 0;
+
+        for entry in expired {
+            if let TrashedItem::Recording { metadata, .. } = &entry.item {
+                if let (AudioLocation::Managed, Some(audio_file_hash)) =
+                    (&metadata.audio_location, &metadata.audio_file_hash)
+                {
+                    if !referenced.contains(audio_file_hash) {
+                        let audio_file_path =
+                            root_db_path.clone().join("audio/").join(audio_file_hash);
+
+                        let size = std::fs::metadata(&audio_file_path)
+                            .map(|file| file.len())
+                            .unwrap_or_default();
+
+                        if std::fs::remove_file(&audio_file_path).is_ok() {
+                            bytes_reclaimed += size;
+                        }
+
+                        referenced.push(audio_file_hash.clone());
+                    }
+                }
+            }
+
+            if self
+                .trash_db
+                .lock()
+                .await
+                .remove(&trash_key(&entry))
+                .is_ok()
+            {
+                removed.push(entry.id);
+            }
+        }
+
+        if bytes_reclaimed > 0 {
+            self.storage_state().await;
+        }
+
+        (removed, bytes_reclaimed)
+    }
+
+    async fn write_trash_entry(&self, entry: &TrashEntry) -> Result<(), DatabaseError> {
+        let Ok(entry_bytes) = serde_json::to_vec(entry) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        self.trash_db
+            .lock()
+            .await
+            .insert(&trash_key(entry), &entry_bytes)
+            .map_err(|_| DatabaseError::DatabaseFailure)
+    }
+
+    async fn is_trashed(&self, id: &str) -> bool {
+        matches!(
+            self.trash_db
+                .lock()
+                .await
+                .get(format!("{}{}", TRASH_RECORDING_PREFIX, id).as_bytes()),
+            Ok(Some(_))
+        )
+    }
+
+    pub async fn health_check(&self) -> Result<(), DatabaseError> {
+        for store in [&self.metadata_db, &self.playlist_db] {
+            if store.lock().await.get(HEALTH_CHECK_KEY.as_bytes()).is_err() {
+                return Err(DatabaseError::DatabaseFailure);
+            }
+        }
+
+        let settings_db = self.settings_db.lock().await;
+
+        let scratch_bytes = unix_timestamp().to_be_bytes();
+
+        if settings_db
+            .insert(HEALTH_CHECK_KEY.as_bytes(), &scratch_bytes)
+            .is_err()
+        {
+            return Err(DatabaseError::DatabaseFailure);
+        }
+
+        let round_trip = settings_db.get(HEALTH_CHECK_KEY.as_bytes());
+
+        let _ = settings_db.remove(HEALTH_CHECK_KEY.as_bytes());
+
+        let Ok(Some(round_trip_bytes)) = round_trip else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        if round_trip_bytes != scratch_bytes {
+            return Err(DatabaseError::DataConversionFailure);
+        }
+
+        Ok(())
+    }
+
+    pub async fn get_playlists(&self) -> Vec<PlaylistMetadata> {
+        let Ok(entries) = self.playlist_db.lock().await.range_from(&[]) else {
+            return Vec::new();
+        };
+
+        let mut playlists = Vec::new();
+
+        for (_, metadata_bytes) in entries {
+            let Ok(mut metadata) = serde_json::from_slice::<PlaylistMetadata>(&metadata_bytes)
+            else {
+                continue;
+            };
+
+            metadata.resume_position = self.get_playlist_position(metadata.id.clone()).await;
+
+            playlists.push(metadata);
+        }
+
+        playlists
+    }
+
+    pub async fn get_playlist_position(&self, id: String) -> Option<PlaylistPosition> {
+        let Ok(Some(position_bytes)) = self.position_db.lock().await.get(id.as_bytes()) else {
+            return None;
+        };
+
+        serde_json::from_slice(&position_bytes).ok()
+    }
+
+    pub async fn record_playlist_progress(&self, id: String, position: PlaylistPosition) {
+        let Ok(playlist) = self.get_playlist(id.clone()).await else {
+            return;
+        };
+
+        if position.index + 1 >= playlist.recordings.len() {
+            self.reset_playlist_position(id).await;
+
+            return;
+        }
+
+        let Ok(position_bytes) = serde_json::to_vec(&position) else {
+            return;
+        };
+
+        if self
+            .position_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &position_bytes)
+            .is_ok()
+        {
+            let _ = self.events.send(DatabaseEvent::PlaylistUpserted(id));
+        }
+    }
+
+    pub async fn reset_playlist_position(&self, id: String) {
+        let locked_position_db = self.position_db.lock().await;
+
+        if !matches!(locked_position_db.get(id.as_bytes()), Ok(Some(_))) {
+            return;
+        }
+
+        if locked_position_db.remove(id.as_bytes()).is_ok() {
+            let _ = self.events.send(DatabaseEvent::PlaylistUpserted(id));
+        }
+    }
+
+    pub async fn get_library(&self) -> Vec<(String, RecordingMetadata)> {
+        let Ok(entries) = self.metadata_db.lock().await.range_from(&[]) else {
+            return Vec::new();
+        };
+
+        entries
+            .into_iter()
+            .filter_map(|(key, metadata_bytes)| {
+                let id = String::from_utf8(key).ok()?;
+                let metadata: RecordingMetadata = serde_json::from_slice(&metadata_bytes).ok()?;
+
+                metadata.audio_file_hash.as_ref()?;
+
+                Some((id, metadata))
+            })
+            .collect()
+    }
+
+    pub async fn get_related_recordings(
+        &self,
+        id: String,
+    ) -> Result<Vec<(String, WorkRelation)>, DatabaseError> {
+        let id = self.resolve_alias(id).await;
+
+        let metadata = self.get_recording_metadata(id.clone()).await?;
+
+        Ok(self
+            .get_library()
+            .await
+            .into_iter()
+            .filter(|(other_id, _)| *other_id != id)
+            .filter_map(|(other_id, other)| {
+                let work = other.works.into_iter().find(|work| {
+                    metadata
+                        .works
+                        .iter()
+                        .any(|own_work| own_work.work_id == work.work_id)
+                })?;
+
+                Some((other_id, work))
+            })
+            .collect())
+    }
+
+    pub async fn are_adjacent_tracks(&self, current: &str, next: &str) -> bool {
+        let (Some(current), Some(next)) = (
+            self.get_cached_recording_metadata(current.to_owned()).await,
+            self.get_cached_recording_metadata(next.to_owned()).await,
+        ) else {
+            return false;
+        };
+
+        let next_releases = next.release_ids();
+
+        for release_id in current.release_ids().intersection(&next_releases) {
+            let Ok(Some(recordings_bytes)) =
+                self.release_db.lock().await.get(release_id.as_bytes())
+            else {
+                continue;
+            };
+
+            let Ok(recordings) = serde_json::from_slice::<Vec<String>>(&recordings_bytes) else {
+                continue;
+            };
+
+            let current_position = recordings.iter().position(|id| *id == current.recording.id);
+            let next_position = recordings.iter().position(|id| *id == next.recording.id);
+
+            if let (Some(current_position), Some(next_position)) = (current_position, next_position)
+            {
+                if next_position == current_position + 1 {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    pub async fn get_release_recordings(
+        &self,
+        release_id: String,
+    ) -> Result<Vec<String>, DatabaseError> {
+        if let Ok(Some(recordings_bytes)) = self.release_db.lock().await.get(release_id.as_bytes())
+        {
+            if let Ok(recordings) = serde_json::from_slice::<Vec<String>>(&recordings_bytes) {
+                return Ok(recordings);
+            }
+        }
+
+        let provider = self.provider.lock().await.clone();
+
+        let recordings = match self
+            .observe_network(provider.fetch_release(&release_id).await)
+            .await
+        {
+            Ok(recordings) => recordings,
+            Err(ProviderError::NotFound) => return Err(DatabaseError::ReleaseNotFound),
+            Err(error) => return Err(DatabaseError::MusicbrainzFailure(error)),
+        };
+
+        let Ok(recordings_bytes) = serde_json::to_vec(&recordings) else {
+            return Err(DatabaseError::DataConversionFailure);
+        };
+
+        if let Err(error) = self
+            .release_db
+            .lock()
+            .await
+            .insert(release_id.as_bytes(), &recordings_bytes)
+        {
+            self.write_failed("ReleaseCache", error);
+        }
+
+        Ok(recordings)
+    }
+
+    pub async fn get_remote_work_recordings(
+        &self,
+        work_id: &str,
+    ) -> Result<Vec<(String, WorkRelation)>, DatabaseError> {
+        let provider = self.provider.lock().await.clone();
+
+        self.observe_network(provider.fetch_work_recordings(work_id).await)
+            .await
+            .map_err(DatabaseError::MusicbrainzFailure)
+    }
+
+    pub async fn verify_audio_file(&self, audio_file_hash: String) -> AudioFileStatus {
+        let Ok(file_contents) =
+            tokio::fs::read(root_db_path.clone().join("audio/").join(&audio_file_hash)).await
+        else {
+            return AudioFileStatus::Missing;
+        };
+
+        let Ok(digest) = tokio::task::spawn_blocking(move || sha256::digest(&file_contents)).await
+        else {
+            return AudioFileStatus::Mismatched;
+        };
+
+        if digest == audio_file_hash {
+            AudioFileStatus::Intact
+        } else {
+            AudioFileStatus::Mismatched
+        }
+    }
+
+    pub async fn verify_external_file(&self, id: String, path: PathBuf) -> AudioFileStatus {
+        let Ok(file_contents) = tokio::fs::read(&path).await else {
+            self.external_file_missing(id, &path);
+
+            return AudioFileStatus::Missing;
+        };
+
+        let Ok(digest) = tokio::task::spawn_blocking(move || sha256::digest(&file_contents)).await
+        else {
+            return AudioFileStatus::Mismatched;
+        };
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return AudioFileStatus::Missing;
+        };
+
+        let AudioLocation::External {
+            last_verified_hash, ..
+        } = &mut metadata.audio_location
+        else {
+            return AudioFileStatus::Mismatched;
+        };
+
+        match last_verified_hash {
+            Some(hash) if *hash == digest => return AudioFileStatus::Intact,
+            Some(_) => return AudioFileStatus::Mismatched,
+            None => *last_verified_hash = Some(digest),
+        }
+
+        if let Ok(metadata_bytes) = serde_json::to_vec(&metadata) {
+            if let Err(error) = self
+                .metadata_db
+                .lock()
+                .await
+                .insert(id.as_bytes(), &metadata_bytes)
+            {
+                self.write_failed("RecordingMetadata", error);
+            }
+        }
+
+        AudioFileStatus::Intact
+    }
+
+    pub fn is_recording_updating(&self, id: &str) -> bool {
+        self.file_locks.is_updating(id)
+    }
+
+    pub async fn get_cached_recording_metadata(&self, id: String) -> Option<RecordingMetadata> {
+        let id = self.resolve_alias(id).await;
+
+        let Ok(Some(metadata_bytes)) = self.metadata_db.lock().await.get(id.as_bytes()) else {
+            return None;
+        };
+
+        self.read_recording_metadata(id, &metadata_bytes).await.ok()
+    }
+
+    pub async fn record_play_event(
+        &self,
+        recording_id: String,
+        listened: Duration,
+        reason: TransitionReason,
+    ) {
+        let timestamp = unix_timestamp();
+
+        let event = PlayEvent {
+            timestamp,
+            recording_id,
+            listened,
+            reason,
+        };
+
+        let Ok(event_bytes): Result<Vec<u8>, serde_json::Error> = serde_json::to_vec(&event) else {
+            return;
+        };
+
+        let locked_history_db = self.history_db.lock().await;
+
+        let result = locked_history_db.generate_id().and_then(|sequence| {
+            locked_history_db.insert(&timestamp_key(timestamp, sequence), &event_bytes)
+        });
+
+        if let Err(error) = result {
+            self.write_failed("PlayHistory", error);
+        }
+    }
+
+    pub async fn record_play_counted(&self, id: String) {
+        let id = self.resolve_alias(id).await;
+
+        let Some(mut metadata) = self.get_cached_recording_metadata(id.clone()).await else {
+            return;
+        };
+
+        metadata.play_count += 1;
+        metadata.last_played = Some(SystemTime::now());
+
+        let Ok(metadata_bytes) = serde_json::to_vec(&metadata) else {
+            return;
+        };
+
+        if let Err(error) = self
+            .metadata_db
+            .lock()
+            .await
+            .insert(id.as_bytes(), &metadata_bytes)
+        {
+            self.write_failed("RecordingMetadata", error);
+        }
+    }
+
+    pub async fn get_play_history(
+        &self,
+        since: Option<u64>,
+    ) -> Result<Vec<PlayEvent>, DatabaseError> {
+        let Ok(entries) = self
+            .history_db
+            .lock()
+            .await
+            .range_from(&timestamp_key(since.unwrap_or(0), 0))
+        else {
+            return Err(DatabaseError::DatabaseFailure);
+        };
+
+        let mut events = Vec::new();
+
+        for (_, event_bytes) in entries {
+            let Ok(event): Result<PlayEvent, serde_json::Error> =
+                serde_json::from_slice(&event_bytes)
+            else {
+                continue;
+            };
+
+            events.push(event);
+        }
+
+        Ok(events)
+    }
+
+    pub async fn get_play_counts(&self) -> HashMap<String, u64> {
+        let mut play_counts = HashMap::<String, u64>::new();
+
+        for event in self.get_play_history(None).await.unwrap_or_default() {
+            *play_counts.entry(event.recording_id).or_default() += 1;
+        }
+
+        play_counts
+    }
+
+    pub async fn get_setting<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let Ok(Some(setting_bytes)) = self.settings_db.lock().await.get(key.as_bytes()) else {
+            return None;
+        };
+
+        serde_json::from_slice(&setting_bytes).ok()
+    }
+
+    pub async fn set_setting<T: Serialize>(&self, key: &str, value: &T) {
+        let Ok(setting_bytes) = serde_json::to_vec(value) else {
+            return;
+        };
+
+        if let Err(error) = self
+            .settings_db
+            .lock()
+            .await
+            .insert(key.as_bytes(), &setting_bytes)
+        {
+            self.write_failed("Settings", error);
+        }
+    }
+
+    pub async fn remove_setting(&self, key: &str) {
+        if let Err(error) = self.settings_db.lock().await.remove(key.as_bytes()) {
+            self.write_failed("Settings", error);
+        }
+    }
+
+    pub async fn record_denial(
+        &self,
+        device: String,
+        command: String,
+        permission: Permission,
+    ) -> Option<AuditEntry> {
+        let timestamp = unix_timestamp();
+
+        let locked_journal_db = self.journal_db.lock().await;
+
+        let window_start = timestamp.saturating_sub(DENIAL_COLLAPSE_WINDOW);
+
+        let Ok(recent_entries) = locked_journal_db.range_from(&timestamp_key(window_start, 0))
+        else {
+            return None;
+        };
+
+        for (key, entry_bytes) in recent_entries.into_iter().rev() {
+            let Ok(JournalEntry::PermissionDenied(mut audit_entry)) =
+                serde_json::from_slice(&entry_bytes)
+            else {
+                continue;
+            };
+
+            if audit_entry.device != device
+                || audit_entry.command != command
+                || audit_entry.permission != permission
+            {
+                continue;
+            }
+
+            audit_entry.count += 1;
+
+            let Ok(entry_bytes) =
+                serde_json::to_vec(&JournalEntry::PermissionDenied(audit_entry.clone()))
+            else {
+                return None;
+            };
+
+            if let Err(error) = locked_journal_db.insert(&key, &entry_bytes) {
+                self.write_failed("AuditLog", error);
+            }
+
+            return Some(audit_entry);
+        }
+
+        let audit_entry = AuditEntry {
+            timestamp,
+            device,
+            command,
+            permission,
+            count: 1,
+        };
+
+        let Ok(entry_bytes) =
+            serde_json::to_vec(&JournalEntry::PermissionDenied(audit_entry.clone()))
+        else {
+            return None;
+        };
+
+        let sequence = match locked_journal_db.generate_id() {
+            Ok(sequence) => sequence,
+            Err(error) => {
+                self.write_failed("AuditLog", error);
+
+                return None;
+            }
+        };
+
+        if let Err(error) =
+            locked_journal_db.insert(&timestamp_key(timestamp, sequence), &entry_bytes)
+        {
+            self.write_failed("AuditLog", error);
+        }
+
+        Some(audit_entry)
+    }
+
+    pub async fn get_audit_log(&self, since: Option<u64>, limit: Option<usize>) -> Vec<AuditEntry> {
+        let Ok(entries) = self
+            .journal_db
+            .lock()
+            .await
+            .range_from(&timestamp_key(since.unwrap_or(0), 0))
+        else {
+            return Vec::new();
+        };
+
+        entries
+            .into_iter()
+            .filter_map(|(_, entry_bytes)| {
+                match serde_json::from_slice::<JournalEntry>(&entry_bytes) {
+                    Ok(JournalEntry::PermissionDenied(audit_entry)) => Some(audit_entry),
+                    _ => None,
+                }
+            })
+            .take(limit.unwrap_or(usize::MAX))
+            .collect()
     }
 }
 
@@ -202,6 +2986,166 @@ impl Clone for Database {
         Self {
             metadata_db: self.metadata_db.clone(),
             playlist_db: self.playlist_db.clone(),
+            history_db: self.history_db.clone(),
+            journal_db: self.journal_db.clone(),
+            quarantine_db: self.quarantine_db.clone(),
+            settings_db: self.settings_db.clone(),
+            position_db: self.position_db.clone(),
+            alias_db: self.alias_db.clone(),
+            tag_db: self.tag_db.clone(),
+            playlist_sync_db: self.playlist_sync_db.clone(),
+            playlist_conflict_db: self.playlist_conflict_db.clone(),
+            release_db: self.release_db.clone(),
+            trash_db: self.trash_db.clone(),
+
+            provider: self.provider.clone(),
+            network: self.network.clone(),
+            storage: self.storage.clone(),
+
+            waveform_jobs: self.waveform_jobs.clone(),
+            file_locks: self.file_locks.clone(),
+
+            events: self.events.clone(),
+        }
+    }
+}
+
+fn merge_recordings(local: &[String], remote: &[String]) -> Vec<String> {
+    let mut merged = local.to_vec();
+    let mut anchor = 0;
+
+    for id in remote {
+        if let Some(position) = merged.iter().position(|merged_id| merged_id == id) {
+            anchor = position + 1;
+
+            continue;
         }
+
+        merged.insert(anchor, id.clone());
+
+        anchor += 1;
+    }
+
+    merged
+}
+
+fn apply_local_patch(
+    recording: &mut Recording,
+    patch: &LocalTagPatch,
+) -> Result<(), DatabaseError> {
+    if let Some(title) = &patch.title {
+        recording.title = title.clone();
+    }
+
+    if let Some(artist) = &patch.artist {
+        recording.artist_credit = Some(vec![ArtistCredit {
+            name: artist.clone(),
+            joinphrase: None,
+            artist: Artist {
+                name: artist.clone(),
+                ..Default::default()
+            },
+        }]);
+    }
+
+    if patch.album.is_none() && patch.year.is_none() {
+        return Ok(());
+    }
+
+    let existing = recording
+        .releases
+        .as_ref()
+        .and_then(|releases| releases.first());
+
+    let Some(album) = patch
+        .album
+        .clone()
+        .or_else(|| existing.map(|release| release.title.clone()))
+    else {
+        return Err(DatabaseError::DataConversionFailure);
+    };
+
+    let date = match patch.year {
+        Some(year) => Some(format!("{year:04}-01-01")),
+        None => existing
+            .and_then(|release| release.date)
+            .map(|date| date.to_string()),
+    };
+
+    let Ok(release) = serde_json::from_value::<Release>(json!({
+        "id": format!("{LOCAL_RECORDING_PREFIX}{album}"),
+        "title": album,
+        "date": date,
+    })) else {
+        return Err(DatabaseError::DataConversionFailure);
+    };
+
+    recording.releases = Some(vec![release]);
+
+    Ok(())
+}
+
+pub fn normalize_tag(tag: &str) -> Option<String> {
+    let tag = tag.trim().to_lowercase();
+
+    if tag.is_empty() || tag.chars().count() > TAG_MAX_LENGTH || tag.chars().any(char::is_control) {
+        return None;
     }
+
+    Some(tag)
+}
+
+fn tag_key(tag: &str, id: &str) -> Vec<u8> {
+    let mut key = tag.as_bytes().to_vec();
+
+    key.push(0);
+    key.extend_from_slice(id.as_bytes());
+
+    key
+}
+
+fn transfer_owner_prefix(owner: &str) -> String {
+    format!("{}-", sha256::digest(owner.as_bytes()))
+}
+
+fn transfer_path(owner: &str, id: &str) -> PathBuf {
+    root_db_path.clone().join("transfers/").join(format!(
+        "{}{}",
+        transfer_owner_prefix(owner),
+        sha256::digest(id.as_bytes())
+    ))
+}
+
+fn trash_key(entry: &TrashEntry) -> Vec<u8> {
+    let prefix = match entry.item {
+        TrashedItem::Recording { .. } => TRASH_RECORDING_PREFIX,
+        TrashedItem::Playlist(_) => TRASH_PLAYLIST_PREFIX,
+    };
+
+    format!("{}{}", prefix, entry.id).into_bytes()
+}
+
+fn split_tag_key(key: &[u8]) -> Option<(String, String)> {
+    let separator = key.iter().position(|byte| *byte == 0)?;
+
+    Some((
+        String::from_utf8(key[..separator].to_vec()).ok()?,
+        String::from_utf8(key[separator + 1..].to_vec()).ok()?,
+    ))
+}
+
+pub fn unix_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0)
+}
+
+fn timestamp_key(timestamp: u64, sequence: u64) -> [u8; 16] {
+    let mut key = [0u8; 16];
+
+    key[..8].copy_from_slice(&timestamp.to_be_bytes());
+    key[8..].copy_from_slice(&sequence.to_be_bytes());
+
+    key
 }
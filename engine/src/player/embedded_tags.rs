@@ -0,0 +1,61 @@
+use std::io::{Cursor, Seek};
+
+use lofty::{
+    config::WriteOptions,
+    file::TaggedFileExt,
+    probe::Probe,
+    tag::{Accessor, Tag, TagExt},
+};
+
+use super::LocalTagPatch;
+
+pub enum TagWriteError {
+    UnsupportedFormat,
+    WriteFailed,
+}
+
+/// Applies `patch` to the primary tag embedded in `file_contents`, creating
+/// the tag if the file has none, and returns the rewritten file.
+pub fn write_embedded_tags(
+    file_contents: Vec<u8>,
+    patch: &LocalTagPatch,
+) -> Result<Vec<u8>, TagWriteError> {
+    let mut file = Cursor::new(file_contents);
+
+    let Ok(probe) = Probe::new(&mut file).guess_file_type() else {
+        return Err(TagWriteError::UnsupportedFormat);
+    };
+
+    let Ok(tagged_file) = probe.read() else {
+        return Err(TagWriteError::UnsupportedFormat);
+    };
+
+    let tag_type = tagged_file.primary_tag_type();
+
+    let mut tag = tagged_file
+        .tag(tag_type)
+        .cloned()
+        .unwrap_or_else(|| Tag::new(tag_type));
+
+    if let Some(title) = &patch.title {
+        tag.set_title(title.clone());
+    }
+
+    if let Some(artist) = &patch.artist {
+        tag.set_artist(artist.clone());
+    }
+
+    if let Some(album) = &patch.album {
+        tag.set_album(album.clone());
+    }
+
+    if let Some(year) = patch.year {
+        tag.set_year(year.into());
+    }
+
+    if file.rewind().is_err() || tag.save_to(&mut file, WriteOptions::default()).is_err() {
+        return Err(TagWriteError::WriteFailed);
+    }
+
+    Ok(file.into_inner())
+}
@@ -0,0 +1,173 @@
+use serde::Serialize;
+
+use crate::ExportFormat;
+
+use super::{
+    database::{Database, DatabaseError},
+    TransitionReason,
+};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct HistoryRow {
+    pub timestamp: u64,
+    pub recording_id: String,
+    pub title: String,
+    pub artist: String,
+    pub listened_ms: u128,
+    pub reason: TransitionReason,
+}
+
+pub async fn export_history(
+    database: &Database,
+    since: Option<u64>,
+    format: ExportFormat,
+) -> Result<Vec<u8>, DatabaseError> {
+    let mut rows = Vec::new();
+
+    for event in database.get_play_history(since).await? {
+        let (title, artist) = match database
+            .get_cached_recording_metadata(event.recording_id.clone())
+            .await
+        {
+            Some(metadata) => (metadata.recording.title.clone(), metadata.artist()),
+            None => (String::new(), String::new()),
+        };
+
+        rows.push(HistoryRow {
+            timestamp: event.timestamp,
+            recording_id: event.recording_id,
+            title,
+            artist,
+            listened_ms: event.listened.as_millis(),
+            reason: event.reason,
+        });
+    }
+
+    match format {
+        ExportFormat::Csv => Ok(history_to_csv(&rows)),
+        ExportFormat::Json => {
+            serde_json::to_vec(&rows).map_err(|_| DatabaseError::DataConversionFailure)
+        }
+    }
+}
+
+pub fn history_to_csv(rows: &[HistoryRow]) -> Vec<u8> {
+    let mut csv = String::from("timestamp,recording_id,title,artist,listened_ms,reason\r\n");
+
+    for row in rows {
+        let fields = [
+            row.timestamp.to_string(),
+            row.recording_id.clone(),
+            row.title.clone(),
+            row.artist.clone(),
+            row.listened_ms.to_string(),
+            format!("{:?}", row.reason),
+        ];
+
+        csv.push_str(
+            &fields
+                .iter()
+                .map(|field| csv_field(field))
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+        csv.push_str("\r\n");
+    }
+
+    csv.into_bytes()
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_csv(csv: &str) -> Vec<Vec<String>> {
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = csv.chars().peekable();
+
+        while let Some(char) = chars.next() {
+            match (quoted, char) {
+                (true, '"') if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                (true, '"') => quoted = false,
+                (true, char) => field.push(char),
+                (false, '"') => quoted = true,
+                (false, ',') => record.push(std::mem::take(&mut field)),
+                (false, '\r') => {}
+                (false, '\n') => {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (false, char) => field.push(char),
+            }
+        }
+
+        records
+    }
+
+    fn row(title: &str, artist: &str, reason: TransitionReason) -> HistoryRow {
+        HistoryRow {
+            timestamp: 1_700_000_000,
+            recording_id: "0f3f6a0e-1c9b-4a45-9a36-5f2ad2a7f1d0".to_owned(),
+            title: title.to_owned(),
+            artist: artist.to_owned(),
+            listened_ms: 183_250,
+            reason,
+        }
+    }
+
+    #[test]
+    fn csv_round_trips_awkward_fields() {
+        let rows = vec![
+            row("Plain", "Artist", TransitionReason::Finished),
+            row("Hello, World", "Say \"Hi\"", TransitionReason::Next),
+            row("Line\r\nBreak", "", TransitionReason::Stop),
+        ];
+
+        let csv = String::from_utf8(history_to_csv(&rows)).unwrap();
+        let records = parse_csv(&csv);
+
+        assert_eq!(records.len(), rows.len() + 1);
+        assert_eq!(
+            records[0],
+            [
+                "timestamp",
+                "recording_id",
+                "title",
+                "artist",
+                "listened_ms",
+                "reason"
+            ]
+        );
+
+        for (record, row) in records[1..].iter().zip(&rows) {
+            assert_eq!(record.len(), 6);
+            assert_eq!(record[0], row.timestamp.to_string());
+            assert_eq!(record[1], row.recording_id);
+            assert_eq!(record[2], row.title);
+            assert_eq!(record[3], row.artist);
+            assert_eq!(record[4], row.listened_ms.to_string());
+            assert_eq!(record[5], format!("{:?}", row.reason));
+        }
+    }
+
+    #[test]
+    fn csv_without_rows_is_only_the_header() {
+        let csv = String::from_utf8(history_to_csv(&[])).unwrap();
+
+        assert_eq!(parse_csv(&csv).len(), 1);
+    }
+}
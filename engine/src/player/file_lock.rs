@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use tokio::sync::{OwnedRwLockReadGuard, OwnedRwLockWriteGuard, RwLock};
+
+type LockMap = Arc<Mutex<HashMap<String, Arc<RwLock<()>>>>>;
+
+#[derive(Clone, Default)]
+pub struct RecordingFileLocks {
+    locks: LockMap,
+}
+
+enum Held {
+    Read { _guard: OwnedRwLockReadGuard<()> },
+    Write { _guard: OwnedRwLockWriteGuard<()> },
+}
+
+pub struct RecordingFileGuard {
+    id: String,
+    locks: LockMap,
+    held: Option<Held>,
+}
+
+impl RecordingFileLocks {
+    pub async fn read(&self, id: &str) -> RecordingFileGuard {
+        let lock = self.lock(id);
+
+        self.guard(
+            id,
+            Held::Read {
+                _guard: lock.read_owned().await,
+            },
+        )
+    }
+
+    pub async fn write(&self, id: &str) -> RecordingFileGuard {
+        let lock = self.lock(id);
+
+        self.guard(
+            id,
+            Held::Write {
+                _guard: lock.write_owned().await,
+            },
+        )
+    }
+
+    pub fn is_updating(&self, id: &str) -> bool {
+        self.locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .get(id)
+            .is_some_and(|lock| lock.try_read().is_err())
+    }
+
+    fn lock(&self, id: &str) -> Arc<RwLock<()>> {
+        self.locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .entry(id.to_owned())
+            .or_default()
+            .clone()
+    }
+
+    fn guard(&self, id: &str, held: Held) -> RecordingFileGuard {
+        RecordingFileGuard {
+            id: id.to_owned(),
+            locks: self.locks.clone(),
+            held: Some(held),
+        }
+    }
+}
+
+impl Drop for RecordingFileGuard {
+    fn drop(&mut self) {
+        let mut locks = self
+            .locks
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        self.held.take();
+
+        if locks
+            .get(&self.id)
+            .is_some_and(|lock| Arc::strong_count(lock) == 1)
+        {
+            locks.remove(&self.id);
+        }
+    }
+}
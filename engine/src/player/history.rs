@@ -0,0 +1,49 @@
+use std::collections::VecDeque;
+
+pub struct PlayedHistory {
+    entries: VecDeque<String>,
+    capacity: usize,
+}
+
+impl PlayedHistory {
+    pub fn new(capacity: usize) -> PlayedHistory {
+        PlayedHistory {
+            entries: VecDeque::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    pub fn push(&mut self, id: String) -> Option<String> {
+        if self.entries.front() == Some(&id) {
+            return None;
+        }
+
+        self.entries.push_front(id);
+
+        if self.entries.len() > self.capacity {
+            return self.entries.pop_back();
+        }
+
+        None
+    }
+
+    pub fn pop_latest(&mut self) -> Option<String> {
+        self.entries.pop_front()
+    }
+
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &String> {
+        self.entries.iter().take(count)
+    }
+
+    pub fn replace(&mut self, old_id: &str, new_id: &str) {
+        for id in self.entries.iter_mut() {
+            if id == old_id {
+                *id = new_id.to_owned();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
@@ -0,0 +1,200 @@
+use std::time::Duration;
+
+use rodio::{source::SeekError, Source};
+
+const LEVEL_JUMP_THRESHOLD_DB: f32 = 6.0;
+const RAMP_DURATION_MS: u32 = 1000;
+const SILENCE_DB: f32 = -96.0;
+
+pub fn loudness_db(sum_of_squares: f64, sample_count: u64) -> f32 {
+    if sample_count == 0 || sum_of_squares <= 0.0 {
+        return SILENCE_DB;
+    }
+
+    ((10.0 * (sum_of_squares / sample_count as f64).log10()) as f32).max(SILENCE_DB)
+}
+
+pub fn transition_gain(outgoing_db: f32, incoming_db: f32) -> Option<f32> {
+    let jump = incoming_db - outgoing_db;
+
+    if jump <= LEVEL_JUMP_THRESHOLD_DB {
+        return None;
+    }
+
+    Some(10f32.powf(-(jump - LEVEL_JUMP_THRESHOLD_DB) / 20.0))
+}
+
+pub struct LevelRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    start_gain: f32,
+    ramp_len: usize,
+    position: usize,
+}
+
+impl<S> LevelRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, start_gain: Option<f32>) -> LevelRamp<S> {
+        let ramp_len = match start_gain {
+            Some(_) => {
+                (source.sample_rate() * RAMP_DURATION_MS / 1000) as usize
+                    * source.channels() as usize
+            }
+            None => 0,
+        };
+
+        LevelRamp {
+            source,
+            start_gain: start_gain.unwrap_or(1.0),
+            ramp_len,
+            position: 0,
+        }
+    }
+}
+
+impl<S> Iterator for LevelRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next()?;
+
+        if self.position >= self.ramp_len {
+            return Some(sample);
+        }
+
+        let remaining = (self.ramp_len - self.position) as f32 / self.ramp_len as f32;
+
+        self.position += 1;
+
+        Some(sample * self.start_gain.powf(remaining))
+    }
+}
+
+impl<S> Source for LevelRamp<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.position = self.ramp_len;
+
+        self.source.try_seek(position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+
+    use rodio::buffer::SamplesBuffer;
+
+    use super::*;
+    use crate::player::waveform::compute_waveform;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    fn sine_wav(amplitude: f32) -> Vec<u8> {
+        let samples = (0..SAMPLE_RATE)
+            .map(|index| {
+                let phase = index as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32;
+
+                (phase.sin() * amplitude * i16::MAX as f32) as i16
+            })
+            .flat_map(i16::to_le_bytes)
+            .collect::<Vec<u8>>();
+
+        let mut wav = Vec::new();
+
+        wav.extend_from_slice(b"RIFF");
+        wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(b"WAVEfmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+        wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+        wav.extend_from_slice(&samples);
+
+        wav
+    }
+
+    fn metered_db(wav: Vec<u8>) -> f32 {
+        compute_waveform(wav, &AtomicBool::new(false)).unwrap().1
+    }
+
+    #[test]
+    fn a_sine_meters_at_its_rms_level() {
+        // A sine's RMS is its amplitude over the square root of two.
+        for amplitude in [1.0f32, 0.5, 0.1] {
+            let expected = 20.0 * (amplitude / 2f32.sqrt()).log10();
+            let metered = metered_db(sine_wav(amplitude));
+
+            assert!(
+                (metered - expected).abs() < 0.1,
+                "{} metered at {} dB, expected {} dB",
+                amplitude,
+                metered,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn silence_meters_at_the_floor() {
+        assert_eq!(metered_db(sine_wav(0.0)), SILENCE_DB);
+        assert_eq!(loudness_db(0.0, 0), SILENCE_DB);
+        assert_eq!(loudness_db(1e-30, 1), SILENCE_DB);
+    }
+
+    #[test]
+    fn only_jumps_past_the_threshold_are_softened() {
+        assert_eq!(transition_gain(-20.0, -14.0), None);
+        assert_eq!(transition_gain(-10.0, -20.0), None);
+
+        let gain = transition_gain(-20.0, -8.0).unwrap();
+
+        assert!((gain - 10f32.powf(-6.0 / 20.0)).abs() < 1e-6, "{}", gain);
+    }
+
+    #[test]
+    fn the_ramp_climbs_from_the_start_gain_to_unity() {
+        let source = SamplesBuffer::new(1, SAMPLE_RATE, vec![1.0f32; SAMPLE_RATE as usize * 2]);
+        let samples = LevelRamp::new(source, Some(0.25)).collect::<Vec<f32>>();
+
+        assert!((samples[0] - 0.25).abs() < 1e-3, "{}", samples[0]);
+        assert!(samples.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(samples[SAMPLE_RATE as usize..]
+            .iter()
+            .all(|sample| *sample == 1.0));
+
+        let source = SamplesBuffer::new(1, SAMPLE_RATE, vec![0.5f32; 16]);
+
+        assert!(LevelRamp::new(source, None).all(|sample| sample == 0.5));
+    }
+}
@@ -1,16 +1,184 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
 use musicbrainz_rs::entity::recording::Recording;
 use serde::{Deserialize, Serialize};
 
+use crate::{LoopMode, OnQueueEnd, Permission};
+
+pub const LOCAL_RECORDING_PREFIX: &str = "local:";
+pub const SPOKEN_WORD_DURATION: Duration = Duration::from_secs(20 * 60);
+
+pub mod collation;
 pub mod database;
+pub mod embedded_tags;
+pub mod export;
+pub mod file_lock;
+pub mod history;
+pub mod level;
+#[cfg(feature = "opus-stream")]
+pub mod opus;
+pub mod output;
+pub mod preview;
+pub mod probe;
+pub mod provider;
+pub mod recovery;
+pub mod seek;
 pub mod sequencer;
+pub mod state;
+pub mod storage;
+pub mod store;
+pub mod stream;
+pub mod suggester;
+pub mod underrun;
+pub mod waveform;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RecordingMetadata {
     pub audio_file_hash: Option<String>,
+    #[serde(default)]
+    pub audio_source: AudioSource,
+    #[serde(default)]
+    pub audio_location: AudioLocation,
+    #[serde(default)]
+    pub provenance: Vec<ProvenanceEntry>,
+    #[serde(default)]
+    pub audio_format: Option<AudioFormat>,
+    #[serde(default)]
+    pub waveform: Option<Vec<u8>>,
+    #[serde(default)]
+    pub loudness: Option<f32>,
+    #[serde(default)]
+    pub works: Vec<WorkRelation>,
+    #[serde(default)]
+    pub orphaned: bool,
+    #[serde(default)]
+    pub faulty: bool,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub spoken_word: Option<bool>,
+    #[serde(default)]
+    pub resume_position: Option<Duration>,
+    #[serde(default)]
+    pub play_count: u64,
+    #[serde(default)]
+    pub last_played: Option<SystemTime>,
+    #[serde(default)]
+    pub updating: bool,
 
     pub recording: Recording,
 }
 
+impl RecordingMetadata {
+    pub fn duration(&self) -> Option<Duration> {
+        self.audio_format
+            .as_ref()
+            .and_then(|format| format.duration)
+            .or_else(|| {
+                self.recording
+                    .length
+                    .map(|length| Duration::from_millis(length as u64))
+            })
+    }
+
+    pub fn is_spoken_word(&self) -> bool {
+        self.spoken_word.unwrap_or_else(|| {
+            self.duration()
+                .is_some_and(|duration| duration > SPOKEN_WORD_DURATION)
+        })
+    }
+
+    pub fn artist(&self) -> String {
+        self.recording
+            .artist_credit
+            .iter()
+            .flatten()
+            .map(|credit| {
+                format!(
+                    "{}{}",
+                    credit.name,
+                    credit.joinphrase.clone().unwrap_or_default()
+                )
+            })
+            .collect()
+    }
+
+    pub fn artist_ids(&self) -> HashSet<String> {
+        self.recording
+            .artist_credit
+            .iter()
+            .flatten()
+            .map(|credit| credit.artist.id.clone())
+            .filter(|id| !id.is_empty())
+            .collect()
+    }
+
+    pub fn release_ids(&self) -> HashSet<String> {
+        self.recording
+            .releases
+            .iter()
+            .flatten()
+            .map(|release| release.id.clone())
+            .collect()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AudioFormat {
+    pub codec: String,
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub duration: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum AudioSource {
+    Transfer {
+        from_device: String,
+    },
+    Import {
+        path: PathBuf,
+    },
+    Url {
+        url: String,
+    },
+    Scan {
+        path: PathBuf,
+    },
+    #[default]
+    Unknown,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub enum AudioLocation {
+    #[default]
+    Managed,
+    External {
+        path: PathBuf,
+        last_verified_hash: Option<String>,
+        #[serde(default)]
+        transferable: bool,
+    },
+}
+
+impl AudioLocation {
+    pub fn is_external(&self) -> bool {
+        matches!(self, AudioLocation::External { .. })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProvenanceEntry {
+    pub timestamp: u64,
+
+    pub audio_file_hash: String,
+    pub source: AudioSource,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlaylistMetadata {
     pub id: String,
@@ -18,4 +186,245 @@ pub struct PlaylistMetadata {
     pub name: String,
 
     pub recordings: Vec<String>,
+
+    #[serde(default)]
+    pub resume_position: Option<PlaylistPosition>,
+    #[serde(default)]
+    pub modified: u64,
+}
+
+impl PlaylistMetadata {
+    pub fn resume_index(&self) -> usize {
+        let Some(position) = &self.resume_position else {
+            return 0;
+        };
+
+        if self.recordings.get(position.index) == Some(&position.recording_id) {
+            return position.index + 1;
+        }
+
+        self.recordings
+            .iter()
+            .position(|id| *id == position.recording_id)
+            .unwrap_or(position.index)
+            + 1
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct LocalTagPatch {
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub artist: Option<String>,
+    #[serde(default)]
+    pub album: Option<String>,
+    #[serde(default)]
+    pub year: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct NamedQueue {
+    pub name: String,
+    pub recordings: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlaylistPosition {
+    pub index: usize,
+    pub recording_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum NetworkState {
+    Online,
+    Offline { since: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct AudioStatus {
+    pub underruns: u64,
+    pub underruns_per_minute: f32,
+    pub priority_elevated: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum StorageState {
+    Available,
+    Low { available: u64, reserve: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub enum TransitionReason {
+    Play,
+    Next,
+    Previous,
+    Loop,
+    Finished,
+    Stop,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayEvent {
+    pub timestamp: u64,
+
+    pub recording_id: String,
+
+    pub listened: Duration,
+    pub reason: TransitionReason,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SessionSnapshot {
+    pub recording_id: String,
+    pub position: Duration,
+
+    pub queue: Vec<String>,
+    pub loop_mode: LoopMode,
+    pub shuffle: bool,
+    #[serde(default)]
+    pub shuffle_seed: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SavedPlayerState {
+    pub recording_id: Option<String>,
+    pub position: Duration,
+
+    pub queue: Vec<String>,
+    #[serde(default)]
+    pub shuffled_queue: Vec<String>,
+    #[serde(default)]
+    pub request_queue: Vec<String>,
+    #[serde(default)]
+    pub shuffled_request_queue: Vec<String>,
+    #[serde(default)]
+    pub play_order: Vec<usize>,
+    #[serde(default)]
+    pub cursor: Option<usize>,
+    pub loop_mode: LoopMode,
+    pub shuffle: bool,
+    #[serde(default)]
+    pub shuffle_seed: Option<u64>,
+
+    pub volume: f32,
+    #[serde(default)]
+    pub muted: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum TrashedItem {
+    Recording {
+        metadata: Box<RecordingMetadata>,
+        memberships: Vec<(String, usize)>,
+    },
+    Playlist(PlaylistMetadata),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TrashEntry {
+    pub id: String,
+    pub deleted_at: u64,
+
+    pub item: TrashedItem,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlaybackState {
+    pub recording_id: Option<String>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+
+    pub paused: bool,
+    pub position: Duration,
+
+    pub queue_length: usize,
+    pub shuffle: bool,
+    #[serde(default)]
+    pub shuffle_seed: Option<u64>,
+    #[serde(default)]
+    pub on_queue_end: OnQueueEnd,
+    #[serde(default)]
+    pub smooth_level_transition: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct EffectSummary {
+    pub removed: Vec<String>,
+    pub bytes_reclaimed: u64,
+    pub playlists_rewritten: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct AdoptionSummary {
+    pub adopted: Vec<String>,
+    pub skipped: usize,
+    pub failed: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorkRelation {
+    pub work_id: String,
+    pub title: String,
+    pub relationship: String,
+    pub attributes: Vec<String>,
+}
+
+impl WorkRelation {
+    pub fn label(&self) -> String {
+        if self.attributes.is_empty() {
+            return self.relationship.clone();
+        }
+
+        format!("{} ({})", self.relationship, self.attributes.join(", "))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RecordingRelationship {
+    pub work_id: String,
+    pub relationship: String,
+    pub local: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StreamChunk {
+    /// Ogg pages of Opus audio. A listener's first chunk opens with the
+    /// stream headers, and every chunk ends on a page boundary.
+    pub ogg: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+
+    pub device: String,
+    pub command: String,
+    pub permission: Permission,
+
+    pub count: u32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum JournalEntry {
+    PermissionDenied(AuditEntry),
+    FileMissing(MissingFileEntry),
+    Shutdown(ShutdownEntry),
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ShutdownEntry {
+    pub timestamp: u64,
+
+    pub recording_id: Option<String>,
+    pub position: Duration,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MissingFileEntry {
+    pub timestamp: u64,
+
+    pub recording_id: String,
+    pub audio_file_hash: String,
+    pub path: PathBuf,
 }
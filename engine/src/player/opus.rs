@@ -0,0 +1,416 @@
+use ogg::{
+    reading::{BasePacketReader, OggPage, PageParser},
+    PacketWriteEndInfo, PacketWriter,
+};
+use opus_rs::{Application, OpusDecoder, OpusEncoder};
+use tokio::sync::{broadcast, mpsc};
+
+use super::{stream::PcmChunk, StreamChunk};
+
+pub const OPUS_SAMPLE_RATE: u32 = 48000;
+pub const OPUS_CHANNELS: u16 = 2;
+
+/// 20ms at 48kHz, per channel.
+const FRAME_LEN: usize = 960;
+/// The longest packet Opus allows, 120ms.
+const MAX_FRAME_LEN: usize = 5760;
+const MAX_PACKET_LEN: usize = 1276;
+const BITRATE: i32 = 128_000;
+/// The encoder's lookahead at 48kHz, which players trim from the start.
+const PRE_SKIP: u16 = 312;
+const VENDOR: &str = "playit";
+
+const OGG_HEADER_LEN: usize = 27;
+const ENCODED_CHANNEL_CAPACITY: usize = 8;
+
+pub enum EncodedAudio {
+    /// Opus packets of 20ms each.
+    Packets(Vec<Vec<u8>>),
+    /// Encoding fell behind the playback output, and the encoder stopped.
+    FellBehind,
+}
+
+pub struct StreamEncoder {
+    encoder: OpusEncoder,
+    resampler: Resampler,
+    pending: Vec<f32>,
+}
+
+impl StreamEncoder {
+    pub fn new() -> Option<StreamEncoder> {
+        let mut encoder = OpusEncoder::new(
+            OPUS_SAMPLE_RATE as i32,
+            OPUS_CHANNELS as usize,
+            Application::Audio,
+        )
+        .ok()?;
+
+        encoder.bitrate_bps = BITRATE;
+
+        Some(StreamEncoder {
+            encoder,
+            resampler: Resampler::default(),
+            pending: Vec::new(),
+        })
+    }
+
+    /// Encodes every whole frame `chunk` completes. The remainder waits for
+    /// the next chunk.
+    pub fn encode(&mut self, chunk: &PcmChunk) -> Vec<Vec<u8>> {
+        if chunk.sample_rate == 0 || chunk.channels == 0 {
+            return Vec::new();
+        }
+
+        self.resampler.push(chunk, &mut self.pending);
+
+        let frame_samples = FRAME_LEN * OPUS_CHANNELS as usize;
+
+        let mut packets = Vec::<Vec<u8>>::new();
+        let mut packet = [0u8; MAX_PACKET_LEN];
+        let mut consumed = 0;
+
+        while self.pending.len() - consumed >= frame_samples {
+            let frame = &self.pending[consumed..consumed + frame_samples];
+
+            if let Ok(length) = self.encoder.encode(frame, FRAME_LEN, &mut packet) {
+                packets.push(packet[..length].to_vec());
+            }
+
+            consumed += frame_samples;
+        }
+
+        self.pending.drain(..consumed);
+
+        packets
+    }
+}
+
+/// Encodes the playback output as it arrives. When encoding runs slower than
+/// real time, the output piles up in the broadcast until the oldest chunks
+/// are dropped; the encoder then reports that it fell behind and stops,
+/// without ever holding up playback.
+pub fn spawn_encoder(
+    mut encoder: StreamEncoder,
+    mut output: broadcast::Receiver<PcmChunk>,
+) -> mpsc::Receiver<EncodedAudio> {
+    let (sender, receiver) = mpsc::channel(ENCODED_CHANNEL_CAPACITY);
+
+    tokio::spawn(async move {
+        loop {
+            let chunk = tokio::select! {
+                _ = sender.closed() => return,
+                chunk = output.recv() => chunk,
+            };
+
+            let audio = match chunk {
+                Ok(chunk) => {
+                    let Ok((returned, packets)) = tokio::task::spawn_blocking(move || {
+                        let packets = encoder.encode(&chunk);
+
+                        (encoder, packets)
+                    })
+                    .await
+                    else {
+                        return;
+                    };
+
+                    encoder = returned;
+
+                    EncodedAudio::Packets(packets)
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => EncodedAudio::FellBehind,
+                Err(broadcast::error::RecvError::Closed) => return,
+            };
+
+            let fell_behind = matches!(audio, EncodedAudio::FellBehind);
+
+            if sender.send(audio).await.is_err() || fell_behind {
+                return;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// One listener's Ogg stream. Each listener gets its own, so that whenever
+/// they join, their first pages carry the headers.
+pub struct OggMuxer {
+    writer: PacketWriter<Vec<u8>>,
+    serial: u32,
+    granule: u64,
+}
+
+impl OggMuxer {
+    pub fn new() -> OggMuxer {
+        let mut writer = PacketWriter::new(Vec::new());
+        let serial = rand::random();
+
+        let _ = writer.write_packet(opus_head(), serial, PacketWriteEndInfo::EndPage, 0);
+        let _ = writer.write_packet(opus_tags(), serial, PacketWriteEndInfo::EndPage, 0);
+
+        OggMuxer {
+            writer,
+            serial,
+            granule: PRE_SKIP as u64,
+        }
+    }
+
+    /// Wraps `packets` in pages, ending the last one so that the chunk can be
+    /// sent on its own.
+    pub fn mux(&mut self, packets: &[Vec<u8>]) -> StreamChunk {
+        for (index, packet) in packets.iter().enumerate() {
+            self.granule += FRAME_LEN as u64;
+
+            let end = if index + 1 == packets.len() {
+                PacketWriteEndInfo::EndPage
+            } else {
+                PacketWriteEndInfo::NormalPacket
+            };
+
+            let _ = self.writer.write_packet(
+                packet.clone().into_boxed_slice(),
+                self.serial,
+                end,
+                self.granule,
+            );
+        }
+
+        StreamChunk {
+            ogg: std::mem::take(self.writer.inner_mut()),
+        }
+    }
+}
+
+pub struct StreamDecoder {
+    reader: BasePacketReader,
+    decoder: OpusDecoder,
+}
+
+impl StreamDecoder {
+    pub fn new() -> Option<StreamDecoder> {
+        Some(StreamDecoder {
+            reader: BasePacketReader::new(),
+            decoder: OpusDecoder::new(OPUS_SAMPLE_RATE as i32, OPUS_CHANNELS as usize).ok()?,
+        })
+    }
+
+    /// Decodes the audio in `chunk`, skipping the headers and any page that
+    /// does not parse.
+    pub fn decode(&mut self, chunk: &StreamChunk) -> PcmChunk {
+        let mut samples = Vec::<i16>::new();
+        let mut frame = vec![0f32; MAX_FRAME_LEN * OPUS_CHANNELS as usize];
+
+        let mut remaining = chunk.ogg.as_slice();
+
+        while let Some((page, rest)) = next_page(remaining) {
+            remaining = rest;
+
+            if self.reader.push_page(page).is_err() {
+                // Chunks the connection skipped leave the stream with gaps,
+                // which the reader tolerates after a seek. The page is lost.
+                self.reader.update_after_seek();
+
+                continue;
+            }
+
+            while let Some(packet) = self.reader.read_packet() {
+                if packet.data.starts_with(b"OpusHead") || packet.data.starts_with(b"OpusTags") {
+                    continue;
+                }
+
+                let Ok(length) = self.decoder.decode(&packet.data, MAX_FRAME_LEN, &mut frame)
+                else {
+                    continue;
+                };
+
+                samples.extend(
+                    frame[..length * OPUS_CHANNELS as usize]
+                        .iter()
+                        .map(|sample| (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16),
+                );
+            }
+        }
+
+        PcmChunk {
+            sample_rate: OPUS_SAMPLE_RATE,
+            channels: OPUS_CHANNELS,
+            samples,
+        }
+    }
+}
+
+/// Converts any output to interleaved 48kHz stereo, interpolating linearly
+/// across chunk boundaries.
+#[derive(Default)]
+struct Resampler {
+    sample_rate: u32,
+    last: Option<[f32; 2]>,
+    position: f64,
+}
+
+impl Resampler {
+    fn push(&mut self, chunk: &PcmChunk, output: &mut Vec<f32>) {
+        if chunk.sample_rate != self.sample_rate {
+            *self = Resampler {
+                sample_rate: chunk.sample_rate,
+                ..Default::default()
+            };
+        }
+
+        let frames = self
+            .last
+            .into_iter()
+            .chain(
+                chunk
+                    .samples
+                    .chunks_exact(chunk.channels as usize)
+                    .map(|frame| {
+                        let left = frame[0] as f32 / i16::MAX as f32;
+                        let right = frame
+                            .get(1)
+                            .map_or(left, |right| *right as f32 / i16::MAX as f32);
+
+                        [left, right]
+                    }),
+            )
+            .collect::<Vec<[f32; 2]>>();
+
+        let Some(last) = frames.last().copied() else {
+            return;
+        };
+
+        let step = self.sample_rate as f64 / OPUS_SAMPLE_RATE as f64;
+
+        while self.position < (frames.len() - 1) as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+
+            output.extend(
+                frames[index]
+                    .iter()
+                    .zip(frames[index + 1])
+                    .map(|(from, to)| from * (1.0 - fraction) + to * fraction),
+            );
+
+            self.position += step;
+        }
+
+        self.position -= (frames.len() - 1) as f64;
+        self.last = Some(last);
+    }
+}
+
+fn opus_head() -> Box<[u8]> {
+    let mut head = b"OpusHead".to_vec();
+
+    head.push(1);
+    head.push(OPUS_CHANNELS as u8);
+    head.extend(PRE_SKIP.to_le_bytes());
+    head.extend(OPUS_SAMPLE_RATE.to_le_bytes());
+    head.extend(0i16.to_le_bytes());
+    head.push(0);
+
+    head.into_boxed_slice()
+}
+
+fn opus_tags() -> Box<[u8]> {
+    let mut tags = b"OpusTags".to_vec();
+
+    tags.extend((VENDOR.len() as u32).to_le_bytes());
+    tags.extend(VENDOR.as_bytes());
+    tags.extend(0u32.to_le_bytes());
+
+    tags.into_boxed_slice()
+}
+
+fn next_page(bytes: &[u8]) -> Option<(OggPage, &[u8])> {
+    let header = bytes.get(..OGG_HEADER_LEN)?.try_into().ok()?;
+
+    let (mut parser, segments_len) = PageParser::new(header).ok()?;
+
+    let body_start = OGG_HEADER_LEN + segments_len;
+    let body_len = parser.parse_segments(bytes.get(OGG_HEADER_LEN..body_start)?.to_vec());
+    let body_end = body_start + body_len;
+
+    let page = parser
+        .parse_packet_data(bytes.get(body_start..body_end)?.to_vec())
+        .ok()?;
+
+    Some((page, &bytes[body_end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tone(sample_rate: u32, milliseconds: u32) -> PcmChunk {
+        let frames = sample_rate * milliseconds / 1000;
+
+        PcmChunk {
+            sample_rate,
+            channels: 2,
+            samples: (0..frames)
+                .flat_map(|frame| {
+                    let phase = frame as f32 * 440.0 / sample_rate as f32;
+                    let sample = ((phase * std::f32::consts::TAU).sin() * 8000.0) as i16;
+
+                    [sample, sample]
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn a_tone_survives_the_round_trip() {
+        let mut encoder = StreamEncoder::new().unwrap();
+        let mut muxer = OggMuxer::new();
+        let mut decoder = StreamDecoder::new().unwrap();
+
+        let packets = encoder.encode(&tone(44100, 200));
+
+        // 200ms at 44.1kHz resamples to just short of ten 20ms frames.
+        assert_eq!(packets.len(), 9);
+
+        let chunk = muxer.mux(&packets);
+
+        assert!(chunk.ogg.starts_with(b"OggS"));
+
+        let decoded = decoder.decode(&chunk);
+
+        assert_eq!(decoded.sample_rate, OPUS_SAMPLE_RATE);
+        assert_eq!(decoded.channels, OPUS_CHANNELS);
+        assert_eq!(decoded.samples.len(), 9 * FRAME_LEN * 2);
+        assert!(decoded.samples.iter().any(|sample| sample.abs() > 1000));
+    }
+
+    #[test]
+    fn later_chunks_carry_no_headers() {
+        let mut encoder = StreamEncoder::new().unwrap();
+        let mut muxer = OggMuxer::new();
+
+        let first = muxer.mux(&encoder.encode(&tone(48000, 40)));
+        let second = muxer.mux(&encoder.encode(&tone(48000, 40)));
+
+        assert!(first.ogg.windows(8).any(|bytes| bytes == b"OpusHead"));
+        assert!(second.ogg.starts_with(b"OggS"));
+        assert!(!second.ogg.windows(8).any(|bytes| bytes == b"OpusHead"));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn the_encoder_stops_once_it_falls_behind() {
+        let (output, receiver) = broadcast::channel(2);
+        let mut encoded = spawn_encoder(StreamEncoder::new().unwrap(), receiver);
+
+        // The encoder task has not run yet, so the output overtakes it.
+        for _ in 0..4 {
+            let _ = output.send(tone(48000, 20));
+        }
+
+        assert!(matches!(
+            encoded.recv().await,
+            Some(EncodedAudio::FellBehind)
+        ));
+        assert!(encoded.recv().await.is_none());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{
+    sync::{
+        mpsc::{self as std_mpsc, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use rodio::{
+    dynamic_mixer::{self, DynamicMixerController},
+    OutputStream, OutputStreamHandle, Sink,
+};
+
+use crate::{AudioOutput, AudioOutputChoice};
+
+use super::{
+    sequencer::SequencerError,
+    underrun::{promote_current_thread, AudioTelemetry, UnderrunProbe},
+};
+
+const RENDER_BLOCK_MS: u32 = 10;
+
+pub enum OutputHandle {
+    Device(OutputStreamHandle, AudioTelemetry),
+    Custom(Arc<DynamicMixerController<f32>>),
+}
+
+impl OutputHandle {
+    pub fn new_sink(&self) -> Result<Sink, SequencerError> {
+        match self {
+            OutputHandle::Device(stream_handle, telemetry) => {
+                let (sink, queue_output) = Sink::new_idle();
+
+                stream_handle
+                    .play_raw(UnderrunProbe::new(queue_output, telemetry.clone()))
+                    .map_err(|_| SequencerError::AudioInitializationFailed)?;
+
+                Ok(sink)
+            }
+            OutputHandle::Custom(controller) => {
+                let (sink, queue_output) = Sink::new_idle();
+
+                controller.add(queue_output);
+
+                Ok(sink)
+            }
+        }
+    }
+
+    pub fn new_unmonitored_sink(&self) -> Result<Sink, SequencerError> {
+        match self {
+            OutputHandle::Device(stream_handle, _) => {
+                Sink::try_new(stream_handle).map_err(|_| SequencerError::AudioInitializationFailed)
+            }
+            OutputHandle::Custom(_) => self.new_sink(),
+        }
+    }
+}
+
+pub fn open_output(
+    output: &AudioOutputChoice,
+    telemetry: &AudioTelemetry,
+) -> Result<(std_mpsc::Sender<()>, OutputHandle), SequencerError> {
+    match output {
+        AudioOutputChoice::Default => open_device(telemetry.clone()),
+        AudioOutputChoice::Custom(output) => Ok(start_render(output.clone(), telemetry.clone())),
+    }
+}
+
+fn open_device(
+    telemetry: AudioTelemetry,
+) -> Result<(std_mpsc::Sender<()>, OutputHandle), SequencerError> {
+    let (handle_sender, handle_receiver) = std_mpsc::channel();
+    let (keepalive_sender, keepalive_receiver) = std_mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        let Ok((stream, stream_handle)) = OutputStream::try_default() else {
+            let _ = handle_sender.send(None);
+
+            return;
+        };
+
+        let _ = handle_sender.send(Some(stream_handle));
+
+        let _ = keepalive_receiver.recv();
+
+        drop(stream);
+    });
+
+    let Ok(Some(stream_handle)) = handle_receiver.recv() else {
+        return Err(SequencerError::AudioInitializationFailed);
+    };
+
+    Ok((
+        keepalive_sender,
+        OutputHandle::Device(stream_handle, telemetry),
+    ))
+}
+
+fn start_render(
+    output: Arc<Mutex<dyn AudioOutput>>,
+    telemetry: AudioTelemetry,
+) -> (std_mpsc::Sender<()>, OutputHandle) {
+    let (sample_rate, channels) = match output.lock() {
+        Ok(output) => (output.sample_rate().max(1), output.channels().max(1)),
+        Err(_) => (1, 1),
+    };
+
+    let (controller, mut mixer) = dynamic_mixer::mixer::<f32>(channels, sample_rate);
+    let (keepalive_sender, keepalive_receiver) = std_mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        telemetry.set_priority_elevated(promote_current_thread());
+
+        let block_len =
+            ((sample_rate * RENDER_BLOCK_MS / 1000) as usize).max(1) * channels as usize;
+        let block_duration = Duration::from_millis(RENDER_BLOCK_MS as u64);
+
+        let mut block = vec![0.0; block_len];
+        let mut deadline = Instant::now();
+
+        while let Err(TryRecvError::Empty) = keepalive_receiver.try_recv() {
+            for sample in block.iter_mut() {
+                *sample = mixer.next().unwrap_or(0.0);
+            }
+
+            if let Ok(mut output) = output.lock() {
+                output.write(&block);
+            }
+
+            deadline += block_duration;
+
+            let now = Instant::now();
+
+            if now > deadline + block_duration {
+                telemetry.record_underrun();
+
+                deadline = now;
+            }
+
+            thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        }
+    });
+
+    (keepalive_sender, OutputHandle::Custom(controller))
+}
@@ -0,0 +1,58 @@
+use std::{io::Cursor, time::Duration};
+
+use rodio::{Decoder, Source};
+
+pub const PREVIEW_SAMPLE_RATE: u32 = 22050;
+
+const PREVIEW_DURATION: Duration = Duration::from_secs(2);
+const PREVIEW_MAX_BYTES: usize = 2 * PREVIEW_SAMPLE_RATE as usize * 2;
+
+pub fn decode_preview(file_contents: Vec<u8>, position: Duration) -> Option<Vec<u8>> {
+    let Ok(mut decoder) = Decoder::new(Cursor::new(file_contents)) else {
+        return None;
+    };
+
+    let sample_rate = decoder.sample_rate();
+    let channels = decoder.channels() as usize;
+
+    if sample_rate == 0 || channels == 0 {
+        return None;
+    }
+
+    let source: Box<dyn Iterator<Item = f32>> = if decoder.try_seek(position).is_ok() {
+        Box::new(decoder.convert_samples())
+    } else {
+        Box::new(decoder.skip_duration(position).convert_samples())
+    };
+
+    let frame_count = (PREVIEW_DURATION.as_secs_f64() * sample_rate as f64) as usize;
+
+    let frames = source
+        .take(frame_count * channels)
+        .collect::<Vec<f32>>()
+        .chunks(channels)
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect::<Vec<f32>>();
+
+    if frames.is_empty() {
+        return None;
+    }
+
+    let step = sample_rate as f64 / PREVIEW_SAMPLE_RATE as f64;
+    let output_len = ((frames.len() as f64 / step) as usize).min(PREVIEW_MAX_BYTES / 2);
+
+    let mut data = Vec::with_capacity(output_len * 2);
+
+    for index in 0..output_len {
+        let position = index as f64 * step;
+        let before = position as usize;
+        let after = (before + 1).min(frames.len() - 1);
+        let fraction = (position - before as f64) as f32;
+
+        let sample = frames[before] * (1.0 - fraction) + frames[after] * fraction;
+
+        data.extend_from_slice(&((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16).to_le_bytes());
+    }
+
+    Some(data)
+}
@@ -0,0 +1,96 @@
+use std::{
+    fs::File,
+    io::{BufReader, Cursor, Read, Seek},
+    path::Path,
+    time::Duration,
+};
+
+use rodio::{Decoder, Source};
+
+use super::AudioFormat;
+
+pub const SUPPORTED_CODECS: [&str; 4] = ["flac", "mp3", "vorbis", "wav"];
+
+pub enum ProbeError {
+    Undecodable,
+    NoAudio,
+}
+
+pub fn probe_audio(file_contents: &[u8]) -> Result<AudioFormat, ProbeError> {
+    let Ok(decoder) = Decoder::new(Cursor::new(file_contents.to_vec())) else {
+        return Err(ProbeError::Undecodable);
+    };
+
+    probe_decoder(decoder, sniff_codec(file_contents))
+}
+
+pub fn probe_audio_file(path: &Path) -> Result<AudioFormat, ProbeError> {
+    let Ok(mut file) = File::open(path) else {
+        return Err(ProbeError::Undecodable);
+    };
+
+    let mut header = Vec::new();
+
+    if (&mut file).take(12).read_to_end(&mut header).is_err() || file.rewind().is_err() {
+        return Err(ProbeError::Undecodable);
+    }
+
+    let Ok(decoder) = Decoder::new(BufReader::new(file)) else {
+        return Err(ProbeError::Undecodable);
+    };
+
+    probe_decoder(decoder, sniff_codec(&header))
+}
+
+fn probe_decoder<R: Read + Seek + Send + Sync + 'static>(
+    mut decoder: Decoder<R>,
+    codec: &str,
+) -> Result<AudioFormat, ProbeError> {
+    let sample_rate = decoder.sample_rate();
+    let channels = decoder.channels();
+    let duration = decoder.total_duration();
+
+    if sample_rate == 0 || channels == 0 || decoder.next().is_none() {
+        return Err(ProbeError::NoAudio);
+    }
+
+    Ok(AudioFormat {
+        codec: codec.to_owned(),
+        sample_rate,
+        channels,
+        duration,
+    })
+}
+
+pub fn measure_duration(file_contents: &[u8]) -> Option<Duration> {
+    let decoder = Decoder::new(Cursor::new(file_contents.to_vec())).ok()?;
+
+    if let Some(duration) = decoder.total_duration() {
+        return Some(duration);
+    }
+
+    let rate = decoder.sample_rate() as u64 * decoder.channels() as u64;
+
+    if rate == 0 {
+        return None;
+    }
+
+    let samples = decoder.count() as u64;
+
+    if samples == 0 {
+        return None;
+    }
+
+    Some(Duration::from_nanos(samples * 1_000_000_000 / rate))
+}
+
+fn sniff_codec(file_contents: &[u8]) -> &'static str {
+    match file_contents {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => "wav",
+        [b'f', b'L', b'a', b'C', ..] => "flac",
+        [b'O', b'g', b'g', b'S', ..] => "vorbis",
+        [b'I', b'D', b'3', ..] => "mp3",
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => "mp3",
+        _ => "unknown",
+    }
+}
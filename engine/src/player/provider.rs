@@ -0,0 +1,293 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use musicbrainz_rs::entity::{recording::Recording, relations::RelationContent};
+use reqwest::{header, Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use tokio::time::{self, Instant};
+
+use super::{database::unix_timestamp, NetworkState, WorkRelation};
+
+pub const DEFAULT_BASE_URL: &str = "https://musicbrainz.org/ws/2";
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "PlayIt/",
+    env!("CARGO_PKG_VERSION"),
+    " ( https://github.com/FizzyApple12/PlayIt )"
+);
+
+const RATE_LIMIT_RETRIES: u32 = 2;
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(1);
+const OFFLINE_THRESHOLD: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProviderError {
+    InvalidBaseUrl,
+    InvalidUserAgent,
+    ClientFailure,
+    RequestFailed,
+    RateLimited { retry_after: Duration },
+    NetworkUnavailable,
+    ServerError,
+    NotFound,
+}
+
+#[derive(Clone)]
+pub struct MetadataProvider {
+    client: Client,
+    base_url: String,
+    paced_until: Arc<Mutex<Option<Instant>>>,
+}
+
+pub struct NetworkMonitor {
+    consecutive_failures: u32,
+    state: NetworkState,
+}
+
+impl NetworkMonitor {
+    pub fn new() -> NetworkMonitor {
+        NetworkMonitor {
+            consecutive_failures: 0,
+            state: NetworkState::Online,
+        }
+    }
+
+    pub fn state(&self) -> NetworkState {
+        self.state
+    }
+
+    pub fn observe<T>(&mut self, result: &Result<T, ProviderError>) -> Option<NetworkState> {
+        match result {
+            Err(ProviderError::NetworkUnavailable) => {
+                self.consecutive_failures += 1;
+
+                if self.consecutive_failures < OFFLINE_THRESHOLD
+                    || self.state != NetworkState::Online
+                {
+                    return None;
+                }
+
+                self.state = NetworkState::Offline {
+                    since: unix_timestamp(),
+                };
+            }
+            Err(ProviderError::InvalidBaseUrl)
+            | Err(ProviderError::InvalidUserAgent)
+            | Err(ProviderError::ClientFailure) => return None,
+            _ => {
+                self.consecutive_failures = 0;
+
+                if self.state == NetworkState::Online {
+                    return None;
+                }
+
+                self.state = NetworkState::Online;
+            }
+        }
+
+        Some(self.state)
+    }
+}
+
+impl MetadataProvider {
+    pub fn new(
+        base_url: Option<String>,
+        user_agent: String,
+    ) -> Result<MetadataProvider, ProviderError> {
+        let base_url = base_url.unwrap_or_else(|| DEFAULT_BASE_URL.to_owned());
+
+        if !base_url.starts_with("http://") && !base_url.starts_with("https://") {
+            return Err(ProviderError::InvalidBaseUrl);
+        }
+
+        let Ok(user_agent) = header::HeaderValue::from_str(&user_agent) else {
+            return Err(ProviderError::InvalidUserAgent);
+        };
+
+        let Ok(client) = Client::builder().user_agent(user_agent).build() else {
+            return Err(ProviderError::ClientFailure);
+        };
+
+        Ok(MetadataProvider {
+            client,
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            paced_until: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub async fn fetch_recording(&self, id: &str) -> Result<Recording, ProviderError> {
+        self.get(format!(
+            "{}/recording/{}?inc=work-rels+releases&fmt=json",
+            self.base_url, id
+        ))
+        .await
+    }
+
+    pub async fn fetch_release(&self, release_id: &str) -> Result<Vec<String>, ProviderError> {
+        let release = self
+            .get::<Value>(format!(
+                "{}/release/{}?inc=recordings&fmt=json",
+                self.base_url, release_id
+            ))
+            .await?;
+
+        let mut media = release
+            .get("media")
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default();
+
+        media.sort_by_key(|medium| medium.get("position").and_then(Value::as_u64));
+
+        Ok(media
+            .iter()
+            .flat_map(|medium| {
+                let mut tracks = medium
+                    .get("tracks")
+                    .and_then(Value::as_array)
+                    .cloned()
+                    .unwrap_or_default();
+
+                tracks.sort_by_key(|track| track.get("position").and_then(Value::as_u64));
+
+                tracks
+            })
+            .filter_map(|track| Some(track.get("recording")?.get("id")?.as_str()?.to_owned()))
+            .collect())
+    }
+
+    pub async fn fetch_work_recordings(
+        &self,
+        work_id: &str,
+    ) -> Result<Vec<(String, WorkRelation)>, ProviderError> {
+        let work = self
+            .get::<Value>(format!(
+                "{}/work/{}?inc=recording-rels&fmt=json",
+                self.base_url, work_id
+            ))
+            .await?;
+
+        let title = work
+            .get("title")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+            .to_owned();
+
+        Ok(work
+            .get("relations")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(|relation| {
+                let recording_id = relation.get("recording")?.get("id")?.as_str()?.to_owned();
+
+                Some((
+                    recording_id,
+                    WorkRelation {
+                        work_id: work_id.to_owned(),
+                        title: title.clone(),
+                        relationship: relation.get("type")?.as_str()?.to_owned(),
+                        attributes: relation
+                            .get("attributes")
+                            .and_then(Value::as_array)
+                            .into_iter()
+                            .flatten()
+                            .filter_map(Value::as_str)
+                            .map(str::to_owned)
+                            .collect(),
+                    },
+                ))
+            })
+            .collect())
+    }
+
+    async fn get<T: DeserializeOwned>(&self, url: String) -> Result<T, ProviderError> {
+        let mut retries = RATE_LIMIT_RETRIES;
+
+        loop {
+            let paced_until = *self.paced_until.lock().unwrap();
+
+            if let Some(paced_until) = paced_until {
+                time::sleep_until(paced_until).await;
+            }
+
+            let Ok(response) = self.client.get(&url).send().await else {
+                return Err(ProviderError::NetworkUnavailable);
+            };
+
+            let status = response.status();
+
+            if status == StatusCode::SERVICE_UNAVAILABLE || status == StatusCode::TOO_MANY_REQUESTS
+            {
+                let retry_after = response
+                    .headers()
+                    .get(header::RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.parse::<u64>().ok())
+                    .map(Duration::from_secs)
+                    .unwrap_or(DEFAULT_RETRY_AFTER);
+
+                self.pace(retry_after);
+
+                if retries == 0 {
+                    return Err(ProviderError::RateLimited { retry_after });
+                }
+
+                retries -= 1;
+
+                continue;
+            }
+
+            if status == StatusCode::NOT_FOUND {
+                return Err(ProviderError::NotFound);
+            }
+
+            if status.is_server_error() {
+                return Err(ProviderError::ServerError);
+            }
+
+            if !status.is_success() {
+                return Err(ProviderError::RequestFailed);
+            }
+
+            let Ok(body) = response.json::<T>().await else {
+                return Err(ProviderError::RequestFailed);
+            };
+
+            return Ok(body);
+        }
+    }
+
+    fn pace(&self, retry_after: Duration) {
+        let until = Instant::now() + retry_after;
+
+        let mut paced_until = self.paced_until.lock().unwrap();
+
+        if paced_until.is_none_or(|paced_until| paced_until < until) {
+            *paced_until = Some(until);
+        }
+    }
+}
+
+pub fn take_work_relations(recording: &mut Recording) -> Vec<WorkRelation> {
+    recording
+        .relations
+        .take()
+        .into_iter()
+        .flatten()
+        .filter_map(|relation| {
+            let RelationContent::Work(work) = relation.content else {
+                return None;
+            };
+
+            Some(WorkRelation {
+                work_id: work.id,
+                title: work.title,
+                relationship: relation.relation_type,
+                attributes: relation.attributes.unwrap_or_default(),
+            })
+        })
+        .collect()
+}
@@ -0,0 +1,188 @@
+use musicbrainz_rs::entity::{artist::Artist, artist_credit::ArtistCredit, recording::Recording};
+use serde_json::Value;
+
+use super::{AudioLocation, AudioSource, RecordingMetadata};
+
+pub fn recover_recording_metadata(id: &str, metadata_bytes: &[u8]) -> Option<RecordingMetadata> {
+    let Ok(metadata) = serde_json::from_slice::<Value>(metadata_bytes) else {
+        return None;
+    };
+
+    let recording = metadata.get("recording").unwrap_or(&metadata);
+
+    let title = field(recording, &["title"])?.as_str()?.to_owned();
+
+    let length = field(recording, &["length", "duration"])
+        .and_then(Value::as_u64)
+        .map(|length| length as u32);
+
+    Some(RecordingMetadata {
+        audio_file_hash: field(&metadata, &["audio_file_hash", "audio-file-hash", "hash"])
+            .and_then(Value::as_str)
+            .map(str::to_owned),
+        audio_source: AudioSource::Unknown,
+        audio_location: AudioLocation::Managed,
+        provenance: Vec::new(),
+        audio_format: None,
+        waveform: None,
+        loudness: None,
+        works: Vec::new(),
+        orphaned: false,
+        faulty: false,
+        tags: Vec::new(),
+        spoken_word: None,
+        resume_position: None,
+        play_count: 0,
+        last_played: None,
+        updating: false,
+
+        recording: Recording {
+            id: field(recording, &["id"])
+                .and_then(Value::as_str)
+                .unwrap_or(id)
+                .to_owned(),
+            title,
+            video: None,
+            length,
+            disambiguation: None,
+            isrcs: None,
+            relations: None,
+            releases: None,
+            artist_credit: artist_credit(recording),
+            aliases: None,
+            tags: None,
+            rating: None,
+            genres: None,
+            annotation: None,
+        },
+    })
+}
+
+fn field<'a>(value: &'a Value, names: &[&str]) -> Option<&'a Value> {
+    names
+        .iter()
+        .filter_map(|name| value.get(name))
+        .find(|field| !field.is_null())
+}
+
+fn artist_credit(recording: &Value) -> Option<Vec<ArtistCredit>> {
+    if let Some(credits) =
+        field(recording, &["artist_credit", "artist-credit"]).and_then(Value::as_array)
+    {
+        return Some(
+            credits
+                .iter()
+                .filter_map(|credit| {
+                    let artist = credit.get("artist");
+
+                    let name = field(credit, &["name"])
+                        .or_else(|| artist.and_then(|artist| artist.get("name")))?
+                        .as_str()?
+                        .to_owned();
+
+                    Some(ArtistCredit {
+                        name: name.clone(),
+                        joinphrase: field(credit, &["joinphrase"])
+                            .and_then(Value::as_str)
+                            .map(str::to_owned),
+                        artist: Artist {
+                            id: artist
+                                .and_then(|artist| artist.get("id"))
+                                .and_then(Value::as_str)
+                                .unwrap_or_default()
+                                .to_owned(),
+                            name,
+                            ..Default::default()
+                        },
+                    })
+                })
+                .collect(),
+        );
+    }
+
+    let name = field(recording, &["artist"])?.as_str()?.to_owned();
+
+    Some(vec![ArtistCredit {
+        name: name.clone(),
+        joinphrase: None,
+        artist: Artist {
+            name,
+            ..Default::default()
+        },
+    }])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Relations from before MusicBrainz relations carried a type id.
+    const UNTYPED_RELATIONS: &[u8] =
+        include_bytes!("../../tests/fixtures/metadata-untyped-relations.json");
+    // The flat record written before the recording was stored whole.
+    const FLAT: &[u8] = include_bytes!("../../tests/fixtures/metadata-flat.json");
+
+    fn credited(metadata: &RecordingMetadata) -> Vec<(String, Option<String>)> {
+        metadata
+            .recording
+            .artist_credit
+            .iter()
+            .flatten()
+            .map(|credit| (credit.name.clone(), credit.joinphrase.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn untyped_relations_are_recovered() {
+        assert!(serde_json::from_slice::<RecordingMetadata>(UNTYPED_RELATIONS).is_err());
+
+        let metadata = recover_recording_metadata("unused", UNTYPED_RELATIONS).unwrap();
+
+        assert_eq!(
+            metadata.audio_file_hash.as_deref(),
+            Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08")
+        );
+        assert_eq!(
+            metadata.recording.id,
+            "0f3f6a0e-1c9b-4a45-9a36-5f2ad2a7f1d0"
+        );
+        assert_eq!(metadata.recording.title, "Hand-off");
+        assert_eq!(metadata.recording.length, Some(213000));
+        assert_eq!(
+            credited(&metadata),
+            [
+                ("The Relays".to_owned(), Some(" & ".to_owned())),
+                ("Sled".to_owned(), Some(String::new()))
+            ]
+        );
+
+        let rewritten = serde_json::to_vec(&metadata).unwrap();
+
+        assert!(serde_json::from_slice::<RecordingMetadata>(&rewritten).is_ok());
+    }
+
+    #[test]
+    fn flat_records_are_recovered() {
+        assert!(serde_json::from_slice::<RecordingMetadata>(FLAT).is_err());
+
+        let metadata = recover_recording_metadata("unused", FLAT).unwrap();
+
+        assert_eq!(
+            metadata.audio_file_hash.as_deref(),
+            Some("60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752")
+        );
+        assert_eq!(
+            metadata.recording.id,
+            "7c2d1b9e-4f60-4b8a-8e3d-2a1c5f0b6d93"
+        );
+        assert_eq!(metadata.recording.title, "Gapless");
+        assert_eq!(metadata.recording.length, Some(187000));
+        assert_eq!(credited(&metadata), [("Preload".to_owned(), None)]);
+    }
+
+    #[test]
+    fn records_without_a_title_are_not_recovered() {
+        assert!(recover_recording_metadata("unused", br#"{"hash":"00"}"#).is_none());
+        assert!(recover_recording_metadata("unused", b"not json").is_none());
+    }
+}
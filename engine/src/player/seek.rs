@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use rodio::{source::SeekError, Source};
+
+const SKIP_LIMIT: Duration = Duration::from_secs(4 * 60 * 60);
+
+pub struct Skipped<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    offset: Duration,
+}
+
+pub fn skip_to<S>(mut source: S, position: Duration) -> Option<Skipped<S>>
+where
+    S: Source<Item = f32>,
+{
+    if position > SKIP_LIMIT {
+        return None;
+    }
+
+    let target = position.as_secs_f64();
+    let mut elapsed = 0.0;
+
+    while elapsed < target {
+        let samples_per_second =
+            source.sample_rate().max(1) as f64 * source.channels().max(1) as f64;
+
+        source.next()?;
+
+        elapsed += 1.0 / samples_per_second;
+    }
+
+    Some(Skipped {
+        source,
+        offset: position,
+    })
+}
+
+impl<S> Iterator for Skipped<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        self.source.next()
+    }
+}
+
+impl<S> Source for Skipped<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        if position == self.offset {
+            return Ok(());
+        }
+
+        self.source.try_seek(position)
+    }
+}
@@ -1,285 +1,3027 @@
-use std::{sync::Arc, time::Duration};
-
-use rodio::{Decoder, OutputStream, OutputStreamHandle, Sink, Source};
-use tokio::sync::Mutex;
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, Cursor, Read},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        mpsc as std_mpsc, Arc,
+    },
+    time::{Duration, SystemTime},
+};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rodio::{Decoder, Sink, Source};
+use tokio::{
+    sync::{broadcast, Mutex},
+    time,
+};
+
+use crate::{
+    AudioOutputChoice, LoopMode, OnQueueEnd, QueueModel, QueueRejectReason, QueueRemoveTarget,
+};
+
+use super::{
+    database::{Database, DatabaseError},
+    history::PlayedHistory,
+    level::{transition_gain, LevelRamp},
+    output::{open_output, OutputHandle},
+    seek::skip_to,
+    stream::{Follow, PcmChunk, Tee},
+    suggester::Suggester,
+    underrun::AudioTelemetry,
+    EffectSummary, NamedQueue, PlaybackState, PlaylistPosition, RecordingMetadata,
+    SavedPlayerState, SessionSnapshot, TransitionReason,
+};
+
+pub const MAIN_QUEUE: &str = "main";
+pub const REQUESTS_QUEUE: &str = "requests";
+
+const EVENT_CAPACITY: usize = 16;
+const STREAM_CAPACITY: usize = 32;
+const LOW_MEMORY_EVENT_CAPACITY: usize = 4;
+const LOW_MEMORY_STREAM_CAPACITY: usize = 8;
+
+const POSITION_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+const SEEK_END_MARGIN: Duration = Duration::from_millis(250);
+const SUSPEND_THRESHOLD: Duration = Duration::from_secs(5);
+
+const DUCK_ATTACK: Duration = Duration::from_millis(100);
+const DUCK_RELEASE: Duration = Duration::from_millis(1000);
+const DUCK_STEPS: u32 = 10;
+
+const DEFAULT_PAUSE_FADE: Duration = Duration::from_millis(200);
+const MAX_PAUSE_FADE: Duration = Duration::from_secs(2);
+const PAUSE_FADE_STEPS: u32 = 10;
+
+const RADIO_LOW_WATER: usize = 3;
+const RADIO_BATCH: usize = 5;
+const RADIO_RECENT_EXCLUSION: usize = 50;
+
+const SHORT_FINISH_LIMIT: u32 = 2;
+
+const GAPLESS_LEAD: Duration = Duration::from_secs(3);
+const GAPLESS_CANCEL_POLL: Duration = Duration::from_millis(5);
+
+const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(10);
+const RESUME_CLEAR_FRACTION: f32 = 0.95;
+const RESUME_MIN_POSITION: Duration = Duration::from_secs(60);
+
+const PLAY_COUNT_FRACTION: u32 = 2;
+const PLAY_COUNT_MAX_LISTEN: Duration = Duration::from_secs(4 * 60);
+
+const PREVIOUS_RESTART_THRESHOLD: Duration = Duration::from_secs(3);
+
+const MIN_SPEED: f32 = 0.25;
+const MAX_SPEED: f32 = 3.0;
+
+type PreloadedRecording = (String, Decoder<BufReader<File>>);
+type LoopCache = (String, Arc<[u8]>);
+type QueueRejection = (String, QueueRejectReason);
+
+#[derive(Debug, Clone)]
+pub enum SequencerEvent {
+    Suspended {
+        id: String,
+        position: Duration,
+        resumed: bool,
+    },
+    Advanced {
+        id: String,
+    },
+    Faulty {
+        id: String,
+    },
+    Transition {
+        from: Option<String>,
+        to: String,
+        gapless: bool,
+    },
+    QueueExtended {
+        queue: Vec<String>,
+        auto_added: Vec<String>,
+    },
+    QueueEnded {
+        action_taken: OnQueueEnd,
+    },
+    LoopModeChanged(LoopMode),
+    StopAfterCurrent {
+        enabled: bool,
+        fired: bool,
+    },
+}
 
-use crate::LoopMode;
+struct Duck {
+    gain: f32,
+    depth: f32,
+    active_cues: usize,
+    generation: u64,
+}
 
-use super::database::Database;
+struct PauseRamp {
+    gain: f32,
+    pausing: bool,
+    generation: u64,
+}
 
+#[derive(Clone)]
 pub struct Sequencer {
     sink: Arc<Mutex<Sink>>,
-    stream_handle: Arc<Mutex<OutputStreamHandle>>,
+    output: AudioOutputChoice,
+    stream_handle: Arc<Mutex<OutputHandle>>,
+    output_keepalive: Arc<Mutex<std_mpsc::Sender<()>>>,
+    parked: Arc<Mutex<bool>>,
 
     playing: Arc<Mutex<Option<String>>>,
     loop_mode: Arc<Mutex<LoopMode>>,
+    stop_after_current: Arc<Mutex<bool>>,
     shuffle: Arc<Mutex<bool>>,
+    shuffle_seed: Arc<Mutex<Option<u64>>>,
+    shuffle_rng: Arc<Mutex<StdRng>>,
+
+    volume: Arc<Mutex<f32>>,
+    muted: Arc<Mutex<bool>>,
+    duck: Arc<Mutex<Duck>>,
+    pause_ramp: Arc<Mutex<PauseRamp>>,
+    pause_fade: Arc<Mutex<Duration>>,
 
     queue: Arc<Mutex<Vec<String>>>,
     shuffled_queue: Arc<Mutex<Vec<String>>>,
 
-    song_backlog: Arc<Mutex<Vec<String>>>,
+    queue_model: Arc<Mutex<QueueModel>>,
+    cursor: Arc<Mutex<Option<usize>>>,
+    play_order: Arc<Mutex<Vec<usize>>>,
+
+    request_queue: Arc<Mutex<Vec<String>>>,
+    shuffled_request_queue: Arc<Mutex<Vec<String>>>,
+
+    queue_sources: Arc<Mutex<HashMap<String, (String, usize)>>>,
+
+    history: Arc<Mutex<PlayedHistory>>,
+    uncounted_listen: Arc<Mutex<Option<Duration>>>,
+
+    preloaded: Arc<Mutex<Option<PreloadedRecording>>>,
+    adopted: Arc<Mutex<Option<SessionSnapshot>>>,
+    gapless_pending: Arc<Mutex<Option<String>>>,
+    gapless_cancel: Arc<Mutex<Arc<AtomicBool>>>,
+    loop_cache: Arc<Mutex<Option<LoopCache>>>,
+    seek_fallbacks: Arc<AtomicU64>,
+    telemetry: AudioTelemetry,
+
+    radio_mode: Arc<Mutex<bool>>,
+    radio_added: Arc<Mutex<Vec<String>>>,
+
+    on_queue_end: Arc<Mutex<OnQueueEnd>>,
+    queue_end_fade: Arc<Mutex<Option<String>>>,
+
+    smooth_level_transition: Arc<Mutex<bool>>,
+
+    short_finishes: Arc<Mutex<HashMap<String, u32>>>,
+    min_playback: Duration,
+
+    stream_sender: broadcast::Sender<PcmChunk>,
+    follow_sender: Arc<Mutex<Option<std_mpsc::Sender<PcmChunk>>>>,
+
+    resume_after_suspend: bool,
+    low_memory: bool,
+    max_queue_length: usize,
+    events: broadcast::Sender<SequencerEvent>,
 
     database: Database,
 }
 
+#[derive(Debug)]
 pub enum SequencerError {
     AudioInitializationFailed,
     MissingAudioFile,
     DecodingError,
     SeekFailed,
+    UnknownDuration,
+    UnknownQueue,
     NothingPlaying,
     NoSongsPlayed,
     NoSongsQueued,
+    NotInQueue,
+    FaultyRecording,
 }
 
 impl Sequencer {
-    pub fn new(database: Database) -> Result<Sequencer, SequencerError> {
-        let Ok((_stream, stream_handle)) = OutputStream::try_default() else {
-            return Err(SequencerError::AudioInitializationFailed);
-        };
-        let Ok(sink) = Sink::try_new(&stream_handle) else {
-            return Err(SequencerError::AudioInitializationFailed);
-        };
+    pub fn new(
+        database: Database,
+        resume_after_suspend: bool,
+        low_memory: bool,
+        max_queue_length: usize,
+        history_capacity: usize,
+        min_playback: Duration,
+        output: AudioOutputChoice,
+    ) -> Result<Sequencer, SequencerError> {
+        let telemetry = AudioTelemetry::default();
+
+        let (output_keepalive, stream_handle) = open_output(&output, &telemetry)?;
+        let sink = stream_handle.new_sink()?;
 
         sink.pause();
 
-        Ok(Sequencer {
+        let (events, _) = broadcast::channel(if low_memory {
+            LOW_MEMORY_EVENT_CAPACITY
+        } else {
+            EVENT_CAPACITY
+        });
+        let (stream_sender, _) = broadcast::channel(if low_memory {
+            LOW_MEMORY_STREAM_CAPACITY
+        } else {
+            STREAM_CAPACITY
+        });
+
+        let sequencer = Sequencer {
             sink: Arc::new(Mutex::new(sink)),
+            output,
             stream_handle: Arc::new(Mutex::new(stream_handle)),
+            output_keepalive: Arc::new(Mutex::new(output_keepalive)),
+            parked: Arc::new(Mutex::new(false)),
 
             playing: Arc::new(Mutex::new(None)),
             loop_mode: Arc::new(Mutex::new(LoopMode::None)),
+            stop_after_current: Arc::new(Mutex::new(false)),
             shuffle: Arc::new(Mutex::new(false)),
+            shuffle_seed: Arc::new(Mutex::new(None)),
+            shuffle_rng: Arc::new(Mutex::new(StdRng::from_entropy())),
+
+            volume: Arc::new(Mutex::new(1.0)),
+            muted: Arc::new(Mutex::new(false)),
+            duck: Arc::new(Mutex::new(Duck {
+                gain: 1.0,
+                depth: 1.0,
+                active_cues: 0,
+                generation: 0,
+            })),
+            pause_ramp: Arc::new(Mutex::new(PauseRamp {
+                gain: 1.0,
+                pausing: false,
+                generation: 0,
+            })),
+            pause_fade: Arc::new(Mutex::new(DEFAULT_PAUSE_FADE)),
 
             queue: Arc::new(Mutex::new(Vec::new())),
             shuffled_queue: Arc::new(Mutex::new(Vec::new())),
 
-            song_backlog: Arc::new(Mutex::new(Vec::new())),
+            queue_model: Arc::new(Mutex::new(QueueModel::Consuming)),
+            cursor: Arc::new(Mutex::new(None)),
+            play_order: Arc::new(Mutex::new(Vec::new())),
+
+            request_queue: Arc::new(Mutex::new(Vec::new())),
+            shuffled_request_queue: Arc::new(Mutex::new(Vec::new())),
+
+            queue_sources: Arc::new(Mutex::new(HashMap::new())),
+
+            history: Arc::new(Mutex::new(PlayedHistory::new(history_capacity))),
+            uncounted_listen: Arc::new(Mutex::new(None)),
+
+            preloaded: Arc::new(Mutex::new(None)),
+            adopted: Arc::new(Mutex::new(None)),
+            gapless_pending: Arc::new(Mutex::new(None)),
+            gapless_cancel: Arc::new(Mutex::new(Arc::new(AtomicBool::new(false)))),
+            loop_cache: Arc::new(Mutex::new(None)),
+            seek_fallbacks: Arc::new(AtomicU64::new(0)),
+            telemetry,
+
+            radio_mode: Arc::new(Mutex::new(false)),
+            radio_added: Arc::new(Mutex::new(Vec::new())),
+
+            on_queue_end: Arc::new(Mutex::new(OnQueueEnd::Stop)),
+            queue_end_fade: Arc::new(Mutex::new(None)),
+
+            smooth_level_transition: Arc::new(Mutex::new(false)),
+
+            short_finishes: Arc::new(Mutex::new(HashMap::new())),
+            min_playback,
+
+            stream_sender,
+            follow_sender: Arc::new(Mutex::new(None)),
+
+            resume_after_suspend,
+            low_memory,
+            max_queue_length,
+            events,
 
             database,
-        })
+        };
+
+        sequencer.start_position_watcher();
+
+        Ok(sequencer)
     }
 
-    pub async fn get_playing(&self) -> Option<String> {
-        let locked_sink = self.sink.lock().await;
+    pub fn subscribe(&self) -> broadcast::Receiver<SequencerEvent> {
+        self.events.subscribe()
+    }
 
-        if locked_sink.is_paused() {
-            return None;
+    pub fn subscribe_stream(&self) -> broadcast::Receiver<PcmChunk> {
+        self.stream_sender.subscribe()
+    }
+
+    pub async fn follow(&self, enable: bool) {
+        if enable && self.unpark().await.is_err() {
+            return;
         }
 
-        self.playing.lock().await.clone()
-    }
+        let mut locked_sink = self.sink.lock().await;
 
-    pub async fn play(&self, id: String) -> Result<(), SequencerError> {
-        let Ok(file) = self.database.get_recording_file(id.clone()).await else {
-            return Err(SequencerError::MissingAudioFile);
-        };
+        self.replace_sink(&mut locked_sink).await;
 
-        let Ok(decoded_file) = Decoder::new(file) else {
-            return Err(SequencerError::DecodingError);
-        };
+        *self.playing.lock().await = None;
 
-        let locked_sink = self.sink.lock().await;
-        locked_sink.append(decoded_file.convert_samples::<f32>());
-        locked_sink.play();
+        if !enable {
+            *self.follow_sender.lock().await = None;
 
-        *self.playing.lock().await = Some(id);
+            return;
+        }
 
-        Ok(())
-    }
+        let (follow_sender, follow_receiver) = std_mpsc::channel();
 
-    pub async fn pause(&self) {
-        self.sink.lock().await.pause();
+        locked_sink.append(Follow::new(follow_receiver));
+        locked_sink.play();
+
+        *self.follow_sender.lock().await = Some(follow_sender);
     }
 
-    pub async fn seek(&self, position: Duration) -> Result<(), SequencerError> {
-        if self.sink.lock().await.try_seek(position).is_err() {
-            Err(SequencerError::SeekFailed)
-        } else {
-            Ok(())
+    pub async fn push_stream_chunk(&self, chunk: PcmChunk) {
+        if let Some(follow_sender) = self.follow_sender.lock().await.as_ref() {
+            let _ = follow_sender.send(chunk);
         }
     }
 
-    pub async fn next(&self) -> Result<(), SequencerError> {
-        match *self.loop_mode.lock().await {
-            LoopMode::None => {
-                let should_shuffle = *self.shuffle.lock().await;
+    fn start_position_watcher(&self) {
+        let sequencer = self.clone();
 
-                let mut locked_queue = if should_shuffle {
-                    self.shuffled_queue.lock().await
-                } else {
-                    self.queue.lock().await
+        tokio::spawn(async move {
+            let mut last_tick = SystemTime::now();
+            let mut last_position = Duration::ZERO;
+            let mut since_resume_save = Duration::ZERO;
+
+            loop {
+                time::sleep(POSITION_WATCH_INTERVAL).await;
+
+                let now = SystemTime::now();
+                let elapsed = now.duration_since(last_tick).unwrap_or_default();
+
+                last_tick = now;
+
+                let (paused, finished, queued, position) = {
+                    let locked_sink = sequencer.sink.lock().await;
+
+                    (
+                        locked_sink.is_paused(),
+                        locked_sink.empty(),
+                        locked_sink.len(),
+                        locked_sink.get_pos(),
+                    )
                 };
 
-                if locked_queue.len() == 0 {
-                    return Err(SequencerError::NoSongsQueued);
+                if paused || sequencer.playing.lock().await.is_none() {
+                    last_position = position;
+
+                    continue;
                 }
 
-                let song_to_play = locked_queue.remove(0);
+                if elapsed > POSITION_WATCH_INTERVAL + SUSPEND_THRESHOLD {
+                    let Some(id) = sequencer.playing.lock().await.clone() else {
+                        continue;
+                    };
 
-                if should_shuffle {
-                    let mut locked_removal_queue = self.queue.lock().await;
+                    let resumed = sequencer
+                        .recover_from_suspend(id.clone(), last_position)
+                        .await;
 
-                    if let Some(song_index) = locked_removal_queue
-                        .iter()
-                        .position(|song_to_check| *song_to_check == song_to_play)
-                    {
-                        locked_removal_queue.remove(song_index);
-                    }
+                    let _ = sequencer.events.send(SequencerEvent::Suspended {
+                        id,
+                        position: last_position,
+                        resumed,
+                    });
+
+                    continue;
                 }
 
-                self.play(song_to_play).await?;
+                if finished || (queued <= 1 && sequencer.gapless_pending.lock().await.is_some()) {
+                    sequencer.auto_advance(last_position).await;
 
-                Ok(())
-            }
-            LoopMode::LoopQueue => {
-                let should_shuffle = *self.shuffle.lock().await;
+                    last_position = Duration::ZERO;
 
-                if should_shuffle {
-                    let mut locked_shuffle_queue = self.shuffled_queue.lock().await;
+                    continue;
+                }
 
-                    if locked_shuffle_queue.len() == 0 {
-                        let locked_queue = self.queue.lock().await;
+                sequencer.prepare_gapless(position).await;
+                sequencer.fade_towards_queue_end(position).await;
 
-                        if locked_queue.len() == 0 {
-                            return Err(SequencerError::NoSongsQueued);
-                        }
+                sequencer.tally_play(elapsed).await;
 
-                        *self.shuffled_queue.lock().await =
-                            shuffle_queue(self.queue.lock().await.to_vec());
+                since_resume_save += elapsed;
+
+                if since_resume_save >= RESUME_SAVE_INTERVAL {
+                    since_resume_save = Duration::ZERO;
+
+                    if let Some(id) = sequencer.playing.lock().await.clone() {
+                        sequencer.remember_position(id, position).await;
                     }
+                }
 
-                    let song_to_play = locked_shuffle_queue.remove(0);
+                last_position = position;
+            }
+        });
+    }
 
-                    self.play(song_to_play).await?;
+    async fn tally_play(&self, elapsed: Duration) {
+        let threshold = self
+            .track_duration()
+            .await
+            .map_or(PLAY_COUNT_MAX_LISTEN, |duration| {
+                (duration / PLAY_COUNT_FRACTION).min(PLAY_COUNT_MAX_LISTEN)
+            });
 
-                    Ok(())
-                } else {
-                    let mut locked_queue = self.queue.lock().await;
+        {
+            let mut locked_uncounted_listen = self.uncounted_listen.lock().await;
 
-                    if locked_queue.len() == 0 {
-                        return Err(SequencerError::NoSongsQueued);
-                    }
+            let Some(listened) = locked_uncounted_listen.as_mut() else {
+                return;
+            };
 
-                    let song_to_play = locked_queue.remove(0);
+            *listened += elapsed;
 
-                    locked_queue.push(song_to_play.clone());
+            if *listened < threshold {
+                return;
+            }
+
+            *locked_uncounted_listen = None;
+        }
 
-                    self.play(song_to_play).await?;
+        if let Some(id) = self.playing.lock().await.clone() {
+            self.database.record_play_counted(id).await;
+        }
+    }
 
-                    Ok(())
+    async fn auto_advance(&self, played: Duration) {
+        if std::mem::take(&mut *self.stop_after_current.lock().await) {
+            self.sink.lock().await.pause();
+
+            let _ = self.events.send(SequencerEvent::StopAfterCurrent {
+                enabled: false,
+                fired: true,
+            });
+
+            return;
+        }
+
+        let finished = self.playing.lock().await.clone();
+
+        if let Some(finished) = finished.clone() {
+            if self.guard_short_finish(&finished, played).await {
+                let _ = self.events.send(SequencerEvent::Faulty {
+                    id: finished.clone(),
+                });
+
+                if matches!(*self.loop_mode.lock().await, LoopMode::LoopRecording) {
+                    self.sink.lock().await.pause();
+
+                    return;
                 }
             }
-            LoopMode::LoopRecording => {
-                let Some(ref song_to_loop) = *self.playing.lock().await else {
-                    return Err(SequencerError::NothingPlaying);
-                };
 
-                self.play(song_to_loop.clone()).await?;
+            let source = self.queue_sources.lock().await.remove(&finished);
+
+            if let Some((playlist_id, index)) = source {
+                self.database
+                    .record_playlist_progress(
+                        playlist_id,
+                        PlaylistPosition {
+                            index,
+                            recording_id: finished,
+                        },
+                    )
+                    .await;
+            }
+        }
+
+        if *self.radio_mode.lock().await && self.get_queue().await.len() < RADIO_LOW_WATER {
+            self.extend_radio_queue().await;
+        }
 
-                Ok(())
+        if self.next().await.is_err() {
+            if let Some(finished) = finished.clone() {
+                self.database.set_resume_position(finished, None).await;
+            }
+
+            let action_taken = self.end_queue(finished).await;
+
+            let _ = self
+                .events
+                .send(SequencerEvent::QueueEnded { action_taken });
+
+            if !matches!(action_taken, OnQueueEnd::Radio | OnQueueEnd::RepeatLast) {
+                return;
             }
         }
-    }
 
-    pub async fn previous(&self) -> Result<(), SequencerError> {
-        let mut locked_backlog = self.song_backlog.lock().await;
+        if let Some(id) = self.playing.lock().await.clone() {
+            let _ = self.events.send(SequencerEvent::Advanced { id });
+        }
+    }
 
-        if locked_backlog.len() == 0 {
-            return Err(SequencerError::NoSongsPlayed);
+    async fn prepare_gapless(&self, position: Duration) {
+        if self.gapless_pending.lock().await.is_some() {
+            return;
         }
 
-        let song_to_play = locked_backlog.remove(0);
+        let Some(current) = self.playing.lock().await.clone() else {
+            return;
+        };
 
-        self.queue.lock().await.insert(0, song_to_play.clone());
+        let Some(duration) = self.track_duration().await else {
+            return;
+        };
 
-        if *self.shuffle.lock().await {
-            self.shuffled_queue
-                .lock()
-                .await
-                .insert(0, song_to_play.clone());
+        if duration.saturating_sub(position) > GAPLESS_LEAD {
+            return;
         }
 
-        return self.play(song_to_play).await;
-    }
+        let looping = matches!(*self.loop_mode.lock().await, LoopMode::LoopRecording);
 
-    pub async fn add_queue(&self, ids: Vec<String>) -> Result<Vec<String>, SequencerError> {
-        let mut unplayable = Vec::new();
+        let Some(next) = self.upcoming().await else {
+            return;
+        };
 
-        let mut locked_queue = self.queue.lock().await;
+        if !looping && self.database.is_recording_faulty(next.clone()).await {
+            return;
+        }
 
-        for id in ids {
-            if self.database.get_recording_file(id.clone()).await.is_ok() {
-                locked_queue.push(id);
-            } else {
-                unplayable.push(id);
+        let start_gain = if looping || self.database.are_adjacent_tracks(&current, &next).await {
+            None
+        } else {
+            self.level_transition_gain(&next).await
+        };
+
+        let source: Box<dyn Source<Item = f32> + Send> = if looping {
+            match self.decode_loop(next.clone()).await {
+                Ok(source) => source,
+                Err(_) => return,
             }
+        } else {
+            let preloaded = self.preloaded.lock().await.take();
+
+            let decoded_file = match preloaded {
+                Some((preloaded_id, decoded_file)) if preloaded_id == next => decoded_file,
+                _ => match self.decode(next.clone()).await {
+                    Ok(decoded_file) => decoded_file,
+                    Err(_) => return,
+                },
+            };
+
+            Box::new(decoded_file.convert_samples::<f32>())
+        };
+
+        if self.upcoming().await.as_ref() != Some(&next) {
+            return;
         }
 
-        if *self.shuffle.lock().await {
-            *self.shuffled_queue.lock().await = shuffle_queue(self.queue.lock().await.to_vec());
+        let locked_sink = self.sink.lock().await;
+
+        if self.playing.lock().await.as_ref() != Some(&current) || locked_sink.empty() {
+            return;
         }
 
-        return Ok(unplayable);
+        let cancel = Arc::new(AtomicBool::new(false));
+        let cancelled = cancel.clone();
+
+        locked_sink.append(Tee::new(
+            LevelRamp::new(source, start_gain)
+                .stoppable()
+                .periodic_access(GAPLESS_CANCEL_POLL, move |source| {
+                    if cancelled.load(Ordering::Relaxed) {
+                        source.stop();
+                    }
+                }),
+            self.stream_sender.clone(),
+        ));
+
+        *self.gapless_cancel.lock().await = cancel;
+        *self.gapless_pending.lock().await = Some(next);
     }
 
-    pub async fn get_queue(&self) -> Vec<String> {
-        if *self.shuffle.lock().await {
-            self.shuffled_queue.lock().await.clone()
-        } else {
-            self.queue.lock().await.clone()
+    async fn upcoming(&self) -> Option<String> {
+        if *self.stop_after_current.lock().await {
+            return None;
         }
-    }
 
-    pub async fn clear_queue(&self) {
-        self.queue.lock().await.clear();
-        self.shuffled_queue.lock().await.clear();
+        if matches!(*self.loop_mode.lock().await, LoopMode::LoopRecording) {
+            return self.playing.lock().await.clone();
+        }
+
+        self.get_queue().await.first().cloned()
     }
 
-    pub async fn set_loop_mode(&self, mode: LoopMode) {
-        *self.loop_mode.lock().await = mode;
+    async fn refresh_gapless(&self) {
+        let Some(pending) = self.gapless_pending.lock().await.clone() else {
+            return;
+        };
+
+        if self.upcoming().await.as_ref() == Some(&pending) {
+            return;
+        }
+
+        // Once rodio has moved on to the preloaded source it is what the
+        // listener hears, so it is adopted instead of being cut off.
+        if self.sink.lock().await.len() <= 1 {
+            if self
+                .start(pending.clone(), TransitionReason::Next)
+                .await
+                .is_ok()
+            {
+                let _ = self.events.send(SequencerEvent::Advanced { id: pending });
+            }
+
+            return;
+        }
+
+        if self.gapless_pending.lock().await.take().is_some() {
+            self.gapless_cancel
+                .lock()
+                .await
+                .store(true, Ordering::Relaxed);
+        }
     }
 
-    pub async fn set_shuffle(&self, enable: bool) {
-        if enable {
-            *self.shuffled_queue.lock().await = shuffle_queue(self.queue.lock().await.to_vec());
-        } else {
-            self.shuffled_queue.lock().await.clear();
+    async fn guard_short_finish(&self, id: &str, played: Duration) -> bool {
+        let mut locked_short_finishes = self.short_finishes.lock().await;
+
+        if played >= self.min_playback {
+            locked_short_finishes.clear();
+
+            return false;
         }
 
-        *self.shuffle.lock().await = enable;
+        let short_finishes = locked_short_finishes.entry(id.to_owned()).or_insert(0);
+
+        *short_finishes += 1;
+
+        if *short_finishes <= SHORT_FINISH_LIMIT {
+            return false;
+        }
+
+        locked_short_finishes.remove(id);
+
+        drop(locked_short_finishes);
+
+        self.database.mark_recording_faulty(id.to_owned()).await;
+
+        true
     }
 
-    pub async fn set_volume(&self, volume: f32) {
-        self.sink.lock().await.set_volume(volume);
+    async fn end_queue(&self, finished: Option<String>) -> OnQueueEnd {
+        let on_queue_end = *self.on_queue_end.lock().await;
+
+        match on_queue_end {
+            OnQueueEnd::Radio => {
+                self.extend_radio_queue().await;
+
+                if self.next().await.is_ok() {
+                    return on_queue_end;
+                }
+            }
+            OnQueueEnd::RepeatLast => {
+                if let Some(finished) = finished {
+                    if self.start(finished, TransitionReason::Loop).await.is_ok() {
+                        return on_queue_end;
+                    }
+                }
+            }
+            OnQueueEnd::FadeOut(_) => {
+                self.sink.lock().await.pause();
+
+                if self.queue_end_fade.lock().await.take().is_some() {
+                    self.ramp_duck(1.0, Duration::ZERO).await;
+                }
+
+                return on_queue_end;
+            }
+            OnQueueEnd::Stop => {}
+        }
+
+        self.sink.lock().await.pause();
+
+        OnQueueEnd::Stop
     }
-}
 
-impl Clone for Sequencer {
-    fn clone(&self) -> Self {
-        Self {
-            sink: self.sink.clone(),
-            stream_handle: self.stream_handle.clone(),
-            playing: self.playing.clone(),
-            loop_mode: self.loop_mode.clone(),
-            shuffle: self.shuffle.clone(),
-            queue: self.queue.clone(),
-            shuffled_queue: self.queue.clone(),
-            song_backlog: self.song_backlog.clone(),
-            database: self.database.clone(),
+    async fn fade_towards_queue_end(&self, position: Duration) {
+        let OnQueueEnd::FadeOut(fade_duration) = *self.on_queue_end.lock().await else {
+            return;
+        };
+
+        let queue_ending = matches!(*self.loop_mode.lock().await, LoopMode::None)
+            && !*self.radio_mode.lock().await
+            && self.get_queue().await.is_empty();
+
+        let playing = self.playing.lock().await.clone();
+        let faded = self.queue_end_fade.lock().await.clone();
+
+        if faded.is_some() && (!queue_ending || faded != playing) {
+            *self.queue_end_fade.lock().await = None;
+
+            self.ramp_duck(1.0, DUCK_RELEASE).await;
+
+            return;
+        }
+
+        if !queue_ending || faded.is_some() {
+            return;
         }
+
+        let Some(duration) = self.track_duration().await else {
+            return;
+        };
+
+        let remaining = duration.saturating_sub(position);
+
+        if remaining > fade_duration {
+            return;
+        }
+
+        *self.queue_end_fade.lock().await = playing;
+
+        let sequencer = self.clone();
+
+        tokio::spawn(async move {
+            sequencer.ramp_duck(0.0, remaining).await;
+        });
     }
-}
 
-fn shuffle_queue(queue: Vec<String>) -> Vec<String> {
-    let mut shuffle_array = queue;
+    pub async fn set_smooth_level_transition(&self, enable: bool) {
+        *self.smooth_level_transition.lock().await = enable;
+    }
+
+    async fn level_transition_gain(&self, incoming: &str) -> Option<f32> {
+        if !*self.smooth_level_transition.lock().await {
+            return None;
+        }
 
-    for i in 0..(shuffle_array.len() - 2) {
-        let j = (rand::random::<u32>() as usize % (shuffle_array.len() - i)) + i;
+        let outgoing = self.playing.lock().await.clone()?;
 
-        shuffle_array.swap(i, j);
+        let outgoing_loudness = self
+            .database
+            .get_cached_recording_metadata(outgoing)
+            .await?
+            .loudness?;
+        let incoming_loudness = self
+            .database
+            .get_cached_recording_metadata(incoming.to_owned())
+            .await?
+            .loudness?;
+
+        transition_gain(outgoing_loudness, incoming_loudness)
     }
 
-    shuffle_array
+    pub async fn set_on_queue_end(&self, on_queue_end: OnQueueEnd) {
+        *self.on_queue_end.lock().await = on_queue_end;
+    }
+
+    async fn extend_radio_queue(&self) {
+        let playing = self.playing.lock().await.clone();
+
+        let mut exclude = self.get_queue().await;
+        exclude.extend(playing.clone());
+        exclude.extend(
+            self.history
+                .lock()
+                .await
+                .recent(RADIO_RECENT_EXCLUSION)
+                .cloned(),
+        );
+
+        let suggestions = Suggester::new(self.database.clone())
+            .suggest(playing, &exclude, RADIO_BATCH)
+            .await;
+
+        if suggestions.is_empty() {
+            return;
+        }
+
+        let queue_length = {
+            let mut locked_queue = self.queue.lock().await;
+
+            locked_queue.extend(suggestions.clone());
+            locked_queue.len()
+        };
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            self.extend_play_order(queue_length).await;
+        } else if *self.shuffle.lock().await {
+            self.shuffled_queue.lock().await.extend(suggestions.clone());
+        }
+
+        let queue = self.get_queue().await;
+
+        let mut locked_radio_added = self.radio_added.lock().await;
+
+        locked_radio_added.extend(suggestions);
+        locked_radio_added.retain(|id| queue.contains(id));
+
+        let _ = self.events.send(SequencerEvent::QueueExtended {
+            queue,
+            auto_added: locked_radio_added.clone(),
+        });
+    }
+
+    pub async fn set_radio_mode(&self, enable: bool) {
+        *self.radio_mode.lock().await = enable;
+    }
+
+    async fn recover_from_suspend(&self, id: String, position: Duration) -> bool {
+        self.sink.lock().await.pause();
+
+        if self.rebuild_output().await.is_err() {
+            return false;
+        }
+
+        let Ok(decoded_file) = self.decode(id).await else {
+            return false;
+        };
+
+        let locked_sink = self.sink.lock().await;
+
+        locked_sink.append(Tee::new(
+            decoded_file.convert_samples::<f32>(),
+            self.stream_sender.clone(),
+        ));
+
+        let _ = locked_sink.try_seek(position);
+
+        if self.resume_after_suspend {
+            locked_sink.play();
+        }
+
+        self.resume_after_suspend
+    }
+
+    /// Drops everything queued on the sink without waiting for the output
+    /// thread, which rodio's `Sink::clear` would. The fresh sink keeps the
+    /// old volume and speed and starts paused, like a cleared one.
+    async fn replace_sink(&self, locked_sink: &mut Sink) {
+        let sink = match self.stream_handle.lock().await.new_sink() {
+            Ok(sink) => sink,
+            Err(_) => {
+                locked_sink.clear();
+
+                return;
+            }
+        };
+
+        sink.pause();
+        sink.set_volume(locked_sink.volume());
+        sink.set_speed(locked_sink.speed());
+
+        locked_sink.stop();
+
+        *locked_sink = sink;
+    }
+
+    async fn rebuild_output(&self) -> Result<(), SequencerError> {
+        let (output_keepalive, stream_handle) = open_output(&self.output, &self.telemetry)?;
+        let sink = stream_handle.new_sink()?;
+
+        sink.pause();
+
+        let mut locked_sink = self.sink.lock().await;
+
+        sink.set_volume(locked_sink.volume());
+        sink.set_speed(locked_sink.speed());
+
+        locked_sink.stop();
+
+        *locked_sink = sink;
+        *self.stream_handle.lock().await = stream_handle;
+        *self.output_keepalive.lock().await = output_keepalive;
+        *self.parked.lock().await = false;
+
+        Ok(())
+    }
+
+    pub async fn park(&self) {
+        if *self.parked.lock().await {
+            return;
+        }
+
+        let (listened, ended) = {
+            let locked_sink = self.sink.lock().await;
+
+            let listened = locked_sink.get_pos();
+            let ended = stop_reason(&locked_sink);
+
+            locked_sink.stop();
+
+            (listened, ended)
+        };
+
+        *self.preloaded.lock().await = None;
+
+        if let Some(previous_id) = self.playing.lock().await.take() {
+            self.database
+                .record_play_event(previous_id.clone(), listened, ended)
+                .await;
+
+            self.history.lock().await.push(previous_id);
+        }
+
+        let (closed_keepalive, _) = std_mpsc::channel();
+
+        *self.output_keepalive.lock().await = closed_keepalive;
+        *self.parked.lock().await = true;
+    }
+
+    pub async fn history_len(&self) -> usize {
+        self.history.lock().await.len()
+    }
+
+    pub async fn get_history(&self, count: Option<usize>) -> Vec<String> {
+        self.history
+            .lock()
+            .await
+            .recent(count.unwrap_or(usize::MAX))
+            .cloned()
+            .collect()
+    }
+
+    pub fn has_custom_output(&self) -> bool {
+        matches!(self.output, AudioOutputChoice::Custom(_))
+    }
+
+    pub async fn is_parked(&self) -> bool {
+        *self.parked.lock().await
+    }
+
+    async fn unpark(&self) -> Result<(), SequencerError> {
+        if !*self.parked.lock().await {
+            return Ok(());
+        }
+
+        self.rebuild_output().await
+    }
+
+    async fn decode(&self, id: String) -> Result<Decoder<BufReader<File>>, SequencerError> {
+        let Ok(file) = self.database.get_recording_file(id).await else {
+            return Err(SequencerError::MissingAudioFile);
+        };
+
+        let Ok(decoded_file) = Decoder::new(file) else {
+            return Err(SequencerError::DecodingError);
+        };
+
+        Ok(decoded_file)
+    }
+
+    async fn decode_loop(
+        &self,
+        id: String,
+    ) -> Result<Box<dyn Source<Item = f32> + Send>, SequencerError> {
+        if self.low_memory {
+            return Ok(Box::new(self.decode(id).await?.convert_samples()));
+        }
+
+        let cached = self
+            .loop_cache
+            .lock()
+            .await
+            .clone()
+            .filter(|(cached_id, _)| *cached_id == id);
+
+        let data = match cached {
+            Some((_, data)) => data,
+            None => {
+                let Ok(mut file) = self.database.get_recording_file(id.clone()).await else {
+                    return Err(SequencerError::MissingAudioFile);
+                };
+
+                let Ok(Ok(data)) = tokio::task::spawn_blocking(move || {
+                    let mut data = Vec::new();
+
+                    file.read_to_end(&mut data).map(|_| Arc::<[u8]>::from(data))
+                })
+                .await
+                else {
+                    return Err(SequencerError::MissingAudioFile);
+                };
+
+                *self.loop_cache.lock().await = Some((id, data.clone()));
+
+                data
+            }
+        };
+
+        let Ok(decoded_file) = Decoder::new(Cursor::new(data)) else {
+            return Err(SequencerError::DecodingError);
+        };
+
+        Ok(Box::new(decoded_file.convert_samples()))
+    }
+
+    pub async fn warm_up(&self, id: String) {
+        if self.low_memory || self.is_playing_other(&id).await {
+            return;
+        }
+
+        let _ = self.database.get_recording_metadata(id.clone()).await;
+
+        let Ok(file) = self.database.get_recording_file(id.clone()).await else {
+            return;
+        };
+
+        let Ok(decoded_file) = Decoder::new(file) else {
+            return;
+        };
+
+        if self.is_playing_other(&id).await {
+            return;
+        }
+
+        *self.preloaded.lock().await = Some((id, decoded_file));
+    }
+
+    // A restored state leaves its recording loaded but paused, which the
+    // warm-up still preloads so that playing it again starts instantly.
+    async fn is_playing_other(&self, id: &str) -> bool {
+        self.playing
+            .lock()
+            .await
+            .as_ref()
+            .is_some_and(|playing| playing != id)
+    }
+
+    pub async fn get_playing(&self) -> Option<String> {
+        if self.pause_ramp.lock().await.pausing {
+            return None;
+        }
+
+        let locked_sink = self.sink.lock().await;
+
+        if locked_sink.is_paused() {
+            return None;
+        }
+
+        self.playing.lock().await.clone()
+    }
+
+    pub async fn play(&self, id: String, resume: bool) -> Result<Option<Duration>, SequencerError> {
+        self.clear_stop_after_current().await;
+
+        self.start_from(id, TransitionReason::Play, resume).await
+    }
+
+    pub async fn set_stop_after_current(&self, enable: bool) {
+        *self.stop_after_current.lock().await = enable;
+
+        self.refresh_gapless().await;
+    }
+
+    async fn clear_stop_after_current(&self) {
+        if std::mem::take(&mut *self.stop_after_current.lock().await) {
+            let _ = self.events.send(SequencerEvent::StopAfterCurrent {
+                enabled: false,
+                fired: false,
+            });
+        }
+    }
+
+    pub async fn resume(&self) -> Result<Option<String>, SequencerError> {
+        if let Some(id) = self.playing.lock().await.clone() {
+            if !self.sink.lock().await.empty() {
+                self.fade_in().await;
+
+                return Ok(Some(id));
+            }
+        }
+
+        if self.get_queue().await.is_empty() {
+            return Ok(None);
+        }
+
+        self.next().await?;
+
+        Ok(self.get_playing().await)
+    }
+
+    async fn start(&self, id: String, reason: TransitionReason) -> Result<(), SequencerError> {
+        self.start_from(id, reason, true).await.map(|_| ())
+    }
+
+    async fn start_from(
+        &self,
+        id: String,
+        reason: TransitionReason,
+        resume: bool,
+    ) -> Result<Option<Duration>, SequencerError> {
+        if reason == TransitionReason::Next && self.database.is_recording_faulty(id.clone()).await {
+            return Err(SequencerError::FaultyRecording);
+        }
+
+        self.reset_pause_ramp().await;
+
+        self.unpark().await?;
+
+        let gapless_pending = self.gapless_pending.lock().await.take();
+
+        let gapless = gapless_pending.as_ref() == Some(&id) && !self.sink.lock().await.empty();
+
+        let (listened, drained, finished) = if gapless {
+            let outgoing_duration = self.track_duration().await;
+
+            let locked_sink = self.sink.lock().await;
+
+            // After rodio has moved on to the preloaded source the sink
+            // position belongs to it, so the outgoing track counts as
+            // played in full.
+            let switched = locked_sink.len() <= 1;
+            let listened = match outgoing_duration {
+                Some(duration) if switched => duration,
+                _ => locked_sink.get_pos(),
+            };
+
+            if !switched {
+                locked_sink.skip_one();
+            }
+
+            locked_sink.play();
+
+            (listened, true, switched)
+        } else {
+            let preloaded = self.preloaded.lock().await.take();
+
+            let decoded_file = match preloaded {
+                Some((preloaded_id, decoded_file)) if preloaded_id == id => decoded_file,
+                _ => self.decode(id.clone()).await?,
+            };
+
+            let start_gain = self.level_transition_gain(&id).await;
+
+            let mut locked_sink = self.sink.lock().await;
+
+            let listened = locked_sink.get_pos();
+            let drained = locked_sink.empty();
+
+            self.replace_sink(&mut locked_sink).await;
+
+            locked_sink.append(Tee::new(
+                LevelRamp::new(decoded_file.convert_samples::<f32>(), start_gain),
+                self.stream_sender.clone(),
+            ));
+            locked_sink.play();
+
+            (listened, drained, drained)
+        };
+
+        let remember = reason != TransitionReason::Previous;
+
+        let previous = self.playing.lock().await.replace(id.clone());
+
+        *self.uncounted_listen.lock().await = Some(Duration::ZERO);
+
+        {
+            let mut locked_loop_cache = self.loop_cache.lock().await;
+
+            if locked_loop_cache
+                .as_ref()
+                .is_some_and(|(cached_id, _)| *cached_id != id)
+            {
+                *locked_loop_cache = None;
+            }
+        }
+
+        let _ = self.events.send(SequencerEvent::Transition {
+            from: previous.clone(),
+            to: id.clone(),
+            gapless,
+        });
+
+        if let Some(previous_id) = previous.clone() {
+            if drained {
+                self.database
+                    .set_resume_position(previous_id.clone(), None)
+                    .await;
+            } else {
+                self.remember_position(previous_id.clone(), listened).await;
+            }
+        }
+
+        let mut resumed = None;
+
+        if resume && !gapless {
+            if let Some(position) = self.resume_position(&id).await {
+                resumed = self.seek(position).await.ok();
+            }
+        }
+
+        if let Some(previous_id) = previous {
+            let ended = if finished {
+                TransitionReason::Finished
+            } else {
+                reason
+            };
+
+            self.database
+                .record_play_event(previous_id.clone(), listened, ended)
+                .await;
+
+            if remember {
+                self.history.lock().await.push(previous_id);
+            }
+        }
+
+        Ok(resumed)
+    }
+
+    pub async fn state(&self) -> PlaybackState {
+        let pausing = self.pause_ramp.lock().await.pausing;
+
+        let (paused, position) = {
+            let locked_sink = self.sink.lock().await;
+
+            (locked_sink.is_paused() || pausing, locked_sink.get_pos())
+        };
+
+        PlaybackState {
+            recording_id: self.playing.lock().await.clone(),
+            title: None,
+            artist: None,
+
+            paused,
+            position,
+
+            queue_length: self.get_queue().await.len(),
+            shuffle: *self.shuffle.lock().await,
+            shuffle_seed: *self.shuffle_seed.lock().await,
+            on_queue_end: *self.on_queue_end.lock().await,
+            smooth_level_transition: *self.smooth_level_transition.lock().await,
+        }
+    }
+
+    pub async fn position(&self) -> Duration {
+        if self.playing.lock().await.is_none() {
+            return Duration::ZERO;
+        }
+
+        self.sink.lock().await.get_pos()
+    }
+
+    pub async fn is_advancing(&self) -> bool {
+        if self.playing.lock().await.is_none() {
+            return false;
+        }
+
+        let locked_sink = self.sink.lock().await;
+
+        !locked_sink.is_paused() && !locked_sink.empty()
+    }
+
+    pub async fn snapshot(&self) -> Option<SessionSnapshot> {
+        let recording_id = self.playing.lock().await.clone()?;
+
+        Some(SessionSnapshot {
+            recording_id,
+            position: self.sink.lock().await.get_pos(),
+
+            queue: self.get_queue().await,
+            loop_mode: self.loop_mode.lock().await.clone(),
+            shuffle: *self.shuffle.lock().await,
+            shuffle_seed: *self.shuffle_seed.lock().await,
+        })
+    }
+
+    pub async fn saved_state(&self) -> SavedPlayerState {
+        let recording_id = self.playing.lock().await.clone();
+
+        SavedPlayerState {
+            recording_id,
+            position: self.position().await,
+
+            queue: self.queue.lock().await.clone(),
+            shuffled_queue: self.shuffled_queue.lock().await.clone(),
+            request_queue: self.request_queue.lock().await.clone(),
+            shuffled_request_queue: self.shuffled_request_queue.lock().await.clone(),
+            play_order: self.play_order.lock().await.clone(),
+            cursor: *self.cursor.lock().await,
+            loop_mode: self.loop_mode.lock().await.clone(),
+            shuffle: *self.shuffle.lock().await,
+            shuffle_seed: *self.shuffle_seed.lock().await,
+
+            volume: *self.volume.lock().await,
+            muted: *self.muted.lock().await,
+        }
+    }
+
+    async fn queueable_only(&self, ids: Vec<String>) -> Vec<String> {
+        let mut queueable = Vec::new();
+
+        for id in ids {
+            if self.check_queueable(&id).await.is_ok() {
+                queueable.push(id);
+            }
+        }
+
+        queueable
+    }
+
+    pub async fn restore_saved_state(&self, state: SavedPlayerState) -> Result<(), SequencerError> {
+        let mut queue = Vec::new();
+        let mut kept_indices = Vec::new();
+
+        for id in state.queue {
+            if self.check_queueable(&id).await.is_ok() {
+                kept_indices.push(Some(queue.len()));
+                queue.push(id);
+            } else {
+                kept_indices.push(None);
+            }
+        }
+
+        // States saved before the play order was persisted fall back to
+        // queue order, as does an order that no longer matches the queue.
+        let saved_order = if state.play_order.len() == kept_indices.len() {
+            state.play_order
+        } else if *self.queue_model.lock().await == QueueModel::Cursor {
+            (0..kept_indices.len()).collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut play_order = Vec::new();
+        let mut cursor = None;
+
+        for (position, index) in saved_order.into_iter().enumerate() {
+            if let Some(Some(new_index)) = kept_indices.get(index) {
+                play_order.push(*new_index);
+            }
+
+            if state.cursor == Some(position) {
+                cursor = play_order.len().checked_sub(1);
+            }
+        }
+
+        let shuffled_queue = if state.shuffle && state.shuffled_queue.is_empty() {
+            queue.clone()
+        } else {
+            self.queueable_only(state.shuffled_queue).await
+        };
+
+        let request_queue = self.queueable_only(state.request_queue).await;
+        let shuffled_request_queue = if state.shuffle && state.shuffled_request_queue.is_empty() {
+            request_queue.clone()
+        } else {
+            self.queueable_only(state.shuffled_request_queue).await
+        };
+
+        *self.play_order.lock().await = play_order;
+        *self.cursor.lock().await = cursor;
+        *self.queue.lock().await = queue;
+        *self.shuffled_queue.lock().await = shuffled_queue;
+        *self.request_queue.lock().await = request_queue;
+        *self.shuffled_request_queue.lock().await = shuffled_request_queue;
+        *self.loop_mode.lock().await = state.loop_mode;
+
+        let seed = state.shuffle.then_some(state.shuffle_seed).flatten();
+
+        *self.shuffle_seed.lock().await = seed;
+        *self.shuffle_rng.lock().await = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        *self.shuffle.lock().await = state.shuffle;
+
+        *self.volume.lock().await = state.volume;
+        *self.muted.lock().await = state.muted;
+
+        self.apply_volume().await;
+
+        let Some(id) = state.recording_id else {
+            return Ok(());
+        };
+
+        if self.check_queueable(&id).await.is_err() {
+            return Ok(());
+        }
+
+        self.start(id, TransitionReason::Play).await?;
+
+        self.sink.lock().await.pause();
+
+        self.seek(state.position).await?;
+
+        Ok(())
+    }
+
+    pub async fn adopt(&self, snapshot: SessionSnapshot) -> Result<(), SequencerError> {
+        // A hand-off that is retried delivers the same snapshot again, and
+        // adopting it twice would rewind the recording it already started.
+        if self.playing.lock().await.as_ref() == Some(&snapshot.recording_id)
+            && self.adopted.lock().await.as_ref() == Some(&snapshot)
+        {
+            return Ok(());
+        }
+
+        let decoded_file = self.decode(snapshot.recording_id.clone()).await?;
+
+        *self.play_order.lock().await = (0..snapshot.queue.len()).collect();
+        *self.cursor.lock().await = None;
+        *self.queue.lock().await = snapshot.queue.clone();
+        *self.loop_mode.lock().await = snapshot.loop_mode.clone();
+
+        match (snapshot.shuffle, snapshot.shuffle_seed) {
+            (true, Some(seed)) => self.resume_shuffle(seed).await,
+            (enable, _) => {
+                self.set_shuffle(enable, None).await;
+            }
+        }
+
+        self.replace_sink(&mut *self.sink.lock().await).await;
+
+        *self.preloaded.lock().await = Some((snapshot.recording_id.clone(), decoded_file));
+
+        self.start(snapshot.recording_id.clone(), TransitionReason::Play)
+            .await?;
+
+        self.seek(snapshot.position).await?;
+
+        *self.adopted.lock().await = Some(snapshot);
+
+        Ok(())
+    }
+
+    pub async fn pause(&self) {
+        let fade = *self.pause_fade.lock().await;
+
+        let (paused, position) = {
+            let locked_sink = self.sink.lock().await;
+
+            (locked_sink.is_paused(), locked_sink.get_pos())
+        };
+
+        if fade.is_zero() || paused {
+            self.reset_pause_ramp().await;
+            self.sink.lock().await.pause();
+        } else {
+            let generation = {
+                let mut locked_pause_ramp = self.pause_ramp.lock().await;
+
+                locked_pause_ramp.generation += 1;
+                locked_pause_ramp.pausing = true;
+
+                locked_pause_ramp.generation
+            };
+
+            let sequencer = self.clone();
+
+            tokio::spawn(async move {
+                if !sequencer.ramp_pause_gain(0.0, fade, generation).await {
+                    return;
+                }
+
+                {
+                    let mut locked_pause_ramp = sequencer.pause_ramp.lock().await;
+
+                    if locked_pause_ramp.generation != generation {
+                        return;
+                    }
+
+                    sequencer.sink.lock().await.pause();
+
+                    locked_pause_ramp.pausing = false;
+                    locked_pause_ramp.gain = 1.0;
+                }
+
+                sequencer.apply_volume().await;
+            });
+        }
+
+        if let Some(id) = self.playing.lock().await.clone() {
+            self.remember_position(id, position).await;
+        }
+    }
+
+    async fn fade_in(&self) {
+        let fade = *self.pause_fade.lock().await;
+        let paused = self.sink.lock().await.is_paused();
+
+        let generation = {
+            let mut locked_pause_ramp = self.pause_ramp.lock().await;
+
+            locked_pause_ramp.generation += 1;
+            locked_pause_ramp.pausing = false;
+
+            if paused && !fade.is_zero() {
+                locked_pause_ramp.gain = 0.0;
+            }
+
+            locked_pause_ramp.generation
+        };
+
+        self.apply_volume().await;
+        self.sink.lock().await.play();
+
+        let sequencer = self.clone();
+
+        tokio::spawn(async move {
+            sequencer.ramp_pause_gain(1.0, fade, generation).await;
+        });
+    }
+
+    async fn reset_pause_ramp(&self) {
+        {
+            let mut locked_pause_ramp = self.pause_ramp.lock().await;
+
+            locked_pause_ramp.generation += 1;
+            locked_pause_ramp.pausing = false;
+            locked_pause_ramp.gain = 1.0;
+        }
+
+        self.apply_volume().await;
+    }
+
+    async fn ramp_pause_gain(&self, target: f32, over: Duration, generation: u64) -> bool {
+        let start = self.pause_ramp.lock().await.gain;
+
+        for step in 1..=PAUSE_FADE_STEPS {
+            time::sleep(over / PAUSE_FADE_STEPS).await;
+
+            {
+                let mut locked_pause_ramp = self.pause_ramp.lock().await;
+
+                if locked_pause_ramp.generation != generation {
+                    return false;
+                }
+
+                locked_pause_ramp.gain =
+                    start + (target - start) * step as f32 / PAUSE_FADE_STEPS as f32;
+            }
+
+            self.apply_volume().await;
+        }
+
+        true
+    }
+
+    pub async fn set_pause_fade(&self, fade: Duration) -> Duration {
+        let fade = fade.min(MAX_PAUSE_FADE);
+
+        *self.pause_fade.lock().await = fade;
+
+        fade
+    }
+
+    pub async fn stop(&self) {
+        self.reset_pause_ramp().await;
+
+        let (position, ended) = {
+            let mut locked_sink = self.sink.lock().await;
+
+            let position = locked_sink.get_pos();
+            let ended = stop_reason(&locked_sink);
+
+            self.replace_sink(&mut locked_sink).await;
+
+            (position, ended)
+        };
+
+        self.preloaded.lock().await.take();
+        self.gapless_pending.lock().await.take();
+        self.loop_cache.lock().await.take();
+
+        let Some(id) = self.playing.lock().await.take() else {
+            return;
+        };
+
+        self.remember_position(id.clone(), position).await;
+
+        self.database
+            .record_play_event(id.clone(), position, ended)
+            .await;
+
+        self.history.lock().await.push(id);
+    }
+
+    async fn remember_position(&self, id: String, position: Duration) {
+        let Some(metadata) = self
+            .database
+            .get_cached_recording_metadata(id.clone())
+            .await
+        else {
+            return;
+        };
+
+        if !metadata.is_spoken_word() {
+            return;
+        }
+
+        let finished = metadata
+            .duration()
+            .is_some_and(|duration| position >= duration.mul_f32(RESUME_CLEAR_FRACTION));
+
+        self.database
+            .set_resume_position(id, (!finished && !position.is_zero()).then_some(position))
+            .await;
+    }
+
+    async fn resume_position(&self, id: &str) -> Option<Duration> {
+        let metadata = self
+            .database
+            .get_cached_recording_metadata(id.to_owned())
+            .await?;
+
+        if !metadata.is_spoken_word() {
+            return None;
+        }
+
+        metadata
+            .resume_position
+            .filter(|position| *position >= RESUME_MIN_POSITION)
+    }
+
+    pub async fn seek(&self, position: Duration) -> Result<Duration, SequencerError> {
+        let position = match self.track_duration().await {
+            Some(duration) => position.min(duration.saturating_sub(SEEK_END_MARGIN)),
+            None => position,
+        };
+
+        if self.sink.lock().await.try_seek(position).is_ok() {
+            return Ok(position);
+        }
+
+        let Some(id) = self.playing.lock().await.clone() else {
+            return Err(SequencerError::SeekFailed);
+        };
+
+        let Ok(decoded_file) = self.decode(id.clone()).await else {
+            return Err(SequencerError::SeekFailed);
+        };
+
+        let Ok(Some(skipped)) = tokio::task::spawn_blocking(move || {
+            skip_to(decoded_file.convert_samples::<f32>(), position)
+        })
+        .await
+        else {
+            return Err(SequencerError::SeekFailed);
+        };
+
+        if self.playing.lock().await.as_ref() != Some(&id) {
+            return Err(SequencerError::SeekFailed);
+        }
+
+        *self.gapless_pending.lock().await = None;
+
+        let mut locked_sink = self.sink.lock().await;
+
+        let paused = locked_sink.is_paused();
+
+        self.replace_sink(&mut locked_sink).await;
+        locked_sink.append(Tee::new(skipped, self.stream_sender.clone()));
+
+        let _ = locked_sink.try_seek(position);
+
+        if !paused {
+            locked_sink.play();
+        }
+
+        self.seek_fallbacks.fetch_add(1, Ordering::Relaxed);
+
+        Ok(position)
+    }
+
+    pub async fn seek_relative(&self, offset_ms: i64) -> Result<Duration, SequencerError> {
+        if self.playing.lock().await.is_none() {
+            return Err(SequencerError::NothingPlaying);
+        }
+
+        let current = self.position().await;
+        let offset = Duration::from_millis(offset_ms.unsigned_abs());
+
+        let target = if offset_ms < 0 {
+            current.saturating_sub(offset)
+        } else {
+            current.saturating_add(offset)
+        };
+
+        if self
+            .track_duration()
+            .await
+            .is_some_and(|duration| target >= duration)
+        {
+            self.next().await?;
+
+            return Ok(self.position().await);
+        }
+
+        self.seek(target).await
+    }
+
+    pub fn seek_fallbacks(&self) -> u64 {
+        self.seek_fallbacks.load(Ordering::Relaxed)
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.telemetry.underruns()
+    }
+
+    pub fn priority_elevated(&self) -> bool {
+        self.telemetry.priority_elevated()
+    }
+
+    pub async fn seek_fraction(&self, fraction: f32) -> Result<Duration, SequencerError> {
+        let Some(duration) = self.track_duration().await else {
+            return Err(SequencerError::UnknownDuration);
+        };
+
+        if fraction.is_nan() {
+            return Err(SequencerError::SeekFailed);
+        }
+
+        self.seek(duration.mul_f32(fraction.clamp(0.0, 1.0))).await
+    }
+
+    pub async fn track_duration(&self) -> Option<Duration> {
+        let id = self.playing.lock().await.clone()?;
+
+        self.database
+            .get_cached_recording_metadata(id)
+            .await?
+            .duration()
+    }
+
+    pub async fn next(&self) -> Result<(), SequencerError> {
+        self.clear_stop_after_current().await;
+
+        let attempts = self.get_queue().await.len() + 1;
+
+        for _ in 0..attempts {
+            match self.advance().await {
+                Err(SequencerError::FaultyRecording) => continue,
+                result => return result,
+            }
+        }
+
+        Err(SequencerError::NoSongsQueued)
+    }
+
+    async fn advance(&self) -> Result<(), SequencerError> {
+        let loop_mode = self.loop_mode.lock().await.clone();
+
+        if !matches!(loop_mode, LoopMode::LoopRecording) && self.get_main_queue().await.is_empty() {
+            if let Some(request) = self.pop_request().await {
+                return self.start(request, TransitionReason::Next).await;
+            }
+
+            if let LoopMode::LoopPlaylist(playlist_id) = loop_mode {
+                self.refill_from_playlist(playlist_id).await;
+            }
+        }
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            return self.next_cursor().await;
+        }
+
+        match *self.loop_mode.lock().await {
+            LoopMode::None | LoopMode::LoopPlaylist(_) => {
+                let should_shuffle = *self.shuffle.lock().await;
+
+                let mut locked_queue = if should_shuffle {
+                    self.shuffled_queue.lock().await
+                } else {
+                    self.queue.lock().await
+                };
+
+                if locked_queue.len() == 0 {
+                    return Err(SequencerError::NoSongsQueued);
+                }
+
+                let song_to_play = locked_queue.remove(0);
+
+                if should_shuffle {
+                    let mut locked_removal_queue = self.queue.lock().await;
+
+                    if let Some(song_index) = locked_removal_queue
+                        .iter()
+                        .position(|song_to_check| *song_to_check == song_to_play)
+                    {
+                        locked_removal_queue.remove(song_index);
+                    }
+                }
+
+                self.start(song_to_play, TransitionReason::Next).await?;
+
+                Ok(())
+            }
+            LoopMode::LoopQueue => {
+                let should_shuffle = *self.shuffle.lock().await;
+
+                if should_shuffle {
+                    let mut locked_shuffle_queue = self.shuffled_queue.lock().await;
+
+                    if locked_shuffle_queue.len() == 0 {
+                        let locked_queue = self.queue.lock().await;
+
+                        if locked_queue.len() == 0 {
+                            return Err(SequencerError::NoSongsQueued);
+                        }
+
+                        *locked_shuffle_queue = shuffle_queue(
+                            locked_queue.to_vec(),
+                            &mut *self.shuffle_rng.lock().await,
+                        );
+                    }
+
+                    let song_to_play = locked_shuffle_queue.remove(0);
+
+                    self.start(song_to_play, TransitionReason::Next).await?;
+
+                    Ok(())
+                } else {
+                    let mut locked_queue = self.queue.lock().await;
+
+                    if locked_queue.len() == 0 {
+                        return Err(SequencerError::NoSongsQueued);
+                    }
+
+                    let song_to_play = locked_queue.remove(0);
+
+                    locked_queue.push(song_to_play.clone());
+
+                    self.start(song_to_play, TransitionReason::Next).await?;
+
+                    Ok(())
+                }
+            }
+            LoopMode::LoopRecording => {
+                let Some(song_to_loop) = self.playing.lock().await.clone() else {
+                    return Err(SequencerError::NothingPlaying);
+                };
+
+                self.start(song_to_loop, TransitionReason::Loop).await?;
+
+                Ok(())
+            }
+        }
+    }
+
+    async fn refill_from_playlist(&self, playlist_id: String) {
+        let Ok(playlist) = self.database.get_playlist(playlist_id.clone()).await else {
+            self.end_playlist_loop(&playlist_id).await;
+
+            return;
+        };
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            self.queue.lock().await.clear();
+            self.play_order.lock().await.clear();
+            *self.cursor.lock().await = None;
+        }
+
+        self.tag_queue_entries(
+            &playlist_id,
+            playlist.recordings.iter().cloned().enumerate().collect(),
+        )
+        .await;
+
+        let _ = self.add_queue(playlist.recordings).await;
+
+        let _ = self.events.send(SequencerEvent::QueueExtended {
+            queue: self.get_queue().await,
+            auto_added: self.radio_added.lock().await.clone(),
+        });
+    }
+
+    pub async fn end_playlist_loop(&self, playlist_id: &str) {
+        {
+            let mut locked_loop_mode = self.loop_mode.lock().await;
+
+            if *locked_loop_mode != LoopMode::LoopPlaylist(playlist_id.to_owned()) {
+                return;
+            }
+
+            *locked_loop_mode = LoopMode::None;
+        }
+
+        let _ = self
+            .events
+            .send(SequencerEvent::LoopModeChanged(LoopMode::None));
+    }
+
+    async fn next_cursor(&self) -> Result<(), SequencerError> {
+        let loop_mode = self.loop_mode.lock().await.clone();
+        let should_shuffle = *self.shuffle.lock().await;
+
+        if let LoopMode::LoopRecording = loop_mode {
+            let Some(song_to_loop) = self.playing.lock().await.clone() else {
+                return Err(SequencerError::NothingPlaying);
+            };
+
+            return self.start(song_to_loop, TransitionReason::Loop).await;
+        }
+
+        let song_to_play = {
+            let locked_queue = self.queue.lock().await;
+            let mut locked_order = self.play_order.lock().await;
+            let mut locked_cursor = self.cursor.lock().await;
+
+            if locked_order.is_empty() {
+                return Err(SequencerError::NoSongsQueued);
+            }
+
+            let mut position = locked_cursor.map_or(0, |cursor| cursor + 1);
+
+            if position >= locked_order.len() {
+                let LoopMode::LoopQueue = loop_mode else {
+                    return Err(SequencerError::NoSongsQueued);
+                };
+
+                position = 0;
+
+                if should_shuffle {
+                    locked_order.shuffle(&mut *self.shuffle_rng.lock().await);
+                }
+            }
+
+            *locked_cursor = Some(position);
+
+            locked_queue[locked_order[position]].clone()
+        };
+
+        self.start(song_to_play, TransitionReason::Next).await
+    }
+
+    pub async fn previous(&self) -> Result<(), SequencerError> {
+        if self.position().await > PREVIOUS_RESTART_THRESHOLD {
+            return self.seek(Duration::ZERO).await.map(|_| ());
+        }
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            return self.previous_cursor().await;
+        }
+
+        let Some(song_to_play) = self.history.lock().await.pop_latest() else {
+            return Err(SequencerError::NoSongsPlayed);
+        };
+
+        self.queue.lock().await.insert(0, song_to_play.clone());
+
+        if *self.shuffle.lock().await {
+            self.shuffled_queue
+                .lock()
+                .await
+                .insert(0, song_to_play.clone());
+        }
+
+        return self.start(song_to_play, TransitionReason::Previous).await;
+    }
+
+    async fn previous_cursor(&self) -> Result<(), SequencerError> {
+        let loop_mode = self.loop_mode.lock().await.clone();
+
+        let song_to_play = {
+            let locked_queue = self.queue.lock().await;
+            let locked_order = self.play_order.lock().await;
+            let mut locked_cursor = self.cursor.lock().await;
+
+            let position = match *locked_cursor {
+                Some(cursor) if cursor > 0 => cursor - 1,
+                _ if matches!(loop_mode, LoopMode::LoopQueue) && !locked_order.is_empty() => {
+                    locked_order.len() - 1
+                }
+                _ => return Err(SequencerError::NoSongsPlayed),
+            };
+
+            *locked_cursor = Some(position);
+
+            locked_queue[locked_order[position]].clone()
+        };
+
+        self.start(song_to_play, TransitionReason::Previous).await
+    }
+
+    async fn extend_play_order(&self, queue_length: usize) {
+        let should_shuffle = *self.shuffle.lock().await;
+
+        let mut locked_order = self.play_order.lock().await;
+        let locked_cursor = self.cursor.lock().await;
+
+        let first_new = locked_order.len();
+
+        locked_order.extend(first_new..queue_length);
+
+        if should_shuffle {
+            let upcoming = locked_cursor.map_or(0, |cursor| cursor + 1);
+
+            locked_order[upcoming..].shuffle(&mut *self.shuffle_rng.lock().await);
+        }
+    }
+
+    pub async fn set_queue_model(&self, model: QueueModel) {
+        let mut locked_model = self.queue_model.lock().await;
+
+        if *locked_model == model {
+            return;
+        }
+
+        match model {
+            QueueModel::Cursor => {
+                let queue_length = self.queue.lock().await.len();
+                let should_shuffle = *self.shuffle.lock().await;
+
+                let mut locked_order = self.play_order.lock().await;
+
+                *locked_order = (0..queue_length).collect();
+
+                if should_shuffle {
+                    locked_order.shuffle(&mut *self.shuffle_rng.lock().await);
+                }
+
+                *self.cursor.lock().await = None;
+            }
+            QueueModel::Consuming => {
+                let remaining = {
+                    let locked_queue = self.queue.lock().await;
+                    let locked_order = self.play_order.lock().await;
+                    let upcoming = self.cursor.lock().await.map_or(0, |cursor| cursor + 1);
+
+                    locked_order[upcoming.min(locked_order.len())..]
+                        .iter()
+                        .map(|index| locked_queue[*index].clone())
+                        .collect::<Vec<String>>()
+                };
+
+                *self.queue.lock().await = remaining.clone();
+                *self.shuffled_queue.lock().await = remaining;
+
+                self.play_order.lock().await.clear();
+                *self.cursor.lock().await = None;
+            }
+        }
+
+        *locked_model = model;
+
+        drop(locked_model);
+
+        self.refresh_gapless().await;
+    }
+
+    pub async fn add_queue(&self, ids: Vec<String>) -> Result<Vec<QueueRejection>, SequencerError> {
+        let mut rejected = Vec::new();
+
+        let mut locked_queue = self.queue.lock().await;
+
+        for id in ids {
+            if locked_queue.len() >= self.max_queue_length {
+                rejected.push((id, QueueRejectReason::QueueFull));
+
+                continue;
+            }
+
+            if let Err(reason) = self.check_queueable(&id).await {
+                rejected.push((id, reason));
+
+                continue;
+            }
+
+            locked_queue.push(id);
+        }
+
+        let queue = locked_queue.to_vec();
+
+        drop(locked_queue);
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            self.extend_play_order(queue.len()).await;
+        } else if *self.shuffle.lock().await {
+            *self.shuffled_queue.lock().await =
+                shuffle_queue(queue, &mut *self.shuffle_rng.lock().await);
+        }
+
+        self.refresh_gapless().await;
+
+        Ok(rejected)
+    }
+
+    pub async fn queue_after_current_group(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<(usize, Vec<QueueRejection>), SequencerError> {
+        let current = match self.playing.lock().await.clone() {
+            Some(id) => self.database.get_cached_recording_metadata(id).await,
+            None => None,
+        };
+
+        let position = match current {
+            Some(current) => self.group_run(&current, &self.get_main_queue().await).await,
+            None => 0,
+        };
+
+        let rejected = self.insert_queue(position, ids).await?;
+
+        Ok((position, rejected))
+    }
+
+    pub async fn queue_next(
+        &self,
+        ids: Vec<String>,
+    ) -> Result<Vec<QueueRejection>, SequencerError> {
+        self.insert_queue(0, ids).await
+    }
+
+    async fn group_run(&self, current: &RecordingMetadata, upcoming: &[String]) -> usize {
+        let releases = current.release_ids();
+        let artists = current.artist_ids();
+
+        let mut release_open = !releases.is_empty();
+        let mut artist_open = !artists.is_empty();
+
+        let mut release_run = 0;
+        let mut artist_run = 0;
+
+        for id in upcoming {
+            if !release_open && !artist_open {
+                break;
+            }
+
+            let Some(metadata) = self
+                .database
+                .get_cached_recording_metadata(id.clone())
+                .await
+            else {
+                break;
+            };
+
+            release_open = release_open && !metadata.release_ids().is_disjoint(&releases);
+            artist_open = artist_open && !metadata.artist_ids().is_disjoint(&artists);
+
+            release_run += release_open as usize;
+            artist_run += artist_open as usize;
+        }
+
+        if release_run > 0 {
+            release_run
+        } else {
+            artist_run
+        }
+    }
+
+    async fn insert_queue(
+        &self,
+        position: usize,
+        ids: Vec<String>,
+    ) -> Result<Vec<QueueRejection>, SequencerError> {
+        let cursor_model = *self.queue_model.lock().await == QueueModel::Cursor;
+        let should_shuffle = *self.shuffle.lock().await;
+
+        let mut rejected = Vec::new();
+        let mut accepted = Vec::new();
+
+        let mut locked_queue = self.queue.lock().await;
+
+        for id in ids {
+            if locked_queue.len() + accepted.len() >= self.max_queue_length {
+                rejected.push((id, QueueRejectReason::QueueFull));
+
+                continue;
+            }
+
+            if let Err(reason) = self.check_queueable(&id).await {
+                rejected.push((id, reason));
+
+                continue;
+            }
+
+            accepted.push(id);
+        }
+
+        if cursor_model {
+            let mut locked_order = self.play_order.lock().await;
+            let upcoming = self.cursor.lock().await.map_or(0, |cursor| cursor + 1);
+
+            let position = (upcoming + position).min(locked_order.len());
+
+            for (offset, id) in accepted.into_iter().enumerate() {
+                locked_order.insert(position + offset, locked_queue.len());
+                locked_queue.push(id);
+            }
+        } else if should_shuffle {
+            let mut locked_shuffled_queue = self.shuffled_queue.lock().await;
+
+            let position = position.min(locked_shuffled_queue.len());
+
+            for (offset, id) in accepted.into_iter().enumerate() {
+                locked_shuffled_queue.insert(position + offset, id.clone());
+                locked_queue.push(id);
+            }
+        } else {
+            let position = position.min(locked_queue.len());
+
+            locked_queue.splice(position..position, accepted);
+        }
+
+        drop(locked_queue);
+
+        self.refresh_gapless().await;
+
+        Ok(rejected)
+    }
+
+    async fn check_queueable(&self, id: &str) -> Result<(), QueueRejectReason> {
+        match self.database.get_recording_file(id.to_owned()).await {
+            Ok(_) => Ok(()),
+            Err(DatabaseError::RecordingMetadataNotFound) => {
+                Err(QueueRejectReason::UnknownRecording)
+            }
+            Err(_) => Err(QueueRejectReason::MissingAudio),
+        }
+    }
+
+    pub async fn add_to_queue(
+        &self,
+        queue: &str,
+        ids: Vec<String>,
+    ) -> Result<Vec<QueueRejection>, SequencerError> {
+        match queue {
+            MAIN_QUEUE => self.add_queue(ids).await,
+            REQUESTS_QUEUE => {
+                let rejected = self.add_requests(ids).await;
+
+                self.refresh_gapless().await;
+
+                rejected
+            }
+            _ => Err(SequencerError::UnknownQueue),
+        }
+    }
+
+    async fn add_requests(&self, ids: Vec<String>) -> Result<Vec<QueueRejection>, SequencerError> {
+        let mut rejected = Vec::new();
+
+        let should_shuffle = *self.shuffle.lock().await;
+
+        let mut locked_requests = self.request_queue.lock().await;
+        let mut locked_shuffled_requests = self.shuffled_request_queue.lock().await;
+
+        for id in ids {
+            if locked_requests.contains(&id) {
+                rejected.push((id, QueueRejectReason::Duplicate));
+
+                continue;
+            }
+
+            if locked_requests.len() >= self.max_queue_length {
+                rejected.push((id, QueueRejectReason::QueueFull));
+
+                continue;
+            }
+
+            if let Err(reason) = self.check_queueable(&id).await {
+                rejected.push((id, reason));
+
+                continue;
+            }
+
+            if should_shuffle {
+                let position = self
+                    .shuffle_rng
+                    .lock()
+                    .await
+                    .gen_range(0..=locked_shuffled_requests.len());
+
+                locked_shuffled_requests.insert(position, id.clone());
+            }
+
+            locked_requests.push(id);
+        }
+
+        Ok(rejected)
+    }
+
+    async fn pop_request(&self) -> Option<String> {
+        let mut locked_requests = self.request_queue.lock().await;
+
+        if !*self.shuffle.lock().await {
+            if locked_requests.is_empty() {
+                return None;
+            }
+
+            return Some(locked_requests.remove(0));
+        }
+
+        let mut locked_shuffled_requests = self.shuffled_request_queue.lock().await;
+
+        if locked_shuffled_requests.is_empty() {
+            return None;
+        }
+
+        let request = locked_shuffled_requests.remove(0);
+
+        if let Some(index) = locked_requests.iter().position(|id| *id == request) {
+            locked_requests.remove(index);
+        }
+
+        Some(request)
+    }
+
+    async fn get_requests(&self) -> Vec<String> {
+        if *self.shuffle.lock().await {
+            self.shuffled_request_queue.lock().await.clone()
+        } else {
+            self.request_queue.lock().await.clone()
+        }
+    }
+
+    pub async fn get_queues(&self) -> Vec<NamedQueue> {
+        vec![
+            NamedQueue {
+                name: MAIN_QUEUE.to_owned(),
+                recordings: self.get_main_queue().await,
+            },
+            NamedQueue {
+                name: REQUESTS_QUEUE.to_owned(),
+                recordings: self.get_requests().await,
+            },
+        ]
+    }
+
+    pub async fn get_queue(&self) -> Vec<String> {
+        let mut queue = self.get_main_queue().await;
+
+        queue.extend(self.get_requests().await);
+
+        queue
+    }
+
+    async fn get_main_queue(&self) -> Vec<String> {
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            let locked_queue = self.queue.lock().await;
+            let locked_order = self.play_order.lock().await;
+            let upcoming = self.cursor.lock().await.map_or(0, |cursor| cursor + 1);
+
+            return locked_order[upcoming.min(locked_order.len())..]
+                .iter()
+                .map(|index| locked_queue[*index].clone())
+                .collect();
+        }
+
+        if *self.shuffle.lock().await {
+            self.shuffled_queue.lock().await.clone()
+        } else {
+            self.queue.lock().await.clone()
+        }
+    }
+
+    pub async fn replace_recording(&self, old_id: &str, new_id: &str) {
+        for queue in [
+            &self.queue,
+            &self.shuffled_queue,
+            &self.request_queue,
+            &self.shuffled_request_queue,
+        ] {
+            for id in queue.lock().await.iter_mut() {
+                if id == old_id {
+                    *id = new_id.to_owned();
+                }
+            }
+        }
+
+        self.history.lock().await.replace(old_id, new_id);
+
+        {
+            let mut locked_sources = self.queue_sources.lock().await;
+
+            if let Some(source) = locked_sources.remove(old_id) {
+                locked_sources.insert(new_id.to_owned(), source);
+            }
+        }
+
+        let mut locked_playing = self.playing.lock().await;
+
+        if locked_playing.as_deref() == Some(old_id) {
+            *locked_playing = Some(new_id.to_owned());
+        }
+    }
+
+    pub async fn tag_queue_entries(&self, playlist_id: &str, entries: Vec<(usize, String)>) {
+        let mut locked_sources = self.queue_sources.lock().await;
+
+        for (index, id) in entries {
+            locked_sources.insert(id, (playlist_id.to_owned(), index));
+        }
+    }
+
+    pub async fn clear_queue(&self, preview: bool) -> EffectSummary {
+        let mut removed = self.queue.lock().await.clone();
+        removed.extend(self.request_queue.lock().await.iter().cloned());
+
+        let mut playlists_rewritten = self
+            .queue_sources
+            .lock()
+            .await
+            .values()
+            .map(|(playlist_id, _)| playlist_id.clone())
+            .collect::<Vec<String>>();
+
+        playlists_rewritten.sort_unstable();
+        playlists_rewritten.dedup();
+
+        let summary = EffectSummary {
+            removed,
+            bytes_reclaimed: 0,
+            playlists_rewritten,
+        };
+
+        if preview {
+            return summary;
+        }
+
+        self.queue.lock().await.clear();
+        self.shuffled_queue.lock().await.clear();
+        self.play_order.lock().await.clear();
+        *self.cursor.lock().await = None;
+
+        self.request_queue.lock().await.clear();
+        self.shuffled_request_queue.lock().await.clear();
+
+        self.queue_sources.lock().await.clear();
+
+        self.refresh_gapless().await;
+
+        for playlist_id in &summary.playlists_rewritten {
+            self.database
+                .reset_playlist_position(playlist_id.clone())
+                .await;
+        }
+
+        summary
+    }
+
+    pub async fn remove_from_queue(
+        &self,
+        target: &QueueRemoveTarget,
+    ) -> Result<String, SequencerError> {
+        let removed = self.remove_queue_entry(target).await;
+
+        self.refresh_gapless().await;
+
+        removed
+    }
+
+    async fn remove_queue_entry(
+        &self,
+        target: &QueueRemoveTarget,
+    ) -> Result<String, SequencerError> {
+        let main_length = self.get_main_queue().await.len();
+
+        let index = match target {
+            QueueRemoveTarget::Index(index) => *index,
+            QueueRemoveTarget::Id(id) => self
+                .get_queue()
+                .await
+                .iter()
+                .position(|queued| queued == id)
+                .ok_or(SequencerError::NotInQueue)?,
+        };
+
+        if index >= main_length {
+            let index = index - main_length;
+
+            return Ok(self.take_requests(index..index + 1).await?.remove(0));
+        }
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            let mut locked_queue = self.queue.lock().await;
+            let mut locked_order = self.play_order.lock().await;
+            let upcoming = self.cursor.lock().await.map_or(0, |cursor| cursor + 1);
+
+            let queue_index = locked_order.remove(upcoming + index);
+
+            for order_index in locked_order.iter_mut() {
+                if *order_index > queue_index {
+                    *order_index -= 1;
+                }
+            }
+
+            return Ok(locked_queue.remove(queue_index));
+        }
+
+        let should_shuffle = *self.shuffle.lock().await;
+
+        let mut locked_queue = self.queue.lock().await;
+        let mut locked_shuffled_queue = self.shuffled_queue.lock().await;
+
+        let (visible, hidden) = if should_shuffle {
+            (&mut *locked_shuffled_queue, &mut *locked_queue)
+        } else {
+            (&mut *locked_queue, &mut *locked_shuffled_queue)
+        };
+
+        let removed = visible.remove(index);
+
+        if let Some(hidden_index) = hidden.iter().position(|id| *id == removed) {
+            hidden.remove(hidden_index);
+        }
+
+        Ok(removed)
+    }
+
+    async fn take_requests(&self, range: Range<usize>) -> Result<Vec<String>, SequencerError> {
+        let should_shuffle = *self.shuffle.lock().await;
+
+        let mut locked_requests = self.request_queue.lock().await;
+        let mut locked_shuffled_requests = self.shuffled_request_queue.lock().await;
+
+        let (visible, hidden) = if should_shuffle {
+            (&mut *locked_shuffled_requests, &mut *locked_requests)
+        } else {
+            (&mut *locked_requests, &mut *locked_shuffled_requests)
+        };
+
+        if range.end > visible.len() {
+            return Err(SequencerError::NotInQueue);
+        }
+
+        let removed = visible.drain(range).collect::<Vec<String>>();
+
+        for id in &removed {
+            if let Some(hidden_index) = hidden.iter().position(|hidden_id| hidden_id == id) {
+                hidden.remove(hidden_index);
+            }
+        }
+
+        Ok(removed)
+    }
+
+    pub async fn play_index(
+        &self,
+        index: usize,
+        keep_previous: bool,
+    ) -> Result<(), SequencerError> {
+        self.clear_stop_after_current().await;
+
+        let main_length = self.get_main_queue().await.len();
+        let cursor_model = *self.queue_model.lock().await == QueueModel::Cursor;
+
+        let id = if index < main_length {
+            if cursor_model {
+                self.jump_cursor(index, keep_previous).await
+            } else {
+                let start = if keep_previous { index } else { 0 };
+
+                self.take_main(start..index + 1).await.pop()
+            }
+        } else {
+            let request_index = index - main_length;
+
+            if request_index >= self.get_requests().await.len() {
+                return Err(SequencerError::NotInQueue);
+            }
+
+            if !keep_previous {
+                if cursor_model {
+                    let length = self.play_order.lock().await.len();
+
+                    *self.cursor.lock().await = length.checked_sub(1);
+                } else {
+                    self.take_main(0..main_length).await;
+                }
+            }
+
+            let start = if keep_previous { request_index } else { 0 };
+
+            self.take_requests(start..request_index + 1).await?.pop()
+        };
+
+        let Some(id) = id else {
+            return Err(SequencerError::NotInQueue);
+        };
+
+        self.start(id, TransitionReason::Play).await
+    }
+
+    async fn jump_cursor(&self, index: usize, keep_previous: bool) -> Option<String> {
+        let locked_queue = self.queue.lock().await;
+        let mut locked_order = self.play_order.lock().await;
+        let mut locked_cursor = self.cursor.lock().await;
+
+        let upcoming = locked_cursor.map_or(0, |cursor| cursor + 1);
+        let mut position = upcoming + index;
+
+        if position >= locked_order.len() {
+            return None;
+        }
+
+        if keep_previous {
+            locked_order[upcoming..=position].rotate_right(1);
+
+            position = upcoming;
+        }
+
+        *locked_cursor = Some(position);
+
+        Some(locked_queue[locked_order[position]].clone())
+    }
+
+    async fn take_main(&self, range: Range<usize>) -> Vec<String> {
+        let should_shuffle = *self.shuffle.lock().await;
+        let loop_queue = matches!(*self.loop_mode.lock().await, LoopMode::LoopQueue);
+
+        let mut locked_queue = self.queue.lock().await;
+        let mut locked_shuffled_queue = self.shuffled_queue.lock().await;
+
+        let (visible, hidden) = if should_shuffle {
+            (&mut *locked_shuffled_queue, &mut *locked_queue)
+        } else {
+            (&mut *locked_queue, &mut *locked_shuffled_queue)
+        };
+
+        let range = range.start.min(visible.len())..range.end.min(visible.len());
+
+        let taken = visible.drain(range).collect::<Vec<String>>();
+
+        if loop_queue && !should_shuffle {
+            visible.extend(taken.iter().cloned());
+        } else if !loop_queue {
+            for id in &taken {
+                if let Some(hidden_index) = hidden.iter().position(|hidden_id| hidden_id == id) {
+                    hidden.remove(hidden_index);
+                }
+            }
+        }
+
+        taken
+    }
+
+    pub async fn move_queue_item(&self, from: usize, to: usize) -> Result<(), SequencerError> {
+        let moved = self.move_queue_entry(from, to).await;
+
+        self.refresh_gapless().await;
+
+        moved
+    }
+
+    async fn move_queue_entry(&self, from: usize, to: usize) -> Result<(), SequencerError> {
+        let main_length = self.get_main_queue().await.len();
+
+        if from >= main_length && to >= main_length {
+            let mut locked_requests = if *self.shuffle.lock().await {
+                self.shuffled_request_queue.lock().await
+            } else {
+                self.request_queue.lock().await
+            };
+
+            return move_item(&mut locked_requests, from - main_length, to - main_length);
+        }
+
+        if from >= main_length || to >= main_length {
+            return Err(SequencerError::NotInQueue);
+        }
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            let mut locked_order = self.play_order.lock().await;
+            let upcoming = self.cursor.lock().await.map_or(0, |cursor| cursor + 1);
+
+            return move_item(&mut locked_order[upcoming..], from, to);
+        }
+
+        let mut locked_queue = if *self.shuffle.lock().await {
+            self.shuffled_queue.lock().await
+        } else {
+            self.queue.lock().await
+        };
+
+        move_item(&mut locked_queue, from, to)
+    }
+
+    pub async fn set_loop_mode(&self, mode: LoopMode) {
+        if !matches!(mode, LoopMode::LoopRecording) {
+            *self.loop_cache.lock().await = None;
+        }
+
+        *self.loop_mode.lock().await = mode;
+
+        self.refresh_gapless().await;
+    }
+
+    pub async fn set_shuffle(&self, enable: bool, seed: Option<u64>) -> Option<u64> {
+        let seed = self.apply_shuffle(enable, seed).await;
+
+        self.refresh_gapless().await;
+
+        seed
+    }
+
+    async fn apply_shuffle(&self, enable: bool, seed: Option<u64>) -> Option<u64> {
+        let seed = enable.then(|| seed.unwrap_or_else(rand::random));
+
+        *self.shuffle_seed.lock().await = seed;
+
+        let mut locked_rng = self.shuffle_rng.lock().await;
+
+        if let Some(seed) = seed {
+            *locked_rng = StdRng::seed_from_u64(seed);
+        }
+
+        if enable {
+            let mut shuffled_requests = self.request_queue.lock().await.to_vec();
+
+            shuffled_requests.shuffle(&mut *locked_rng);
+
+            *self.shuffled_request_queue.lock().await = shuffled_requests;
+        } else {
+            self.shuffled_request_queue.lock().await.clear();
+        }
+
+        if *self.queue_model.lock().await == QueueModel::Cursor {
+            let mut locked_order = self.play_order.lock().await;
+            let mut locked_cursor = self.cursor.lock().await;
+
+            if enable {
+                let upcoming = locked_cursor
+                    .map_or(0, |cursor| cursor + 1)
+                    .min(locked_order.len());
+
+                locked_order[upcoming..].shuffle(&mut *locked_rng);
+            } else {
+                let current = locked_cursor.map(|cursor| locked_order[cursor]);
+
+                locked_order.sort_unstable();
+
+                *locked_cursor = current;
+            }
+
+            *self.shuffle.lock().await = enable;
+
+            return seed;
+        }
+
+        if enable {
+            *self.shuffled_queue.lock().await =
+                shuffle_queue(self.queue.lock().await.to_vec(), &mut *locked_rng);
+        } else {
+            self.shuffled_queue.lock().await.clear();
+        }
+
+        *self.shuffle.lock().await = enable;
+
+        seed
+    }
+
+    async fn resume_shuffle(&self, seed: u64) {
+        *self.shuffle_seed.lock().await = Some(seed);
+        *self.shuffle_rng.lock().await = StdRng::seed_from_u64(seed);
+
+        *self.shuffled_request_queue.lock().await = self.request_queue.lock().await.to_vec();
+        *self.shuffled_queue.lock().await = self.queue.lock().await.to_vec();
+
+        *self.shuffle.lock().await = true;
+    }
+
+    pub async fn set_volume(&self, volume: f32) -> f32 {
+        *self.volume.lock().await = volume;
+
+        self.apply_volume().await;
+
+        self.get_volume().await
+    }
+
+    pub async fn mute(&self, muted: bool) -> f32 {
+        *self.muted.lock().await = muted;
+
+        self.apply_volume().await;
+
+        self.get_volume().await
+    }
+
+    pub async fn get_volume(&self) -> f32 {
+        if *self.muted.lock().await {
+            return 0.0;
+        }
+
+        *self.volume.lock().await
+    }
+
+    pub async fn set_speed(&self, speed: f32) -> f32 {
+        let speed = speed.clamp(MIN_SPEED, MAX_SPEED);
+
+        self.sink.lock().await.set_speed(speed);
+
+        speed
+    }
+
+    pub async fn speed(&self) -> f32 {
+        self.sink.lock().await.speed()
+    }
+
+    async fn apply_volume(&self) {
+        let volume = self.get_volume().await
+            * self.duck.lock().await.gain
+            * self.pause_ramp.lock().await.gain;
+
+        self.sink.lock().await.set_volume(volume);
+    }
+
+    pub async fn play_cue(&self, data: Vec<u8>, duck_db: f32) -> Result<(), SequencerError> {
+        let Ok(decoded_cue) = Decoder::new(Cursor::new(data)) else {
+            return Err(SequencerError::DecodingError);
+        };
+
+        self.unpark().await?;
+
+        let cue_sink = self.stream_handle.lock().await.new_unmonitored_sink()?;
+
+        cue_sink.append(decoded_cue);
+
+        let depth = 10f32.powf(-duck_db.abs() / 20.0);
+
+        {
+            let mut locked_duck = self.duck.lock().await;
+
+            locked_duck.active_cues += 1;
+            locked_duck.depth = if locked_duck.active_cues > 1 {
+                locked_duck.depth.min(depth)
+            } else {
+                depth
+            };
+        }
+
+        let sequencer = self.clone();
+
+        tokio::spawn(async move {
+            let depth = sequencer.duck.lock().await.depth;
+
+            sequencer.ramp_duck(depth, DUCK_ATTACK).await;
+
+            let _ = tokio::task::spawn_blocking(move || cue_sink.sleep_until_end()).await;
+
+            let release = {
+                let mut locked_duck = sequencer.duck.lock().await;
+
+                locked_duck.active_cues -= 1;
+                locked_duck.active_cues == 0
+            };
+
+            if release {
+                sequencer.ramp_duck(1.0, DUCK_RELEASE).await;
+            }
+        });
+
+        Ok(())
+    }
+
+    async fn ramp_duck(&self, target: f32, over: Duration) {
+        let (generation, start) = {
+            let mut locked_duck = self.duck.lock().await;
+
+            locked_duck.generation += 1;
+
+            (locked_duck.generation, locked_duck.gain)
+        };
+
+        for step in 1..=DUCK_STEPS {
+            {
+                let mut locked_duck = self.duck.lock().await;
+
+                if locked_duck.generation != generation {
+                    return;
+                }
+
+                locked_duck.gain = start + (target - start) * step as f32 / DUCK_STEPS as f32;
+            }
+
+            self.apply_volume().await;
+
+            time::sleep(over / DUCK_STEPS).await;
+        }
+    }
+}
+
+fn stop_reason(sink: &Sink) -> TransitionReason {
+    if sink.empty() {
+        TransitionReason::Finished
+    } else {
+        TransitionReason::Stop
+    }
+}
+
+fn move_item<T>(items: &mut [T], from: usize, to: usize) -> Result<(), SequencerError> {
+    if from >= items.len() || to >= items.len() {
+        return Err(SequencerError::NotInQueue);
+    }
+
+    if from < to {
+        items[from..=to].rotate_left(1);
+    } else {
+        items[to..=from].rotate_right(1);
+    }
+
+    Ok(())
+}
+
+fn shuffle_queue(queue: Vec<String>, rng: &mut impl Rng) -> Vec<String> {
+    let mut shuffle_array = queue;
+
+    shuffle_array.shuffle(rng);
+
+    shuffle_array
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn move_item_shifts_the_items_in_between() {
+        let mut items = vec!['a', 'b', 'c', 'd', 'e'];
+
+        move_item(&mut items, 1, 3).unwrap();
+        assert_eq!(items, ['a', 'c', 'd', 'b', 'e']);
+
+        move_item(&mut items, 4, 0).unwrap();
+        assert_eq!(items, ['e', 'a', 'c', 'd', 'b']);
+
+        move_item(&mut items, 2, 2).unwrap();
+        assert_eq!(items, ['e', 'a', 'c', 'd', 'b']);
+    }
+
+    #[test]
+    fn move_item_rejects_out_of_range_indices() {
+        let mut items = vec!['a', 'b'];
+
+        assert!(matches!(
+            move_item(&mut items, 2, 0),
+            Err(SequencerError::NotInQueue)
+        ));
+        assert!(matches!(
+            move_item(&mut items, 0, 2),
+            Err(SequencerError::NotInQueue)
+        ));
+        assert_eq!(items, ['a', 'b']);
+    }
+
+    fn queue(length: usize) -> Vec<String> {
+        (0..length).map(|index| index.to_string()).collect()
+    }
+
+    #[test]
+    fn shuffle_queue_handles_empty_and_single_item_queues() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        assert!(shuffle_queue(queue(0), &mut rng).is_empty());
+        assert_eq!(shuffle_queue(queue(1), &mut rng), queue(1));
+    }
+
+    #[test]
+    fn shuffle_queue_swaps_two_items_about_half_the_time() {
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let swapped = (0..1000)
+            .map(|_| shuffle_queue(queue(2), &mut rng))
+            .inspect(|shuffled| {
+                let mut sorted = shuffled.clone();
+
+                sorted.sort();
+                assert_eq!(sorted, queue(2));
+            })
+            .filter(|shuffled| *shuffled != queue(2))
+            .count();
+
+        assert!(
+            (400..=600).contains(&swapped),
+            "swapped {} of 1000",
+            swapped
+        );
+    }
+
+    #[test]
+    fn shuffle_queue_places_every_item_everywhere_evenly() {
+        const LENGTH: usize = 5;
+        const ROUNDS: usize = 20_000;
+
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut counts = [[0usize; LENGTH]; LENGTH];
+
+        for _ in 0..ROUNDS {
+            for (position, item) in shuffle_queue(queue(LENGTH), &mut rng).iter().enumerate() {
+                counts[item.parse::<usize>().unwrap()][position] += 1;
+            }
+        }
+
+        // Each item should land in each position a fifth of the time; allow
+        // 10% either way.
+        let expected = ROUNDS / LENGTH;
+
+        for (item, positions) in counts.iter().enumerate() {
+            for (position, count) in positions.iter().enumerate() {
+                assert!(
+                    count.abs_diff(expected) < expected / 10,
+                    "item {} was at position {} {} times",
+                    item,
+                    position,
+                    count
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,103 @@
+use std::{path::PathBuf, time::Duration};
+
+use tokio::{fs, sync::watch, task::JoinHandle, time};
+use tokio_util::sync::CancellationToken;
+
+use super::{database::Database, sequencer::Sequencer, PlaybackState};
+
+pub struct StateMirror {
+    path: PathBuf,
+    cancellation: CancellationToken,
+    poller: JoinHandle<()>,
+    writer: JoinHandle<()>,
+}
+
+impl StateMirror {
+    pub async fn finish(self, state: &PlaybackState) {
+        self.cancellation.cancel();
+
+        let _ = self.poller.await;
+        let _ = self.writer.await;
+
+        write_state(&self.path, state).await;
+    }
+}
+
+pub async fn resolve_state(sequencer: &Sequencer, database: &Database) -> PlaybackState {
+    let mut state = sequencer.state().await;
+
+    if let Some(recording_id) = state.recording_id.clone() {
+        if let Some(metadata) = database.get_cached_recording_metadata(recording_id).await {
+            state.title = Some(metadata.recording.title.clone());
+            state.artist = Some(metadata.artist());
+        }
+    }
+
+    state
+}
+
+pub fn start_state_mirror(
+    sequencer: Sequencer,
+    database: Database,
+    path: PathBuf,
+    interval: Duration,
+) -> StateMirror {
+    let (state_sender, mut state_receiver) = watch::channel::<Option<PlaybackState>>(None);
+
+    let cancellation = CancellationToken::new();
+    let poller_cancellation = cancellation.clone();
+
+    let poller = tokio::spawn(async move {
+        loop {
+            let state = resolve_state(&sequencer, &database).await;
+
+            state_sender.send_if_modified(|current_state| {
+                if current_state.as_ref() == Some(&state) {
+                    return false;
+                }
+
+                *current_state = Some(state);
+
+                true
+            });
+
+            tokio::select! {
+                _ = poller_cancellation.cancelled() => return,
+                _ = time::sleep(interval) => {}
+            }
+        }
+    });
+
+    let writer_path = path.clone();
+
+    let writer = tokio::spawn(async move {
+        while state_receiver.changed().await.is_ok() {
+            let Some(state) = state_receiver.borrow_and_update().clone() else {
+                continue;
+            };
+
+            write_state(&writer_path, &state).await;
+        }
+    });
+
+    StateMirror {
+        path,
+        cancellation,
+        poller,
+        writer,
+    }
+}
+
+async fn write_state(path: &PathBuf, state: &PlaybackState) {
+    let temporary_path = path.with_extension("json.tmp");
+
+    let Ok(state_bytes) = serde_json::to_vec_pretty(state) else {
+        return;
+    };
+
+    if fs::write(&temporary_path, state_bytes).await.is_err() {
+        return;
+    }
+
+    let _ = fs::rename(&temporary_path, path).await;
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::Path,
+};
+
+use super::StorageState;
+
+pub type SpaceProbe = fn(&Path) -> Option<u64>;
+
+pub struct StorageMonitor {
+    probe: SpaceProbe,
+    reserve: u64,
+    state: StorageState,
+}
+
+impl StorageMonitor {
+    pub fn new(probe: SpaceProbe) -> StorageMonitor {
+        StorageMonitor {
+            probe,
+            reserve: 0,
+            state: StorageState::Available,
+        }
+    }
+
+    pub fn set_reserve(&mut self, reserve: u64) {
+        self.reserve = reserve;
+    }
+
+    pub fn available(&self, path: &Path) -> Option<u64> {
+        (self.probe)(path)
+    }
+
+    pub fn check(&self, path: &Path, needed: u64) -> Result<(), u64> {
+        let Some(available) = self.available(path) else {
+            return Ok(());
+        };
+
+        if available < needed.saturating_add(self.reserve) {
+            return Err(available);
+        }
+
+        Ok(())
+    }
+
+    pub fn observe(&mut self, path: &Path) -> Option<StorageState> {
+        let state = match self.available(path) {
+            Some(available) if available < self.reserve => StorageState::Low {
+                available,
+                reserve: self.reserve,
+            },
+            _ => StorageState::Available,
+        };
+
+        let changed = matches!(
+            (self.state, state),
+            (StorageState::Available, StorageState::Low { .. })
+                | (StorageState::Low { .. }, StorageState::Available)
+        );
+
+        self.state = state;
+
+        changed.then_some(state)
+    }
+
+    pub fn state(&self) -> StorageState {
+        self.state
+    }
+}
+
+pub fn available_space(path: &Path) -> Option<u64> {
+    fs2::available_space(path).ok()
+}
+
+pub fn write_atomically(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let temporary_path = path.with_extension("partial");
+
+    let written = File::create(&temporary_path).and_then(|mut file| {
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+
+    if let Err(error) = written.and_then(|_| fs::rename(&temporary_path, path)) {
+        let _ = fs::remove_file(&temporary_path);
+
+        return Err(error);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+use sled::Db;
+
+use crate::StorageBackend;
+
+pub type StoreEntry = (Vec<u8>, Vec<u8>);
+
+#[derive(Debug)]
+pub enum StoreError {
+    OpenFailed,
+    BackendFailure,
+}
+
+pub trait MetadataStore: Send + Sync {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError>;
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), StoreError>;
+    fn remove(&self, key: &[u8]) -> Result<(), StoreError>;
+
+    fn range_from(&self, start: &[u8]) -> Result<Vec<StoreEntry>, StoreError>;
+
+    fn generate_id(&self) -> Result<u64, StoreError>;
+    fn flush(&self) -> Result<(), StoreError>;
+}
+
+pub fn open_store(
+    backend: &StorageBackend,
+    path: &Path,
+) -> Result<Box<dyn MetadataStore>, StoreError> {
+    match backend {
+        StorageBackend::Sled => {
+            let Ok(db) = sled::open(path) else {
+                return Err(StoreError::OpenFailed);
+            };
+
+            Ok(Box::new(SledStore { db }))
+        }
+        StorageBackend::Memory => Ok(Box::new(MemoryStore {
+            entries: Mutex::new(BTreeMap::new()),
+            next_id: AtomicU64::new(0),
+        })),
+    }
+}
+
+pub struct SledStore {
+    db: Db,
+}
+
+impl MetadataStore for SledStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        match self.db.get(key) {
+            Ok(value) => Ok(value.map(|value| value.to_vec())),
+            Err(_) => Err(StoreError::BackendFailure),
+        }
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        match self.db.insert(key, value) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(StoreError::BackendFailure),
+        }
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), StoreError> {
+        match self.db.remove(key) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(StoreError::BackendFailure),
+        }
+    }
+
+    fn range_from(&self, start: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        self.db
+            .range(start..)
+            .map(|entry| match entry {
+                Ok((key, value)) => Ok((key.to_vec(), value.to_vec())),
+                Err(_) => Err(StoreError::BackendFailure),
+            })
+            .collect()
+    }
+
+    fn generate_id(&self) -> Result<u64, StoreError> {
+        match self.db.generate_id() {
+            Ok(id) => Ok(id),
+            Err(_) => Err(StoreError::BackendFailure),
+        }
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        match self.db.flush() {
+            Ok(_) => Ok(()),
+            Err(_) => Err(StoreError::BackendFailure),
+        }
+    }
+}
+
+pub struct MemoryStore {
+    entries: Mutex<BTreeMap<Vec<u8>, Vec<u8>>>,
+    next_id: AtomicU64,
+}
+
+impl MetadataStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let Ok(entries) = self.entries.lock() else {
+            return Err(StoreError::BackendFailure);
+        };
+
+        Ok(entries.get(key).cloned())
+    }
+
+    fn insert(&self, key: &[u8], value: &[u8]) -> Result<(), StoreError> {
+        let Ok(mut entries) = self.entries.lock() else {
+            return Err(StoreError::BackendFailure);
+        };
+
+        entries.insert(key.to_vec(), value.to_vec());
+
+        Ok(())
+    }
+
+    fn remove(&self, key: &[u8]) -> Result<(), StoreError> {
+        let Ok(mut entries) = self.entries.lock() else {
+            return Err(StoreError::BackendFailure);
+        };
+
+        entries.remove(key);
+
+        Ok(())
+    }
+
+    fn range_from(&self, start: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        let Ok(entries) = self.entries.lock() else {
+            return Err(StoreError::BackendFailure);
+        };
+
+        Ok(entries
+            .range(start.to_vec()..)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn generate_id(&self) -> Result<u64, StoreError> {
+        Ok(self.next_id.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn flush(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
@@ -0,0 +1,230 @@
+use std::{
+    collections::VecDeque,
+    sync::mpsc::{self as std_mpsc, TryRecvError},
+    time::Duration,
+};
+
+use rodio::{source::SeekError, Source};
+use tokio::sync::broadcast;
+
+const CHUNK_DURATION_MS: u32 = 250;
+const FOLLOW_BUFFER_MS: u32 = 2000;
+
+const DEFAULT_SAMPLE_RATE: u32 = 44100;
+const DEFAULT_CHANNELS: u16 = 2;
+
+/// A slice of the playback output as interleaved 16-bit PCM.
+#[derive(Debug, Clone)]
+pub struct PcmChunk {
+    pub sample_rate: u32,
+    pub channels: u16,
+
+    pub samples: Vec<i16>,
+}
+
+pub struct Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    source: S,
+    sender: broadcast::Sender<PcmChunk>,
+    buffer: Vec<i16>,
+}
+
+impl<S> Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(source: S, sender: broadcast::Sender<PcmChunk>) -> Tee<S> {
+        Tee {
+            source,
+            sender,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn flush(&mut self) {
+        if self.buffer.is_empty() {
+            return;
+        }
+
+        let _ = self.sender.send(PcmChunk {
+            sample_rate: self.source.sample_rate(),
+            channels: self.source.channels(),
+            samples: std::mem::take(&mut self.buffer),
+        });
+    }
+}
+
+impl<S> Iterator for Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let sample = self.source.next();
+
+        if self.sender.receiver_count() == 0 {
+            self.buffer.clear();
+
+            return sample;
+        }
+
+        let Some(sample) = sample else {
+            self.flush();
+
+            return None;
+        };
+
+        self.buffer
+            .push((sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16);
+
+        if self.buffer.len() >= chunk_len(self.source.sample_rate(), self.source.channels()) {
+            self.flush();
+        }
+
+        Some(sample)
+    }
+}
+
+impl<S> Source for Tee<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.source.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.source.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.source.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.source.total_duration()
+    }
+
+    fn try_seek(&mut self, position: Duration) -> Result<(), SeekError> {
+        self.buffer.clear();
+
+        self.source.try_seek(position)
+    }
+}
+
+pub struct Follow {
+    receiver: std_mpsc::Receiver<PcmChunk>,
+    pending: VecDeque<PcmChunk>,
+    buffering: bool,
+
+    samples: Vec<i16>,
+    position: usize,
+
+    sample_rate: u32,
+    channels: u16,
+}
+
+impl Follow {
+    pub fn new(receiver: std_mpsc::Receiver<PcmChunk>) -> Follow {
+        Follow {
+            receiver,
+            pending: VecDeque::new(),
+            buffering: true,
+
+            samples: Vec::new(),
+            position: 0,
+
+            sample_rate: DEFAULT_SAMPLE_RATE,
+            channels: DEFAULT_CHANNELS,
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), TryRecvError> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(chunk) if chunk.sample_rate > 0 && chunk.channels > 0 => {
+                    self.pending.push_back(chunk)
+                }
+                Ok(_) => continue,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => return Err(TryRecvError::Disconnected),
+            }
+        }
+
+        if self.buffering {
+            let buffered_ms = self.pending.iter().map(chunk_duration_ms).sum::<u32>();
+
+            self.buffering = buffered_ms < FOLLOW_BUFFER_MS;
+        }
+
+        Ok(())
+    }
+}
+
+impl Iterator for Follow {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.position >= self.samples.len() {
+            if self.fill().is_err() {
+                return None;
+            }
+
+            let next_chunk = if self.buffering {
+                None
+            } else {
+                self.pending.pop_front()
+            };
+
+            if let Some(chunk) = next_chunk {
+                self.sample_rate = chunk.sample_rate;
+                self.channels = chunk.channels;
+                self.samples = chunk.samples;
+            } else {
+                self.buffering = true;
+                self.samples = vec![0; self.channels as usize];
+            }
+
+            self.position = 0;
+        }
+
+        let sample = self.samples.get(self.position).copied().unwrap_or(0);
+
+        self.position += 1;
+
+        Some(sample as f32 / i16::MAX as f32)
+    }
+}
+
+impl Source for Follow {
+    fn current_frame_len(&self) -> Option<usize> {
+        if self.position < self.samples.len() {
+            Some(self.samples.len() - self.position)
+        } else {
+            Some(self.channels as usize)
+        }
+    }
+
+    fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+fn chunk_len(sample_rate: u32, channels: u16) -> usize {
+    (sample_rate * CHUNK_DURATION_MS / 1000) as usize * channels as usize
+}
+
+fn chunk_duration_ms(chunk: &PcmChunk) -> u32 {
+    (chunk.samples.len() as u64 * 1000 / (chunk.sample_rate as u64 * chunk.channels as u64)) as u32
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    database::{unix_timestamp, Database},
+    RecordingMetadata,
+};
+
+const RECENT_WINDOW: u64 = 3 * 60 * 60;
+
+const ARTIST_WEIGHT: u32 = 8;
+const TAG_WEIGHT: u32 = 2;
+const PLAY_COUNT_CAP: u32 = 5;
+
+pub struct Suggester {
+    database: Database,
+}
+
+impl Suggester {
+    pub fn new(database: Database) -> Suggester {
+        Suggester { database }
+    }
+
+    pub async fn suggest(
+        &self,
+        seed: Option<String>,
+        exclude: &[String],
+        count: usize,
+    ) -> Vec<String> {
+        let seed_metadata = match seed {
+            Some(id) => self.database.get_cached_recording_metadata(id).await,
+            None => None,
+        };
+
+        let recent_cutoff = unix_timestamp().saturating_sub(RECENT_WINDOW);
+
+        let mut play_counts = HashMap::<String, u32>::new();
+        let mut recently_played = HashSet::<String>::new();
+
+        for event in self
+            .database
+            .get_play_history(None)
+            .await
+            .unwrap_or_default()
+        {
+            if event.timestamp >= recent_cutoff {
+                recently_played.insert(event.recording_id.clone());
+            }
+
+            *play_counts.entry(event.recording_id).or_insert(0) += 1;
+        }
+
+        let mut candidates = self
+            .database
+            .get_library()
+            .await
+            .into_iter()
+            .filter(|(id, _)| !exclude.contains(id) && !recently_played.contains(id))
+            .map(|(id, metadata)| {
+                let play_count = play_counts.get(&id).copied().unwrap_or(0);
+
+                (
+                    score(seed_metadata.as_ref(), &metadata, play_count),
+                    rand::random::<u32>(),
+                    id,
+                )
+            })
+            .collect::<Vec<(u32, u32, String)>>();
+
+        candidates.sort_by(|a, b| b.cmp(a));
+
+        candidates
+            .into_iter()
+            .take(count)
+            .map(|(_, _, id)| id)
+            .collect()
+    }
+}
+
+pub fn score(
+    seed: Option<&RecordingMetadata>,
+    candidate: &RecordingMetadata,
+    play_count: u32,
+) -> u32 {
+    let mut score = 1 + play_count.min(PLAY_COUNT_CAP);
+
+    let Some(seed) = seed else {
+        return score;
+    };
+
+    let seed_artists = seed.artist_ids();
+
+    score += candidate
+        .artist_ids()
+        .iter()
+        .filter(|artist| seed_artists.contains(*artist))
+        .count() as u32
+        * ARTIST_WEIGHT;
+
+    let seed_labels = labels(seed);
+
+    score += labels(candidate)
+        .iter()
+        .filter(|label| seed_labels.contains(*label))
+        .count() as u32
+        * TAG_WEIGHT;
+
+    score
+}
+
+fn labels(metadata: &RecordingMetadata) -> HashSet<String> {
+    let tags = metadata
+        .recording
+        .tags
+        .iter()
+        .flatten()
+        .map(|tag| tag.name.to_lowercase());
+    let genres = metadata
+        .recording
+        .genres
+        .iter()
+        .flatten()
+        .map(|genre| genre.name.to_lowercase());
+
+    tags.chain(genres).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use musicbrainz_rs::entity::{genre::Genre, tag::Tag};
+
+    use super::*;
+    use crate::player::recovery::recover_recording_metadata;
+
+    fn recording(artist: &str, tags: &[&str], genres: &[&str]) -> RecordingMetadata {
+        let mut metadata = recover_recording_metadata(
+            "recording",
+            format!(
+                r#"{{"title":"Suggested","artist_credit":[{{"name":"{0}","artist":{{"id":"{0}"}}}}]}}"#,
+                artist
+            )
+            .as_bytes(),
+        )
+        .unwrap();
+
+        metadata.recording.tags = Some(
+            tags.iter()
+                .map(|name| Tag {
+                    name: name.to_string(),
+                    count: 1,
+                })
+                .collect(),
+        );
+        metadata.recording.genres = Some(
+            genres
+                .iter()
+                .map(|name| Genre {
+                    name: name.to_string(),
+                    count: 1,
+                })
+                .collect(),
+        );
+
+        metadata
+    }
+
+    #[test]
+    fn the_same_artist_outranks_shared_labels() {
+        let seed = recording("artist-a", &["late night"], &["jazz"]);
+
+        let same_artist = score(Some(&seed), &recording("artist-a", &[], &[]), 0);
+        let shared_labels = score(
+            Some(&seed),
+            &recording("artist-b", &["Late Night"], &["jazz"]),
+            0,
+        );
+        let shared_tag = score(Some(&seed), &recording("artist-b", &["late night"], &[]), 0);
+        let unrelated = score(
+            Some(&seed),
+            &recording("artist-b", &["loud"], &["metal"]),
+            0,
+        );
+
+        assert!(
+            same_artist > shared_labels,
+            "{} {}",
+            same_artist,
+            shared_labels
+        );
+        assert!(
+            shared_labels > shared_tag,
+            "{} {}",
+            shared_labels,
+            shared_tag
+        );
+        assert!(shared_tag > unrelated, "{} {}", shared_tag, unrelated);
+        assert_eq!(unrelated, 1);
+    }
+
+    #[test]
+    fn play_counts_break_ties_up_to_a_cap() {
+        let candidate = recording("artist-b", &[], &[]);
+
+        assert_eq!(score(None, &candidate, 0), 1);
+        assert_eq!(score(None, &candidate, 3), 4);
+        assert_eq!(
+            score(None, &candidate, PLAY_COUNT_CAP + 10),
+            1 + PLAY_COUNT_CAP
+        );
+
+        // No amount of listening catches up with a shared artist.
+        let seed = recording("artist-a", &[], &[]);
+
+        assert!(
+            score(Some(&seed), &recording("artist-a", &[], &[]), 0)
+                > score(Some(&seed), &candidate, u32::MAX)
+        );
+    }
+}
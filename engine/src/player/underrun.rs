@@ -0,0 +1,304 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use rodio::Source;
+
+const STARVATION_CHECK_MS: u64 = 10;
+const STARVATION_TOLERANCE: Duration = Duration::from_millis(50);
+const UNDERRUN_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Default)]
+pub struct AudioTelemetry {
+    underruns: Arc<AtomicU64>,
+    priority_elevated: Arc<AtomicBool>,
+}
+
+impl AudioTelemetry {
+    pub fn record_underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn set_priority_elevated(&self, elevated: bool) {
+        self.priority_elevated.store(elevated, Ordering::Relaxed);
+    }
+
+    pub fn priority_elevated(&self) -> bool {
+        self.priority_elevated.load(Ordering::Relaxed)
+    }
+}
+
+pub struct StarvationDetector {
+    started: Option<Instant>,
+    consumed: u64,
+    samples_per_second: u64,
+}
+
+impl StarvationDetector {
+    pub fn new(sample_rate: u32, channels: u16) -> StarvationDetector {
+        StarvationDetector {
+            started: None,
+            consumed: 0,
+            samples_per_second: (sample_rate as u64 * channels as u64).max(1),
+        }
+    }
+
+    pub fn check_interval(&self) -> u64 {
+        (self.samples_per_second * STARVATION_CHECK_MS / 1000).max(1)
+    }
+
+    pub fn reset(&mut self, sample_rate: u32, channels: u16) {
+        *self = StarvationDetector::new(sample_rate, channels);
+    }
+
+    pub fn advance(&mut self, samples: u64, now: Instant) -> bool {
+        let Some(started) = self.started else {
+            self.started = Some(now);
+
+            return false;
+        };
+
+        self.consumed += samples;
+
+        let played = Duration::from_secs_f64(self.consumed as f64 / self.samples_per_second as f64);
+
+        if now.saturating_duration_since(started) <= played + STARVATION_TOLERANCE {
+            return false;
+        }
+
+        self.started = Some(now);
+        self.consumed = 0;
+
+        true
+    }
+}
+
+pub struct UnderrunProbe<S> {
+    inner: S,
+    telemetry: AudioTelemetry,
+    detector: StarvationDetector,
+    format: (u32, u16),
+    pending: u64,
+    promoted: bool,
+}
+
+impl<S> UnderrunProbe<S>
+where
+    S: Source<Item = f32>,
+{
+    pub fn new(inner: S, telemetry: AudioTelemetry) -> UnderrunProbe<S> {
+        let format = (inner.sample_rate(), inner.channels());
+
+        UnderrunProbe {
+            inner,
+            telemetry,
+            detector: StarvationDetector::new(format.0, format.1),
+            format,
+            pending: 0,
+            promoted: false,
+        }
+    }
+}
+
+impl<S> Iterator for UnderrunProbe<S>
+where
+    S: Source<Item = f32>,
+{
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if !self.promoted {
+            self.promoted = true;
+
+            self.telemetry
+                .set_priority_elevated(promote_callback_thread());
+        }
+
+        self.pending += 1;
+
+        if self.pending >= self.detector.check_interval() {
+            let format = (self.inner.sample_rate(), self.inner.channels());
+
+            if format != self.format {
+                self.format = format;
+                self.detector.reset(format.0, format.1);
+            }
+
+            if self.detector.advance(self.pending, Instant::now()) {
+                self.telemetry.record_underrun();
+            }
+
+            self.pending = 0;
+        }
+
+        self.inner.next()
+    }
+}
+
+impl<S> Source for UnderrunProbe<S>
+where
+    S: Source<Item = f32>,
+{
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+#[derive(Default)]
+pub struct UnderrunWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl UnderrunWindow {
+    pub fn observe(&mut self, now: Instant, underruns: u64) -> f32 {
+        self.samples.push_back((now, underruns));
+
+        while self.samples.len() > 2
+            && self.samples.get(1).is_some_and(|(sampled, _)| {
+                now.saturating_duration_since(*sampled) >= UNDERRUN_WINDOW
+            })
+        {
+            self.samples.pop_front();
+        }
+
+        let Some((oldest, oldest_underruns)) = self.samples.front() else {
+            return 0.0;
+        };
+
+        let elapsed = now.saturating_duration_since(*oldest);
+
+        if elapsed < Duration::from_secs(1) {
+            return 0.0;
+        }
+
+        underruns.saturating_sub(*oldest_underruns) as f32 * 60.0 / elapsed.as_secs_f32()
+    }
+}
+
+pub fn promote_callback_thread() -> bool {
+    cfg!(any(target_os = "windows", target_os = "macos")) || promote_current_thread()
+}
+
+#[cfg(target_os = "linux")]
+pub fn promote_current_thread() -> bool {
+    unsafe {
+        let thread = libc::pthread_self();
+
+        let mut policy = 0;
+        let mut param = libc::sched_param { sched_priority: 0 };
+
+        if libc::pthread_getschedparam(thread, &mut policy, &mut param) == 0
+            && (policy == libc::SCHED_FIFO || policy == libc::SCHED_RR)
+        {
+            return true;
+        }
+
+        let priority = libc::sched_get_priority_min(libc::SCHED_FIFO);
+
+        if priority < 0 {
+            return false;
+        }
+
+        param.sched_priority = priority;
+
+        libc::pthread_setschedparam(thread, libc::SCHED_FIFO, &param) == 0
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn promote_current_thread() -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn telemetry_counts_every_underrun() {
+        let telemetry = AudioTelemetry::default();
+        let shared = telemetry.clone();
+
+        telemetry.record_underrun();
+        shared.record_underrun();
+
+        assert_eq!(telemetry.underruns(), 2);
+        assert_eq!(shared.underruns(), 2);
+    }
+
+    #[test]
+    fn starving_counts_once_and_restarts_the_clock() {
+        let start = Instant::now();
+        let mut detector = StarvationDetector::new(1000, 2);
+
+        assert!(!detector.advance(0, start));
+
+        // A second of stereo audio consumed in a second keeps up.
+        assert!(!detector.advance(2000, start + Duration::from_secs(1)));
+
+        // Another second's worth taking two seconds falls behind.
+        assert!(detector.advance(2000, start + Duration::from_secs(3)));
+
+        // The clock restarted at the underrun, so keeping up from there is fine.
+        assert!(!detector.advance(2000, start + Duration::from_secs(4)));
+    }
+
+    #[test]
+    fn a_reset_forgets_the_consumed_samples() {
+        let start = Instant::now();
+        let mut detector = StarvationDetector::new(1000, 1);
+
+        detector.advance(0, start);
+        detector.advance(500, start + Duration::from_millis(500));
+
+        detector.reset(2000, 1);
+
+        assert_eq!(detector.check_interval(), 20);
+        assert!(!detector.advance(0, start + Duration::from_secs(10)));
+        assert!(!detector.advance(2000, start + Duration::from_secs(11)));
+    }
+
+    #[test]
+    fn the_window_reports_underruns_per_minute() {
+        let start = Instant::now();
+        let mut window = UnderrunWindow::default();
+
+        assert_eq!(window.observe(start, 0), 0.0);
+        assert_eq!(window.observe(start + Duration::from_secs(30), 3), 6.0);
+        assert_eq!(window.observe(start + Duration::from_secs(60), 6), 6.0);
+    }
+
+    #[test]
+    fn old_underruns_leave_the_window() {
+        let start = Instant::now();
+        let mut window = UnderrunWindow::default();
+
+        window.observe(start, 0);
+        window.observe(start + Duration::from_secs(10), 10);
+        window.observe(start + Duration::from_secs(70), 10);
+
+        // The burst is over a minute old, so nothing is left to report.
+        assert_eq!(window.observe(start + Duration::from_secs(130), 10), 0.0);
+    }
+}
@@ -0,0 +1,84 @@
+use std::{
+    io::Cursor,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use rodio::{Decoder, Source};
+
+use super::level::loudness_db;
+
+pub const WAVEFORM_BUCKETS: usize = 400;
+
+const PEAK_WINDOW: usize = 1024;
+
+pub fn compute_waveform(file_contents: Vec<u8>, cancelled: &AtomicBool) -> Option<(Vec<u8>, f32)> {
+    let Ok(decoder) = Decoder::new(Cursor::new(file_contents)) else {
+        return None;
+    };
+
+    let mut peaks = Vec::new();
+
+    let mut window_peak = 0.0f32;
+    let mut window_len = 0;
+
+    let mut sum_of_squares = 0.0f64;
+    let mut sample_count = 0u64;
+
+    for sample in decoder.convert_samples::<f32>() {
+        sum_of_squares += (sample as f64).powi(2);
+        sample_count += 1;
+
+        window_peak = window_peak.max(sample.abs());
+        window_len += 1;
+
+        if window_len < PEAK_WINDOW {
+            continue;
+        }
+
+        if cancelled.load(Ordering::Relaxed) {
+            return None;
+        }
+
+        peaks.push(window_peak);
+
+        window_peak = 0.0;
+        window_len = 0;
+    }
+
+    if window_len > 0 {
+        peaks.push(window_peak);
+    }
+
+    if peaks.is_empty() {
+        return None;
+    }
+
+    let buckets = (0..WAVEFORM_BUCKETS)
+        .map(|bucket| {
+            let start = bucket * peaks.len() / WAVEFORM_BUCKETS;
+            let end = ((bucket + 1) * peaks.len() / WAVEFORM_BUCKETS).max(start + 1);
+
+            peaks[start..end]
+                .iter()
+                .fold(0.0f32, |peak, sample| peak.max(*sample))
+        })
+        .collect::<Vec<f32>>();
+
+    let loudest = buckets
+        .iter()
+        .fold(0.0f32, |peak, bucket| peak.max(*bucket));
+
+    Some((
+        buckets
+            .into_iter()
+            .map(|bucket| {
+                if loudest > 0.0 {
+                    (bucket / loudest * u8::MAX as f32).round() as u8
+                } else {
+                    0
+                }
+            })
+            .collect(),
+        loudness_db(sum_of_squares, sample_count),
+    ))
+}
@@ -0,0 +1,216 @@
+use tokio::sync::broadcast;
+#[cfg(feature = "opus-stream")]
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+#[cfg(feature = "opus-stream")]
+use crate::player::opus::{spawn_encoder, EncodedAudio, OggMuxer, StreamDecoder, StreamEncoder};
+use crate::{
+    player::{stream::PcmChunk, StreamChunk},
+    NopeReason,
+};
+
+#[cfg_attr(not(feature = "opus-stream"), allow(dead_code))]
+pub enum StreamUpdate {
+    /// Pages for each listener.
+    Audio(Vec<(StreamChunk, Uuid)>),
+    /// Encoding fell behind real time, and these listeners were dropped.
+    FellBehind(Vec<Uuid>),
+}
+
+/// The serving side: one encoder for the playback output, running only while
+/// anyone listens, and an Ogg stream per listener.
+#[derive(Default)]
+pub struct StreamListeners {
+    #[cfg(feature = "opus-stream")]
+    listeners: Vec<(Uuid, OggMuxer)>,
+    #[cfg(feature = "opus-stream")]
+    encoded: Option<mpsc::Receiver<EncodedAudio>>,
+}
+
+impl StreamListeners {
+    #[cfg(feature = "opus-stream")]
+    pub fn join(
+        &mut self,
+        uuid: Uuid,
+        subscribe: impl FnOnce() -> broadcast::Receiver<PcmChunk>,
+    ) -> Result<(), NopeReason> {
+        if self.encoded.is_none() {
+            let Some(encoder) = StreamEncoder::new() else {
+                return Err(NopeReason::Unspecified);
+            };
+
+            self.encoded = Some(spawn_encoder(encoder, subscribe()));
+        }
+
+        self.listeners.retain(|(listener, _)| *listener != uuid);
+        self.listeners.push((uuid, OggMuxer::new()));
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub fn join(
+        &mut self,
+        _uuid: Uuid,
+        _subscribe: impl FnOnce() -> broadcast::Receiver<PcmChunk>,
+    ) -> Result<(), NopeReason> {
+        Err(NopeReason::Unsupported)
+    }
+
+    #[cfg(feature = "opus-stream")]
+    pub fn leave(&mut self, uuid: Uuid) {
+        self.listeners.retain(|(listener, _)| *listener != uuid);
+
+        if self.listeners.is_empty() {
+            self.encoded = None;
+        }
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub fn leave(&mut self, _uuid: Uuid) {}
+
+    /// Waits for the encoder's next output. Never returns while nobody
+    /// listens.
+    #[cfg(feature = "opus-stream")]
+    pub async fn next(&mut self) -> StreamUpdate {
+        let audio = match &mut self.encoded {
+            Some(encoded) => encoded.recv().await,
+            None => std::future::pending().await,
+        };
+
+        match audio {
+            Some(EncodedAudio::Packets(packets)) => StreamUpdate::Audio(
+                self.listeners
+                    .iter_mut()
+                    .filter(|_| !packets.is_empty())
+                    .map(|(listener, muxer)| (muxer.mux(&packets), *listener))
+                    .collect(),
+            ),
+            Some(EncodedAudio::FellBehind) | None => {
+                self.encoded = None;
+
+                StreamUpdate::FellBehind(
+                    self.listeners
+                        .drain(..)
+                        .map(|(listener, _)| listener)
+                        .collect(),
+                )
+            }
+        }
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub async fn next(&mut self) -> StreamUpdate {
+        std::future::pending().await
+    }
+}
+
+/// The following side, turning the stream back into output for the local
+/// sequencer.
+#[derive(Default)]
+pub struct Follower {
+    #[cfg(feature = "opus-stream")]
+    decoder: Option<StreamDecoder>,
+}
+
+impl Follower {
+    #[cfg(feature = "opus-stream")]
+    pub fn start(&mut self) -> Result<(), NopeReason> {
+        self.decoder = Some(StreamDecoder::new().ok_or(NopeReason::Unspecified)?);
+
+        Ok(())
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub fn start(&mut self) -> Result<(), NopeReason> {
+        Err(NopeReason::Unsupported)
+    }
+
+    #[cfg(feature = "opus-stream")]
+    pub fn stop(&mut self) {
+        self.decoder = None;
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub fn stop(&mut self) {}
+
+    #[cfg(feature = "opus-stream")]
+    pub fn decode(&mut self, chunk: &StreamChunk) -> Option<PcmChunk> {
+        Some(self.decoder.as_mut()?.decode(chunk))
+    }
+
+    #[cfg(not(feature = "opus-stream"))]
+    pub fn decode(&mut self, _chunk: &StreamChunk) -> Option<PcmChunk> {
+        None
+    }
+}
+
+#[cfg(all(test, feature = "opus-stream"))]
+mod tests {
+    use super::*;
+
+    fn silence(milliseconds: u32) -> PcmChunk {
+        PcmChunk {
+            sample_rate: 48000,
+            channels: 2,
+            samples: vec![0; 48 * milliseconds as usize * 2],
+        }
+    }
+
+    #[tokio::test]
+    async fn every_listener_gets_its_own_stream() {
+        let (output, _) = broadcast::channel(8);
+        let mut listeners = StreamListeners::default();
+
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+
+        listeners.join(first, || output.subscribe()).unwrap();
+        listeners.join(second, || output.subscribe()).unwrap();
+
+        let _ = output.send(silence(40));
+
+        let StreamUpdate::Audio(chunks) = listeners.next().await else {
+            panic!("the listeners were dropped");
+        };
+
+        assert_eq!(
+            chunks.iter().map(|(_, uuid)| *uuid).collect::<Vec<_>>(),
+            vec![first, second]
+        );
+        assert!(chunks
+            .iter()
+            .all(|(chunk, _)| chunk.ogg.starts_with(b"OggS")));
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn listeners_are_dropped_when_encoding_falls_behind() {
+        let (output, _) = broadcast::channel(2);
+        let mut listeners = StreamListeners::default();
+
+        let first = Uuid::new_v4();
+        let second = Uuid::new_v4();
+
+        listeners.join(first, || output.subscribe()).unwrap();
+        listeners.join(second, || output.subscribe()).unwrap();
+
+        // Playback keeps producing output while the encoder gets no time.
+        for _ in 0..4 {
+            let _ = output.send(silence(20));
+        }
+
+        let StreamUpdate::FellBehind(dropped) = listeners.next().await else {
+            panic!("the encoder kept up");
+        };
+
+        assert_eq!(dropped, vec![first, second]);
+        assert!(listeners.listeners.is_empty());
+        assert!(listeners.encoded.is_none());
+
+        // Anyone listening again starts a fresh encoder.
+        listeners.join(first, || output.subscribe()).unwrap();
+
+        assert!(listeners.encoded.is_some());
+    }
+}
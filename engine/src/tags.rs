@@ -0,0 +1,148 @@
+use crate::{
+    database_nope_reason, player::database::DatabaseError, route_response, CommandContext,
+    EngineCommand, EngineResponse, MetadataEditRejectReason, NopeReason,
+};
+
+pub async fn handle(command: EngineCommand, context: CommandContext<'_>) {
+    let CommandContext {
+        internal,
+        uuid,
+        database,
+        diagnostics,
+        internal_response_sender,
+        response_sender,
+        ..
+    } = context;
+
+    match command {
+        EngineCommand::TagRecording {
+            ref id,
+            ref add,
+            ref remove,
+        } => {
+            let response = match database
+                .tag_recording(id.clone(), add.clone(), remove.clone())
+                .await
+            {
+                Ok(tags) => EngineResponse::RecordingTags {
+                    id: id.clone(),
+                    tags,
+                },
+                Err(DatabaseError::InvalidTag) => EngineResponse::Nope {
+                    command,
+                    reason: NopeReason::InvalidArgument,
+                },
+                Err(DatabaseError::RecordingMetadataNotFound) => EngineResponse::Nope {
+                    command,
+                    reason: NopeReason::NotFound,
+                },
+                Err(error) => {
+                    diagnostics.record_error(command.kind(), &error);
+
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::Unspecified,
+                    }
+                }
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        EngineCommand::EditLocalMetadata {
+            ref ids,
+            ref set,
+            write_tags,
+        } => {
+            let mut edited = Vec::<String>::new();
+            let mut rejected = Vec::<(String, MetadataEditRejectReason)>::new();
+
+            for id in ids {
+                match database
+                    .edit_local_metadata(id.clone(), set, write_tags)
+                    .await
+                {
+                    Ok(()) => edited.push(id.clone()),
+                    Err(DatabaseError::NotLocalRecording) => {
+                        rejected.push((id.clone(), MetadataEditRejectReason::NotLocal))
+                    }
+                    Err(
+                        DatabaseError::TagWriteFailed
+                        | DatabaseError::RecordingFileNotFound
+                        | DatabaseError::DecodeFailed,
+                    ) => rejected.push((id.clone(), MetadataEditRejectReason::TagWriteFailed)),
+                    Err(error) => {
+                        diagnostics.record_error(command.kind(), &error);
+
+                        rejected.push((id.clone(), MetadataEditRejectReason::UnknownRecording))
+                    }
+                }
+            }
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::LocalMetadataEdited { edited, rejected },
+                uuid,
+            );
+        }
+        EngineCommand::SetSpokenWord {
+            ref id,
+            spoken_word,
+        } => {
+            let response = match database.set_spoken_word(id.clone(), spoken_word).await {
+                Ok(metadata) => EngineResponse::RecordingMetadata(metadata),
+                Err(error) => EngineResponse::Nope {
+                    reason: database_nope_reason(&error),
+                    command,
+                },
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        EngineCommand::ListTags => {
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Tags {
+                    tags: database.get_tags().await,
+                },
+                uuid,
+            );
+        }
+        EngineCommand::RecordingsByTag { ref tag } => {
+            let response = match database.get_recordings_by_tag(tag).await {
+                Ok(recordings) => EngineResponse::Recordings {
+                    recordings,
+                    relationships: Vec::new(),
+                },
+                Err(_) => EngineResponse::Nope {
+                    command,
+                    reason: NopeReason::InvalidArgument,
+                },
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        _ => unreachable!("{} is not a tag command", command.kind()),
+    }
+}
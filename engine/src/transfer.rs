@@ -0,0 +1,659 @@
+use std::{collections::HashMap, io::Read};
+
+use uuid::Uuid;
+
+use crate::{
+    bandwidth::{BandwidthLedger, TransferDirection},
+    database_nope_reason,
+    player::{
+        database::{unix_timestamp, Database, DatabaseError},
+        AudioLocation, AudioSource, RecordingMetadata,
+    },
+    route_response, ChangeFeed, ClientUsage, CommandContext, EngineCommand, EngineResponse,
+    NopeReason, LOCAL_DEVICE,
+};
+
+const TRANSFER_CHUNK_SIZE: usize = 256 * 1024;
+const GUEST_DEVICE: &str = "guest";
+const DEVICE_TRANSFER_BUDGETS_SETTING: &str = "device_transfer_budgets";
+
+/// The recordings each connection is downloading and what every device has
+/// moved against its budget.
+pub struct Transfers {
+    pub in_flight: HashMap<Uuid, Vec<String>>,
+    pub bandwidth: BandwidthLedger,
+    limit: usize,
+}
+
+impl Transfers {
+    pub async fn load(database: &Database, limit: usize) -> Self {
+        Self {
+            in_flight: HashMap::new(),
+            bandwidth: BandwidthLedger::new(
+                database
+                    .get_setting::<HashMap<String, u64>>(DEVICE_TRANSFER_BUDGETS_SETTING)
+                    .await
+                    .unwrap_or_default(),
+            ),
+            limit,
+        }
+    }
+
+    pub fn forget(&mut self, connection: Uuid) {
+        self.in_flight.remove(&connection);
+        self.bandwidth.forget_connection(&connection);
+    }
+
+    pub fn in_flight_count(&self) -> usize {
+        self.in_flight.values().map(Vec::len).sum()
+    }
+}
+
+pub async fn handle(
+    command: EngineCommand,
+    context: CommandContext<'_>,
+    transfers: &mut Transfers,
+    connection_devices: &HashMap<Uuid, String>,
+) {
+    let CommandContext {
+        internal,
+        uuid,
+        database,
+        internal_response_sender,
+        response_sender,
+        ..
+    } = context;
+
+    match command {
+        EngineCommand::RecordingFile(id) => {
+            if !internal && transfers.in_flight.get(&uuid).map_or(0, Vec::len) >= transfers.limit {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command: EngineCommand::RecordingFile(id),
+                        reason: NopeReason::Busy,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            if !internal
+                && database
+                    .get_cached_recording_metadata(id.clone())
+                    .await
+                    .is_some_and(|metadata| !is_transferable(&metadata))
+            {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command: EngineCommand::RecordingFile(id),
+                        reason: NopeReason::NotTransferable,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let Ok(mut recording_file) = database.get_recording_file(id.clone()).await else {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command: EngineCommand::RecordingFile(id),
+                        reason: NopeReason::Unspecified,
+                    },
+                    uuid,
+                );
+                return;
+            };
+
+            let mut buffer = Vec::new();
+            let _ = recording_file.read_to_end(&mut buffer);
+
+            let charged = if internal {
+                Ok(())
+            } else {
+                charge_transfer(
+                    &mut transfers.bandwidth,
+                    connection_devices,
+                    response_sender,
+                    uuid,
+                    TransferDirection::Sent,
+                    buffer.len() as u64,
+                )
+            };
+
+            if let Err(reason) = charged {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command: EngineCommand::RecordingFile(id),
+                        reason,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            if !internal {
+                transfers
+                    .in_flight
+                    .entry(uuid)
+                    .or_default()
+                    .push(id.clone());
+            }
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::RecordingFile((id, buffer)),
+                uuid,
+            );
+        }
+        EngineCommand::RecordingFileChunk { ref id, offset } => {
+            let in_flight = transfers.in_flight.get(&uuid);
+
+            if !internal
+                && !in_flight.is_some_and(|in_flight| in_flight.contains(id))
+                && in_flight.map_or(0, Vec::len) >= transfers.limit
+            {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::Busy,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            if !internal
+                && database
+                    .get_cached_recording_metadata(id.clone())
+                    .await
+                    .is_some_and(|metadata| !is_transferable(&metadata))
+            {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command,
+                        reason: NopeReason::NotTransferable,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let (total_len, data) = match database
+                .read_recording_chunk(id.clone(), offset, TRANSFER_CHUNK_SIZE)
+                .await
+            {
+                Ok(chunk) => chunk,
+                Err(error) => {
+                    route_response(
+                        internal,
+                        internal_response_sender,
+                        response_sender,
+                        EngineResponse::Nope {
+                            reason: database_nope_reason(&error),
+                            command,
+                        },
+                        uuid,
+                    );
+
+                    return;
+                }
+            };
+
+            let charged = if internal {
+                Ok(())
+            } else {
+                charge_transfer(
+                    &mut transfers.bandwidth,
+                    connection_devices,
+                    response_sender,
+                    uuid,
+                    TransferDirection::Sent,
+                    data.len() as u64,
+                )
+            };
+
+            if let Err(reason) = charged {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope { command, reason },
+                    uuid,
+                );
+
+                return;
+            }
+
+            if !internal {
+                let in_flight = transfers.in_flight.entry(uuid).or_default();
+
+                if !in_flight.contains(id) {
+                    in_flight.push(id.clone());
+                }
+            }
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::RecordingFileChunk {
+                    id: id.clone(),
+                    offset,
+                    total_len,
+                    data,
+                },
+                uuid,
+            );
+        }
+        EngineCommand::TransferAck { ref id } => {
+            if let Some(in_flight) = transfers.in_flight.get_mut(&uuid) {
+                if let Some(index) = in_flight.iter().position(|transfer| transfer == id) {
+                    in_flight.remove(index);
+                }
+            }
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Ok { command },
+                uuid,
+            );
+        }
+        EngineCommand::SendRecording((id, recording)) => {
+            let charged = if internal {
+                Ok(())
+            } else {
+                charge_transfer(
+                    &mut transfers.bandwidth,
+                    connection_devices,
+                    response_sender,
+                    uuid,
+                    TransferDirection::Received,
+                    recording.len() as u64,
+                )
+            };
+
+            if let Err(reason) = charged {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope {
+                        command: EngineCommand::SendRecording((id, recording)),
+                        reason,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let stored = database
+                .set_recording_file(
+                    id.clone(),
+                    Some(recording.clone()),
+                    AudioSource::Transfer {
+                        from_device: if internal {
+                            LOCAL_DEVICE.to_owned()
+                        } else {
+                            uuid.to_string()
+                        },
+                    },
+                )
+                .await;
+
+            let command = EngineCommand::SendRecording((id, recording));
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                match stored {
+                    Ok(()) => EngineResponse::Ok { command },
+                    Err(error) => EngineResponse::Nope {
+                        command,
+                        reason: database_nope_reason(&error),
+                    },
+                },
+                uuid,
+            );
+        }
+        EngineCommand::SendVerifiedRecording {
+            ref id,
+            ref sha256,
+            ref data,
+        } => {
+            let charged = if internal {
+                Ok(())
+            } else {
+                charge_transfer(
+                    &mut transfers.bandwidth,
+                    connection_devices,
+                    response_sender,
+                    uuid,
+                    TransferDirection::Received,
+                    data.len() as u64,
+                )
+            };
+
+            if let Err(reason) = charged {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope { command, reason },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let stored = database
+                .set_verified_recording_file(
+                    id.clone(),
+                    data.clone(),
+                    sha256,
+                    AudioSource::Transfer {
+                        from_device: if internal {
+                            LOCAL_DEVICE.to_owned()
+                        } else {
+                            uuid.to_string()
+                        },
+                    },
+                )
+                .await;
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                match stored {
+                    Ok(sha256) => EngineResponse::RecordingStored {
+                        id: id.clone(),
+                        sha256,
+                    },
+                    Err(error) => EngineResponse::Nope {
+                        reason: database_nope_reason(&error),
+                        command,
+                    },
+                },
+                uuid,
+            );
+        }
+        EngineCommand::SendRecordingChunk {
+            ref id,
+            offset,
+            total_len,
+            ref sha256,
+            ref data,
+        } => {
+            let charged = if internal {
+                Ok(())
+            } else {
+                charge_transfer(
+                    &mut transfers.bandwidth,
+                    connection_devices,
+                    response_sender,
+                    uuid,
+                    TransferDirection::Received,
+                    data.len() as u64,
+                )
+            };
+
+            if let Err(reason) = charged {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::Nope { command, reason },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let owner = transfer_owner(internal, connection_devices, uuid);
+
+            let received = match database
+                .append_transfer_chunk(&owner, id, offset, total_len, data)
+                .await
+            {
+                Ok(received) => received,
+                Err(error) => {
+                    if let DatabaseError::TransferOffsetMismatch { received } = error {
+                        route_response(
+                            internal,
+                            internal_response_sender,
+                            response_sender,
+                            EngineResponse::TransferProgress {
+                                id: id.clone(),
+                                received,
+                            },
+                            uuid,
+                        );
+                    }
+
+                    route_response(
+                        internal,
+                        internal_response_sender,
+                        response_sender,
+                        EngineResponse::Nope {
+                            reason: database_nope_reason(&error),
+                            command,
+                        },
+                        uuid,
+                    );
+
+                    return;
+                }
+            };
+
+            if received < total_len {
+                route_response(
+                    internal,
+                    internal_response_sender,
+                    response_sender,
+                    EngineResponse::TransferProgress {
+                        id: id.clone(),
+                        received,
+                    },
+                    uuid,
+                );
+
+                return;
+            }
+
+            let stored = database
+                .finish_transfer(
+                    &owner,
+                    id.clone(),
+                    sha256,
+                    AudioSource::Transfer {
+                        from_device: if internal {
+                            LOCAL_DEVICE.to_owned()
+                        } else {
+                            uuid.to_string()
+                        },
+                    },
+                )
+                .await;
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                match stored {
+                    Ok(sha256) => EngineResponse::RecordingStored {
+                        id: id.clone(),
+                        sha256,
+                    },
+                    Err(error) => EngineResponse::Nope {
+                        reason: database_nope_reason(&error),
+                        command,
+                    },
+                },
+                uuid,
+            );
+        }
+        EngineCommand::ListClients => {
+            let now = unix_timestamp();
+
+            let guests = transfers
+                .bandwidth
+                .connections()
+                .filter(|connection| !connection_devices.contains_key(connection))
+                .map(|connection| (connection, GUEST_DEVICE.to_owned()))
+                .collect::<Vec<(&Uuid, String)>>();
+
+            let mut clients = connection_devices
+                .iter()
+                .map(|(connection, device)| (connection, device.clone()))
+                .chain(guests)
+                .map(|(connection, device)| {
+                    let totals = transfers.bandwidth.connection_totals(connection);
+
+                    ClientUsage {
+                        connection: *connection,
+                        bytes_sent: totals.bytes_sent,
+                        bytes_received: totals.bytes_received,
+                        budget: transfers.bandwidth.budget(&device),
+                        used_today: transfers.bandwidth.used_today(&device, now),
+                        device,
+                    }
+                })
+                .collect::<Vec<ClientUsage>>();
+
+            clients.sort_by(|a, b| {
+                a.device
+                    .cmp(&b.device)
+                    .then_with(|| a.connection.cmp(&b.connection))
+            });
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Clients { clients },
+                uuid,
+            );
+        }
+        EngineCommand::SetDeviceTransferBudget {
+            ref device_id,
+            bytes_per_day,
+        } => {
+            transfers
+                .bandwidth
+                .set_budget(device_id.clone(), bytes_per_day);
+
+            database
+                .set_setting(
+                    DEVICE_TRANSFER_BUDGETS_SETTING,
+                    transfers.bandwidth.budgets(),
+                )
+                .await;
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Ok { command },
+                uuid,
+            );
+        }
+        _ => unreachable!("{} is not a transfer command", command.kind()),
+    }
+}
+
+fn transfer_owner(
+    internal: bool,
+    connection_devices: &HashMap<Uuid, String>,
+    connection: Uuid,
+) -> String {
+    if internal {
+        return LOCAL_DEVICE.to_owned();
+    }
+
+    connection_devices
+        .get(&connection)
+        .cloned()
+        .unwrap_or_else(|| connection.to_string())
+}
+
+fn charge_transfer(
+    bandwidth: &mut BandwidthLedger,
+    connection_devices: &HashMap<Uuid, String>,
+    response_sender: &ChangeFeed,
+    connection: Uuid,
+    direction: TransferDirection,
+    bytes: u64,
+) -> Result<(), NopeReason> {
+    // Only paired devices have an identity the engine can trust, so every
+    // unpaired connection draws from one shared guest budget.
+    let device = connection_devices
+        .get(&connection)
+        .cloned()
+        .unwrap_or_else(|| GUEST_DEVICE.to_owned());
+
+    let (used, budget) =
+        match bandwidth.charge(connection, &device, direction, bytes, unix_timestamp()) {
+            Ok(Some(warning)) => warning,
+            Ok(None) => return Ok(()),
+            Err(budget) => return Err(NopeReason::BudgetExceeded { budget }),
+        };
+
+    let listeners = if connection_devices.contains_key(&connection) {
+        connection_devices
+            .iter()
+            .filter(|(_, listener_device)| **listener_device == device)
+            .map(|(listener, _)| *listener)
+            .collect::<Vec<Uuid>>()
+    } else {
+        vec![connection]
+    };
+
+    for listener in listeners {
+        let _ = response_sender.send((
+            EngineResponse::TransferBudgetWarning {
+                device_id: device.clone(),
+                used,
+                budget,
+            },
+            listener,
+        ));
+    }
+
+    Ok(())
+}
+
+pub fn is_transferable(metadata: &RecordingMetadata) -> bool {
+    match metadata.audio_location {
+        AudioLocation::Managed => true,
+        AudioLocation::External { transferable, .. } => transferable,
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{database_nope_reason, route_response, CommandContext, EngineCommand, EngineResponse};
+
+pub async fn handle(command: EngineCommand, context: CommandContext<'_>) {
+    let CommandContext {
+        internal,
+        uuid,
+        database,
+        internal_response_sender,
+        response_sender,
+        ..
+    } = context;
+
+    match command {
+        EngineCommand::DeleteRecording { ref id } => {
+            let response = match database.trash_recording(id.clone()).await {
+                Ok(_) => EngineResponse::Ok { command },
+                Err(error) => EngineResponse::Nope {
+                    reason: database_nope_reason(&error),
+                    command,
+                },
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        EngineCommand::DeletePlaylist { ref id } => {
+            let response = match database.trash_playlist(id.clone()).await {
+                Ok(_) => EngineResponse::Ok { command },
+                Err(error) => EngineResponse::Nope {
+                    reason: database_nope_reason(&error),
+                    command,
+                },
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        EngineCommand::ListTrash => {
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::Trash {
+                    entries: database.get_trash().await,
+                },
+                uuid,
+            );
+        }
+        EngineCommand::RestoreFromTrash { ref id } => {
+            let response = match database.restore_from_trash(id.clone()).await {
+                Ok(_) => EngineResponse::Ok { command },
+                Err(error) => EngineResponse::Nope {
+                    reason: database_nope_reason(&error),
+                    command,
+                },
+            };
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                response,
+                uuid,
+            );
+        }
+        EngineCommand::EmptyTrash { older_than } => {
+            let (removed, bytes_reclaimed) = database.empty_trash(older_than).await;
+
+            route_response(
+                internal,
+                internal_response_sender,
+                response_sender,
+                EngineResponse::TrashEmptied {
+                    removed,
+                    bytes_reclaimed,
+                },
+                uuid,
+            );
+        }
+        _ => unreachable!("{} is not a trash command", command.kind()),
+    }
+}
@@ -0,0 +1,335 @@
+#[macro_use]
+mod common;
+
+use std::path::PathBuf;
+
+use common::{
+    connect, expect, musicbrainz_stub, set_permissions, start_client, start_engine, RawClient,
+    TestEngine, RECORDING_ID,
+};
+use playit_engine::{EngineCommand, EngineResponse, NopeReason, Permission};
+use uuid::Uuid;
+
+fn sample_commands() -> Vec<EngineCommand> {
+    vec![
+        EngineCommand::GetState,
+        EngineCommand::GetPermissions,
+        EngineCommand::ListTags,
+        EngineCommand::Pause,
+        EngineCommand::Stop,
+        EngineCommand::Mute { muted: false },
+        EngineCommand::ClearQueue { preview: true },
+        EngineCommand::TagRecording {
+            id: RECORDING_ID.to_owned(),
+            add: Vec::new(),
+            remove: Vec::new(),
+        },
+        EngineCommand::EmptyTrash { older_than: None },
+        EngineCommand::ListClients,
+        EngineCommand::ResetMetrics,
+        EngineCommand::StreamListen { enabled: false },
+        EngineCommand::LinkExternalFile {
+            id: RECORDING_ID.to_owned(),
+            path: PathBuf::from("/nonexistent"),
+            transferable: false,
+        },
+    ]
+}
+
+async fn allowed_commands(client: &mut TestEngine) -> Vec<String> {
+    let _ = client.commands.send(EngineCommand::AllowedCommands);
+
+    expect(&mut client.responses, |response| match response {
+        EngineResponse::AllowedCommands { commands } => Some(commands.clone()),
+        _ => None,
+    })
+    .await
+}
+
+// Commands on one connection are answered in order, so a trailing None marks
+// the point by which a denial would have arrived.
+async fn is_denied(client: &mut TestEngine, command: EngineCommand) -> bool {
+    let kind = command.kind();
+    let mut denied = false;
+
+    let _ = client.commands.send(command);
+    let _ = client.commands.send(EngineCommand::None);
+
+    expect(&mut client.responses, |response| match response {
+        EngineResponse::Nope {
+            command,
+            reason: NopeReason::PermissionDenied,
+        } if command.kind() == kind => {
+            denied = true;
+
+            None
+        }
+        EngineResponse::Ok {
+            command: EngineCommand::None,
+        } => Some(()),
+        _ => None,
+    })
+    .await;
+
+    denied
+}
+
+async fn request_permissions(client: &mut RawClient, permissions: Vec<Permission>) {
+    client
+        .send(EngineCommand::RequestPermissions { permissions })
+        .await;
+}
+
+async fn await_permissions(client: &mut RawClient) -> Vec<Permission> {
+    client
+        .expect(|response| match response {
+            EngineResponse::Permissions { permissions } => Some(permissions.clone()),
+            _ => None,
+        })
+        .await
+}
+
+fn asking_for(requested: Permission) -> impl FnMut(&EngineResponse) -> Option<Uuid> {
+    move |response| match response {
+        EngineResponse::PermissionRequest {
+            request_id,
+            requested: asked,
+            ..
+        } if *asked == [requested.clone()] => Some(*request_id),
+        _ => None,
+    }
+}
+
+// The engine's own user approves the first admin.
+async fn pair_admin(server: &mut TestEngine) -> RawClient {
+    let mut admin = connect(server).await;
+
+    request_permissions(&mut admin, vec![Permission::Admin]).await;
+
+    let request_id = expect(&mut server.responses, asking_for(Permission::Admin)).await;
+
+    let _ = server
+        .commands
+        .send(EngineCommand::ResolvePermissionRequest {
+            request_id,
+            grant: vec![Permission::Admin],
+        });
+
+    assert_eq!(await_permissions(&mut admin).await, [Permission::Admin]);
+
+    admin
+}
+
+// (device, command, permission, count)
+async fn audit_event(admin: &mut RawClient) -> (String, String, Permission, u32) {
+    admin
+        .expect(|response| match response {
+            EngineResponse::AuditEvent(entry) => Some((
+                entry.device.clone(),
+                entry.command.clone(),
+                entry.permission.clone(),
+                entry.count,
+            )),
+            _ => None,
+        })
+        .await
+}
+
+// Denials reach the guest in order, so no audit event may show up before
+// the denial it caused.
+async fn denied_without_audit(guest: &mut RawClient, command: EngineCommand) {
+    let kind = command.kind();
+
+    guest.send(command).await;
+
+    guest
+        .expect(|response| match response {
+            EngineResponse::AuditEvent(entry) => panic!("the guest saw {:?}", entry),
+            EngineResponse::Nope {
+                command,
+                reason: NopeReason::PermissionDenied,
+            } if command.kind() == kind => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+storage_backends! {
+    async fn enforcement_matches_the_advertised_commands() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("access", &musicbrainz).await;
+
+        for permissions in [
+            vec![],
+            vec![Permission::Control],
+            vec![Permission::Queue, Permission::Playlist],
+            vec![Permission::Library, Permission::Admin, Permission::Transfer],
+        ] {
+            set_permissions(&mut server, permissions.clone()).await;
+
+            let mut client = start_client(&server).await;
+
+            let allowed = allowed_commands(&mut client).await;
+
+            for command in sample_commands() {
+                let kind = command.kind().to_owned();
+
+                assert_eq!(
+                    is_denied(&mut client, command).await,
+                    !allowed.contains(&kind),
+                    "{} with {:?}",
+                    kind,
+                    permissions
+                );
+            }
+
+            client.engine.shutdown().await;
+        }
+
+        server.engine.shutdown().await;
+    }
+
+    async fn permission_requests_are_approved_and_denied_by_an_admin() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("permission-requests", &musicbrainz).await;
+
+        let mut admin = pair_admin(&mut server).await;
+        let mut approved = connect(&server).await;
+        let mut denied = connect(&server).await;
+
+        request_permissions(&mut approved, vec![Permission::Control]).await;
+
+        let request_id = admin.expect(asking_for(Permission::Control)).await;
+
+        request_permissions(&mut denied, vec![Permission::Queue]).await;
+
+        let denied_request_id = admin.expect(asking_for(Permission::Queue)).await;
+
+        assert_ne!(request_id, denied_request_id);
+
+        admin
+            .send(EngineCommand::ResolvePermissionRequest {
+                request_id: denied_request_id,
+                grant: Vec::new(),
+            })
+            .await;
+
+        assert!(await_permissions(&mut denied).await.is_empty());
+
+        admin
+            .send(EngineCommand::ResolvePermissionRequest {
+                request_id,
+                grant: vec![Permission::Control],
+            })
+            .await;
+
+        let device = approved
+            .expect(|response| match response {
+                EngineResponse::Paired { device, .. } => Some(device.clone()),
+                _ => None,
+            })
+            .await;
+
+        assert!(!device.is_empty());
+        assert_eq!(await_permissions(&mut approved).await, [Permission::Control]);
+
+        // A resolved request cannot be answered again.
+        admin
+            .send(EngineCommand::ResolvePermissionRequest {
+                request_id: denied_request_id,
+                grant: vec![Permission::Queue],
+            })
+            .await;
+
+        let reason = admin
+            .expect(|response| match response {
+                EngineResponse::Nope {
+                    command: EngineCommand::ResolvePermissionRequest { .. },
+                    reason,
+                } => Some(*reason),
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(reason, NopeReason::NotFound);
+
+        denied.send(EngineCommand::GetPermissions).await;
+
+        assert!(await_permissions(&mut denied).await.is_empty());
+
+        server.engine.shutdown().await;
+    }
+
+    async fn only_admins_see_the_audit_log() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("audit", &musicbrainz).await;
+
+        let mut admin = pair_admin(&mut server).await;
+        let mut guest = connect(&server).await;
+
+        denied_without_audit(&mut guest, EngineCommand::Stop).await;
+
+        let (device, command, permission, count) = audit_event(&mut admin).await;
+
+        assert_eq!(
+            (command.as_str(), permission, count),
+            ("Stop", Permission::Control, 1)
+        );
+
+        // A repeated denial within the window bumps the same entry.
+        denied_without_audit(&mut guest, EngineCommand::Stop).await;
+
+        assert_eq!(
+            audit_event(&mut admin).await,
+            (device.clone(), command, Permission::Control, 2)
+        );
+
+        denied_without_audit(
+            &mut guest,
+            EngineCommand::AuditLog {
+                since: None,
+                limit: None,
+            },
+        )
+        .await;
+
+        assert_eq!(
+            audit_event(&mut admin).await,
+            (device.clone(), "AuditLog".to_owned(), Permission::Admin, 1)
+        );
+
+        admin
+            .send(EngineCommand::AuditLog {
+                since: None,
+                limit: None,
+            })
+            .await;
+
+        let entries = admin
+            .expect(|response| match response {
+                EngineResponse::AuditLog { entries } => Some(entries.clone()),
+                _ => None,
+            })
+            .await;
+
+        let mut logged = entries
+            .iter()
+            .map(|entry| (entry.device.as_str(), entry.command.as_str(), entry.count))
+            .collect::<Vec<_>>();
+
+        logged.sort();
+
+        assert_eq!(
+            logged,
+            [
+                (device.as_str(), "AuditLog", 1),
+                (device.as_str(), "Stop", 2)
+            ]
+        );
+
+        server.engine.shutdown().await;
+    }
+}
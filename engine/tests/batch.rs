@@ -0,0 +1,149 @@
+#[macro_use]
+mod common;
+
+use std::time::Duration;
+
+use common::{
+    connect, expect, musicbrainz_stub, set_permissions, start_engine, store_recording, wav,
+    TestEngine, OTHER_RECORDING_ID, RECORDING_ID,
+};
+use playit_engine::{EngineCommand, EngineResponse, NopeReason, Permission};
+use tokio::sync::broadcast::error::RecvError;
+
+const ROUNDS: usize = 20;
+const QUIET: Duration = Duration::from_secs(2);
+
+// The reads in the middle give a competing command plenty of chances to slip
+// in between the two additions if batches were not atomic.
+fn queue_both() -> EngineCommand {
+    let mut commands = vec![
+        EngineCommand::ClearQueue { preview: false },
+        EngineCommand::QueueNext {
+            recordings: vec![RECORDING_ID.to_owned()],
+        },
+    ];
+
+    commands.extend((0..8).map(|_| EngineCommand::GetState));
+    commands.push(EngineCommand::QueueNext {
+        recordings: vec![OTHER_RECORDING_ID.to_owned()],
+    });
+
+    EngineCommand::Batch { commands }
+}
+
+async fn start_with_recordings(name: &str) -> TestEngine {
+    let musicbrainz = musicbrainz_stub().await;
+
+    let mut engine = start_engine(name, &musicbrainz).await;
+
+    store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+    store_recording(&mut engine, OTHER_RECORDING_ID, wav(30)).await;
+
+    engine
+}
+
+async fn current_queue(engine: &mut TestEngine) -> Vec<String> {
+    let _ = engine.commands.send(EngineCommand::Queue(None));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Queue { queue } => Some(queue.clone()),
+        _ => None,
+    })
+    .await
+}
+
+storage_backends! {
+    async fn batches_are_not_interleaved_with_other_clients() {
+        let mut engine = start_with_recordings("batch-atomic").await;
+
+        set_permissions(&mut engine, vec![Permission::Queue]).await;
+
+        let mut batching = connect(&engine).await;
+        let mut competing = connect(&engine).await;
+
+        // Each clear is sent while the batch before it is still running.
+        for _ in 0..ROUNDS {
+            batching.send(queue_both()).await;
+            competing
+                .send(EngineCommand::ClearQueue { preview: false })
+                .await;
+        }
+
+        // Every queue change is broadcast, so a competing clear that ran in
+        // the middle of a batch would show up between its two additions. A
+        // lagging receiver has missed some, so the pair around the gap is not
+        // compared.
+        let mut previous = None;
+        let mut checked = 0;
+
+        while let Ok(received) = tokio::time::timeout(QUIET, engine.responses.recv()).await {
+            let queue = match received {
+                Ok(EngineResponse::Queue { queue }) => queue,
+                Ok(_) => continue,
+                Err(RecvError::Lagged(_)) => {
+                    previous = None;
+
+                    continue;
+                }
+                Err(RecvError::Closed) => panic!("engine closed"),
+            };
+
+            if previous.as_deref() == Some(&[RECORDING_ID.to_owned()][..]) {
+                assert_eq!(queue.len(), 2, "a batch was interleaved: {:?}", queue);
+
+                checked += 1;
+            }
+
+            previous = Some(queue);
+        }
+
+        assert!(checked > 0);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn batches_are_refused_whole_when_an_item_is_not_permitted() {
+        let mut engine = start_with_recordings("batch-permissions").await;
+
+        set_permissions(&mut engine, vec![Permission::Queue]).await;
+
+        let mut client = connect(&engine).await;
+
+        let EngineCommand::Batch { mut commands } = queue_both() else {
+            unreachable!();
+        };
+
+        commands.push(EngineCommand::Pause);
+
+        client.send(EngineCommand::Batch { commands }).await;
+
+        client
+            .expect(|response| match response {
+                EngineResponse::Nope {
+                    command: EngineCommand::Batch { .. },
+                    reason,
+                } => {
+                    assert!(matches!(reason, NopeReason::PermissionDenied), "{:?}", reason);
+
+                    Some(())
+                }
+                EngineResponse::BatchResult { .. } => panic!("the batch ran"),
+                _ => None,
+            })
+            .await;
+
+        assert!(current_queue(&mut engine).await.is_empty());
+
+        set_permissions(&mut engine, vec![Permission::Queue, Permission::Control]).await;
+
+        client.send(queue_both()).await;
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Queue { queue } if queue.len() == 2 => Some(()),
+            _ => None,
+        })
+        .await;
+
+        engine.engine.shutdown().await;
+    }
+}
@@ -0,0 +1,542 @@
+#![allow(dead_code)]
+
+use std::{
+    cell::Cell,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Once,
+    },
+    time::Duration,
+};
+
+use interprocess::local_socket::{
+    tokio::{prelude::*, Stream},
+    GenericFilePath, GenericNamespaced,
+};
+use playit_engine::{
+    AudioOutputChoice, Engine, EngineCommand, EngineConfig, EngineResponse, PcmCallback,
+    Permission, ResponseFrame, StorageBackend,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::{broadcast, Mutex},
+};
+
+pub const RECORDING_ID: &str = "0f3f6a0e-1c9b-4a45-9a36-5f2ad2a7f1d0";
+pub const OTHER_RECORDING_ID: &str = "7c2d1b9e-4f60-4b8a-8e3d-2a1c5f0b6d93";
+/// Known to the MusicBrainz stub but never stored locally.
+pub const REMOTE_RECORDING_ID: &str = "e4a7c9d2-1b3f-4e5a-9c8d-6f0b2a1d3e57";
+/// The MusicBrainz stub answers 404 for this one.
+pub const UNKNOWN_RECORDING_ID: &str = "00000000-0000-4000-8000-000000000000";
+pub const WORK_ID: &str = "9d5c1e2a-7b4f-4a3e-8d6c-0e1f2a3b4c5d";
+const SAMPLE_RATE: u32 = 44100;
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(40);
+
+static ISOLATE_HOME: Once = Once::new();
+static SLED: Mutex<()> = Mutex::const_new(());
+
+tokio::task_local! {
+    static BACKEND: Backend;
+}
+
+struct Backend {
+    storage: Cell<Option<StorageBackend>>,
+    label: &'static str,
+}
+
+/// Runs each test once per storage backend, as `<test>::memory` and
+/// `<test>::sled`. Needs `#[macro_use] mod common;`.
+macro_rules! storage_backends {
+    ($(async fn $name:ident() $body:block)*) => {
+        $(
+            mod $name {
+                use super::*;
+
+                #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+                async fn memory() {
+                    $crate::common::with_backend(playit_engine::StorageBackend::Memory, async move $body)
+                        .await;
+                }
+
+                #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+                async fn sled() {
+                    $crate::common::with_backend(playit_engine::StorageBackend::Sled, async move $body)
+                        .await;
+                }
+            }
+        )*
+    };
+}
+
+/// Runs each test on sled alone, for checks that need the stores to survive
+/// [`restart_engine`].
+#[allow(unused_macros)]
+macro_rules! sled_backend {
+    ($(async fn $name:ident() $body:block)*) => {
+        $(
+            #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+            async fn $name() {
+                $crate::common::with_backend(playit_engine::StorageBackend::Sled, async move $body)
+                    .await;
+            }
+        )*
+    };
+}
+
+/// A bare IPC connection, for checks that need to see exactly what one remote
+/// connection is sent.
+pub struct RawClient {
+    connection: BufReader<Stream>,
+}
+
+pub struct TestEngine {
+    pub engine: Engine,
+    pub commands: broadcast::Sender<EngineCommand>,
+    pub responses: broadcast::Receiver<EngineResponse>,
+    pub socket: String,
+}
+
+fn isolate_home() {
+    ISOLATE_HOME.call_once(|| {
+        let home = std::env::temp_dir().join(format!("playit-test-{}", std::process::id()));
+
+        let _ = std::fs::create_dir_all(&home);
+
+        std::env::set_var("HOME", home);
+    });
+}
+
+fn home() -> PathBuf {
+    isolate_home();
+
+    PathBuf::from(std::env::var_os("HOME").unwrap())
+}
+
+// Sled locks its directories and every engine in this process shares one
+// home, so sled tests take turns and start from empty stores. Only the first
+// engine a test starts uses the backend under test; the rest stay in memory.
+pub async fn with_backend(storage: StorageBackend, test: impl Future<Output = ()>) {
+    let (_sled, label) = match storage {
+        StorageBackend::Sled => {
+            let guard = SLED.lock().await;
+
+            if let Ok(entries) = std::fs::read_dir(home().join(".playit")) {
+                for entry in entries.flatten() {
+                    if entry.path().join("conf").is_file() {
+                        let _ = std::fs::remove_dir_all(entry.path());
+                    }
+                }
+            }
+
+            (Some(guard), "sled")
+        }
+        StorageBackend::Memory => (None, "memory"),
+    };
+
+    let backend = Backend {
+        storage: Cell::new(Some(storage)),
+        label,
+    };
+
+    BACKEND.scope(backend, test).await;
+}
+
+// Every stub recording is a performance of the same work, which MusicBrainz
+// also knows one more recording of.
+fn recording_body(id: &str) -> String {
+    format!(
+        r#"{{"id":"{}","title":"Hand-off","video":false,"length":2000,"relations":[{{"type":"performance","type-id":"a3005666-a872-32c3-ad06-98af558e99b0","direction":"forward","target-type":"work","attributes":[],"work":{{"id":"{}","title":"Hand-off"}}}}]}}"#,
+        id, WORK_ID
+    )
+}
+
+fn work_body() -> String {
+    let relations = [RECORDING_ID, OTHER_RECORDING_ID, REMOTE_RECORDING_ID]
+        .map(|id| {
+            format!(
+                r#"{{"type":"performance","attributes":["cover"],"recording":{{"id":"{}"}}}}"#,
+                id
+            )
+        })
+        .join(",");
+
+    format!(
+        r#"{{"id":"{}","title":"Hand-off","relations":[{}]}}"#,
+        WORK_ID, relations
+    )
+}
+
+pub async fn musicbrainz_stub() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+
+    tokio::spawn(async move {
+        while let Ok((mut connection, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = Vec::new();
+                let mut buffer = [0; 1024];
+
+                loop {
+                    let Ok(read) = connection.read(&mut buffer).await else {
+                        return;
+                    };
+
+                    if read == 0 {
+                        return;
+                    }
+
+                    request.extend_from_slice(&buffer[..read]);
+
+                    if !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                        continue;
+                    }
+
+                    let head = String::from_utf8_lossy(&request).into_owned();
+
+                    request.clear();
+
+                    let id = head
+                        .split_once("/recording/")
+                        .and_then(|(_, path)| path.get(..36))
+                        .unwrap_or(RECORDING_ID);
+
+                    let (status, body) = if head.contains("/work/") {
+                        ("200 OK", work_body())
+                    } else if id == UNKNOWN_RECORDING_ID {
+                        ("404 Not Found", r#"{"error":"Not Found"}"#.to_owned())
+                    } else {
+                        ("200 OK", recording_body(id))
+                    };
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+
+                    if connection.write_all(response.as_bytes()).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+
+    format!("http://{}", address)
+}
+
+fn tone(index: u32) -> i16 {
+    let phase = index as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32;
+
+    (phase.sin() * i16::MAX as f32 * 0.25) as i16
+}
+
+pub fn wav(seconds: u32) -> Vec<u8> {
+    pcm_wav((0..SAMPLE_RATE * seconds).map(tone))
+}
+
+/// A second of tone that no other call returns, so removing its stored file
+/// cannot disturb other tests sharing the audio directory.
+pub fn private_wav() -> Vec<u8> {
+    static NEXT_OFFSET: AtomicU32 = AtomicU32::new(1);
+
+    let offset = NEXT_OFFSET.fetch_add(1, Ordering::Relaxed);
+
+    pcm_wav((offset..offset + SAMPLE_RATE).map(tone))
+}
+
+/// Where a managed recording's audio is stored.
+pub fn audio_file(hash: &str) -> PathBuf {
+    home().join(".playit").join("audio").join(hash)
+}
+
+/// Silence for `silent` seconds, then a tone for `loud` seconds.
+pub fn burst_wav(silent: u32, loud: u32) -> Vec<u8> {
+    pcm_wav((0..SAMPLE_RATE * (silent + loud)).map(|index| {
+        if index < SAMPLE_RATE * silent {
+            0
+        } else {
+            tone(index)
+        }
+    }))
+}
+
+fn pcm_wav(samples: impl Iterator<Item = i16>) -> Vec<u8> {
+    let samples = samples.flat_map(i16::to_le_bytes).collect::<Vec<u8>>();
+
+    let mut wav = Vec::new();
+
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    wav.extend_from_slice(&samples);
+
+    wav
+}
+
+/// A `path:` socket address in the test home, unique to the backend under
+/// test.
+pub fn socket_path(name: &str) -> String {
+    let label = BACKEND
+        .try_with(|backend| backend.label)
+        .unwrap_or("memory");
+
+    format!(
+        "path:{}",
+        home().join(format!("{}-{}.sock", name, label)).display()
+    )
+}
+
+pub async fn start_engine(name: &str, musicbrainz: &str) -> TestEngine {
+    start_engine_with(name, musicbrainz, EngineConfig::default()).await
+}
+
+/// Like [`start_engine`], taking everything but storage, socket, MusicBrainz
+/// and audio output from `config`.
+pub async fn start_engine_with(name: &str, musicbrainz: &str, config: EngineConfig) -> TestEngine {
+    isolate_home();
+
+    let (storage, label) = BACKEND
+        .try_with(|backend| (backend.storage.take(), backend.label))
+        .unwrap_or((None, "memory"));
+
+    launch(
+        name,
+        label,
+        musicbrainz,
+        EngineConfig {
+            storage: storage.unwrap_or(StorageBackend::Memory),
+            audio_output: AudioOutputChoice::custom(PcmCallback::new(
+                SAMPLE_RATE,
+                2,
+                |_: &[f32]| {},
+            )),
+            ..config
+        },
+    )
+    .await
+}
+
+/// Shuts `engine` down and starts another on its sled stores, handing every
+/// sample the new engine plays to `output`. The old engine's tasks keep its
+/// stores locked, so the new one opens copies moved into their place.
+pub async fn restart_engine(
+    engine: TestEngine,
+    name: &str,
+    musicbrainz: &str,
+    output: impl FnMut(&[f32]) + Send + 'static,
+) -> TestEngine {
+    engine.engine.shutdown().await;
+
+    let root = home().join(".playit");
+
+    for entry in std::fs::read_dir(&root).unwrap().flatten() {
+        let path = entry.path();
+
+        if path.extension().is_some() || !path.join("conf").is_file() {
+            continue;
+        }
+
+        let copy = path.with_extension("copy");
+        let stale = path.with_extension("stale");
+
+        copy_dir(&path, &copy);
+        let _ = std::fs::remove_dir_all(&stale);
+        std::fs::rename(&path, &stale).unwrap();
+        std::fs::rename(&copy, &path).unwrap();
+    }
+
+    launch(
+        name,
+        "restarted",
+        musicbrainz,
+        EngineConfig {
+            storage: StorageBackend::Sled,
+            audio_output: AudioOutputChoice::custom(PcmCallback::new(SAMPLE_RATE, 2, output)),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    let _ = std::fs::remove_dir_all(to);
+
+    std::fs::create_dir_all(to).unwrap();
+
+    for entry in std::fs::read_dir(from).unwrap().flatten() {
+        if entry.path().is_dir() {
+            copy_dir(&entry.path(), &to.join(entry.file_name()));
+        } else {
+            std::fs::copy(entry.path(), to.join(entry.file_name())).unwrap();
+        }
+    }
+}
+
+async fn launch(name: &str, label: &str, musicbrainz: &str, config: EngineConfig) -> TestEngine {
+    let socket = config
+        .socket
+        .clone()
+        .unwrap_or_else(|| format!("playit-test-{}-{}-{}.sock", name, label, std::process::id()));
+
+    let config = EngineConfig {
+        socket: Some(socket.clone()),
+        musicbrainz_base_url: Some(musicbrainz.to_owned()),
+        ..config
+    };
+
+    let Ok((engine, commands, responses)) = Engine::create_with_config(config).await else {
+        panic!("engine {} failed to start", name);
+    };
+
+    TestEngine {
+        engine,
+        commands,
+        responses,
+        socket,
+    }
+}
+
+pub async fn expect<T>(
+    responses: &mut broadcast::Receiver<EngineResponse>,
+    mut matches: impl FnMut(&EngineResponse) -> Option<T>,
+) -> T {
+    let found = tokio::time::timeout(RESPONSE_TIMEOUT, async {
+        loop {
+            match responses.recv().await {
+                Ok(response) => {
+                    if let Some(found) = matches(&response) {
+                        return found;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => panic!("engine closed"),
+            }
+        }
+    });
+
+    found.await.expect("timed out waiting for a response")
+}
+
+pub async fn store_recording(engine: &mut TestEngine, id: &str, audio: Vec<u8>) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::SendRecording((id.to_owned(), audio)));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Ok {
+            command: EngineCommand::SendRecording(_),
+        } => Some(()),
+        EngineResponse::Nope {
+            command: EngineCommand::SendRecording(_),
+            reason,
+        } => panic!("storing the recording failed: {:?}", reason),
+        _ => None,
+    })
+    .await;
+}
+
+pub async fn play(engine: &mut TestEngine, id: &str) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::Play(Some(id.to_owned())));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::NowPlaying { id: playing } if playing == id => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+pub async fn set_permissions(server: &mut TestEngine, permissions: Vec<Permission>) {
+    let _ = server
+        .commands
+        .send(EngineCommand::SetPermissions(permissions));
+
+    expect(&mut server.responses, |response| {
+        matches!(response, EngineResponse::Permissions { .. }).then_some(())
+    })
+    .await;
+}
+
+pub async fn start_client(server: &TestEngine) -> TestEngine {
+    let config = EngineConfig {
+        storage: StorageBackend::Memory,
+        socket: Some(server.socket.clone()),
+        ..Default::default()
+    };
+
+    let Ok((engine, commands, responses)) = Engine::create_with_config(config).await else {
+        panic!("client of {} failed to start", server.socket);
+    };
+
+    TestEngine {
+        engine,
+        commands,
+        responses,
+        socket: server.socket.clone(),
+    }
+}
+
+pub async fn connect(server: &TestEngine) -> RawClient {
+    let name = match server.socket.strip_prefix("path:") {
+        Some(path) => path.to_fs_name::<GenericFilePath>().unwrap(),
+        None => server
+            .socket
+            .as_str()
+            .to_ns_name::<GenericNamespaced>()
+            .unwrap(),
+    };
+
+    RawClient {
+        connection: BufReader::new(Stream::connect(name).await.unwrap()),
+    }
+}
+
+impl RawClient {
+    pub async fn send(&mut self, command: EngineCommand) {
+        let mut message = serde_json::to_vec(&command).unwrap();
+
+        message.push(b'\n');
+
+        let connection = self.connection.get_mut();
+
+        connection.write_all(&message).await.unwrap();
+        connection.flush().await.unwrap();
+    }
+
+    pub async fn expect<T>(&mut self, mut matches: impl FnMut(&EngineResponse) -> Option<T>) -> T {
+        let found = tokio::time::timeout(RESPONSE_TIMEOUT, async {
+            let mut line = String::new();
+
+            loop {
+                line.clear();
+
+                if self.connection.read_line(&mut line).await.unwrap() == 0 {
+                    panic!("connection closed");
+                }
+
+                let frame = serde_json::from_str::<ResponseFrame>(&line)
+                    .unwrap_or_else(|error| panic!("unparseable line {:?}: {}", line, error));
+
+                if let Some(found) = matches(&frame.into_response()) {
+                    return found;
+                }
+            }
+        });
+
+        found.await.expect("timed out waiting for a response")
+    }
+}
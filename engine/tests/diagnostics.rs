@@ -0,0 +1,57 @@
+#[macro_use]
+mod common;
+
+use common::{expect, musicbrainz_stub, start_engine};
+use interprocess::local_socket::{
+    tokio::{prelude::*, Stream},
+    GenericNamespaced,
+};
+use playit_engine::{EngineCommand, EngineResponse};
+use tokio::io::AsyncWriteExt;
+
+storage_backends! {
+    async fn malformed_ipc_messages_reach_the_diagnostic_bundle() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("diagnostics", &musicbrainz).await;
+
+        let name = engine
+            .socket
+            .clone()
+            .to_ns_name::<GenericNamespaced>()
+            .unwrap();
+        let mut connection = Stream::connect(name).await.unwrap();
+
+        connection
+            .write_all(b"{\"type\":\"NotACommand\"}\n")
+            .await
+            .unwrap();
+        connection.flush().await.unwrap();
+
+        let errors = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            loop {
+                let _ = engine.commands.send(EngineCommand::DiagnosticBundle);
+
+                let errors = expect(&mut engine.responses, |response| match response {
+                    EngineResponse::Diagnostics { last_errors, .. } => Some(last_errors.clone()),
+                    _ => None,
+                })
+                .await;
+
+                if errors.iter().any(|(_, error)| error.starts_with("IPCRead")) {
+                    return errors;
+                }
+
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the malformed message was never recorded");
+
+        assert!(errors
+            .iter()
+            .any(|(_, error)| error.contains("NotACommand")));
+
+        engine.engine.shutdown().await;
+    }
+}
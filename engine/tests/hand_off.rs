@@ -0,0 +1,223 @@
+#[macro_use]
+mod common;
+
+use std::time::Duration;
+
+use common::{expect, musicbrainz_stub, start_engine, wav, TestEngine, RECORDING_ID};
+use playit_engine::{EngineCommand, EngineConnectionStatus, EngineResponse, Permission};
+use serde_json::json;
+
+async fn store_recording(engine: &mut TestEngine, audio: Vec<u8>) {
+    let _ = engine.commands.send(EngineCommand::SendRecording((
+        RECORDING_ID.to_owned(),
+        audio,
+    )));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Ok {
+            command: EngineCommand::SendRecording(_),
+        } => Some(()),
+        EngineResponse::Nope {
+            command: EngineCommand::SendRecording(_),
+            reason,
+        } => panic!("storing the recording failed: {:?}", reason),
+        _ => None,
+    })
+    .await;
+}
+
+async fn accept_transfers(engine: &mut TestEngine) {
+    let _ = engine.commands.send(EngineCommand::SetPermissions(vec![
+        Permission::Control,
+        Permission::Transfer,
+    ]));
+
+    expect(&mut engine.responses, |response| {
+        matches!(response, EngineResponse::Permissions { .. }).then_some(())
+    })
+    .await;
+}
+
+async fn start_playing(engine: &mut TestEngine) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::Play(Some(RECORDING_ID.to_owned())));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::NowPlaying { id } if id == RECORDING_ID => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+async fn hand_off(source: &mut TestEngine, target: &mut TestEngine) {
+    let _ = source.commands.send(EngineCommand::HandOff {
+        target: target.socket.clone(),
+    });
+
+    expect(&mut source.responses, |response| match response {
+        EngineResponse::HandOffComplete { .. } => Some(()),
+        EngineResponse::Nope {
+            command: EngineCommand::HandOff { .. },
+            reason,
+        } => panic!("hand-off failed: {:?}", reason),
+        _ => None,
+    })
+    .await;
+
+    let _ = target.commands.send(EngineCommand::GetState);
+
+    let (recording_id, paused) = expect(&mut target.responses, |response| match response {
+        EngineResponse::State(state) => Some((state.recording_id.clone(), state.paused)),
+        _ => None,
+    })
+    .await;
+
+    assert_eq!(recording_id.as_deref(), Some(RECORDING_ID));
+    assert!(!paused);
+
+    let _ = source.commands.send(EngineCommand::GetState);
+
+    let paused = expect(&mut source.responses, |response| match response {
+        EngineResponse::State(state) => Some(state.paused),
+        _ => None,
+    })
+    .await;
+
+    assert!(paused);
+}
+
+async fn adopt(engine: &mut TestEngine, command: EngineCommand) {
+    let _ = engine.commands.send(command);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Ok {
+            command: EngineCommand::AdoptSession(_),
+        } => Some(()),
+        EngineResponse::Nope {
+            command: EngineCommand::AdoptSession(_),
+            reason,
+        } => panic!("adopting the session failed: {:?}", reason),
+        _ => None,
+    })
+    .await;
+}
+
+async fn position(engine: &mut TestEngine) -> Duration {
+    let _ = engine.commands.send(EngineCommand::GetState);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::State(state) => Some(state.position),
+        _ => None,
+    })
+    .await
+}
+
+storage_backends! {
+    async fn adopting_the_same_snapshot_again_does_not_rewind() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("adopt-twice", &musicbrainz).await;
+
+        store_recording(&mut engine, wav(5)).await;
+
+        let snapshot = serde_json::from_value::<EngineCommand>(json!({
+            "type": "AdoptSession",
+            "recording_id": RECORDING_ID,
+            "position": { "secs": 1, "nanos": 0 },
+            "queue": [],
+            "loop_mode": "None",
+            "shuffle": false,
+        }))
+        .unwrap();
+
+        adopt(&mut engine, snapshot.clone()).await;
+
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        adopt(&mut engine, snapshot).await;
+
+        let position = position(&mut engine).await;
+
+        assert!(position > Duration::from_millis(1300), "{:?}", position);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn hand_off_to_a_target_with_the_audio() {
+        let musicbrainz = musicbrainz_stub().await;
+        let audio = wav(2);
+
+        let mut source = start_engine("source", &musicbrainz).await;
+        let mut target = start_engine("target", &musicbrainz).await;
+
+        store_recording(&mut source, audio.clone()).await;
+        store_recording(&mut target, audio).await;
+        accept_transfers(&mut target).await;
+
+        start_playing(&mut source).await;
+        hand_off(&mut source, &mut target).await;
+
+        source.engine.shutdown().await;
+        target.engine.shutdown().await;
+    }
+
+    async fn hand_off_transfers_audio_the_target_is_missing() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut source = start_engine("source-transfer", &musicbrainz).await;
+        let mut target = start_engine("target-transfer", &musicbrainz).await;
+
+        store_recording(&mut source, wav(3)).await;
+        accept_transfers(&mut target).await;
+
+        start_playing(&mut source).await;
+        hand_off(&mut source, &mut target).await;
+
+        source.engine.shutdown().await;
+        target.engine.shutdown().await;
+    }
+
+    async fn a_second_engine_on_the_same_socket_becomes_a_client() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("double-start", &musicbrainz).await;
+        let mut client = start_engine("double-start", &musicbrainz).await;
+
+        assert_eq!(
+            server.engine.connection_status(),
+            EngineConnectionStatus::ConnectedLocal
+        );
+        assert_eq!(
+            client.engine.connection_status(),
+            EngineConnectionStatus::ConnectedLocalClient
+        );
+
+        store_recording(&mut server, wav(2)).await;
+        accept_transfers(&mut server).await;
+
+        let _ = client.commands.send(EngineCommand::PlayFromStart {
+            id: RECORDING_ID.to_owned(),
+        });
+
+        expect(&mut server.responses, |response| match response {
+            EngineResponse::NowPlaying { id } if id == RECORDING_ID => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let _ = client.commands.send(EngineCommand::GetState);
+
+        let (recording_id, paused) = expect(&mut client.responses, |response| match response {
+            EngineResponse::State(state) => Some((state.recording_id.clone(), state.paused)),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(recording_id.as_deref(), Some(RECORDING_ID));
+        assert!(!paused);
+
+        client.engine.shutdown().await;
+        server.engine.shutdown().await;
+    }
+}
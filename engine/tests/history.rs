@@ -0,0 +1,83 @@
+#[macro_use]
+mod common;
+
+use std::time::Duration;
+
+use common::{
+    expect, musicbrainz_stub, play, start_engine, store_recording, wav, TestEngine,
+    OTHER_RECORDING_ID, RECORDING_ID,
+};
+use playit_engine::{EngineCommand, EngineResponse, ExportFormat};
+
+async fn export_history(engine: &mut TestEngine) -> Vec<serde_json::Value> {
+    let _ = engine.commands.send(EngineCommand::ExportHistory {
+        since: None,
+        format: ExportFormat::Json,
+    });
+
+    let mut export = Vec::new();
+
+    loop {
+        let (total_len, data) = expect(&mut engine.responses, |response| match response {
+            EngineResponse::HistoryExport {
+                total_len, data, ..
+            } => Some((*total_len, data.clone())),
+            _ => None,
+        })
+        .await;
+
+        export.extend(data);
+
+        if export.len() >= total_len {
+            break;
+        }
+    }
+
+    serde_json::from_slice(&export).unwrap()
+}
+
+storage_backends! {
+    async fn history_records_why_each_track_ended() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("history", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(10)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(10)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        play(&mut engine, OTHER_RECORDING_ID).await;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let _ = engine.commands.send(EngineCommand::Stop);
+
+        expect(&mut engine.responses, |response| {
+            matches!(response, EngineResponse::NowPaused).then_some(())
+        })
+        .await;
+
+        let rows = export_history(&mut engine).await;
+
+        let plays = rows
+            .iter()
+            .map(|row| {
+                (
+                    row["recording_id"].as_str().unwrap(),
+                    row["reason"].as_str().unwrap(),
+                )
+            })
+            .collect::<Vec<(&str, &str)>>();
+
+        assert_eq!(
+            plays,
+            [(RECORDING_ID, "Play"), (OTHER_RECORDING_ID, "Stop")]
+        );
+        assert!(rows
+            .iter()
+            .all(|row| row["listened_ms"].as_u64().unwrap() > 0));
+
+        engine.engine.shutdown().await;
+    }
+}
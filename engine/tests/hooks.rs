@@ -0,0 +1,203 @@
+#[macro_use]
+mod common;
+
+use std::time::Duration;
+
+use common::{expect, musicbrainz_stub, start_engine_with, TestEngine};
+use playit_engine::{
+    async_trait, EngineCommand, EngineConfig, EngineResponse, Hook, HookContext, HookDecision,
+    HookFailureReason, Hooks, NopeReason,
+};
+
+struct Reject;
+
+#[async_trait]
+impl Hook for Reject {
+    fn name(&self) -> &str {
+        "reject"
+    }
+
+    async fn on_command(&self, command: &EngineCommand, _context: &HookContext) -> HookDecision {
+        match command {
+            EngineCommand::SetVolume { .. } => HookDecision::Reject(NopeReason::PermissionDenied),
+            _ => HookDecision::Continue,
+        }
+    }
+}
+
+struct Cap;
+
+#[async_trait]
+impl Hook for Cap {
+    fn name(&self) -> &str {
+        "cap"
+    }
+
+    async fn on_command(&self, command: &EngineCommand, _context: &HookContext) -> HookDecision {
+        match command {
+            EngineCommand::SetVolume { volume } if *volume > 0.5 => {
+                HookDecision::Mutate(EngineCommand::SetVolume { volume: 0.5 })
+            }
+            _ => HookDecision::Continue,
+        }
+    }
+}
+
+struct Slow;
+
+#[async_trait]
+impl Hook for Slow {
+    fn name(&self) -> &str {
+        "slow"
+    }
+
+    async fn on_command(&self, command: &EngineCommand, _context: &HookContext) -> HookDecision {
+        if let EngineCommand::GetVolume = command {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        }
+
+        HookDecision::Continue
+    }
+}
+
+struct Panics;
+
+#[async_trait]
+impl Hook for Panics {
+    fn name(&self) -> &str {
+        "panics"
+    }
+
+    async fn on_command(&self, command: &EngineCommand, _context: &HookContext) -> HookDecision {
+        if let EngineCommand::GetVolume = command {
+            panic!("the hook failed");
+        }
+
+        HookDecision::Continue
+    }
+}
+
+async fn start_hooked(name: &str, musicbrainz: &str, hook: impl Hook) -> TestEngine {
+    let mut hooks = Hooks::default();
+
+    hooks.register(hook);
+    hooks.set_timeout(Duration::from_millis(200));
+
+    start_engine_with(
+        name,
+        musicbrainz,
+        EngineConfig {
+            hooks,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn volume(engine: &mut TestEngine, command: EngineCommand) -> f32 {
+    let _ = engine.commands.send(command);
+
+    volume_reply(engine).await
+}
+
+async fn volume_reply(engine: &mut TestEngine) -> f32 {
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Volume { volume } => Some(*volume),
+        EngineResponse::Nope { command, reason } => {
+            panic!("{:?} was rejected: {:?}", command, reason)
+        }
+        _ => None,
+    })
+    .await
+}
+
+async fn hook_failure(engine: &mut TestEngine) -> (String, HookFailureReason) {
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::HookFailed { hook, reason } => Some((hook.clone(), *reason)),
+        _ => None,
+    })
+    .await
+}
+
+storage_backends! {
+    async fn a_rejecting_hook_turns_the_command_away() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_hooked("hook-reject", &musicbrainz, Reject).await;
+
+        let _ = engine
+            .commands
+            .send(EngineCommand::SetVolume { volume: 0.2 });
+
+        let reason = expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::SetVolume { .. },
+                reason,
+            } => Some(*reason),
+            EngineResponse::Volume { .. } => panic!("the volume changed"),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(reason, NopeReason::PermissionDenied);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_mutating_hook_replaces_the_command() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_hooked("hook-mutate", &musicbrainz, Cap).await;
+
+        assert_eq!(
+            volume(&mut engine, EngineCommand::SetVolume { volume: 0.9 }).await,
+            0.5
+        );
+        assert_eq!(
+            volume(&mut engine, EngineCommand::SetVolume { volume: 0.3 }).await,
+            0.3
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_hook_past_its_timeout_is_skipped() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_hooked("hook-timeout", &musicbrainz, Slow).await;
+
+        let _ = engine.commands.send(EngineCommand::GetVolume);
+
+        assert_eq!(
+            hook_failure(&mut engine).await,
+            ("slow".to_owned(), HookFailureReason::TimedOut)
+        );
+
+        // The command goes ahead without the hook's decision.
+        volume_reply(&mut engine).await;
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_panicking_hook_does_not_stop_the_processor() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_hooked("hook-panic", &musicbrainz, Panics).await;
+
+        let _ = engine.commands.send(EngineCommand::GetVolume);
+
+        assert_eq!(
+            hook_failure(&mut engine).await,
+            ("panics".to_owned(), HookFailureReason::Panicked)
+        );
+
+        volume_reply(&mut engine).await;
+
+        assert_eq!(
+            volume(&mut engine, EngineCommand::SetVolume { volume: 0.7 }).await,
+            0.7
+        );
+
+        engine.engine.shutdown().await;
+    }
+}
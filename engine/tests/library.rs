@@ -0,0 +1,1084 @@
+#[macro_use]
+mod common;
+
+use std::{io::Cursor, time::Duration};
+
+use common::{
+    audio_file, burst_wav, connect, expect, musicbrainz_stub, private_wav, set_permissions,
+    start_client, start_engine, store_recording, wav, RawClient, TestEngine, OTHER_RECORDING_ID,
+    RECORDING_ID, REMOTE_RECORDING_ID, UNKNOWN_RECORDING_ID, WORK_ID,
+};
+use lofty::{file::TaggedFileExt, probe::Probe, tag::Accessor};
+use playit_engine::{
+    EngineCommand, EngineConnectionStatus, EngineResponse, LibraryChangeKind, LibrarySort,
+    MetadataEditRejectReason, NopeReason, Permission, PlaylistOrigin, QueueRejectReason,
+    SortDirection,
+};
+use serde_json::json;
+
+const PLAYLIST_ID: &str = "3b8e0c47-95d1-4f2a-b6c3-0d7e9a1f5c24";
+
+const TONE_500MS: &[u8] = include_bytes!("fixtures/tone-500ms.wav");
+
+async fn save_playlist(engine: &mut TestEngine, recordings: &[&str]) {
+    let command = serde_json::from_value::<EngineCommand>(json!({
+        "type": "SetPlaylistMetadata",
+        "id": PLAYLIST_ID,
+        "name": "Library",
+        "recordings": recordings,
+    }))
+    .unwrap();
+
+    let _ = engine.commands.send(command);
+
+    expect(&mut engine.responses, |response| {
+        matches!(response, EngineResponse::PlaylistMetadata { .. }).then_some(())
+    })
+    .await;
+}
+
+async fn playlist_recordings(engine: &mut TestEngine) -> Option<Vec<String>> {
+    let _ = engine.commands.send(EngineCommand::PlaylistMetadata {
+        id: PLAYLIST_ID.to_owned(),
+    });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::PlaylistMetadata { metadata, .. } if metadata.id == PLAYLIST_ID => {
+            Some(Some(metadata.recordings.clone()))
+        }
+        EngineResponse::Nope {
+            command: EngineCommand::PlaylistMetadata { .. },
+            ..
+        } => Some(None),
+        _ => None,
+    })
+    .await
+}
+
+/// Reconnects locally and waits for the local processor to answer, so that
+/// nothing sent afterwards is relayed to the peer or answered by it.
+async fn reconnect_locally(engine: &mut TestEngine) {
+    assert!(engine.engine.connect_to_local().await.is_ok());
+    assert_eq!(
+        engine.engine.connection_status(),
+        EngineConnectionStatus::ConnectedLocal
+    );
+
+    let _ = engine.commands.send(EngineCommand::GetState);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::State(_) => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+/// Returns the missing-metadata and missing-audio ids of the playlist.
+async fn validate_playlist(engine: &mut TestEngine, prune: bool) -> (Vec<String>, Vec<String>) {
+    let _ = engine.commands.send(EngineCommand::ValidatePlaylist {
+        id: PLAYLIST_ID.to_owned(),
+        prune,
+    });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::PlaylistValidation {
+            id,
+            missing_metadata,
+            missing_audio,
+        } if id == PLAYLIST_ID => Some((missing_metadata.clone(), missing_audio.clone())),
+        EngineResponse::Nope {
+            command: EngineCommand::ValidatePlaylist { .. },
+            reason,
+        } => panic!("the validation was refused: {:?}", reason),
+        _ => None,
+    })
+    .await
+}
+
+async fn recording_duration(engine: &mut TestEngine, id: &str) -> Result<Duration, NopeReason> {
+    let _ = engine
+        .commands
+        .send(EngineCommand::RecordingDuration { id: id.to_owned() });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::RecordingDuration {
+            id: measured,
+            duration,
+        } if measured == id => Some(Ok(*duration)),
+        EngineResponse::Nope {
+            command: EngineCommand::RecordingDuration { id: refused },
+            reason,
+        } if refused == id => Some(Err(*reason)),
+        _ => None,
+    })
+    .await
+}
+
+/// Returns the adopted ids and the files that failed.
+async fn adopt_loose_files(engine: &mut TestEngine) -> (Vec<String>, Vec<String>) {
+    let _ = engine.commands.send(EngineCommand::AdoptLooseFiles);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::LooseFilesAdopted { summary } => {
+            Some((summary.adopted.clone(), summary.failed.clone()))
+        }
+        _ => None,
+    })
+    .await
+}
+
+async fn request_ok(client: &mut RawClient, command: EngineCommand) {
+    let kind = command.kind();
+
+    client.send(command).await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::Ok { command } if command.kind() == kind => Some(()),
+            EngineResponse::Nope { command, reason } if command.kind() == kind => {
+                panic!("{} was refused: {:?}", kind, reason)
+            }
+            _ => None,
+        })
+        .await;
+}
+
+async fn tag(
+    client: &mut RawClient,
+    id: &str,
+    add: &[&str],
+    remove: &[&str],
+) -> Result<Vec<String>, NopeReason> {
+    client
+        .send(EngineCommand::TagRecording {
+            id: id.to_owned(),
+            add: add.iter().map(|tag| tag.to_string()).collect(),
+            remove: remove.iter().map(|tag| tag.to_string()).collect(),
+        })
+        .await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::RecordingTags { tags, .. } => Some(Ok(tags.clone())),
+            EngineResponse::Nope {
+                command: EngineCommand::TagRecording { .. },
+                reason,
+            } => Some(Err(*reason)),
+            _ => None,
+        })
+        .await
+}
+
+async fn tagged(client: &mut RawClient, tag: &str) -> Vec<String> {
+    client
+        .send(EngineCommand::RecordingsByTag {
+            tag: tag.to_owned(),
+        })
+        .await;
+
+    let mut recordings = client
+        .expect(|response| match response {
+            EngineResponse::Recordings { recordings, .. } => Some(recordings.clone()),
+            _ => None,
+        })
+        .await;
+
+    recordings.sort();
+
+    recordings
+}
+
+async fn tag_counts(client: &mut RawClient) -> Vec<(String, usize)> {
+    client.send(EngineCommand::ListTags).await;
+
+    let mut tags = client
+        .expect(|response| match response {
+            EngineResponse::Tags { tags } => Some(tags.clone()),
+            _ => None,
+        })
+        .await;
+
+    tags.sort();
+
+    tags
+}
+
+async fn trashed_ids(client: &mut RawClient) -> Vec<String> {
+    client.send(EngineCommand::ListTrash).await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::Trash { entries } => {
+                Some(entries.iter().map(|entry| entry.id.clone()).collect())
+            }
+            _ => None,
+        })
+        .await
+}
+
+async fn empty_trash(client: &mut RawClient, older_than: Option<Duration>) -> (Vec<String>, u64) {
+    client.send(EngineCommand::EmptyTrash { older_than }).await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::TrashEmptied {
+                removed,
+                bytes_reclaimed,
+            } => Some((removed.clone(), *bytes_reclaimed)),
+            _ => None,
+        })
+        .await
+}
+
+async fn send_recording(engine: &mut TestEngine, audio: Vec<u8>) -> Result<(), NopeReason> {
+    let _ = engine.commands.send(EngineCommand::SendRecording((
+        RECORDING_ID.to_owned(),
+        audio,
+    )));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Ok {
+            command: EngineCommand::SendRecording(_),
+        } => Some(Ok(())),
+        EngineResponse::Nope {
+            command: EngineCommand::SendRecording(_),
+            reason,
+        } => Some(Err(*reason)),
+        _ => None,
+    })
+    .await
+}
+
+async fn library_ids(engine: &mut TestEngine) -> Vec<String> {
+    let _ = engine.commands.send(EngineCommand::ListRecordings {
+        tag: None,
+        sort: None,
+        direction: SortDirection::Ascending,
+    });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Recordings { recordings, .. } => Some(recordings.clone()),
+        _ => None,
+    })
+    .await
+}
+
+/// Drops a fresh tone into the audio directory and adopts it as a local
+/// recording.
+async fn adopt_local_recording(engine: &mut TestEngine) -> String {
+    let audio = private_wav();
+    let hash = sha256::digest(&audio);
+    let id = format!("local:{}", hash);
+
+    std::fs::write(audio_file(&format!("loose-{}.wav", &hash[..8])), &audio).unwrap();
+
+    let (adopted, failed) = adopt_loose_files(engine).await;
+
+    assert!(adopted.contains(&id), "{:?} {:?}", adopted, failed);
+
+    id
+}
+
+/// Returns the edited ids and the rejected ones with their reasons.
+async fn edit_local_metadata(
+    engine: &mut TestEngine,
+    ids: &[&str],
+    set: serde_json::Value,
+    write_tags: bool,
+) -> (Vec<String>, Vec<(String, MetadataEditRejectReason)>) {
+    let command = serde_json::from_value::<EngineCommand>(json!({
+        "type": "EditLocalMetadata",
+        "ids": ids,
+        "set": set,
+        "write_tags": write_tags,
+    }))
+    .unwrap();
+
+    let _ = engine.commands.send(command);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::LocalMetadataEdited { edited, rejected } => {
+            Some((edited.clone(), rejected.clone()))
+        }
+        _ => None,
+    })
+    .await
+}
+
+/// Returns the title and stored file hash of a recording.
+async fn title_and_hash(engine: &mut TestEngine, id: &str) -> (String, Option<String>) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::RecordingMetadata { id: id.to_owned() });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::RecordingMetadata(metadata) if metadata.recording.id == id => Some((
+            metadata.recording.title.clone(),
+            metadata.audio_file_hash.clone(),
+        )),
+        _ => None,
+    })
+    .await
+}
+
+async fn titles_in_order(engine: &mut TestEngine) -> Vec<String> {
+    let _ = engine.commands.send(EngineCommand::ListRecordings {
+        tag: None,
+        sort: Some(LibrarySort::Title),
+        direction: SortDirection::Ascending,
+    });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Recordings { recordings, .. } => Some(recordings.clone()),
+        _ => None,
+    })
+    .await
+}
+
+storage_backends! {
+    async fn validating_a_playlist_sorts_out_broken_entries() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("validate-playlist", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+        save_playlist(
+            &mut engine,
+            &[RECORDING_ID, UNKNOWN_RECORDING_ID, REMOTE_RECORDING_ID],
+        )
+        .await;
+
+        let broken = (
+            vec![UNKNOWN_RECORDING_ID.to_owned()],
+            vec![REMOTE_RECORDING_ID.to_owned()],
+        );
+
+        assert_eq!(validate_playlist(&mut engine, false).await, broken);
+        assert_eq!(
+            playlist_recordings(&mut engine).await.unwrap(),
+            [RECORDING_ID, UNKNOWN_RECORDING_ID, REMOTE_RECORDING_ID]
+        );
+
+        // Queueing the playlist rejects the same entries for the same reasons.
+        let _ = engine.commands.send(EngineCommand::QueuePlaylist {
+            id: PLAYLIST_ID.to_owned(),
+            resume: false,
+        });
+
+        let rejected = expect(&mut engine.responses, |response| match response {
+            EngineResponse::QueueRejected { rejected } => Some(rejected.clone()),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(
+            rejected,
+            [
+                (UNKNOWN_RECORDING_ID.to_owned(), QueueRejectReason::UnknownRecording),
+                (REMOTE_RECORDING_ID.to_owned(), QueueRejectReason::MissingAudio),
+            ]
+        );
+
+        // Pruning only drops the entries without metadata.
+        assert_eq!(validate_playlist(&mut engine, true).await, broken);
+        assert_eq!(
+            playlist_recordings(&mut engine).await.unwrap(),
+            [RECORDING_ID, REMOTE_RECORDING_ID]
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn waveform_over_ipc_follows_the_envelope() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("waveform", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, burst_wav(2, 2)).await;
+
+        let mut client = connect(&engine).await;
+
+        client
+            .send(EngineCommand::Waveform {
+                id: RECORDING_ID.to_owned(),
+            })
+            .await;
+
+        let buckets = client
+            .expect(|response| match response {
+                EngineResponse::Waveform { id, buckets } if id == RECORDING_ID => {
+                    Some(buckets.clone())
+                }
+                EngineResponse::Nope {
+                    command: EngineCommand::Waveform { .. },
+                    reason,
+                } => panic!("the waveform was refused: {:?}", reason),
+                _ => None,
+            })
+            .await;
+
+        let (silent, loud) = buckets.split_at(buckets.len() / 2);
+
+        assert_eq!(buckets.len(), 400);
+        assert!(silent[..silent.len() - 2].iter().all(|bucket| *bucket < 8), "{:?}", silent);
+        assert!(loud[2..].iter().all(|bucket| *bucket > 240), "{:?}", loud);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_client_cancels_a_job_halfway() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("cancel-job", &musicbrainz).await;
+
+        store_recording(&mut server, RECORDING_ID, wav(1)).await;
+        store_recording(&mut server, OTHER_RECORDING_ID, wav(1)).await;
+        set_permissions(&mut server, vec![Permission::Library]).await;
+
+        let mut client = start_client(&server).await;
+
+        // Refreshing waits between recordings, so the job is still running
+        // after the first one is done.
+        let mut job = client.engine.refresh_library();
+
+        loop {
+            match job.progress().await {
+                Some(EngineResponse::JobProgress {
+                    completed, total, ..
+                }) => {
+                    assert!(completed < total);
+
+                    break;
+                }
+                Some(EngineResponse::JobStarted { .. }) => {}
+                other => panic!("the job did not make progress: {:?}", other),
+            }
+        }
+
+        job.cancel();
+
+        let job_id = job.id();
+
+        expect(&mut client.responses, |response| match response {
+            EngineResponse::Ok {
+                command: EngineCommand::CancelJob { job_id: cancelled },
+            } if *cancelled == job_id => Some(()),
+            EngineResponse::Nope {
+                command: EngineCommand::CancelJob { .. },
+                reason,
+            } => panic!("cancelling was refused: {:?}", reason),
+            _ => None,
+        })
+        .await;
+
+        let mut outcome = Vec::new();
+
+        while let Some(response) = job.progress().await {
+            outcome.push(response);
+        }
+
+        assert!(
+            matches!(
+                outcome.last(),
+                Some(EngineResponse::JobFinished {
+                    cancelled: true,
+                    ..
+                })
+            ),
+            "{:?}",
+            outcome
+        );
+        assert!(
+            !outcome
+                .iter()
+                .any(|response| matches!(response, EngineResponse::LibraryRefreshed { .. })),
+            "a cancelled refresh reported a result: {:?}",
+            outcome
+        );
+
+        client.engine.shutdown().await;
+        server.engine.shutdown().await;
+    }
+
+    async fn trash_round_trip_over_ipc() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("trash", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(2)).await;
+        save_playlist(&mut engine, &[RECORDING_ID, OTHER_RECORDING_ID]).await;
+        set_permissions(&mut engine, vec![Permission::Library, Permission::Playlist]).await;
+
+        let mut client = connect(&engine).await;
+
+        request_ok(
+            &mut client,
+            EngineCommand::DeleteRecording {
+                id: RECORDING_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert_eq!(trashed_ids(&mut client).await, [RECORDING_ID]);
+        assert_eq!(
+            playlist_recordings(&mut engine).await,
+            Some(vec![OTHER_RECORDING_ID.to_owned()])
+        );
+
+        request_ok(
+            &mut client,
+            EngineCommand::RestoreFromTrash {
+                id: RECORDING_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert!(trashed_ids(&mut client).await.is_empty());
+        assert_eq!(
+            playlist_recordings(&mut engine).await,
+            Some(vec![RECORDING_ID.to_owned(), OTHER_RECORDING_ID.to_owned()])
+        );
+
+        request_ok(
+            &mut client,
+            EngineCommand::DeletePlaylist {
+                id: PLAYLIST_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert_eq!(playlist_recordings(&mut engine).await, None);
+
+        request_ok(
+            &mut client,
+            EngineCommand::RestoreFromTrash {
+                id: PLAYLIST_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert!(playlist_recordings(&mut engine).await.is_some());
+
+        request_ok(
+            &mut client,
+            EngineCommand::DeleteRecording {
+                id: RECORDING_ID.to_owned(),
+            },
+        )
+        .await;
+
+        // Nothing has been in the trash for a day yet, so only emptying
+        // everything reclaims the audio.
+        let (removed, bytes_reclaimed) =
+            empty_trash(&mut client, Some(Duration::from_secs(24 * 60 * 60))).await;
+
+        assert!(removed.is_empty());
+        assert_eq!(bytes_reclaimed, 0);
+        assert_eq!(trashed_ids(&mut client).await, [RECORDING_ID]);
+
+        let (removed, bytes_reclaimed) = empty_trash(&mut client, None).await;
+
+        assert_eq!(removed, [RECORDING_ID]);
+        assert!(bytes_reclaimed > 0);
+        assert!(trashed_ids(&mut client).await.is_empty());
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn browsing_a_remote_playlist_caches_it_only_on_request() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut server = start_engine("playlist-source", &musicbrainz).await;
+        let mut browser = start_engine("playlist-browser", &musicbrainz).await;
+
+        save_playlist(&mut server, &[RECORDING_ID]).await;
+
+        assert!(browser
+            .engine
+            .connect_to_remote(server.socket.clone())
+            .await
+            .is_ok());
+
+        let _ = browser.commands.send(EngineCommand::ListPlaylists {
+            sort: None,
+            direction: SortDirection::Ascending,
+        });
+
+        let (ids, origin) = expect(&mut browser.responses, |response| match response {
+            EngineResponse::Playlists { playlists, origin } => Some((
+                playlists
+                    .iter()
+                    .map(|playlist| playlist.id.clone())
+                    .collect::<Vec<String>>(),
+                *origin,
+            )),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(ids, [PLAYLIST_ID]);
+        assert_eq!(origin, PlaylistOrigin::Remote);
+
+        // Reconnecting locally drops what was browsed, so caching has to fetch
+        // the playlist from the peer again.
+        reconnect_locally(&mut browser).await;
+        assert_eq!(playlist_recordings(&mut browser).await, None);
+        assert!(browser
+            .engine
+            .connect_to_remote(server.socket.clone())
+            .await
+            .is_ok());
+
+        let _ = browser.commands.send(EngineCommand::CachePlaylist {
+            id: PLAYLIST_ID.to_owned(),
+        });
+
+        expect(&mut browser.responses, |response| match response {
+            EngineResponse::PlaylistMetadata {
+                metadata,
+                origin: PlaylistOrigin::Local,
+            } if metadata.id == PLAYLIST_ID => Some(()),
+            EngineResponse::Nope {
+                command: EngineCommand::CachePlaylist { .. },
+                reason,
+            } => panic!("caching the playlist failed: {:?}", reason),
+            _ => None,
+        })
+        .await;
+
+        reconnect_locally(&mut browser).await;
+        assert_eq!(
+            playlist_recordings(&mut browser).await,
+            Some(vec![RECORDING_ID.to_owned()])
+        );
+
+        browser.engine.shutdown().await;
+        server.engine.shutdown().await;
+    }
+
+    async fn an_ipc_subscriber_sees_library_changes() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("library-events", &musicbrainz).await;
+
+        set_permissions(&mut engine, vec![Permission::Library]).await;
+
+        let mut subscriber = connect(&engine).await;
+
+        request_ok(&mut subscriber, EngineCommand::LibraryEvents { enabled: true }).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+
+        let _ = engine.commands.send(EngineCommand::DeleteRecording {
+            id: RECORDING_ID.to_owned(),
+        });
+
+        let mut changes = Vec::new();
+
+        // The deletion comes after everything the import announced.
+        subscriber
+            .expect(|response| match response {
+                EngineResponse::LibraryChanged { kind, id } => {
+                    assert_eq!(id, RECORDING_ID);
+
+                    changes.push(*kind);
+
+                    (*kind == LibraryChangeKind::RecordingDeleted).then_some(())
+                }
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(
+            changes,
+            [
+                LibraryChangeKind::RecordingUpserted,
+                LibraryChangeKind::AudioStored,
+                LibraryChangeKind::RecordingDeleted,
+            ]
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn tags_stay_consistent_with_the_library() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("tags", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(1)).await;
+        set_permissions(&mut engine, vec![Permission::Library, Permission::Playlist]).await;
+
+        let mut client = connect(&engine).await;
+
+        assert_eq!(
+            tag(&mut client, RECORDING_ID, &[" Workout ", "focus"], &[]).await,
+            Ok(vec!["workout".to_owned(), "focus".to_owned()])
+        );
+        assert_eq!(
+            tag(&mut client, OTHER_RECORDING_ID, &["WORKOUT"], &[]).await,
+            Ok(vec!["workout".to_owned()])
+        );
+        assert_eq!(
+            tag(&mut client, RECORDING_ID, &["   "], &[]).await,
+            Err(NopeReason::InvalidArgument)
+        );
+
+        let mut both = vec![RECORDING_ID.to_owned(), OTHER_RECORDING_ID.to_owned()];
+
+        both.sort();
+
+        assert_eq!(tagged(&mut client, "workout").await, both);
+        assert_eq!(tagged(&mut client, "Focus").await, [RECORDING_ID]);
+        assert_eq!(
+            tag_counts(&mut client).await,
+            [("focus".to_owned(), 1), ("workout".to_owned(), 2)]
+        );
+
+        assert_eq!(
+            tag(&mut client, RECORDING_ID, &[], &["focus"]).await,
+            Ok(vec!["workout".to_owned()])
+        );
+        assert!(tagged(&mut client, "focus").await.is_empty());
+        assert_eq!(tag_counts(&mut client).await, [("workout".to_owned(), 2)]);
+
+        request_ok(
+            &mut client,
+            EngineCommand::DeleteRecording {
+                id: OTHER_RECORDING_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert_eq!(tagged(&mut client, "workout").await, [RECORDING_ID]);
+        assert_eq!(tag_counts(&mut client).await, [("workout".to_owned(), 1)]);
+
+        request_ok(
+            &mut client,
+            EngineCommand::RestoreFromTrash {
+                id: OTHER_RECORDING_ID.to_owned(),
+            },
+        )
+        .await;
+
+        assert_eq!(tagged(&mut client, "workout").await, both);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn related_recordings_span_the_library_and_musicbrainz() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("related", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(1)).await;
+        set_permissions(&mut engine, vec![Permission::Library]).await;
+
+        let mut client = connect(&engine).await;
+
+        client
+            .send(EngineCommand::RelatedRecordings {
+                id: RECORDING_ID.to_owned(),
+            })
+            .await;
+
+        let related = client
+            .expect(|response| match response {
+                EngineResponse::Recordings {
+                    recordings,
+                    relationships,
+                } if !relationships.is_empty() => Some(
+                    recordings
+                        .iter()
+                        .cloned()
+                        .zip(relationships.iter().cloned())
+                        .collect::<Vec<_>>(),
+                ),
+                EngineResponse::Nope {
+                    command: EngineCommand::RelatedRecordings { .. },
+                    reason,
+                } => panic!("related recordings were refused: {:?}", reason),
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(related.len(), 2);
+
+        let (local_id, local) = &related[0];
+        let (remote_id, remote) = &related[1];
+
+        assert_eq!(local_id, OTHER_RECORDING_ID);
+        assert!(local.local);
+        assert_eq!(local.work_id, WORK_ID);
+        assert_eq!(local.relationship, "performance");
+
+        assert_eq!(remote_id, REMOTE_RECORDING_ID);
+        assert!(!remote.local);
+        assert_eq!(remote.work_id, WORK_ID);
+        assert_eq!(remote.relationship, "performance (cover)");
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_text_file_is_not_stored() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("ingest-text", &musicbrainz).await;
+
+        assert_eq!(
+            send_recording(&mut engine, b"these are not the samples you are looking for".to_vec())
+                .await,
+            Err(NopeReason::DecodeFailed)
+        );
+        assert!(library_ids(&mut engine).await.is_empty());
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_truncated_mp3_is_not_stored() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("ingest-mp3", &musicbrainz).await;
+
+        // An MPEG-1 layer III frame header (128 kbit/s, 44.1 kHz) cut off
+        // well before the 417 bytes the frame needs.
+        let mut mp3 = vec![0xFF, 0xFB, 0x90, 0x64];
+
+        mp3.resize(64, 0);
+
+        assert_eq!(
+            send_recording(&mut engine, mp3).await,
+            Err(NopeReason::DecodeFailed)
+        );
+        assert!(library_ids(&mut engine).await.is_empty());
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn the_duration_comes_from_the_stored_file() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("recording-duration", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, TONE_500MS.to_vec()).await;
+
+        assert_eq!(
+            recording_duration(&mut engine, RECORDING_ID).await,
+            Ok(Duration::from_millis(500))
+        );
+        assert!(recording_duration(&mut engine, REMOTE_RECORDING_ID)
+            .await
+            .is_err());
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_valid_wav_is_stored_with_its_format() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("ingest-wav", &musicbrainz).await;
+
+        assert_eq!(send_recording(&mut engine, wav(2)).await, Ok(()));
+        assert_eq!(library_ids(&mut engine).await, [RECORDING_ID]);
+
+        let _ = engine.commands.send(EngineCommand::RecordingMetadata {
+            id: RECORDING_ID.to_owned(),
+        });
+
+        let audio_format = expect(&mut engine.responses, |response| match response {
+            EngineResponse::RecordingMetadata(metadata) => Some(metadata.audio_format.clone()),
+            _ => None,
+        })
+        .await
+        .expect("the stored recording has no audio format");
+
+        assert_eq!(audio_format.codec, "wav");
+        assert_eq!(audio_format.sample_rate, 44100);
+        assert_eq!(audio_format.channels, 1);
+        assert_eq!(audio_format.duration, Some(Duration::from_secs(2)));
+
+        engine.engine.shutdown().await;
+    }
+}
+
+sled_backend! {
+    async fn adopting_loose_files_twice_adopts_them_once() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("adopt-loose-files", &musicbrainz).await;
+
+        let audio = private_wav();
+        let hash = sha256::digest(&audio);
+        let dropped = audio_file(&format!("dropped-{}.wav", &hash[..8]));
+        let broken = audio_file(&format!("broken-{}.txt", &hash[..8]));
+        let id = format!("local:{}", hash);
+
+        std::fs::write(&dropped, &audio).unwrap();
+        std::fs::write(&broken, b"not audio").unwrap();
+
+        let broken_name = broken.file_name().unwrap().to_str().unwrap().to_owned();
+
+        let (adopted, failed) = adopt_loose_files(&mut engine).await;
+
+        assert!(adopted.contains(&id), "{:?}", adopted);
+        assert!(failed.contains(&broken_name), "{:?}", failed);
+
+        // The second pass finds the renamed file referenced and leaves the
+        // undecodable one where it was.
+        let (adopted, failed) = adopt_loose_files(&mut engine).await;
+
+        assert!(!adopted.contains(&id), "{:?}", adopted);
+        assert!(failed.contains(&broken_name), "{:?}", failed);
+
+        assert!(!dropped.exists());
+        assert_eq!(std::fs::read(audio_file(&hash)).unwrap(), audio);
+        assert!(broken.exists());
+
+        let _ = engine.commands.send(EngineCommand::RecordingMetadata { id: id.clone() });
+
+        let title = expect(&mut engine.responses, |response| match response {
+            EngineResponse::RecordingMetadata(metadata) if metadata.recording.id == id => {
+                Some(metadata.recording.title.clone())
+            }
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(title, format!("dropped-{}", &hash[..8]));
+
+        let _ = std::fs::remove_file(broken);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn editing_local_metadata_leaves_musicbrainz_recordings_alone() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("edit-local-mixed", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(1)).await;
+
+        let local = adopt_local_recording(&mut engine).await;
+        let unknown = "local:0000000000000000000000000000000000000000000000000000000000000000";
+
+        let (edited, rejected) = edit_local_metadata(
+            &mut engine,
+            &[&local, RECORDING_ID, unknown],
+            json!({ "title": "Edited" }),
+            false,
+        )
+        .await;
+
+        assert_eq!(edited, [local.as_str()]);
+        assert_eq!(
+            rejected,
+            [
+                (RECORDING_ID.to_owned(), MetadataEditRejectReason::NotLocal),
+                (unknown.to_owned(), MetadataEditRejectReason::UnknownRecording),
+            ]
+        );
+
+        assert_eq!(title_and_hash(&mut engine, &local).await.0, "Edited");
+        assert_eq!(title_and_hash(&mut engine, RECORDING_ID).await.0, "Hand-off");
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn local_metadata_edits_reach_the_sorted_library() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("edit-local-index", &musicbrainz).await;
+
+        set_permissions(&mut engine, vec![Permission::Library]).await;
+
+        let first = adopt_local_recording(&mut engine).await;
+        let second = adopt_local_recording(&mut engine).await;
+
+        let mut subscriber = connect(&engine).await;
+
+        request_ok(&mut subscriber, EngineCommand::LibraryEvents { enabled: true }).await;
+
+        edit_local_metadata(&mut engine, &[&first], json!({ "title": "Zither" }), false).await;
+        edit_local_metadata(&mut engine, &[&second], json!({ "title": "Accordion" }), false).await;
+
+        let order = titles_in_order(&mut engine).await;
+        let position = |id: &str| order.iter().position(|entry| entry == id).unwrap();
+
+        assert!(position(&second) < position(&first), "{:?}", order);
+
+        edit_local_metadata(&mut engine, &[&second], json!({ "title": "Zymbal" }), false).await;
+
+        let order = titles_in_order(&mut engine).await;
+        let position = |id: &str| order.iter().position(|entry| entry == id).unwrap();
+
+        assert!(position(&first) < position(&second), "{:?}", order);
+
+        for id in [&first, &second] {
+            subscriber
+                .expect(|response| match response {
+                    EngineResponse::LibraryChanged {
+                        kind: LibraryChangeKind::RecordingUpserted,
+                        id: changed,
+                    } if changed == id => Some(()),
+                    _ => None,
+                })
+                .await;
+        }
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn writing_tags_back_rekeys_the_stored_file() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("edit-local-write-tags", &musicbrainz).await;
+
+        let id = adopt_local_recording(&mut engine).await;
+        let (_, original_hash) = title_and_hash(&mut engine, &id).await;
+
+        let (edited, rejected) = edit_local_metadata(
+            &mut engine,
+            &[&id],
+            json!({ "title": "Written", "artist": "Someone", "album": "Tagged", "year": 1999 }),
+            true,
+        )
+        .await;
+
+        assert!(rejected.is_empty(), "{:?}", rejected);
+        assert_eq!(edited, [id.as_str()]);
+
+        // The recording keeps its id while its file moves to the new hash.
+        let (title, hash) = title_and_hash(&mut engine, &id).await;
+        let hash = hash.expect("the recording lost its file");
+
+        assert_eq!(title, "Written");
+        assert_ne!(Some(&hash), original_hash.as_ref());
+
+        let stored = std::fs::read(audio_file(&hash)).unwrap();
+
+        assert_eq!(sha256::digest(&stored), hash);
+
+        let tagged_file = Probe::new(Cursor::new(stored))
+            .guess_file_type()
+            .unwrap()
+            .read()
+            .unwrap();
+        let tag = tagged_file.primary_tag().expect("no tag was written");
+
+        assert_eq!(tag.title().as_deref(), Some("Written"));
+        assert_eq!(tag.artist().as_deref(), Some("Someone"));
+        assert_eq!(tag.album().as_deref(), Some("Tagged"));
+        assert_eq!(tag.year(), Some(1999));
+
+        // The audio itself is unchanged.
+        assert_eq!(
+            recording_duration(&mut engine, &id).await,
+            Ok(Duration::from_secs(1))
+        );
+
+        engine.engine.shutdown().await;
+    }
+}
@@ -0,0 +1,596 @@
+#[macro_use]
+mod common;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use common::{
+    audio_file, connect, expect, musicbrainz_stub, play, private_wav, restart_engine,
+    set_permissions, start_engine, store_recording, wav, RawClient, TestEngine, OTHER_RECORDING_ID,
+    RECORDING_ID,
+};
+use playit_engine::{EngineCommand, EngineResponse, NopeReason, Permission};
+
+const FIRST_SAMPLE_BUDGET: Duration = Duration::from_millis(250);
+
+const DELETED_RECORDING_ID: &str = "7c4d9e1a-2b3f-4a5c-8d6e-9f0a1b2c3d4e";
+
+async fn queue(engine: &mut TestEngine, id: &str) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::Queue(Some(vec![id.to_owned()])));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Queue { queue } if queue.iter().any(|queued| queued == id) => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+async fn clear_queue_at(engine: &mut TestEngine, deadline: Instant) {
+    tokio::time::sleep_until(deadline.into()).await;
+
+    let _ = engine
+        .commands
+        .send(EngineCommand::ClearQueue { preview: false });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Queue { queue } if queue.is_empty() => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+async fn state(engine: &mut TestEngine) -> (Option<String>, bool, usize) {
+    let _ = engine.commands.send(EngineCommand::GetState);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::State(state) => {
+            Some((state.recording_id.clone(), state.paused, state.queue_length))
+        }
+        _ => None,
+    })
+    .await
+}
+
+async fn restored_state(engine: &mut TestEngine) -> (Duration, bool, Option<u64>) {
+    let _ = engine.commands.send(EngineCommand::GetState);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::State(state) => Some((state.position, state.shuffle, state.shuffle_seed)),
+        _ => None,
+    })
+    .await
+}
+
+async fn queued(engine: &mut TestEngine) -> Vec<String> {
+    let _ = engine.commands.send(EngineCommand::Queue(None));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Queue { queue } => Some(queue.clone()),
+        _ => None,
+    })
+    .await
+}
+
+async fn volume(engine: &mut TestEngine, command: EngineCommand) -> f32 {
+    let _ = engine.commands.send(command);
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Volume { volume } => Some(*volume),
+        _ => None,
+    })
+    .await
+}
+
+async fn client_volume(client: &mut RawClient, command: EngineCommand) -> f32 {
+    client.send(command).await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::Volume { volume } => Some(*volume),
+            _ => None,
+        })
+        .await
+}
+
+async fn audio_file_hash(engine: &mut TestEngine, id: &str) -> Option<String> {
+    let _ = engine
+        .commands
+        .send(EngineCommand::RecordingMetadata { id: id.to_owned() });
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::RecordingMetadata(metadata) if metadata.recording.id == id => {
+            Some(metadata.audio_file_hash.clone())
+        }
+        _ => None,
+    })
+    .await
+}
+
+async fn play_refused(engine: &mut TestEngine, id: &str) -> NopeReason {
+    let _ = engine
+        .commands
+        .send(EngineCommand::Play(Some(id.to_owned())));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::NowPlaying { id: playing } => panic!("{} played", playing),
+        EngineResponse::Nope {
+            command: EngineCommand::Play(Some(refused)),
+            reason,
+        } if refused == id => Some(*reason),
+        _ => None,
+    })
+    .await
+}
+
+/// Says hello asking for `current_time_hz` and waits until the connection is
+/// subscribed to broadcasts.
+async fn greet(client: &mut RawClient, current_time_hz: Option<f32>) {
+    client
+        .send(EngineCommand::Hello {
+            current_time_hz,
+            device: None,
+            token: None,
+        })
+        .await;
+
+    client_volume(client, EngineCommand::GetVolume).await;
+}
+
+/// Counts the `CurrentTime` updates `client` receives before `deadline`.
+async fn current_times(client: &mut RawClient, deadline: Instant) -> usize {
+    let mut count = 0;
+
+    while tokio::time::timeout_at(
+        deadline.into(),
+        client.expect(|response| matches!(response, EngineResponse::CurrentTime(_)).then_some(())),
+    )
+    .await
+    .is_ok()
+    {
+        count += 1;
+    }
+
+    count
+}
+
+storage_backends! {
+    async fn play_switches_tracks_immediately() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("play-switch", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(30)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+        play(&mut engine, OTHER_RECORDING_ID).await;
+
+        let _ = engine.commands.send(EngineCommand::GetState);
+
+        let (recording_id, position) = expect(&mut engine.responses, |response| match response {
+            EngineResponse::State(state) => Some((state.recording_id.clone(), state.position)),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(recording_id.as_deref(), Some(OTHER_RECORDING_ID));
+        assert!(position < Duration::from_secs(5));
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn clearing_the_queue_before_a_gapless_switch_drops_the_preload() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("gapless-clear-early", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(2)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(10)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        let started = Instant::now();
+
+        queue(&mut engine, OTHER_RECORDING_ID).await;
+        clear_queue_at(&mut engine, started + Duration::from_millis(1500)).await;
+
+        tokio::time::sleep_until((started + Duration::from_millis(3500)).into()).await;
+
+        let (recording_id, _, queue_length) = state(&mut engine).await;
+
+        assert_ne!(recording_id.as_deref(), Some(OTHER_RECORDING_ID));
+        assert_eq!(queue_length, 0);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn clearing_the_queue_after_a_gapless_switch_keeps_the_new_track() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("gapless-clear-late", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(2)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(10)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        let started = Instant::now();
+
+        queue(&mut engine, OTHER_RECORDING_ID).await;
+        clear_queue_at(&mut engine, started + Duration::from_millis(2300)).await;
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::NowPlaying { id } if id == OTHER_RECORDING_ID => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let (recording_id, paused, queue_length) = state(&mut engine).await;
+
+        assert_eq!(recording_id.as_deref(), Some(OTHER_RECORDING_ID));
+        assert!(!paused);
+        assert_eq!(queue_length, 0);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn unmuting_restores_a_volume_set_while_muted() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("mute", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(10)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        assert_eq!(volume(&mut engine, EngineCommand::SetVolume { volume: 0.8 }).await, 0.8);
+        assert_eq!(
+            volume(&mut engine, EngineCommand::Mute { muted: true }).await,
+            0.0
+        );
+        assert_eq!(volume(&mut engine, EngineCommand::SetVolume { volume: 0.4 }).await, 0.0);
+        assert_eq!(volume(&mut engine, EngineCommand::GetVolume).await, 0.0);
+        assert_eq!(
+            volume(&mut engine, EngineCommand::Mute { muted: false }).await,
+            0.4
+        );
+        assert_eq!(volume(&mut engine, EngineCommand::GetVolume).await, 0.4);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn volume_changes_reach_ipc_clients() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("mute-wire", &musicbrainz).await;
+
+        set_permissions(&mut engine, vec![Permission::Control]).await;
+
+        let mut client = connect(&engine).await;
+
+        // A reply means the connection is subscribed to broadcasts.
+        client_volume(&mut client, EngineCommand::GetVolume).await;
+
+        let _ = engine.commands.send(EngineCommand::SetVolume { volume: 0.6 });
+
+        client
+            .expect(|response| match response {
+                EngineResponse::Volume { volume } if *volume == 0.6 => Some(()),
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(
+            client_volume(&mut client, EngineCommand::Mute { muted: true }).await,
+            0.0
+        );
+        assert_eq!(client_volume(&mut client, EngineCommand::GetVolume).await, 0.0);
+        assert_eq!(
+            client_volume(&mut client, EngineCommand::Mute { muted: false }).await,
+            0.6
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn ipc_clients_can_set_the_volume() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("set-volume-wire", &musicbrainz).await;
+
+        set_permissions(&mut engine, vec![Permission::Control]).await;
+
+        let mut client = connect(&engine).await;
+
+        assert_eq!(
+            client_volume(&mut client, EngineCommand::SetVolume { volume: 0.3 }).await,
+            0.3
+        );
+        assert_eq!(volume(&mut engine, EngineCommand::GetVolume).await, 0.3);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn listeners_receive_the_playback_as_ogg_opus() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("stream-listen", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+        set_permissions(&mut engine, vec![Permission::Transfer]).await;
+
+        let mut client = connect(&engine).await;
+
+        client.send(EngineCommand::StreamListen { enabled: true }).await;
+
+        client
+            .expect(|response| match response {
+                EngineResponse::Ok {
+                    command: EngineCommand::StreamListen { .. },
+                } => Some(()),
+                EngineResponse::Nope {
+                    command: EngineCommand::StreamListen { .. },
+                    reason,
+                } => panic!("listening was refused: {:?}", reason),
+                _ => None,
+            })
+            .await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        let ogg = client
+            .expect(|response| match response {
+                EngineResponse::StreamChunk(chunk) => Some(chunk.ogg.clone()),
+                _ => None,
+            })
+            .await;
+
+        // The first chunk opens the listener's own Ogg stream.
+        assert!(ogg.starts_with(b"OggS"));
+        assert!(ogg.windows(8).any(|bytes| bytes == b"OpusHead"));
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn an_engine_without_a_peer_has_no_playback_to_follow() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("follow-local", &musicbrainz).await;
+
+        let _ = engine
+            .commands
+            .send(EngineCommand::FollowPlayback { enabled: true });
+
+        let reason = expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::FollowPlayback { .. },
+                reason,
+            } => Some(*reason),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(reason, NopeReason::Unsupported);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn playing_a_missing_file_heals_the_recording() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("missing-file", &musicbrainz).await;
+
+        let audio = private_wav();
+
+        store_recording(&mut engine, RECORDING_ID, audio.clone()).await;
+
+        let hash = audio_file_hash(&mut engine, RECORDING_ID)
+            .await
+            .expect("the stored recording has no audio hash");
+        let path = audio_file(&hash);
+
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(play_refused(&mut engine, RECORDING_ID).await, NopeReason::NotFound);
+        assert_eq!(audio_file_hash(&mut engine, RECORDING_ID).await, None);
+
+        // Were the dead path probed again, this copy would be found and played.
+        std::fs::write(&path, &audio).unwrap();
+
+        assert_eq!(play_refused(&mut engine, RECORDING_ID).await, NopeReason::NotFound);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn each_client_gets_current_time_at_its_own_rate() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("time-rates", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+
+        let mut every = connect(&engine).await;
+        let mut slow = connect(&engine).await;
+        let mut off = connect(&engine).await;
+
+        greet(&mut every, None).await;
+        greet(&mut slow, Some(0.5)).await;
+        greet(&mut off, Some(0.0)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        // The engine ticks once a second, so six seconds hold about six
+        // updates, half as many at 0.5 Hz and none at all at 0 Hz.
+        let deadline = Instant::now() + Duration::from_secs(6);
+
+        let (every, slow, off) = tokio::join!(
+            current_times(&mut every, deadline),
+            current_times(&mut slow, deadline),
+            current_times(&mut off, deadline),
+        );
+
+        assert!((5..=7).contains(&every), "{}", every);
+        assert!((2..=4).contains(&slow) && slow < every, "{} {}", slow, every);
+        assert_eq!(off, 0);
+
+        engine.engine.shutdown().await;
+    }
+}
+
+sled_backend! {
+    async fn the_restored_recording_starts_within_budget() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("warm-up", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(10)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+
+        let first_sample = Arc::new(Mutex::new(None));
+        let output_first_sample = first_sample.clone();
+
+        let mut engine = restart_engine(engine, "warm-up", &musicbrainz, move |samples: &[f32]| {
+            if samples.iter().any(|sample| *sample != 0.0) {
+                output_first_sample
+                    .lock()
+                    .unwrap()
+                    .get_or_insert_with(Instant::now);
+            }
+        })
+        .await;
+
+        // Give the warm-up time to preload the restored recording.
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        assert!(first_sample.lock().unwrap().is_none());
+        assert_eq!(
+            state(&mut engine).await,
+            (Some(RECORDING_ID.to_owned()), true, 0)
+        );
+
+        let started = Instant::now();
+
+        play(&mut engine, RECORDING_ID).await;
+
+        let first_sample = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(first_sample) = *first_sample.lock().unwrap() {
+                    return first_sample;
+                }
+
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .expect("the restored recording never played");
+
+        assert!(
+            first_sample - started < FIRST_SAMPLE_BUDGET,
+            "first sample after {:?}",
+            first_sample - started
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn the_player_state_survives_a_restart() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("restore-state", &musicbrainz).await;
+
+        let deleted = private_wav();
+
+        store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(30)).await;
+        store_recording(&mut engine, DELETED_RECORDING_ID, deleted.clone()).await;
+
+        play(&mut engine, RECORDING_ID).await;
+        queue(&mut engine, OTHER_RECORDING_ID).await;
+        queue(&mut engine, DELETED_RECORDING_ID).await;
+
+        let _ = engine.commands.send(EngineCommand::ShuffleQueue {
+            enable: true,
+            seed: Some(3),
+        });
+
+        expect(&mut engine.responses, |response| {
+            matches!(response, EngineResponse::Shuffle { enable: true, .. }).then_some(())
+        })
+        .await;
+
+        assert_eq!(volume(&mut engine, EngineCommand::SetVolume { volume: 0.4 }).await, 0.4);
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let _ = engine.commands.send(EngineCommand::Pause);
+
+        expect(&mut engine.responses, |response| {
+            matches!(response, EngineResponse::NowPaused).then_some(())
+        })
+        .await;
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+
+        let (paused_at, _, _) = restored_state(&mut engine).await;
+
+        std::fs::remove_file(audio_file(&sha256::digest(&deleted))).unwrap();
+
+        let mut engine = restart_engine(engine, "restore-state", &musicbrainz, |_: &[f32]| {}).await;
+
+        assert_eq!(
+            state(&mut engine).await,
+            (Some(RECORDING_ID.to_owned()), true, 1)
+        );
+
+        let (position, shuffle, shuffle_seed) = restored_state(&mut engine).await;
+
+        assert!(
+            position.abs_diff(paused_at) < Duration::from_millis(100),
+            "paused at {:?}, restored at {:?}",
+            paused_at,
+            position
+        );
+        assert_eq!((shuffle, shuffle_seed), (true, Some(3)));
+
+        // The recording whose audio went missing is dropped from the queue.
+        assert_eq!(queued(&mut engine).await, [OTHER_RECORDING_ID]);
+        assert_eq!(volume(&mut engine, EngineCommand::GetVolume).await, 0.4);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn a_cleared_state_is_not_restored() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("clear-state", &musicbrainz).await;
+
+        store_recording(&mut engine, RECORDING_ID, wav(30)).await;
+        store_recording(&mut engine, OTHER_RECORDING_ID, wav(30)).await;
+
+        play(&mut engine, RECORDING_ID).await;
+        queue(&mut engine, OTHER_RECORDING_ID).await;
+
+        let _ = engine
+            .commands
+            .send(EngineCommand::ClearSavedState { keep_saving: false });
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Ok {
+                command: EngineCommand::ClearSavedState { .. },
+            } => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let mut engine = restart_engine(engine, "clear-state", &musicbrainz, |_: &[f32]| {}).await;
+
+        assert_eq!(state(&mut engine).await, (None, true, 0));
+
+        engine.engine.shutdown().await;
+    }
+}
@@ -0,0 +1,414 @@
+#[macro_use]
+mod common;
+
+use common::{
+    expect, musicbrainz_stub, play, start_engine, start_engine_with, store_recording, wav,
+    TestEngine, REMOTE_RECORDING_ID, UNKNOWN_RECORDING_ID,
+};
+use playit_engine::{
+    EngineCommand, EngineConfig, EngineResponse, LoopMode, QueueModel, QueueRejectReason,
+    QueueRemoveTarget,
+};
+use serde_json::json;
+
+const FIRST: &str = "1b0f5e52-8d0c-4c1e-9f8a-3e6d2b7c4a01";
+const SECOND: &str = "2c1a6f63-9e1d-4d2f-8a9b-4f7e3c8d5b02";
+const THIRD: &str = "3d2b7a74-af2e-4e3a-9bac-5a8f4d9e6c03";
+const FOURTH: &str = "4e3c8b85-b03f-4f4b-8cbd-6b9a5eaf7d04";
+
+const PLAYLIST_ID: &str = "5f4d9c96-c14a-4a5c-9dce-7cab6fb08e05";
+
+async fn start_with_recordings(name: &str, lengths: &[(&str, u32)]) -> TestEngine {
+    let musicbrainz = musicbrainz_stub().await;
+
+    let mut engine = start_engine(name, &musicbrainz).await;
+
+    for (id, seconds) in lengths {
+        store_recording(&mut engine, id, wav(*seconds)).await;
+    }
+
+    engine
+}
+
+async fn queued(engine: &mut TestEngine) -> Vec<String> {
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::Queue { queue } => Some(queue.clone()),
+        _ => None,
+    })
+    .await
+}
+
+async fn queue(engine: &mut TestEngine, ids: &[&str]) -> Vec<String> {
+    let _ = engine.commands.send(EngineCommand::Queue(Some(
+        ids.iter().map(|id| id.to_string()).collect(),
+    )));
+
+    queued(engine).await
+}
+
+async fn rejected(engine: &mut TestEngine) -> Vec<(String, QueueRejectReason)> {
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::QueueRejected { rejected } => Some(rejected.clone()),
+        _ => None,
+    })
+    .await
+}
+
+async fn set_model(engine: &mut TestEngine, model: QueueModel) {
+    let _ = engine.commands.send(EngineCommand::QueueModel(model));
+
+    queued(engine).await;
+}
+
+async fn set_loop(engine: &mut TestEngine, loop_mode: LoopMode) {
+    let _ = engine
+        .commands
+        .send(EngineCommand::LoopMode(loop_mode.clone()));
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::LoopMode(mode) if *mode == loop_mode => Some(()),
+        _ => None,
+    })
+    .await;
+}
+
+async fn queue_playlist(engine: &mut TestEngine, recordings: &[&str]) -> Vec<String> {
+    let command = serde_json::from_value::<EngineCommand>(json!({
+        "type": "SetPlaylistMetadata",
+        "id": PLAYLIST_ID,
+        "name": "Loop",
+        "recordings": recordings,
+    }))
+    .unwrap();
+
+    let _ = engine.commands.send(command);
+
+    expect(&mut engine.responses, |response| {
+        matches!(response, EngineResponse::PlaylistMetadata { .. }).then_some(())
+    })
+    .await;
+
+    let _ = engine.commands.send(EngineCommand::QueuePlaylist {
+        id: PLAYLIST_ID.to_owned(),
+        resume: false,
+    });
+
+    queued(engine).await
+}
+
+/// Sends Next or Previous and returns what is playing and what is left.
+async fn skip(engine: &mut TestEngine, command: EngineCommand) -> (String, Vec<String>) {
+    let _ = engine.commands.send(command);
+
+    let playing = expect(&mut engine.responses, |response| match response {
+        EngineResponse::NowPlaying { id } => Some(id.clone()),
+        _ => None,
+    })
+    .await;
+
+    (playing, queued(engine).await)
+}
+
+async fn shuffle(engine: &mut TestEngine, enable: bool, seed: Option<u64>) -> Vec<String> {
+    let _ = engine
+        .commands
+        .send(EngineCommand::ShuffleQueue { enable, seed });
+
+    queued(engine).await
+}
+
+async fn next_and_previous(name: &str, model: QueueModel) {
+    let mut engine = start_with_recordings(name, &[(FIRST, 30), (SECOND, 30), (THIRD, 30)]).await;
+
+    set_model(&mut engine, model).await;
+    queue(&mut engine, &[FIRST, SECOND, THIRD]).await;
+
+    assert_eq!(
+        skip(&mut engine, EngineCommand::Next).await,
+        (FIRST.to_owned(), vec![SECOND.to_owned(), THIRD.to_owned()])
+    );
+    assert_eq!(
+        skip(&mut engine, EngineCommand::Next).await,
+        (SECOND.to_owned(), vec![THIRD.to_owned()])
+    );
+    assert_eq!(skip(&mut engine, EngineCommand::Previous).await.0, FIRST);
+
+    engine.engine.shutdown().await;
+}
+
+async fn loop_queue_wraps(name: &str, model: QueueModel) {
+    let mut engine = start_with_recordings(name, &[(FIRST, 30), (SECOND, 30), (THIRD, 30)]).await;
+
+    set_model(&mut engine, model).await;
+    set_loop(&mut engine, LoopMode::LoopQueue).await;
+    queue(&mut engine, &[FIRST, SECOND, THIRD]).await;
+
+    let mut played = Vec::new();
+
+    for _ in 0..4 {
+        played.push(skip(&mut engine, EngineCommand::Next).await.0);
+    }
+
+    assert_eq!(played, [FIRST, SECOND, THIRD, FIRST]);
+
+    engine.engine.shutdown().await;
+}
+
+async fn loop_playlist_refills_from_the_playlist(name: &str, model: QueueModel) {
+    let mut engine = start_with_recordings(name, &[(FIRST, 30), (SECOND, 30), (THIRD, 30)]).await;
+
+    set_model(&mut engine, model).await;
+    queue_playlist(&mut engine, &[FIRST, SECOND, THIRD]).await;
+    set_loop(&mut engine, LoopMode::LoopPlaylist(PLAYLIST_ID.to_owned())).await;
+
+    assert_eq!(skip(&mut engine, EngineCommand::Next).await.0, FIRST);
+
+    let _ = engine
+        .commands
+        .send(EngineCommand::QueueRemove(QueueRemoveTarget::Id(
+            SECOND.to_owned(),
+        )));
+
+    assert_eq!(queued(&mut engine).await, [THIRD]);
+
+    assert_eq!(
+        skip(&mut engine, EngineCommand::Next).await,
+        (THIRD.to_owned(), Vec::new())
+    );
+
+    // The refill comes from the playlist, so the removed recording is back.
+    assert_eq!(
+        skip(&mut engine, EngineCommand::Next).await,
+        (FIRST.to_owned(), vec![SECOND.to_owned(), THIRD.to_owned()])
+    );
+
+    engine.engine.shutdown().await;
+}
+
+async fn shuffled_next_follows_the_shuffle(name: &str, model: QueueModel) {
+    let mut engine = start_with_recordings(name, &[(FIRST, 30), (SECOND, 30), (THIRD, 30)]).await;
+
+    set_model(&mut engine, model).await;
+    queue(&mut engine, &[FIRST, SECOND, THIRD]).await;
+
+    let shuffled = shuffle(&mut engine, true, Some(7)).await;
+    let mut played = Vec::new();
+
+    for _ in 0..3 {
+        played.push(skip(&mut engine, EngineCommand::Next).await.0);
+    }
+
+    assert_eq!(played, shuffled);
+
+    engine.engine.shutdown().await;
+}
+
+storage_backends! {
+    async fn rejected_ids_carry_their_reason() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine_with(
+            "queue-reject-reasons",
+            &musicbrainz,
+            EngineConfig {
+                max_queue_length: Some(2),
+                ..Default::default()
+            },
+        )
+        .await;
+
+        for id in [FIRST, SECOND, THIRD] {
+            store_recording(&mut engine, id, wav(30)).await;
+        }
+
+        let _ = engine.commands.send(EngineCommand::Queue(Some(
+            [FIRST, UNKNOWN_RECORDING_ID, REMOTE_RECORDING_ID, SECOND, THIRD]
+                .into_iter()
+                .map(str::to_owned)
+                .collect(),
+        )));
+
+        assert_eq!(
+            rejected(&mut engine).await,
+            [
+                (UNKNOWN_RECORDING_ID.to_owned(), QueueRejectReason::UnknownRecording),
+                (REMOTE_RECORDING_ID.to_owned(), QueueRejectReason::MissingAudio),
+                (THIRD.to_owned(), QueueRejectReason::QueueFull),
+            ]
+        );
+        assert_eq!(queued(&mut engine).await, [FIRST, SECOND]);
+
+        let _ = engine.commands.send(EngineCommand::QueueTo {
+            queue: "requests".to_owned(),
+            recordings: vec![THIRD.to_owned(), THIRD.to_owned()],
+        });
+
+        assert_eq!(
+            rejected(&mut engine).await,
+            [(THIRD.to_owned(), QueueRejectReason::Duplicate)]
+        );
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn previous_walks_back_through_history() {
+        let mut engine = start_with_recordings(
+            "queue-previous-history",
+            &[(FIRST, 30), (SECOND, 30), (THIRD, 30)],
+        )
+        .await;
+
+        queue(&mut engine, &[FIRST, SECOND, THIRD]).await;
+
+        for _ in 0..3 {
+            skip(&mut engine, EngineCommand::Next).await;
+        }
+
+        assert_eq!(skip(&mut engine, EngineCommand::Previous).await.0, SECOND);
+        assert_eq!(skip(&mut engine, EngineCommand::Previous).await.0, FIRST);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn previous_without_history_is_refused() {
+        let mut engine = start_with_recordings("queue-previous-empty", &[(FIRST, 30)]).await;
+
+        let _ = engine.commands.send(EngineCommand::Previous);
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::Previous,
+                ..
+            } => Some(()),
+            _ => None,
+        })
+        .await;
+
+        play(&mut engine, FIRST).await;
+
+        let _ = engine.commands.send(EngineCommand::Previous);
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::Previous,
+                ..
+            } => Some(()),
+            _ => None,
+        })
+        .await;
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn consuming_next_and_previous() {
+        next_and_previous("queue-consuming-skip", QueueModel::Consuming).await;
+    }
+
+    async fn cursor_next_and_previous() {
+        next_and_previous("queue-cursor-skip", QueueModel::Cursor).await;
+    }
+
+    async fn consuming_loop_queue_wraps() {
+        loop_queue_wraps("queue-consuming-loop", QueueModel::Consuming).await;
+    }
+
+    async fn cursor_loop_queue_wraps() {
+        loop_queue_wraps("queue-cursor-loop", QueueModel::Cursor).await;
+    }
+
+    async fn consuming_loop_playlist_refills() {
+        loop_playlist_refills_from_the_playlist("queue-consuming-loop-playlist", QueueModel::Consuming)
+            .await;
+    }
+
+    async fn cursor_loop_playlist_refills() {
+        loop_playlist_refills_from_the_playlist("queue-cursor-loop-playlist", QueueModel::Cursor)
+            .await;
+    }
+
+    async fn deleting_the_looped_playlist_ends_the_loop() {
+        let mut engine = start_with_recordings("queue-loop-playlist-deleted", &[(FIRST, 30)]).await;
+
+        queue_playlist(&mut engine, &[FIRST]).await;
+        set_loop(&mut engine, LoopMode::LoopPlaylist(PLAYLIST_ID.to_owned())).await;
+
+        let _ = engine.commands.send(EngineCommand::DeletePlaylist {
+            id: PLAYLIST_ID.to_owned(),
+        });
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::LoopMode(LoopMode::None) => Some(()),
+            _ => None,
+        })
+        .await;
+
+        // A playlist that is gone can't be looped again.
+        let _ = engine
+            .commands
+            .send(EngineCommand::LoopMode(LoopMode::LoopPlaylist(
+                PLAYLIST_ID.to_owned(),
+            )));
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::LoopMode(LoopMode::LoopPlaylist(_)),
+                ..
+            } => Some(()),
+            EngineResponse::LoopMode(LoopMode::LoopPlaylist(_)) => {
+                panic!("a deleted playlist was looped")
+            }
+            _ => None,
+        })
+        .await;
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn consuming_shuffle() {
+        shuffled_next_follows_the_shuffle("queue-consuming-shuffle", QueueModel::Consuming).await;
+    }
+
+    async fn cursor_shuffle() {
+        shuffled_next_follows_the_shuffle("queue-cursor-shuffle", QueueModel::Cursor).await;
+    }
+
+    async fn shuffle_is_shared_with_the_advancing_clone() {
+        let mut engine = start_with_recordings(
+            "queue-shuffle-clone",
+            &[(FIRST, 1), (SECOND, 30), (THIRD, 30), (FOURTH, 30)],
+        )
+        .await;
+
+        queue(&mut engine, &[SECOND, THIRD, FOURTH]).await;
+
+        let shuffled = shuffle(&mut engine, true, Some(3)).await;
+
+        // The command processor shuffled the queue, while the position watcher,
+        // another clone of the sequencer, advances into it once FIRST ends.
+        let _ = engine
+            .commands
+            .send(EngineCommand::Play(Some(FIRST.to_owned())));
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::NowPlaying { id } if *id == shuffled[0] => Some(()),
+            _ => None,
+        })
+        .await;
+
+        let _ = engine.commands.send(EngineCommand::Queue(None));
+
+        assert_eq!(queued(&mut engine).await, shuffled[1..]);
+
+        let unshuffled = shuffle(&mut engine, false, None).await;
+
+        assert_eq!(
+            unshuffled,
+            [SECOND, THIRD, FOURTH]
+                .into_iter()
+                .filter(|id| *id != shuffled[0])
+                .collect::<Vec<&str>>()
+        );
+
+        engine.engine.shutdown().await;
+    }
+}
@@ -0,0 +1,72 @@
+#[macro_use]
+mod common;
+
+use std::{os::unix::net::UnixListener, path::Path, time::Duration};
+
+use common::{connect, musicbrainz_stub, socket_path, start_engine_with, TestEngine};
+use playit_engine::{EngineCommand, EngineConfig, EngineResponse};
+
+async fn start_on(socket: &str, musicbrainz: &str) -> TestEngine {
+    start_engine_with(
+        "socket-path",
+        musicbrainz,
+        EngineConfig {
+            socket: Some(socket.to_owned()),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+async fn round_trip(engine: &TestEngine) {
+    let mut client = connect(engine).await;
+
+    client.send(EngineCommand::GetVolume).await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::Volume { .. } => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+storage_backends! {
+    async fn an_engine_restarts_over_a_stale_socket_path() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let socket = socket_path("restart");
+        let path = Path::new(socket.strip_prefix("path:").unwrap()).to_owned();
+
+        let engine = start_on(&socket, &musicbrainz).await;
+
+        assert!(path.exists());
+
+        round_trip(&engine).await;
+
+        engine.engine.shutdown().await;
+        drop(engine);
+
+        // The listener removes its socket once it is gone.
+        for _ in 0..50 {
+            if !path.exists() {
+                break;
+            }
+
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+
+        assert!(!path.exists());
+
+        // A killed engine leaves its socket behind with nobody listening.
+        drop(UnixListener::bind(&path).unwrap());
+
+        assert!(path.exists());
+
+        let engine = start_on(&socket, &musicbrainz).await;
+
+        round_trip(&engine).await;
+
+        engine.engine.shutdown().await;
+    }
+}
@@ -0,0 +1,427 @@
+#[macro_use]
+mod common;
+
+use std::{path::PathBuf, time::Duration};
+
+use common::{
+    connect, expect, musicbrainz_stub, set_permissions, start_engine, store_recording, wav,
+    RawClient, TestEngine, RECORDING_ID,
+};
+use playit_engine::{EngineCommand, EngineResponse, NopeReason, Permission};
+
+const UPLOAD_CHUNKS: usize = 16;
+
+const TRANSFER_IDS: [&str; 5] = [
+    "5a1e2f30-6b4c-4d5e-8f70-81a2b3c4d5e1",
+    "5a1e2f30-6b4c-4d5e-8f70-81a2b3c4d5e2",
+    "5a1e2f30-6b4c-4d5e-8f70-81a2b3c4d5e3",
+    "5a1e2f30-6b4c-4d5e-8f70-81a2b3c4d5e4",
+    "5a1e2f30-6b4c-4d5e-8f70-81a2b3c4d5e5",
+];
+
+async fn ask_for_chunk(client: &mut RawClient, id: &str) {
+    client
+        .send(EngineCommand::RecordingFileChunk {
+            id: id.to_owned(),
+            offset: 0,
+        })
+        .await;
+}
+
+/// Waits for the answer to a chunk request: true if the chunk was sent, false
+/// if the request was refused as busy.
+async fn chunk_sent(client: &mut RawClient, id: &str) -> bool {
+    client
+        .expect(|response| match response {
+            EngineResponse::RecordingFileChunk { id: sent, .. } if sent == id => Some(true),
+            EngineResponse::Nope {
+                command: EngineCommand::RecordingFileChunk { id: refused, .. },
+                reason,
+            } if refused == id => {
+                assert!(matches!(reason, NopeReason::Busy), "{:?}", reason);
+
+                Some(false)
+            }
+            _ => None,
+        })
+        .await
+}
+
+async fn acknowledge(client: &mut RawClient, id: &str) {
+    client
+        .send(EngineCommand::TransferAck { id: id.to_owned() })
+        .await;
+
+    client
+        .expect(|response| match response {
+            EngineResponse::Ok {
+                command: EngineCommand::TransferAck { id: acked },
+            } if acked == id => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+/// Uploads the chunks in order, returning the stored hash or why the last
+/// chunk was refused.
+async fn upload_chunks(
+    engine: &mut TestEngine,
+    chunks: &[Vec<u8>],
+    sha256: &str,
+) -> Result<String, NopeReason> {
+    let total_len = chunks.iter().map(|chunk| chunk.len() as u64).sum();
+    let mut offset = 0;
+
+    for chunk in chunks {
+        let _ = engine.commands.send(EngineCommand::SendRecordingChunk {
+            id: RECORDING_ID.to_owned(),
+            offset,
+            total_len,
+            sha256: sha256.to_owned(),
+            data: chunk.clone(),
+        });
+
+        offset += chunk.len() as u64;
+
+        if offset < total_len {
+            expect(&mut engine.responses, |response| match response {
+                EngineResponse::TransferProgress { received, .. } if *received == offset => {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+        }
+    }
+
+    expect(&mut engine.responses, |response| match response {
+        EngineResponse::RecordingStored { sha256, .. } => Some(Ok(sha256.clone())),
+        EngineResponse::Nope {
+            command: EngineCommand::SendRecordingChunk { .. },
+            reason,
+        } => Some(Err(*reason)),
+        _ => None,
+    })
+    .await
+}
+
+/// The partial files kept for uploads of `id`, whoever is sending them.
+fn partial_uploads(id: &str) -> Vec<PathBuf> {
+    let transfers = PathBuf::from(std::env::var_os("HOME").unwrap()).join(".playit/transfers");
+    let suffix = sha256::digest(id.as_bytes());
+
+    std::fs::read_dir(transfers)
+        .map(|entries| {
+            entries
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.to_string_lossy().ends_with(&suffix))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+storage_backends! {
+    async fn a_corrupted_chunk_fails_the_upload() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("chunked-corrupted", &musicbrainz).await;
+
+        let audio = wav(2);
+        let sha256 = sha256::digest(&audio);
+        let chunks = audio
+            .chunks(audio.len() / 3 + 1)
+            .map(<[u8]>::to_vec)
+            .collect::<Vec<Vec<u8>>>();
+
+        let mut corrupted = chunks.clone();
+        corrupted[1][100] ^= 0xff;
+
+        assert_eq!(
+            upload_chunks(&mut engine, &corrupted, &sha256).await,
+            Err(NopeReason::IntegrityMismatch)
+        );
+
+        let _ = engine.commands.send(EngineCommand::RecordingFileChunk {
+            id: RECORDING_ID.to_owned(),
+            offset: 0,
+        });
+
+        expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::RecordingFileChunk { .. },
+                ..
+            } => Some(()),
+            EngineResponse::RecordingFileChunk { .. } => panic!("a corrupted upload was stored"),
+            _ => None,
+        })
+        .await;
+
+        // The partial file was dropped, so the sender starts over.
+        assert_eq!(upload_chunks(&mut engine, &chunks, &sha256).await, Ok(sha256));
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn an_unpaired_client_leaving_mid_upload_discards_it() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("chunked-abandoned", &musicbrainz).await;
+
+        set_permissions(&mut engine, vec![Permission::Transfer]).await;
+
+        // Both backends run at once, so each uploads under its own id.
+        let id = format!("abandoned-{}", engine.socket);
+        let audio = wav(2);
+
+        let mut client = connect(&engine).await;
+
+        client
+            .send(EngineCommand::SendRecordingChunk {
+                id: id.clone(),
+                offset: 0,
+                total_len: audio.len() as u64,
+                sha256: sha256::digest(&audio),
+                data: audio[..audio.len() / 2].to_vec(),
+            })
+            .await;
+
+        client
+            .expect(|response| match response {
+                EngineResponse::TransferProgress { id: progressing, .. } if *progressing == id => {
+                    Some(())
+                }
+                _ => None,
+            })
+            .await;
+
+        assert_eq!(partial_uploads(&id).len(), 1);
+
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !partial_uploads(&id).is_empty() {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the partial upload outlived its connection");
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn chunked_upload_is_committed_once_complete() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("chunked-upload", &musicbrainz).await;
+
+        let audio = wav(2);
+        let sha256 = sha256::digest(&audio);
+        let total_len = audio.len() as u64;
+        let (first, second) = audio.split_at(audio.len() / 2);
+
+        let _ = engine.commands.send(EngineCommand::SendRecordingChunk {
+            id: RECORDING_ID.to_owned(),
+            offset: 0,
+            total_len,
+            sha256: sha256.clone(),
+            data: first.to_vec(),
+        });
+
+        let received = expect(&mut engine.responses, |response| match response {
+            EngineResponse::TransferProgress { received, .. } => Some(*received),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(received, first.len() as u64);
+
+        let _ = engine.commands.send(EngineCommand::SendRecordingChunk {
+            id: RECORDING_ID.to_owned(),
+            offset: received,
+            total_len,
+            sha256: sha256.clone(),
+            data: second.to_vec(),
+        });
+
+        let stored = expect(&mut engine.responses, |response| match response {
+            EngineResponse::RecordingStored { sha256, .. } => Some(sha256.clone()),
+            EngineResponse::Nope {
+                command: EngineCommand::SendRecordingChunk { .. },
+                reason,
+            } => panic!("storing the upload failed: {:?}", reason),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(stored, sha256);
+
+        let _ = engine.commands.send(EngineCommand::RecordingFileChunk {
+            id: RECORDING_ID.to_owned(),
+            offset: 0,
+        });
+
+        let (read_len, data) = expect(&mut engine.responses, |response| match response {
+            EngineResponse::RecordingFileChunk {
+                total_len, data, ..
+            } => Some((*total_len, data.clone())),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(read_len, total_len);
+        assert_eq!(data, audio[..data.len()]);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn chunked_upload_with_the_wrong_hash_is_rejected() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("chunked-mismatch", &musicbrainz).await;
+
+        let audio = wav(1);
+
+        let _ = engine.commands.send(EngineCommand::SendRecordingChunk {
+            id: RECORDING_ID.to_owned(),
+            offset: 0,
+            total_len: audio.len() as u64,
+            sha256: sha256::digest(b"something else"),
+            data: audio,
+        });
+
+        let reason = expect(&mut engine.responses, |response| match response {
+            EngineResponse::Nope {
+                command: EngineCommand::SendRecordingChunk { .. },
+                reason,
+            } => Some(*reason),
+            EngineResponse::RecordingStored { .. } => panic!("a mismatched upload was stored"),
+            _ => None,
+        })
+        .await;
+
+        assert_eq!(reason, NopeReason::IntegrityMismatch);
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn transfers_beyond_the_limit_wait_for_an_ack() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("transfer-limit", &musicbrainz).await;
+
+        for id in TRANSFER_IDS {
+            store_recording(&mut engine, id, wav(1)).await;
+        }
+
+        let mut client = connect(&engine).await;
+
+        for id in TRANSFER_IDS {
+            ask_for_chunk(&mut client, id).await;
+        }
+
+        let mut sent = Vec::new();
+
+        // A connection's commands are answered in order.
+        for id in TRANSFER_IDS {
+            sent.push(chunk_sent(&mut client, id).await);
+        }
+
+        assert_eq!(sent, [true, true, false, false, false]);
+
+        for (acked, waiting) in TRANSFER_IDS.iter().zip(&TRANSFER_IDS[2..]) {
+            ask_for_chunk(&mut client, waiting).await;
+            assert!(!chunk_sent(&mut client, waiting).await);
+
+            acknowledge(&mut client, acked).await;
+
+            ask_for_chunk(&mut client, waiting).await;
+            assert!(chunk_sent(&mut client, waiting).await);
+        }
+
+        engine.engine.shutdown().await;
+    }
+
+    async fn reads_during_an_upload_see_a_whole_file() {
+        let musicbrainz = musicbrainz_stub().await;
+
+        let mut engine = start_engine("upload-reads", &musicbrainz).await;
+
+        let committed = wav(1);
+        let uploaded = wav(2);
+        let versions = [sha256::digest(&committed), sha256::digest(&uploaded)];
+
+        store_recording(&mut engine, RECORDING_ID, committed).await;
+
+        let commands = engine.commands.clone();
+        let mut responses = engine.responses.resubscribe();
+        let total_len = uploaded.len() as u64;
+        let sha256 = versions[1].clone();
+
+        let mut upload = tokio::spawn(async move {
+            let mut offset = 0;
+
+            for chunk in uploaded.chunks(uploaded.len() / UPLOAD_CHUNKS + 1) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+
+                let _ = commands.send(EngineCommand::SendRecordingChunk {
+                    id: RECORDING_ID.to_owned(),
+                    offset,
+                    total_len,
+                    sha256: sha256.clone(),
+                    data: chunk.to_vec(),
+                });
+
+                offset = expect(&mut responses, |response| match response {
+                    EngineResponse::TransferProgress { received, .. } => Some(*received),
+                    EngineResponse::RecordingStored { .. } => Some(total_len),
+                    EngineResponse::Nope {
+                        command: EngineCommand::SendRecordingChunk { .. },
+                        reason,
+                    } => panic!("the upload failed: {:?}", reason),
+                    _ => None,
+                })
+                .await;
+            }
+        });
+
+        let mut seen = Vec::new();
+
+        loop {
+            let finished = tokio::select! {
+                _ = &mut upload => true,
+                _ = tokio::time::sleep(Duration::from_millis(5)) => false,
+            };
+
+            let _ = engine.commands.send(EngineCommand::RecordingFileChunk {
+                id: RECORDING_ID.to_owned(),
+                offset: 0,
+            });
+
+            let (total_len, data) = expect(&mut engine.responses, |response| match response {
+                EngineResponse::RecordingFileChunk {
+                    total_len, data, ..
+                } => Some((*total_len, data.clone())),
+                _ => None,
+            })
+            .await;
+
+            assert_eq!(data.len() as u64, total_len);
+
+            let digest = sha256::digest(&data);
+
+            assert!(versions.contains(&digest), "read a file that was never committed");
+
+            seen.push(digest);
+
+            if finished {
+                break;
+            }
+        }
+
+        assert_eq!(seen.first(), Some(&versions[0]));
+        assert_eq!(seen.last(), Some(&versions[1]));
+
+        engine.engine.shutdown().await;
+    }
+}
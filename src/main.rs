@@ -1,23 +1,305 @@
-use playit_engine::{Engine, EngineCommand};
+use std::time::Duration;
+
+use playit_engine::{Engine, EngineCommand, EngineConfig, EngineResponse, HealthState, IdleAction};
+use tokio::{signal, sync::broadcast};
+
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+const DIAGNOSTICS_TIMEOUT: Duration = Duration::from_secs(10);
+const SHUTDOWN_DEADLINE: Duration = Duration::from_secs(5);
+const FORCED_SHUTDOWN_EXIT_CODE: i32 = 2;
 
 #[derive(Debug)]
 enum PlayItError {
     EngineError,
+    MissingSocketAddress,
+    MissingSeekPosition,
+    InvalidSeekPosition,
+    HealthCheckTimedOut,
+    MissingIdleMinutes,
+    InvalidIdleMinutes,
+    MissingOutputPath,
+    DiagnosticsTimedOut,
+    DiagnosticsWriteFailed,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), PlayItError> {
-    let Ok((mut audio_engine, command_sender, mut command_receiver)) = Engine::create() else {
+    let mut config = EngineConfig::default();
+    let mut command = EngineCommand::RecordingMetadata {
+        id: "e2c2390c-32d3-446d-b904-0b347927165c".to_string(),
+    };
+
+    let mut health_check = false;
+    let mut diagnostics = false;
+    let mut diagnostics_output = None;
+    let mut daemon = false;
+
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => {
+                let Some(address) = args.next() else {
+                    return Err(PlayItError::MissingSocketAddress);
+                };
+
+                config.socket = Some(address);
+            }
+            "seek" => {
+                let Some(position) = args.next() else {
+                    return Err(PlayItError::MissingSeekPosition);
+                };
+
+                let Some(seek_command) = parse_seek(&position) else {
+                    return Err(PlayItError::InvalidSeekPosition);
+                };
+
+                command = seek_command;
+            }
+            "health" => {
+                command = EngineCommand::HealthCheck;
+                health_check = true;
+            }
+            "diagnostics" => {
+                command = EngineCommand::DiagnosticBundle;
+                diagnostics = true;
+            }
+            "--output" => {
+                let Some(path) = args.next() else {
+                    return Err(PlayItError::MissingOutputPath);
+                };
+
+                diagnostics_output = Some(path);
+            }
+            "daemon" => {
+                daemon = true;
+            }
+            "--idle-exit" => {
+                let Some(minutes) = args.next() else {
+                    return Err(PlayItError::MissingIdleMinutes);
+                };
+
+                let Some(seconds) = minutes
+                    .parse::<u64>()
+                    .ok()
+                    .and_then(|minutes| minutes.checked_mul(60))
+                else {
+                    return Err(PlayItError::InvalidIdleMinutes);
+                };
+
+                config.idle_timeout = Some(Duration::from_secs(seconds));
+            }
+            "--park-instead" => {
+                config.idle_action = IdleAction::Park;
+            }
+            "--adopt-loose-files" => {
+                config.adopt_loose_files = true;
+            }
+            _ => {}
+        }
+    }
+
+    let Ok((audio_engine, command_sender, mut command_receiver)) =
+        Engine::create_with_config(config).await
+    else {
         return Err(PlayItError::EngineError);
     };
 
-    let _ = audio_engine.connect_to_local();
+    let _ = audio_engine.connect_to_local().await;
+
+    if daemon {
+        tokio::select! {
+            _ = wait_for_idle_shutdown(&mut command_receiver) => {}
+            _ = shutdown_signal() => {}
+        }
 
-    let _ = command_sender.send(EngineCommand::RecordingMetadata(
-        "e2c2390c-32d3-446d-b904-0b347927165c".to_string(),
-    ));
+        return shutdown(&audio_engine).await;
+    }
+
+    let _ = command_sender.send(command);
+
+    if health_check {
+        return report_health(&mut command_receiver).await;
+    }
+
+    if diagnostics {
+        return report_diagnostics(&mut command_receiver, diagnostics_output).await;
+    }
 
     loop {
-        println!("Get Metadata: {:?}", command_receiver.recv().await);
+        println!("Response: {:?}", command_receiver.recv().await);
+    }
+}
+
+async fn wait_for_idle_shutdown(command_receiver: &mut broadcast::Receiver<EngineResponse>) {
+    loop {
+        match command_receiver.recv().await {
+            Ok(EngineResponse::Idle {
+                action: IdleAction::Shutdown,
+            })
+            | Err(broadcast::error::RecvError::Closed) => return,
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+        }
+    }
+}
+
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let Ok(mut terminate) = signal::unix::signal(signal::unix::SignalKind::terminate()) else {
+            let _ = signal::ctrl_c().await;
+
+            return;
+        };
+
+        tokio::select! {
+            _ = signal::ctrl_c() => {}
+            _ = terminate.recv() => {}
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        let _ = signal::ctrl_c().await;
+    }
+}
+
+async fn shutdown(audio_engine: &Engine) -> Result<(), PlayItError> {
+    tokio::select! {
+        finished = tokio::time::timeout(SHUTDOWN_DEADLINE, audio_engine.shutdown()) => {
+            if finished.is_ok() {
+                return Ok(());
+            }
+        }
+        _ = shutdown_signal() => {}
+    }
+
+    std::process::exit(FORCED_SHUTDOWN_EXIT_CODE);
+}
+
+async fn report_health(
+    command_receiver: &mut broadcast::Receiver<EngineResponse>,
+) -> Result<(), PlayItError> {
+    let health = tokio::time::timeout(HEALTH_CHECK_TIMEOUT, async {
+        loop {
+            match command_receiver.recv().await {
+                Ok(EngineResponse::Health {
+                    audio,
+                    database,
+                    network,
+                    details,
+                }) => return Some(([audio, database, network], details)),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let Ok(Some((states, details))) = health.await else {
+        return Err(PlayItError::HealthCheckTimedOut);
+    };
+
+    for detail in details {
+        println!("{}", detail);
+    }
+
+    if states.contains(&HealthState::Unhealthy) {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+async fn report_diagnostics(
+    command_receiver: &mut broadcast::Receiver<EngineResponse>,
+    output: Option<String>,
+) -> Result<(), PlayItError> {
+    let diagnostics = tokio::time::timeout(DIAGNOSTICS_TIMEOUT, async {
+        loop {
+            match command_receiver.recv().await {
+                Ok(response @ EngineResponse::Diagnostics { .. }) => return Some(response),
+                Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let Ok(Some(diagnostics)) = diagnostics.await else {
+        return Err(PlayItError::DiagnosticsTimedOut);
+    };
+
+    let Ok(bundle) = serde_json::to_string_pretty(&diagnostics) else {
+        return Err(PlayItError::DiagnosticsWriteFailed);
+    };
+
+    let Some(output) = output else {
+        println!("{}", bundle);
+
+        return Ok(());
+    };
+
+    if std::fs::write(output, bundle).is_err() {
+        return Err(PlayItError::DiagnosticsWriteFailed);
+    }
+
+    Ok(())
+}
+
+fn parse_seek(position: &str) -> Option<EngineCommand> {
+    if let Some(percent) = position.strip_suffix('%') {
+        let Ok(percent) = percent.trim().parse::<f32>() else {
+            return None;
+        };
+
+        if !percent.is_finite() {
+            return None;
+        }
+
+        return Some(EngineCommand::SeekPercent {
+            fraction: percent / 100.0,
+        });
+    }
+
+    let (minutes, seconds) = position.split_once(':').unwrap_or(("0", position));
+
+    let (Ok(minutes), Ok(seconds)) = (minutes.parse::<u64>(), seconds.parse::<f64>()) else {
+        return None;
+    };
+
+    let (Some(minutes), Ok(seconds)) = (
+        minutes.checked_mul(60),
+        Duration::try_from_secs_f64(seconds),
+    ) else {
+        return None;
+    };
+
+    Duration::from_secs(minutes)
+        .checked_add(seconds)
+        .map(EngineCommand::Seek)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_seek_accepts_minutes_and_percentages() {
+        assert!(matches!(
+            parse_seek("1:30.5"),
+            Some(EngineCommand::Seek(position)) if position == Duration::from_millis(90_500)
+        ));
+        assert!(matches!(
+            parse_seek("25%"),
+            Some(EngineCommand::SeekPercent { fraction }) if fraction == 0.25
+        ));
+    }
+
+    #[test]
+    fn parse_seek_rejects_out_of_range_positions() {
+        assert!(parse_seek("1e20").is_none());
+        assert!(parse_seek("-1").is_none());
+        assert!(parse_seek("NaN").is_none());
+        assert!(parse_seek(&format!("{}:00", u64::MAX)).is_none());
+        assert!(parse_seek("inf%").is_none());
     }
 }